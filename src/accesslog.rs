@@ -0,0 +1,191 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use hyper::Request;
+use hyper::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::logging::log_limited;
+use crate::metrics::ACCESS_LOG_DROPPED_TOTAL;
+use crate::server::client_ip;
+
+// Lines waiting for the writer, past this new lines are dropped and counted
+const QUEUE_CAPACITY: usize = 65536;
+// Lines written per batch, the file is flushed after each batch
+const WRITE_BATCH: usize = 1024;
+const WRITE_BUFFER_BYTES: usize = 256 * 1024;
+const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+const DEFAULT_KEEP: u32 = 5;
+
+// Set at startup from --access-log or the config's access_log section
+pub static ACCESS_LOG: OnceLock<AccessLog> = OnceLock::new();
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AccessLogConfig {
+    pub path: PathBuf,
+    // Size at which the file is rotated to <path>.1, default 100MB
+    pub max_bytes: Option<u64>,
+    // Rotated files kept, <path>.1 being the newest, default 5
+    pub keep: Option<u32>,
+}
+
+/// Request side of an access log line, taken before the request is consumed
+#[derive(Debug, Serialize)]
+pub struct AccessRequest {
+    time: String,
+    client_ip: Option<IpAddr>,
+    method: String,
+    path: String,
+    range: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AccessRequest {
+    pub fn new<B>(req: &Request<B>) -> Self {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        Self {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            client_ip: client_ip(req),
+            method: req.method().to_string(),
+            // Without the query, it carries signatures and tokens
+            path: req.uri().path().to_string(),
+            range: header("range"),
+            user_agent: header("user-agent"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AccessLine<'a> {
+    #[serde(flatten)]
+    request: &'a AccessRequest,
+    status: u16,
+    bytes: u64,
+    duration_ms: u64,
+}
+
+/// One JSON line per completed response, written to a file by a background task
+pub struct AccessLog {
+    sender: mpsc::Sender<String>,
+}
+
+impl AccessLog {
+    /// Start the writer for `config` and install it as ACCESS_LOG
+    pub async fn start(config: &AccessLogConfig) -> anyhow::Result<()> {
+        let file = open_log(&config.path).await?;
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        if ACCESS_LOG.set(Self { sender }).is_err() {
+            anyhow::bail!("access log already started");
+        }
+        info!("Writing JSON access log to {}", config.path.display());
+        tokio::spawn(write_task(receiver, file, config.clone()));
+        Ok(())
+    }
+
+    pub fn record(
+        &self,
+        request: &AccessRequest,
+        status: StatusCode,
+        bytes: u64,
+        duration: Duration,
+    ) {
+        let line = AccessLine {
+            request,
+            status: status.as_u16(),
+            bytes,
+            duration_ms: duration.as_millis() as u64,
+        };
+        let Ok(mut line) = serde_json::to_string(&line) else {
+            return;
+        };
+        line.push('\n');
+        // Never hold up a response for the log, a full queue means the disk can't keep up
+        if self.sender.try_send(line).is_err() {
+            ACCESS_LOG_DROPPED_TOTAL.inc();
+        }
+    }
+}
+
+struct LogFile {
+    writer: tokio::io::BufWriter<tokio::fs::File>,
+    size: u64,
+}
+
+async fn open_log(path: &Path) -> std::io::Result<LogFile> {
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let size = file.metadata().await?.len();
+    Ok(LogFile {
+        writer: tokio::io::BufWriter::with_capacity(WRITE_BUFFER_BYTES, file),
+        size,
+    })
+}
+
+async fn write_task(
+    mut receiver: mpsc::Receiver<String>,
+    mut file: LogFile,
+    config: AccessLogConfig,
+) {
+    let max_bytes = config.max_bytes.unwrap_or(DEFAULT_MAX_BYTES);
+    let keep = config.keep.unwrap_or(DEFAULT_KEEP);
+    let mut lines = Vec::with_capacity(WRITE_BATCH);
+    while receiver.recv_many(&mut lines, WRITE_BATCH).await > 0 {
+        for line in lines.drain(..) {
+            match file.writer.write_all(line.as_bytes()).await {
+                Ok(()) => file.size += line.len() as u64,
+                Err(e) => log_limited!(warn, "access_log", "Failed to write access log: {}", e),
+            }
+        }
+        if let Err(e) = file.writer.flush().await {
+            log_limited!(warn, "access_log", "Failed to write access log: {}", e);
+        }
+        if file.size < max_bytes {
+            continue;
+        }
+        match rotate(&config.path, keep).await {
+            Ok(rotated) => file = rotated,
+            Err(e) => {
+                // Keep appending to the current file, retried after the next batch
+                log_limited!(
+                    warn,
+                    "access_log_rotate",
+                    "Failed to rotate access log {}: {}",
+                    config.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+// <path>.{n} -> <path>.{n+1}, dropping the oldest, then <path> -> <path>.1
+async fn rotate(path: &Path, keep: u32) -> std::io::Result<LogFile> {
+    let numbered = |n: u32| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+    if keep == 0 {
+        tokio::fs::remove_file(path).await?;
+        return open_log(path).await;
+    }
+    let _ = tokio::fs::remove_file(numbered(keep)).await;
+    for n in (1..keep).rev() {
+        let _ = tokio::fs::rename(numbered(n), numbered(n + 1)).await;
+    }
+    tokio::fs::rename(path, numbered(1)).await?;
+    open_log(path).await
+}
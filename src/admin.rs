@@ -0,0 +1,266 @@
+use std::str::FromStr;
+
+use hyper::body::Bytes;
+use hyper::http::StatusCode;
+use hyper::{Method, Request, Response};
+use librqbit::dht::Id20;
+use tracing::{info, warn};
+
+use crate::app::AppState;
+use crate::config::{Config, node_status, reload_config, restore_config, spawn_torrent_verify};
+use crate::handlers::{management_validation, read_body_limited, read_only_response};
+use crate::response::ResBody;
+use crate::warm::resolve_data_path;
+
+// Snapshots embed every .torrent, allow large torrent sets
+const MAX_SNAPSHOT_BODY_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Node management API under /-/admin, behind the management token:
+///
+/// - `GET /-/admin/config`: version of the config in use and where it came from
+/// - `POST /-/admin/config/refresh`: load the config now instead of waiting for the next poll
+/// - `GET /-/admin/torrents`: configured torrents with their status
+/// - `POST /-/admin/torrents/{info_hash}/verify`: hash check a torrent's files again
+/// - `POST /-/admin/cache/invalidate?path=...`: drop file status cache entries
+pub async fn handle_admin_request(
+    state: &AppState,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // auth
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+
+    let rest = req.uri().path()["/-/admin".len()..].trim_matches('/');
+    let segments: Vec<&str> = rest.split('/').collect();
+    let response = match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["config"]) => config_info(state),
+        (&Method::POST, ["config", "refresh"]) => refresh_config(state).await,
+        (&Method::GET, ["torrents"]) => json_response(
+            StatusCode::OK,
+            serde_json::json!({ "torrents": node_status(state).torrents }),
+        ),
+        (&Method::POST, ["torrents", info_hash, "verify"]) => verify_torrent(state, info_hash),
+        (&Method::POST, ["cache", "invalidate"]) => invalidate_cache(state, req.uri().query()),
+        (_, ["config"] | ["torrents"]) => method_not_allowed("GET"),
+        (_, ["config", "refresh"] | ["torrents", _, "verify"] | ["cache", "invalidate"]) => {
+            method_not_allowed("POST")
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(ResBody::Empty)
+            .unwrap(),
+    };
+    Ok(response)
+}
+
+/// Config backup for disaster recovery, behind the management token:
+///
+/// - `GET /-/config/snapshot`: the applied config, torrents included inline
+/// - `POST /-/config/restore`: apply a snapshot and keep it for starts without
+///   the config source
+///
+/// Snapshots carry every secret of the config, so both are refused on nodes
+/// without a management token.
+pub async fn handle_config_request(
+    state: &AppState,
+    mut req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    if state.config.load().management_token.is_none() {
+        let response = Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(ResBody::Bytes(Bytes::from_static(
+                b"config snapshots need a management_token",
+            )))
+            .unwrap();
+        return Ok(response);
+    }
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/-/config/snapshot") => config_snapshot(state),
+        (&Method::POST, "/-/config/restore") if state.read_only => read_only_response(),
+        (&Method::POST, "/-/config/restore") => {
+            match read_body_limited(req.body_mut(), MAX_SNAPSHOT_BODY_BYTES).await {
+                Ok(body) => restore_snapshot(state, &body).await,
+                Err(status) => Response::builder()
+                    .status(status)
+                    .body(ResBody::Empty)
+                    .unwrap(),
+            }
+        }
+        (_, "/-/config/snapshot") => method_not_allowed("GET"),
+        (_, "/-/config/restore") => method_not_allowed("POST"),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(ResBody::Empty)
+            .unwrap(),
+    };
+    Ok(response)
+}
+
+fn config_snapshot(state: &AppState) -> Response<ResBody> {
+    let snapshot = state.config.load().snapshot();
+    let filename = format!("dfsnode-config-{}.json", snapshot.version.unwrap_or(0));
+    let mut response = json_response(
+        StatusCode::OK,
+        serde_json::to_value(&snapshot).unwrap_or_default(),
+    );
+    if let Ok(value) = format!("attachment; filename=\"{}\"", filename).parse() {
+        response.headers_mut().insert("Content-Disposition", value);
+    }
+    response
+}
+
+async fn restore_snapshot(state: &AppState, body: &[u8]) -> Response<ResBody> {
+    // serde_yml also reads JSON, so a snapshot can be edited as YAML first
+    let snapshot: Config = match serde_yml::from_slice(body) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                serde_json::json!({ "error": format!("invalid snapshot: {}", e) }),
+            );
+        }
+    };
+    // A partial config would be merged with whatever this node has now
+    if snapshot.paths.is_none() || snapshot.torrents.is_none() {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            serde_json::json!({ "error": "a snapshot needs both paths and torrents" }),
+        );
+    }
+    let previous = state.config.load().get_version();
+    match restore_config(state, snapshot).await {
+        Ok(()) => {
+            let config = state.config.load();
+            json_response(
+                StatusCode::OK,
+                serde_json::json!({
+                    "previous_version": previous,
+                    "version": config.get_version(),
+                    "torrents": config.torrents().len(),
+                    "issues": config.issues,
+                }),
+            )
+        }
+        Err(e) => {
+            warn!("Config restore failed: {:#}", e);
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                serde_json::json!({ "version": previous, "error": format!("{:#}", e) }),
+            )
+        }
+    }
+}
+
+fn config_info(state: &AppState) -> Response<ResBody> {
+    let config = state.config.load();
+    let source = match &state.config_path {
+        Some(config_path) => config_path.clone(),
+        None => state.central_url.clone().unwrap_or_default(),
+    };
+    json_response(
+        StatusCode::OK,
+        serde_json::json!({
+            "version": config.get_version(),
+            "torrents_version": config.torrent_table.version,
+            "source": source,
+            "paths": config.paths.path_trie.iter().count(),
+            "torrents": config.torrents().len(),
+            "issues": config.issues,
+        }),
+    )
+}
+
+async fn refresh_config(state: &AppState) -> Response<ResBody> {
+    let previous = state.config.load().get_version();
+    match reload_config(state).await {
+        Ok(()) => {
+            let version = state.config.load().get_version();
+            info!("Config refreshed on request ({} -> {})", previous, version);
+            json_response(
+                StatusCode::OK,
+                serde_json::json!({ "previous_version": previous, "version": version }),
+            )
+        }
+        Err(e) => {
+            warn!("Requested config refresh failed: {:#}", e);
+            json_response(
+                StatusCode::BAD_GATEWAY,
+                serde_json::json!({ "version": previous, "error": format!("{:#}", e) }),
+            )
+        }
+    }
+}
+
+fn verify_torrent(state: &AppState, info_hash: &str) -> Response<ResBody> {
+    if state.read_only {
+        return read_only_response();
+    }
+    let job_id = Id20::from_str(info_hash)
+        .ok()
+        .and_then(|info_hash| spawn_torrent_verify(state, info_hash));
+    match job_id {
+        Some(job_id) => json_response(
+            StatusCode::ACCEPTED,
+            serde_json::json!({ "job_id": job_id }),
+        ),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(ResBody::Empty)
+            .unwrap(),
+    }
+}
+
+// Each `path` drops its entry and everything below it, no `path` clears the cache
+fn invalidate_cache(state: &AppState, query: Option<&str>) -> Response<ResBody> {
+    let parsed = serde_querystring::DuplicateQS::parse(query.unwrap_or("").as_bytes());
+    let paths: Vec<String> = parsed
+        .values(b"path")
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .map(|value| String::from_utf8_lossy(&value).into_owned())
+        .collect();
+    if paths.is_empty() {
+        state.fs_cache.invalidate_all();
+        return json_response(StatusCode::OK, serde_json::json!({ "invalidated": "all" }));
+    }
+    let mut resolved = Vec::with_capacity(paths.len());
+    let config = state.config.load();
+    for path in &paths {
+        match resolve_data_path(config.data_root(path, &state.data_dir), path) {
+            Ok(file_path) => resolved.push(file_path),
+            Err(_) => {
+                return json_response(
+                    StatusCode::BAD_REQUEST,
+                    serde_json::json!({ "error": format!("invalid path {}", path) }),
+                );
+            }
+        }
+    }
+    for file_path in &resolved {
+        state.fs_cache.invalidate_tree(file_path);
+    }
+    json_response(StatusCode::OK, serde_json::json!({ "invalidated": paths }))
+}
+
+fn method_not_allowed(allow: &'static str) -> Response<ResBody> {
+    Response::builder()
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .header("Allow", allow)
+        .body(ResBody::Empty)
+        .unwrap()
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<ResBody> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .body(ResBody::Bytes(Bytes::from(body.to_string())))
+        .unwrap()
+}
@@ -1,27 +1,98 @@
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use anyhow::Context;
 use arc_swap::ArcSwap;
-use dav_server::{DavHandler, fakels::FakeLs, localfs::LocalFs};
+use dav_server::{DavHandler, DavMethodSet, fakels::FakeLs, localfs::LocalFs};
 use hyper_staticfile::Static;
 use tokio::time::Duration as TokioDuration;
 
+use crate::btstats::TorrentStatsStore;
 use crate::cache::FileSystemCache;
+use crate::cas::CasStore;
+use crate::challenge::Challenge;
+use crate::checksums::ChecksumDb;
 use crate::config::OptimizedConfig;
+use crate::dns::{DnsOptions, DnsResolver};
+use crate::etag::EtagCache;
+use crate::gossip::GossipTable;
+use crate::hotcache::HotFileCache;
+use crate::jobs::JobManager;
+use crate::journal::ChangeJournal;
+use crate::limits::KeyedLimiter;
+use crate::mirror::RequestMirror;
+use crate::mmap::MappedFiles;
+use crate::quota::DiskQuota;
+use crate::throttle::ByteBudget;
+use crate::tombstone::TombstoneStore;
+
+// Node-internal state (job history etc.) lives in this directory under data_dir
+pub const STATE_DIR: &str = ".dfsnode";
+
+// Outbound HTTP settings for central and origin fetches
+#[derive(Debug, Clone, Default)]
+pub struct HttpClientOptions {
+    // http://, https:// or socks5:// proxy for all outbound requests
+    pub proxy: Option<String>,
+    // Comma separated hosts/CIDRs that bypass the proxy
+    pub no_proxy: Option<String>,
+    pub dns: DnsOptions,
+    // Source address / interface for outbound connections on multi-homed hosts
+    pub local_address: Option<IpAddr>,
+    pub interface: Option<String>,
+}
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<ArcSwap<OptimizedConfig>>,
     pub data_dir: PathBuf,
+    pub state_dir: PathBuf,
     pub central_url: Option<String>,
+    // --config file, None when the config comes from the central server
+    pub config_path: Option<String>,
     pub auth_header: Option<String>,
     pub server_id: Option<String>,
     pub static_service: Static,
+    // Static services of path `root`s other than data_dir, created on first use
+    pub root_services: Arc<Mutex<HashMap<PathBuf, Static>>>,
     pub http_client: reqwest::Client,
     pub fs_cache: Arc<FileSystemCache>,
     pub bt_api: librqbit::Api,
     pub dav_server: DavHandler,
+    pub jobs: Arc<JobManager>,
+    pub mirror: Arc<RequestMirror>,
+    pub challenge: Arc<Challenge>,
+    // Per path prefix transfer limits (max_concurrent)
+    pub path_limiter: Arc<KeyedLimiter>,
+    // Transfers per client IP (max_transfers_per_ip), node-wide under GLOBAL_LIMIT_KEY
+    // and running compressions under COMPRESSION_LIMIT_KEY
+    pub transfer_limiter: Arc<KeyedLimiter>,
+    // Transfers per issued link (max_per_signature), keyed by the `$` signature
+    pub signature_limiter: Arc<KeyedLimiter>,
+    // Global disk read budget for file responses, shared fairly by active transfers
+    pub disk_budget: Option<Arc<ByteBudget>>,
+    // --read-only: mutating admin endpoints are refused regardless of config
+    pub read_only: bool,
+    // Persistent file hashes, shared by manifests and anything else needing a checksum
+    pub checksums: Arc<ChecksumDb>,
+    pub mapped_files: Arc<MappedFiles>,
+    // --hot-cache-size: contents of frequently requested files kept in memory
+    pub hot_files: Option<Arc<HotFileCache>>,
+    pub cas: Arc<CasStore>,
+    // --watch: data directory changes, for incremental manifest and replication clients
+    pub journal: Option<Arc<ChangeJournal>>,
+    // Lifetime per-torrent upload/download totals
+    pub bt_stats: Arc<TorrentStatsStore>,
+    // Access times for max_disk_bytes eviction
+    pub quota: Arc<DiskQuota>,
+    // Strong ETags of served files
+    pub etags: Arc<EtagCache>,
+    // Removed torrents and paths kept for tombstone_secs
+    pub tombstones: Arc<TombstoneStore>,
+    // Torrents other cluster nodes have announced as finished
+    pub gossip: Arc<GossipTable>,
 }
 
 impl AppState {
@@ -31,40 +102,126 @@ impl AppState {
         auth_header: Option<String>,
         server_id: Option<String>,
         bt_session: Arc<librqbit::Session>,
-    ) -> Self {
+        http_options: &HttpClientOptions,
+        read_only: bool,
+    ) -> anyhow::Result<Self> {
         let static_service = Static::new(&data_dir);
 
-        // Configure HTTP client with optimized settings for better performance
-        let http_client = reqwest::Client::builder()
-            .pool_max_idle_per_host(50) // 增加连接池
-            .pool_idle_timeout(TokioDuration::from_secs(300)) // 延长空闲时间
-            .timeout(TokioDuration::from_secs(15)) // 减少超时时间
-            .tcp_keepalive(TokioDuration::from_secs(600)) // 更长的 keepalive
-            .tcp_nodelay(true) // Disable Nagle's algorithm for better latency
-            .build()
-            .expect("Failed to create HTTP client");
-
-        let dav_server = DavHandler::builder()
+        let state_dir = data_dir.join(STATE_DIR);
+        std::fs::create_dir_all(&state_dir).expect("Failed to create state directory");
+        let jobs = Arc::new(JobManager::load(state_dir.join("jobs.json")));
+        let challenge = Arc::new(Challenge::load(&state_dir.join("challenge.key")));
+        let checksums = Arc::new(ChecksumDb::open(&state_dir.join("checksums.redb")));
+        let cas = Arc::new(CasStore::new(&state_dir));
+        let bt_stats = Arc::new(TorrentStatsStore::load(state_dir.join("bt_stats.json")));
+        let tombstones = Arc::new(TombstoneStore::load(state_dir.join("tombstones.json")));
+
+        let http_client = build_http_client(http_options)?;
+
+        let mut dav_builder = DavHandler::builder()
             .filesystem(LocalFs::new(&data_dir, true, false, false))
-            .locksystem(FakeLs::new())
-            .strip_prefix("/-/dav")
-            .build_handler();
+            .locksystem(FakeLs::new());
+        if read_only {
+            dav_builder = dav_builder.methods(DavMethodSet::WEBDAV_RO);
+        }
+        let dav_server = dav_builder.build_handler();
 
-        Self {
+        Ok(Self {
             config: Arc::new(ArcSwap::from_pointee(OptimizedConfig::default())),
             data_dir,
+            state_dir,
             central_url,
+            config_path: None,
             auth_header,
             server_id,
             static_service,
+            root_services: Arc::new(Mutex::new(HashMap::new())),
             http_client,
             fs_cache: Arc::new(FileSystemCache::new()),
             bt_api: librqbit::Api::new(bt_session, None),
             dav_server,
+            jobs,
+            mirror: Arc::new(RequestMirror::new()),
+            challenge,
+            path_limiter: Arc::new(KeyedLimiter::new()),
+            transfer_limiter: Arc::new(KeyedLimiter::new()),
+            signature_limiter: Arc::new(KeyedLimiter::new()),
+            disk_budget: None,
+            read_only,
+            checksums,
+            mapped_files: Arc::new(MappedFiles::new()),
+            hot_files: None,
+            cas,
+            journal: None,
+            bt_stats,
+            quota: Arc::new(DiskQuota::new()),
+            etags: Arc::new(EtagCache::new()),
+            tombstones,
+            gossip: Arc::new(GossipTable::new()),
+        })
+    }
+
+    /// Where the file for request path `path` is kept, below its path's root
+    pub fn data_path(&self, path: &str) -> PathBuf {
+        let config = self.config.load();
+        config
+            .data_root(path, &self.data_dir)
+            .join(path.trim_start_matches('/'))
+    }
+
+    /// Static file service serving request paths from `root`
+    pub fn static_service_for(&self, root: &Path) -> Static {
+        if root == self.data_dir {
+            return self.static_service.clone();
         }
+        self.root_services
+            .lock()
+            .unwrap()
+            .entry(root.to_path_buf())
+            .or_insert_with(|| Static::new(root))
+            .clone()
     }
 }
 
+fn build_http_client(options: &HttpClientOptions) -> anyhow::Result<reqwest::Client> {
+    // Configure HTTP client with optimized settings for better performance
+    let mut builder = reqwest::Client::builder()
+        .pool_max_idle_per_host(50) // 增加连接池
+        .pool_idle_timeout(TokioDuration::from_secs(300)) // 延长空闲时间
+        .timeout(TokioDuration::from_secs(15)) // 减少超时时间
+        .tcp_keepalive(TokioDuration::from_secs(600)) // 更长的 keepalive
+        .tcp_nodelay(true); // Disable Nagle's algorithm for better latency
+
+    if let Some(proxy_url) = &options.proxy {
+        let proxy = reqwest::Proxy::all(proxy_url).context("Invalid proxy URL")?;
+        let no_proxy = options
+            .no_proxy
+            .as_deref()
+            .and_then(reqwest::NoProxy::from_string);
+        builder = builder.proxy(proxy.no_proxy(no_proxy));
+    }
+
+    if let Some(local_address) = options.local_address {
+        builder = builder.local_address(local_address);
+    }
+    if let Some(interface) = &options.interface {
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        {
+            builder = builder.interface(interface);
+        }
+        #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+        anyhow::bail!(
+            "Binding to interface {} is not supported on this platform",
+            interface
+        );
+    }
+    if let Some(resolver) = DnsResolver::from_options(&options.dns)? {
+        builder = builder.dns_resolver(Arc::new(resolver));
+    }
+
+    builder.build().context("Failed to create HTTP client")
+}
+
 pub fn parse_central_url(
     central: &str,
 ) -> anyhow::Result<(Option<String>, Option<String>, Option<String>)> {
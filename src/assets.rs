@@ -0,0 +1,31 @@
+// Static assets for the autoindex UI, compiled into the binary and served at /-/assets/
+const ASSETS: &[(&str, &str, &[u8])] = &[
+    (
+        "autoindex.css",
+        "text/css; charset=utf-8",
+        include_bytes!("../assets/autoindex.css"),
+    ),
+    (
+        "favicon.svg",
+        "image/svg+xml",
+        include_bytes!("../assets/favicon.svg"),
+    ),
+    (
+        "folder.svg",
+        "image/svg+xml",
+        include_bytes!("../assets/folder.svg"),
+    ),
+    (
+        "file.svg",
+        "image/svg+xml",
+        include_bytes!("../assets/file.svg"),
+    ),
+];
+
+/// Content type and body of an embedded asset
+pub fn find_asset(name: &str) -> Option<(&'static str, &'static [u8])> {
+    ASSETS
+        .iter()
+        .find(|(asset_name, _, _)| *asset_name == name)
+        .map(|(_, content_type, content)| (*content_type, *content))
+}
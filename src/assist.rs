@@ -0,0 +1,159 @@
+// Peer-assisted ranges: Range requests for pieces a torrent hasn't downloaded
+// yet are answered by fetching exactly those pieces from the swarm, within a
+// latency budget. With `thin` nothing else is downloaded, so the node only
+// keeps what clients asked for.
+use std::io::SeekFrom;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::Response;
+use hyper::body::Bytes;
+use hyper::http::{HeaderMap, StatusCode};
+use librqbit::api::TorrentIdOrHash;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::app::AppState;
+use crate::logging::log_limited;
+use crate::metrics::{PEER_ASSIST_BYTES_TOTAL, PEER_ASSIST_TOTAL};
+use crate::range::parse_byte_ranges;
+use crate::response::ResBody;
+use crate::torrents::TorrentLayout;
+use crate::urlpath::decode_path;
+
+const DEFAULT_MAX_RANGE_BYTES: u64 = 4 * 1024 * 1024;
+const DEFAULT_TIMEOUT_MS: u64 = 3000;
+// Clients retry a range that didn't arrive in time after this long
+const RETRY_AFTER_SECS: u64 = 2;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PeerAssistConfig {
+    // Larger ranges are served from disk as usual, default 4MiB
+    pub max_range_bytes: Option<u64>,
+    // Time allowed to fetch the pieces before answering 503, default 3000
+    pub timeout_ms: Option<u64>,
+    // Download nothing but the pieces requested over HTTP
+    #[serde(default)]
+    pub thin: bool,
+}
+
+/// A torrent with peer_assist and where its files sit in the pieces
+#[derive(Debug)]
+pub struct AssistedTorrent {
+    layout: Arc<TorrentLayout>,
+    max_range_bytes: u64,
+    timeout: Duration,
+}
+
+/// A range of an assisted torrent's file that isn't fully downloaded
+#[derive(Debug)]
+pub struct AssistedRange {
+    torrent: Arc<AssistedTorrent>,
+    file_index: usize,
+    file_length: u64,
+    start: u64,
+    end: u64,
+}
+
+impl AssistedTorrent {
+    pub fn new(layout: Arc<TorrentLayout>, config: &PeerAssistConfig) -> Self {
+        Self {
+            layout,
+            max_range_bytes: config.max_range_bytes.unwrap_or(DEFAULT_MAX_RANGE_BYTES),
+            timeout: Duration::from_millis(config.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS)),
+        }
+    }
+
+    /// The single small range requested from `relative`, the request path
+    /// below the torrent's URL prefix, if some of its pieces are still missing
+    /// here. None means the request is served from disk.
+    pub fn missing_range(
+        self: &Arc<Self>,
+        state: &AppState,
+        relative: &str,
+        headers: &HeaderMap,
+    ) -> Option<AssistedRange> {
+        let file = self.layout.file(&decode_path(relative)?)?;
+        let ranges = headers
+            .get("range")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_byte_ranges)?;
+        let [range] = ranges.as_slice() else {
+            return None;
+        };
+        let (start, end) = range.resolve(file.length)?;
+        if end - start + 1 > self.max_range_bytes {
+            return None;
+        }
+        let downloaded_until = self
+            .layout
+            .downloaded_until(&state.bt_api, &file, start, end)?;
+        (downloaded_until <= end).then(|| AssistedRange {
+            torrent: self.clone(),
+            file_index: file.index,
+            file_length: file.length,
+            start,
+            end,
+        })
+    }
+}
+
+impl AssistedRange {
+    /// Fetch the range through a librqbit file stream, which asks peers for
+    /// its pieces first, and answer 206 or 503 once the time budget is spent
+    pub async fn response(&self, state: &AppState, content_type: &str) -> Response<ResBody> {
+        let len = (self.end - self.start + 1) as usize;
+        let read = async {
+            let mut stream = state.bt_api.api_stream(
+                TorrentIdOrHash::Hash(self.torrent.layout.info_hash),
+                self.file_index,
+            )?;
+            stream.seek(SeekFrom::Start(self.start)).await?;
+            let mut content = vec![0u8; len];
+            stream.read_exact(&mut content).await?;
+            anyhow::Ok(content)
+        };
+        match tokio::time::timeout(self.torrent.timeout, read).await {
+            Ok(Ok(content)) => {
+                PEER_ASSIST_TOTAL.with_label_values(&["served"]).inc();
+                PEER_ASSIST_BYTES_TOTAL.inc_by(len as u64);
+                Response::builder()
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header("Content-Type", content_type)
+                    .header("Content-Length", len)
+                    .header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", self.start, self.end, self.file_length),
+                    )
+                    .header("Accept-Ranges", "bytes")
+                    .body(ResBody::Bytes(Bytes::from(content)))
+                    .unwrap()
+            }
+            Ok(Err(e)) => {
+                PEER_ASSIST_TOTAL.with_label_values(&["error"]).inc();
+                log_limited!(
+                    warn,
+                    "peer_assist",
+                    "Failed to fetch range of torrent {} file {} from peers: {:#}",
+                    self.torrent.layout.info_hash.as_string(),
+                    self.file_index,
+                    e
+                );
+                retry_later()
+            }
+            Err(_) => {
+                PEER_ASSIST_TOTAL.with_label_values(&["timeout"]).inc();
+                retry_later()
+            }
+        }
+    }
+}
+
+// The pieces are on their way, by the retry they will likely be on disk
+fn retry_later() -> Response<ResBody> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header("Retry-After", RETRY_AFTER_SECS.to_string())
+        .body(ResBody::Empty)
+        .unwrap()
+}
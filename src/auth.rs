@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::IpAddr;
+
+use base64::Engine;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use hyper::Response;
+use hyper::http::{HeaderMap, HeaderValue, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::PathConfig;
+use crate::net::IpRule;
+use crate::response::ResBody;
+use crate::signature::{
+    constant_time_eq, is_signature_exempt, signature_credential, signature_param,
+    verify_signature_with_params,
+};
+use crate::urlpath::{decode_path, has_dot_segments, is_within};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_JWT_PARAM: &str = "token";
+const DEFAULT_BASIC_REALM: &str = "dfsnode";
+
+/// One entry of a path's `auth` list
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthConfig {
+    // `$` URL signature with the path's `signature` key, honouring
+    // signature_exempt and signature_params
+    Hmac,
+    // HS256 JWT in `Authorization: Bearer` or the `param` query parameter
+    Jwt {
+        secret: String,
+        // Query parameter carrying the token, default `token`
+        param: Option<String>,
+        // Required `aud` claim
+        audience: Option<String>,
+    },
+    // HTTP basic auth, passwords in plain text or as `sha256:{hex}`
+    Basic {
+        users: HashMap<String, String>,
+        realm: Option<String>,
+    },
+    // Client addresses or CIDRs; deny wins, an empty allow list allows the rest
+    Ip {
+        #[serde(default)]
+        allow: Vec<String>,
+        #[serde(default)]
+        deny: Vec<String>,
+    },
+    // Passes when any of `of` passes
+    Any {
+        of: Vec<AuthConfig>,
+    },
+}
+
+/// What an authorizer sees of a request
+pub struct AuthRequest<'a> {
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub headers: &'a HeaderMap,
+    pub client_ip: Option<IpAddr>,
+}
+
+/// A passed check
+#[derive(Debug, Default)]
+pub struct Grant {
+    // Byte ranges a signature restricts the request to, empty for the whole file
+    pub signed_ranges: Vec<(u64, u64)>,
+    // The issued link, token or user the request came with, for max_per_signature
+    pub credential: Option<String>,
+}
+
+impl Grant {
+    /// Fill in what this grant lacks from `other`, what it has stays
+    pub fn merge(&mut self, other: Grant) {
+        if self.signed_ranges.is_empty() {
+            self.signed_ranges = other.signed_ranges;
+        }
+        if self.credential.is_none() {
+            self.credential = other.credential;
+        }
+    }
+}
+
+/// A failed check, answered with `status` and an empty body
+#[derive(Debug)]
+pub struct Denial {
+    pub status: StatusCode,
+    // WWW-Authenticate challenge for credentials the client may retry with
+    challenge: Option<HeaderValue>,
+}
+
+impl Denial {
+    fn with_status(status: StatusCode) -> Self {
+        Self {
+            status,
+            challenge: None,
+        }
+    }
+
+    pub fn into_response(self) -> Response<ResBody> {
+        let mut response = Response::builder().status(self.status);
+        if let Some(challenge) = self.challenge {
+            response = response.header("WWW-Authenticate", challenge);
+        }
+        response.body(ResBody::Empty).unwrap()
+    }
+}
+
+pub trait Authorizer: Debug + Send + Sync {
+    fn authorize(&self, req: &AuthRequest) -> Result<Grant, Denial>;
+}
+
+/// The authorizers of a path prefix, all of which must pass. Built once per
+/// config from `auth`, or from `signature` alone when `auth` isn't set.
+#[derive(Debug, Default)]
+pub struct AuthChain {
+    authorizers: Vec<Box<dyn Authorizer>>,
+    // Config error; such a chain refuses every request rather than guess
+    error: Option<String>,
+}
+
+impl AuthChain {
+    pub fn build(prefix: &str, path_config: &PathConfig) -> Self {
+        let entries = match &path_config.auth {
+            Some(entries) => entries.clone(),
+            None if path_config.signature.is_some() => vec![AuthConfig::Hmac],
+            None => Vec::new(),
+        };
+        let built: Result<Vec<_>, String> = entries
+            .iter()
+            .map(|entry| build_authorizer(prefix, path_config, entry))
+            .collect();
+        match built {
+            Ok(authorizers) => Self {
+                authorizers,
+                error: None,
+            },
+            Err(e) => Self {
+                authorizers: Vec::new(),
+                error: Some(e),
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.authorizers.is_empty() && self.error.is_none()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+}
+
+impl Authorizer for AuthChain {
+    fn authorize(&self, req: &AuthRequest) -> Result<Grant, Denial> {
+        if self.error.is_some() {
+            return Err(Denial::with_status(StatusCode::FORBIDDEN));
+        }
+        let mut grant = Grant::default();
+        for authorizer in &self.authorizers {
+            grant.merge(authorizer.authorize(req)?);
+        }
+        Ok(grant)
+    }
+}
+
+/// Whether a URL signed with the path's `signature` alone gets through `auth`
+pub fn signature_suffices(path_config: &PathConfig) -> bool {
+    fn suffices(entry: &AuthConfig) -> bool {
+        match entry {
+            AuthConfig::Hmac => true,
+            AuthConfig::Any { of } => of.iter().any(suffices),
+            _ => false,
+        }
+    }
+    path_config
+        .auth
+        .as_ref()
+        .is_none_or(|entries| entries.iter().all(suffices))
+}
+
+/// Whether the URL signature is the only check of the path, so a response
+/// can be shared by everyone holding the same URL
+pub fn signature_only(path_config: &PathConfig) -> bool {
+    path_config.auth.as_ref().is_none_or(|entries| {
+        entries
+            .iter()
+            .all(|entry| matches!(entry, AuthConfig::Hmac))
+    })
+}
+
+/// Hide the secrets of a serialized path config: its signing keys and the
+/// secrets and passwords of its `auth` entries
+pub fn redact_secrets(path_config: &mut serde_json::Value) {
+    fn redact(entry: &mut serde_json::Value) {
+        if let Some(secret) = entry.get_mut("secret") {
+            *secret = "<redacted>".into();
+        }
+        if let Some(users) = entry.get_mut("users").and_then(|u| u.as_object_mut()) {
+            users
+                .values_mut()
+                .for_each(|password| *password = "<redacted>".into());
+        }
+        if let Some(of) = entry.get_mut("of").and_then(|of| of.as_array_mut()) {
+            of.iter_mut().for_each(redact);
+        }
+    }
+    // One key, or every key of a rotation
+    match path_config.get_mut("signature") {
+        Some(serde_json::Value::Array(tokens)) => tokens
+            .iter_mut()
+            .for_each(|token| *token = "<redacted>".into()),
+        Some(token) if !token.is_null() => *token = "<redacted>".into(),
+        _ => {}
+    }
+    if let Some(entries) = path_config.get_mut("auth").and_then(|a| a.as_array_mut()) {
+        entries.iter_mut().for_each(redact);
+    }
+}
+
+fn build_authorizer(
+    prefix: &str,
+    path_config: &PathConfig,
+    entry: &AuthConfig,
+) -> Result<Box<dyn Authorizer>, String> {
+    let authorizer: Box<dyn Authorizer> = match entry {
+        AuthConfig::Hmac => {
+            let tokens = path_config.signature_tokens().to_vec();
+            if tokens.is_empty() {
+                return Err(format!("{}: hmac auth needs `signature`", prefix));
+            }
+            Box::new(HmacAuthorizer {
+                prefix: prefix.to_string(),
+                tokens,
+                signed_params: path_config.signature_params.clone().unwrap_or_default(),
+                exempt: path_config.signature_exempt.clone().unwrap_or_default(),
+            })
+        }
+        AuthConfig::Jwt {
+            secret,
+            param,
+            audience,
+        } => Box::new(JwtAuthorizer {
+            secret: secret.clone(),
+            param: param
+                .clone()
+                .unwrap_or_else(|| DEFAULT_JWT_PARAM.to_string()),
+            audience: audience.clone(),
+        }),
+        AuthConfig::Basic { users, realm } => {
+            let realm = realm.as_deref().unwrap_or(DEFAULT_BASIC_REALM);
+            let challenge = HeaderValue::from_str(&format!("Basic realm=\"{}\"", realm))
+                .map_err(|_| format!("{}: invalid basic auth realm", prefix))?;
+            Box::new(BasicAuthorizer {
+                users: users.clone(),
+                challenge,
+            })
+        }
+        AuthConfig::Ip { allow, deny } => {
+            let parse = |rules: &[String]| {
+                rules
+                    .iter()
+                    .map(|rule| {
+                        IpRule::parse(rule)
+                            .ok_or_else(|| format!("{}: invalid IP rule {}", prefix, rule))
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+            };
+            Box::new(IpAuthorizer {
+                allow: parse(allow)?,
+                deny: parse(deny)?,
+            })
+        }
+        AuthConfig::Any { of } => {
+            if of.is_empty() {
+                return Err(format!("{}: `any` auth needs at least one entry", prefix));
+            }
+            let of = of
+                .iter()
+                .map(|entry| build_authorizer(prefix, path_config, entry))
+                .collect::<Result<Vec<_>, String>>()?;
+            Box::new(AnyAuthorizer { of })
+        }
+    };
+    Ok(authorizer)
+}
+
+#[derive(Debug)]
+struct HmacAuthorizer {
+    prefix: String,
+    // Accepted signing keys, the current one and any being rotated out
+    tokens: Vec<String>,
+    signed_params: Vec<String>,
+    exempt: Vec<String>,
+}
+
+impl Authorizer for HmacAuthorizer {
+    fn authorize(&self, req: &AuthRequest) -> Result<Grant, Denial> {
+        let relative = req.path.get(self.prefix.len()..).unwrap_or("");
+        if is_signature_exempt(&self.exempt, relative) {
+            return Ok(Grant::default());
+        }
+        let range_header = req.headers.get("range").and_then(|h| h.to_str().ok());
+        let signed_ranges = verify_signature_with_params(
+            req.path,
+            req.query,
+            &self.tokens,
+            range_header,
+            &self.signed_params,
+            req.client_ip,
+        )
+        .map_err(Denial::with_status)?;
+        // Spellings of one signature (hex case, base64 padding bits) share a count
+        Ok(Grant {
+            signed_ranges,
+            credential: signature_credential(req.query).or_else(|| signature_param(req.query)),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct JwtAuthorizer {
+    secret: String,
+    param: String,
+    audience: Option<String>,
+}
+
+impl JwtAuthorizer {
+    fn token(&self, req: &AuthRequest) -> Option<String> {
+        let bearer = req
+            .headers
+            .get("authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        bearer.or_else(|| {
+            let parsed = serde_querystring::DuplicateQS::parse(req.query.unwrap_or("").as_bytes());
+            parsed
+                .values(self.param.as_bytes())
+                .and_then(|v| v.first().cloned().unwrap_or(None))
+                .map(|value| String::from_utf8_lossy(&value).into_owned())
+        })
+    }
+
+    // Claims of a token signed with our secret, None if anything doesn't check out
+    fn verify(&self, token: &str) -> Option<serde_json::Value> {
+        let (signed, signature) = token.rsplit_once('.')?;
+        let (header, payload) = signed.split_once('.')?;
+        let header: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+        // Only HS256, never whatever algorithm the token asks for
+        if header["alg"] != "HS256" {
+            return None;
+        }
+        let mut mac = HmacSha256::new_from_slice(self.secret.as_bytes()).unwrap();
+        mac.update(signed.as_bytes());
+        mac.verify_slice(&URL_SAFE_NO_PAD.decode(signature).ok()?)
+            .ok()?;
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+}
+
+impl Authorizer for JwtAuthorizer {
+    fn authorize(&self, req: &AuthRequest) -> Result<Grant, Denial> {
+        let unauthorized = || Denial {
+            status: StatusCode::UNAUTHORIZED,
+            challenge: Some(HeaderValue::from_static("Bearer")),
+        };
+        let token = self.token(req).ok_or_else(unauthorized)?;
+        let claims = self.verify(&token).ok_or_else(unauthorized)?;
+
+        let now = chrono::Utc::now().timestamp();
+        // Tokens without an expiry would be valid forever
+        let expired = claims["exp"].as_i64().is_none_or(|exp| exp <= now);
+        let not_yet = claims["nbf"].as_i64().is_some_and(|nbf| nbf > now);
+        if expired || not_yet {
+            return Err(unauthorized());
+        }
+        if let Some(audience) = &self.audience {
+            let matches = match &claims["aud"] {
+                serde_json::Value::String(aud) => aud == audience,
+                serde_json::Value::Array(auds) => auds.iter().any(|aud| aud == audience),
+                _ => false,
+            };
+            if !matches {
+                return Err(Denial::with_status(StatusCode::FORBIDDEN));
+            }
+        }
+        // A `path` claim scopes the token to that prefix, compared with the
+        // path the file system will see and never one that climbs out of it
+        let outside = claims["path"].as_str().is_some_and(|scope| {
+            has_dot_segments(req.path)
+                || decode_path(req.path).is_none_or(|path| !is_within(&path, scope))
+        });
+        if outside {
+            return Err(Denial::with_status(StatusCode::FORBIDDEN));
+        }
+        Ok(Grant {
+            signed_ranges: Vec::new(),
+            credential: Some(token),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct BasicAuthorizer {
+    users: HashMap<String, String>,
+    challenge: HeaderValue,
+}
+
+impl Authorizer for BasicAuthorizer {
+    fn authorize(&self, req: &AuthRequest) -> Result<Grant, Denial> {
+        let credentials = req
+            .headers
+            .get("authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Basic "))
+            .and_then(|encoded| STANDARD.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        let user = credentials.as_deref().and_then(|credentials| {
+            let (user, password) = credentials.split_once(':')?;
+            let expected = self.users.get(user)?;
+            let valid = match expected.strip_prefix("sha256:") {
+                Some(hash) => constant_time_eq(
+                    hex::encode(Sha256::digest(password)).as_bytes(),
+                    hash.to_ascii_lowercase().as_bytes(),
+                ),
+                None => constant_time_eq(expected.as_bytes(), password.as_bytes()),
+            };
+            valid.then(|| user.to_string())
+        });
+        match user {
+            Some(user) => Ok(Grant {
+                signed_ranges: Vec::new(),
+                credential: Some(user),
+            }),
+            None => Err(Denial {
+                status: StatusCode::UNAUTHORIZED,
+                challenge: Some(self.challenge.clone()),
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct IpAuthorizer {
+    allow: Vec<IpRule>,
+    deny: Vec<IpRule>,
+}
+
+impl Authorizer for IpAuthorizer {
+    fn authorize(&self, req: &AuthRequest) -> Result<Grant, Denial> {
+        let allowed = req.client_ip.is_some_and(|ip| {
+            !self.deny.iter().any(|rule| rule.contains(ip))
+                && (self.allow.is_empty() || self.allow.iter().any(|rule| rule.contains(ip)))
+        });
+        if !allowed {
+            return Err(Denial::with_status(StatusCode::FORBIDDEN));
+        }
+        Ok(Grant::default())
+    }
+}
+
+#[derive(Debug)]
+struct AnyAuthorizer {
+    of: Vec<Box<dyn Authorizer>>,
+}
+
+impl Authorizer for AnyAuthorizer {
+    // The first entry's rejection is returned when none passes
+    fn authorize(&self, req: &AuthRequest) -> Result<Grant, Denial> {
+        let mut first_denial = None;
+        for authorizer in &self.of {
+            match authorizer.authorize(req) {
+                Ok(grant) => return Ok(grant),
+                Err(denial) => {
+                    first_denial.get_or_insert(denial);
+                }
+            }
+        }
+        Err(first_denial.expect("`any` has at least one entry"))
+    }
+}
@@ -1,13 +1,22 @@
-use std::collections::BTreeMap;
+use std::io::Error as IoError;
 use std::path::Path;
 use std::time::SystemTime;
 
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use futures_util::stream;
+use hyper::body::Bytes;
 use hyper::http::StatusCode;
 use serde::{Deserialize, Serialize};
 use tokio::fs;
+use tokio::sync::mpsc;
 use tracing::{error, warn};
 
-use crate::signature::{create_signature, get_expire_time};
+use crate::response::ResBody;
+use crate::signature::{create_signature, get_expire_time, quantized_expire_time};
+
+const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DirectoryEntry {
@@ -15,65 +24,301 @@ pub struct DirectoryEntry {
     pub path: String,
     pub is_directory: bool,
     pub size: Option<u64>,
+    // Display string in the configured timezone and format
     pub modified: Option<String>,
+    // ISO 8601, for machine consumers of the JSON listing
+    pub modified_at: Option<DateTime<Utc>>,
     pub url: String,
 }
 
-/// Generate HTML directory listing
-pub async fn generate_directory_listing(
-    dir_path: &Path,
-    request_path: &str,
-    signature_token: Option<&str>,
-    signature_expire_seconds: u32,
-) -> Result<String, StatusCode> {
-    let mut entries = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingFormat {
+    Html,
+    Json,
+}
 
-    // Add parent directory link if not at root
-    if request_path != "/" {
-        let parent_path = if let Some(stripped) = request_path.strip_suffix('/') {
-            stripped
+impl ListingFormat {
+    /// JSON when requested with `?format=json` or `Accept: application/json`
+    pub fn from_request(query: Option<&str>, accept: Option<&str>) -> Self {
+        let parsed = serde_querystring::DuplicateQS::parse(query.unwrap_or("").as_bytes());
+        let format_param = parsed
+            .values(b"format")
+            .and_then(|v| v.first().cloned().unwrap_or(None));
+        if format_param.as_deref() == Some(b"json".as_slice())
+            || accept.is_some_and(|accept| accept.contains("application/json"))
+        {
+            ListingFormat::Json
         } else {
-            request_path
-        };
+            ListingFormat::Html
+        }
+    }
 
-        let parent_url = if let Some(last_slash) = parent_path.rfind('/') {
-            if last_slash == 0 {
-                "/".to_string()
-            } else {
-                parent_path[..last_slash].to_string()
-            }
-        } else {
-            "/".to_string()
-        };
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ListingFormat::Html => "text/html; charset=utf-8",
+            ListingFormat::Json => "application/json; charset=utf-8",
+        }
+    }
+}
 
-        entries.push(DirectoryEntry {
-            name: "../".to_string(),
-            path: parent_url.clone(),
-            is_directory: true,
-            size: None,
-            modified: None,
-            url: generate_signed_url(&parent_url, signature_token, signature_expire_seconds),
-        });
+// UI strings of the HTML listing
+pub struct Locale {
+    pub lang: &'static str,
+    pub index_of: &'static str,
+    pub name: &'static str,
+    pub size: &'static str,
+    pub modified: &'static str,
+    pub parent: &'static str,
+    pub footer: &'static str,
+}
+
+const LOCALES: &[Locale] = &[
+    Locale {
+        lang: "en",
+        index_of: "Index of",
+        name: "Name",
+        size: "Size",
+        modified: "Modified",
+        parent: "Parent directory",
+        footer: "Powered by Steambird",
+    },
+    Locale {
+        lang: "zh",
+        index_of: "索引：",
+        name: "名称",
+        size: "大小",
+        modified: "修改时间",
+        parent: "上级目录",
+        footer: "由 Steambird 提供",
+    },
+    Locale {
+        lang: "ja",
+        index_of: "インデックス：",
+        name: "名前",
+        size: "サイズ",
+        modified: "更新日時",
+        parent: "親ディレクトリ",
+        footer: "Powered by Steambird",
+    },
+];
+
+impl Locale {
+    fn find(tag: &str) -> Option<&'static Locale> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_ascii_lowercase();
+        LOCALES.iter().find(|locale| locale.lang == primary)
     }
 
-    // Read directory entries
-    let mut dir_entries = match fs::read_dir(dir_path).await {
-        Ok(entries) => entries,
-        Err(e) => {
-            error!("Failed to read directory {}: {}", dir_path.display(), e);
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    /// Use the configured locale if any, otherwise the best match from Accept-Language
+    pub fn select(configured: Option<&str>, accept_language: Option<&str>) -> &'static Locale {
+        if let Some(locale) = configured.and_then(Locale::find) {
+            return locale;
         }
-    };
+        let mut candidates: Vec<(f32, &str)> = accept_language
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|part| {
+                let mut params = part.split(';');
+                let tag = params.next()?.trim();
+                let quality = params
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                Some((quality, tag))
+            })
+            .collect();
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+        candidates
+            .into_iter()
+            .find_map(|(_, tag)| Locale::find(tag))
+            .unwrap_or(&LOCALES[0])
+    }
+}
 
-    let mut files = BTreeMap::new();
-    let mut directories = BTreeMap::new();
+pub struct ListingOptions<'a> {
+    pub signature_token: Option<&'a str>,
+    pub signature_expire_seconds: u32,
+    // Round expiry times up to a multiple of this, so listings generated within
+    // one step link the same signed URLs and can be cached
+    pub expire_step: Option<u32>,
+    // IANA timezone name, UTC by default
+    pub timezone: Option<&'a str>,
+    // strftime format for the modified column
+    pub time_format: Option<&'a str>,
+    pub format: ListingFormat,
+    pub locale: &'static Locale,
+}
 
+impl ListingOptions<'_> {
+    fn expire_time(&self) -> u32 {
+        match self.expire_step {
+            Some(step) => quantized_expire_time(self.signature_expire_seconds, step),
+            None => get_expire_time(self.signature_expire_seconds),
+        }
+    }
+
+    /// How long a listing stays the same as far as its links are concerned:
+    /// until the quantized expiry moves on. None without `expire_step`.
+    pub fn cache_seconds(&self) -> Option<u32> {
+        let step = self.expire_step.filter(|&step| step > 1)?;
+        let now = get_expire_time(0);
+        let expire_time = quantized_expire_time(self.signature_expire_seconds, step);
+        // Expiry moves on once now + signature_expire_seconds passes it
+        Some(
+            expire_time
+                .saturating_sub(now)
+                .saturating_sub(self.signature_expire_seconds),
+        )
+    }
+}
+
+// Renders modified times, falling back to UTC / the default format on bad config
+struct TimeFormatter {
+    timezone: Tz,
+    format: String,
+}
+
+impl TimeFormatter {
+    fn new(timezone: Option<&str>, format: Option<&str>) -> Self {
+        let timezone = match timezone.map(|name| name.parse::<Tz>()) {
+            Some(Ok(tz)) => tz,
+            Some(Err(e)) => {
+                warn!("Invalid autoindex timezone: {}", e);
+                Tz::UTC
+            }
+            None => Tz::UTC,
+        };
+        let format = match format {
+            Some(format) if StrftimeItems::new(format).any(|item| item == Item::Error) => {
+                warn!("Invalid autoindex time format: {}", format);
+                DEFAULT_TIME_FORMAT
+            }
+            Some(format) => format,
+            None => DEFAULT_TIME_FORMAT,
+        }
+        .to_string();
+        Self { timezone, format }
+    }
+
+    fn format(&self, time: DateTime<Utc>) -> String {
+        time.with_timezone(&self.timezone)
+            .format(&self.format)
+            .to_string()
+    }
+}
+
+/// What a HEAD of a listed directory reports, without rendering the listing
+#[derive(Debug, Default)]
+pub struct DirectorySummary {
+    // Visible entries, hidden files are skipped as in the listing
+    pub entries: u64,
+    // Sum of the sizes of the files directly in the directory
+    pub total_size: u64,
+    // Newest of the entries' and the directory's own mtime (which covers removals)
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+pub async fn directory_summary(dir_path: &Path) -> Result<DirectorySummary, StatusCode> {
+    let to_utc = |time: std::io::Result<SystemTime>| -> Option<DateTime<Utc>> {
+        let duration = time.ok()?.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        DateTime::from_timestamp(duration.as_secs() as i64, 0)
+    };
+    let mut summary = DirectorySummary {
+        last_modified: fs::metadata(dir_path)
+            .await
+            .ok()
+            .and_then(|metadata| to_utc(metadata.modified())),
+        ..Default::default()
+    };
+    let mut dir_entries = fs::read_dir(dir_path).await.map_err(|e| {
+        error!("Failed to read directory {}: {}", dir_path.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
     while let Some(entry) = dir_entries.next_entry().await.map_err(|e| {
         error!("Failed to read directory entry: {}", e);
         StatusCode::INTERNAL_SERVER_ERROR
     })? {
-        let file_name = entry.file_name();
-        let name = file_name.to_string_lossy().to_string();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata().await else {
+            continue;
+        };
+        summary.entries += 1;
+        if metadata.is_file() {
+            summary.total_size += metadata.len();
+        }
+        let modified = to_utc(metadata.modified());
+        summary.last_modified = summary.last_modified.max(modified);
+    }
+    Ok(summary)
+}
+
+// Entries rendered into one chunk of a streamed listing
+const ROWS_PER_CHUNK: usize = 256;
+// Chunks rendered ahead of a slow client
+const LISTING_CHANNEL_CHUNKS: usize = 4;
+
+/// Generate directory listing as HTML or JSON. The body is streamed: the head
+/// goes out while the directory is still being read, and rows are rendered in
+/// chunks as the client takes them, so only the entries' names and metadata
+/// are held in memory however large the directory is.
+pub async fn generate_directory_listing(
+    dir_path: &Path,
+    request_path: &str,
+    options: &ListingOptions<'_>,
+) -> Result<ResBody, StatusCode> {
+    // Opened up front so a missing or unreadable directory still gets a status
+    let dir_entries = fs::read_dir(dir_path).await.map_err(|e| {
+        error!("Failed to read directory {}: {}", dir_path.display(), e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let renderer = ListingRenderer {
+        request_path: request_path.to_string(),
+        signature_token: options.signature_token.map(str::to_string),
+        expire_time: options.expire_time(),
+        time_formatter: TimeFormatter::new(options.timezone, options.time_format),
+        format: options.format,
+        locale: options.locale,
+    };
+    let (tx, mut rx) = mpsc::channel(LISTING_CHANNEL_CHUNKS);
+    tokio::spawn(stream_listing(dir_entries, renderer, tx));
+    Ok(ResBody::Stream(Box::pin(stream::poll_fn(move |cx| {
+        rx.poll_recv(cx)
+    }))))
+}
+
+// What is kept of an entry until the directory is sorted
+struct ListedEntry {
+    name: String,
+    is_directory: bool,
+    size: Option<u64>,
+    modified_at: Option<DateTime<Utc>>,
+}
+
+// Reads, sorts and renders the listing into `tx`, stopping early when the
+// client goes away
+async fn stream_listing(
+    mut dir_entries: fs::ReadDir,
+    renderer: ListingRenderer,
+    tx: mpsc::Sender<Result<Bytes, IoError>>,
+) {
+    if tx.send(Ok(Bytes::from(renderer.head()))).await.is_err() {
+        return;
+    }
+
+    let mut entries = Vec::new();
+    loop {
+        let entry = match dir_entries.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(e) => {
+                // The status is already sent, cutting the body short tells the client
+                error!("Failed to read directory entry: {}", e);
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
 
         // Skip hidden files starting with .
         if name.starts_with('.') {
@@ -87,55 +332,137 @@ pub async fn generate_directory_listing(
                 continue;
             }
         };
-
-        let file_path = if request_path.ends_with('/') {
-            format!("{}{}", request_path, name)
-        } else {
-            format!("{}/{}", request_path, name)
-        };
-
-        let modified = metadata.modified().ok().and_then(|time| {
+        let modified_at = metadata.modified().ok().and_then(|time| {
             time.duration_since(SystemTime::UNIX_EPOCH)
                 .ok()
-                .and_then(|duration| {
-                    let dt = chrono::DateTime::from_timestamp(duration.as_secs() as i64, 0)?;
-                    Some(dt.format("%Y-%m-%d %H:%M:%S").to_string())
-                })
+                .and_then(|duration| DateTime::from_timestamp(duration.as_secs() as i64, 0))
         });
-
-        let entry_data = DirectoryEntry {
-            name: name.clone(),
-            path: file_path.clone(),
+        entries.push(ListedEntry {
+            name,
             is_directory: metadata.is_dir(),
-            size: if metadata.is_file() {
-                Some(metadata.len())
-            } else {
-                None
-            },
-            modified,
-            url: generate_signed_url(&file_path, signature_token, signature_expire_seconds),
+            size: metadata.is_file().then(|| metadata.len()),
+            modified_at,
+        });
+    }
+
+    // Directories first, then files, each by name
+    entries.sort_unstable_by(|a, b| {
+        b.is_directory
+            .cmp(&a.is_directory)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    let mut chunk = String::new();
+    let mut rendered = 0;
+    if let Some(parent) = renderer.parent_entry() {
+        renderer.push_row(&mut chunk, &parent, rendered == 0);
+        rendered += 1;
+    }
+    for listed in entries {
+        let entry = renderer.entry(listed);
+        renderer.push_row(&mut chunk, &entry, rendered == 0);
+        rendered += 1;
+        if rendered % ROWS_PER_CHUNK == 0 {
+            let full = std::mem::take(&mut chunk);
+            if tx.send(Ok(Bytes::from(full))).await.is_err() {
+                return;
+            }
+        }
+    }
+    chunk.push_str(&renderer.tail());
+    let _ = tx.send(Ok(Bytes::from(chunk))).await;
+}
+
+// Owned copy of the listing options, as rendering outlives the request handler
+struct ListingRenderer {
+    request_path: String,
+    signature_token: Option<String>,
+    // Shared by every link of the listing
+    expire_time: u32,
+    time_formatter: TimeFormatter,
+    format: ListingFormat,
+    locale: &'static Locale,
+}
+
+impl ListingRenderer {
+    fn signed_url(&self, path: &str) -> String {
+        generate_signed_url(path, self.signature_token.as_deref(), self.expire_time)
+    }
+
+    // Link to the parent directory, none at the root
+    fn parent_entry(&self) -> Option<DirectoryEntry> {
+        let request_path = self.request_path.as_str();
+        if request_path == "/" {
+            return None;
+        }
+        let parent_path = request_path.strip_suffix('/').unwrap_or(request_path);
+        let parent_url = match parent_path.rfind('/') {
+            Some(last_slash) if last_slash > 0 => parent_path[..last_slash].to_string(),
+            _ => "/".to_string(),
         };
+        Some(DirectoryEntry {
+            name: "../".to_string(),
+            url: self.signed_url(&parent_url),
+            path: parent_url,
+            is_directory: true,
+            size: None,
+            modified: None,
+            modified_at: None,
+        })
+    }
 
-        if metadata.is_dir() {
-            directories.insert(name, entry_data);
+    fn entry(&self, listed: ListedEntry) -> DirectoryEntry {
+        let path = if self.request_path.ends_with('/') {
+            format!("{}{}", self.request_path, listed.name)
         } else {
-            files.insert(name, entry_data);
+            format!("{}/{}", self.request_path, listed.name)
+        };
+        DirectoryEntry {
+            url: self.signed_url(&path),
+            modified: listed
+                .modified_at
+                .map(|time| self.time_formatter.format(time)),
+            name: listed.name,
+            path,
+            is_directory: listed.is_directory,
+            size: listed.size,
+            modified_at: listed.modified_at,
         }
     }
 
-    // Add directories first, then files
-    entries.extend(directories.into_values());
-    entries.extend(files.into_values());
+    fn head(&self) -> String {
+        match self.format {
+            ListingFormat::Html => html_head(&self.request_path, self.locale),
+            ListingFormat::Json => format!(
+                r#"{{"path":{},"entries":["#,
+                serde_json::Value::from(self.request_path.as_str())
+            ),
+        }
+    }
 
-    // Generate HTML
-    let html = generate_html(request_path, &entries);
-    Ok(html)
+    fn push_row(&self, out: &mut String, entry: &DirectoryEntry, first: bool) {
+        match self.format {
+            ListingFormat::Html => push_html_row(out, entry, self.locale),
+            ListingFormat::Json => {
+                if !first {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(entry).unwrap_or_default());
+            }
+        }
+    }
+
+    fn tail(&self) -> String {
+        match self.format {
+            ListingFormat::Html => html_tail(self.locale),
+            ListingFormat::Json => "]}".to_string(),
+        }
+    }
 }
 
-fn generate_signed_url(path: &str, signature_token: Option<&str>, expire_seconds: u32) -> String {
+fn generate_signed_url(path: &str, signature_token: Option<&str>, expire_time: u32) -> String {
     match signature_token {
         Some(token) => {
-            let expire_time = get_expire_time(expire_seconds);
             let signature = create_signature(path, expire_time, token, None);
             format!("{}?$={}", path, signature)
         }
@@ -143,97 +470,17 @@ fn generate_signed_url(path: &str, signature_token: Option<&str>, expire_seconds
     }
 }
 
-fn generate_html(path: &str, entries: &[DirectoryEntry]) -> String {
-    let title = format!("Index of {}", path);
+fn html_head(path: &str, locale: &Locale) -> String {
+    let title = format!("{} {}", locale.index_of, path);
 
-    let mut html = format!(
+    format!(
         r#"<!DOCTYPE html>
-<html>
+<html lang="{}">
 <head>
     <meta charset="utf-8">
     <title>{}</title>
-    <style>
-        body {{
-            font-family: -apple-system, BlinkMacSystemFont, 'Microsoft Yahei UI', Roboto, sans-serif;
-            margin: 2rem;
-            background-color: #eee;
-        }}
-        .container {{
-            max-width: 1200px;
-            margin: 0 auto;
-            background: white;
-            border-radius: 8px;
-            box-shadow: 0 2px 10px rgba(0,0,0,0.1);
-            overflow: hidden;
-        }}
-        .breadcrumb {{
-            background: #e9ecef;
-            padding: 1rem 2rem;
-            font-size: 0.9rem;
-            color: #6c757d;
-        }}
-        table {{
-            width: 100%;
-            border-collapse: collapse;
-            margin: 0;
-        }}
-        th {{
-            background-color: #f8f9fa;
-            padding: 1rem 2rem;
-            text-align: left;
-            border-bottom: 2px solid #dee2e6;
-            font-weight: 600;
-            color: #495057;
-        }}
-        td {{
-            padding: 0.75rem 2rem;
-            border-bottom: 1px solid #dee2e6;
-            vertical-align: middle;
-        }}
-        tr:hover {{
-            background-color: #f8f9fa;
-        }}
-        .file-icon {{
-            width: 20px;
-            height: 20px;
-            margin-right: 10px;
-            vertical-align: middle;
-        }}
-        .file-name {{
-            color: #007bff;
-            text-decoration: none;
-            font-weight: 500;
-        }}
-        .file-name:hover {{
-            text-decoration: underline;
-        }}
-        .directory-name {{
-            color: #6f42c1;
-            text-decoration: none;
-            font-weight: 500;
-        }}
-        .directory-name:hover {{
-            text-decoration: underline;
-        }}
-        .file-size {{
-            text-align: right;
-            color: #6c757d;
-            font-family: Consolas, 'SF Mono', Monaco, 'Roboto Mono', monospace;
-            font-size: 0.9rem;
-        }}
-        .file-date {{
-            color: #6c757d;
-            font-family: Consolas, 'SF Mono', Monaco, 'Roboto Mono', monospace;
-            font-size: 0.9rem;
-        }}
-        .footer {{
-            padding: 1rem;
-            text-align: center;
-            color: #6c757d;
-            font-size: 0.9rem;
-            background: #f8f9fa;
-        }}
-    </style>
+    <link rel="icon" href="/-/assets/favicon.svg" type="image/svg+xml">
+    <link rel="stylesheet" href="/-/assets/autoindex.css">
 </head>
 <body>
     <div class="container">
@@ -241,54 +488,64 @@ fn generate_html(path: &str, entries: &[DirectoryEntry]) -> String {
         <table>
             <thead>
                 <tr>
-                    <th>Name</th>
-                    <th>Size</th>
-                    <th>Modified</th>
+                    <th>{}</th>
+                    <th>{}</th>
+                    <th>{}</th>
                 </tr>
             </thead>
             <tbody>"#,
-        title, path
-    );
+        locale.lang, title, path, locale.name, locale.size, locale.modified
+    )
+}
 
-    for entry in entries {
-        let icon = if entry.is_directory { "📁" } else { "📄" };
-        let name_class = if entry.is_directory {
-            "directory-name"
-        } else {
-            "file-name"
-        };
-        let size_str = match entry.size {
-            Some(size) => format_size(size),
-            None => "-".to_string(),
-        };
-        let modified_str = entry.modified.as_deref().unwrap_or("-");
+fn push_html_row(html: &mut String, entry: &DirectoryEntry, locale: &Locale) {
+    let icon = if entry.is_directory {
+        "/-/assets/folder.svg"
+    } else {
+        "/-/assets/file.svg"
+    };
+    let name_class = if entry.is_directory {
+        "directory-name"
+    } else {
+        "file-name"
+    };
+    let size_str = match entry.size {
+        Some(size) => format_size(size),
+        None => "-".to_string(),
+    };
+    let modified_str = entry.modified.as_deref().unwrap_or("-");
+    let display_name = if entry.name == "../" {
+        locale.parent
+    } else {
+        &entry.name
+    };
 
-        html.push_str(&format!(
-            r#"                <tr>
+    html.push_str(&format!(
+        r#"                <tr>
                     <td>
-                        <span class="file-icon">{}</span>
+                        <img class="file-icon" src="{}" alt="">
                         <a href="{}" class="{}">{}</a>
                     </td>
                     <td class="file-size">{}</td>
                     <td class="file-date">{}</td>
                 </tr>
 "#,
-            icon, entry.url, name_class, entry.name, size_str, modified_str
-        ));
-    }
+        icon, entry.url, name_class, display_name, size_str, modified_str
+    ));
+}
 
-    html.push_str(
+fn html_tail(locale: &Locale) -> String {
+    format!(
         r#"            </tbody>
         </table>
         <div class="footer">
-            Powered by Steambird
+            {}
         </div>
     </div>
 </body>
 </html>"#,
-    );
-
-    html
+        locale.footer
+    )
 }
 
 fn format_size(size: u64) -> String {
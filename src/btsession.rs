@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+
+use tracing::{info, warn};
+
+use crate::app::STATE_DIR;
+use crate::metrics::BT_SESSION_RECLAIMED_BYTES;
+
+// Default output folder of the librqbit session, under the state directory
+const SESSION_DIR: &str = "bt-session";
+
+/// Create the managed librqbit session directory, removing whatever a previous
+/// run left there. Torrents always get an explicit output folder and session
+/// persistence is off, so nothing in it is needed across restarts.
+pub async fn prepare_session_dir(data_dir: &Path) -> anyhow::Result<PathBuf> {
+    let session_dir = data_dir.join(STATE_DIR).join(SESSION_DIR);
+    let dir = session_dir.clone();
+    let reclaimed = tokio::task::spawn_blocking(move || clean_dir(&dir)).await??;
+    if reclaimed > 0 {
+        info!(
+            "Removed {} bytes of stale session files from {}",
+            reclaimed,
+            session_dir.display()
+        );
+    }
+    BT_SESSION_RECLAIMED_BYTES.set(reclaimed as i64);
+    Ok(session_dir)
+}
+
+// Empty `dir` (creating it if missing), returning the bytes removed
+fn clean_dir(dir: &Path) -> std::io::Result<u64> {
+    std::fs::create_dir_all(dir)?;
+    let mut reclaimed = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let size = disk_usage(&path);
+        let removed = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        match removed {
+            Ok(()) => reclaimed += size,
+            Err(e) => warn!(
+                "Failed to remove stale session file {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+    Ok(reclaimed)
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, interval};
+use tracing::warn;
+
+use crate::app::AppState;
+use crate::torrents::torrent_snapshots;
+
+const PERSIST_INTERVAL_SECS: u64 = 60;
+
+/// Lifetime transfer counters of one torrent, across restarts
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LifetimeStats {
+    pub uploaded_bytes: u64,
+    pub downloaded_bytes: u64,
+}
+
+impl LifetimeStats {
+    // Seeding ratio against the torrent size, so seeds that never downloaded still get one
+    pub fn ratio(&self, total_bytes: u64) -> Option<f64> {
+        (total_bytes > 0).then(|| self.uploaded_bytes as f64 / total_bytes as f64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Counters {
+    // Totals of previous sessions (restarts or re-adds)
+    base_uploaded: u64,
+    base_downloaded: u64,
+    // Latest counters reported by librqbit for the current session
+    #[serde(default)]
+    session_uploaded: u64,
+    #[serde(default)]
+    session_downloaded: u64,
+}
+
+impl Counters {
+    fn lifetime(&self) -> LifetimeStats {
+        LifetimeStats {
+            uploaded_bytes: self.base_uploaded + self.session_uploaded,
+            downloaded_bytes: self.base_downloaded + self.session_downloaded,
+        }
+    }
+
+    fn update(&mut self, uploaded: u64, downloaded: u64) {
+        // librqbit counters restart from zero when the torrent is re-added
+        if uploaded < self.session_uploaded || downloaded < self.session_downloaded {
+            self.base_uploaded += self.session_uploaded;
+            self.base_downloaded += self.session_downloaded;
+        }
+        self.session_uploaded = uploaded;
+        self.session_downloaded = downloaded;
+    }
+}
+
+/// Per-torrent upload/download totals persisted under the state directory,
+/// librqbit only counts since the torrent was added to the current session
+pub struct TorrentStatsStore {
+    counters: Mutex<HashMap<String, Counters>>,
+    store_path: PathBuf,
+}
+
+impl TorrentStatsStore {
+    pub fn load(store_path: PathBuf) -> Self {
+        let mut counters: HashMap<String, Counters> = match std::fs::read(&store_path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!(
+                    "Failed to parse torrent stats file {}: {}",
+                    store_path.display(),
+                    e
+                );
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        // The previous process's session is over, fold it into the base
+        for entry in counters.values_mut() {
+            entry.update(0, 0);
+        }
+        Self {
+            counters: Mutex::new(counters),
+            store_path,
+        }
+    }
+
+    pub fn lifetime(&self, info_hash: &str) -> LifetimeStats {
+        let counters = self.counters.lock().unwrap();
+        counters
+            .get(info_hash)
+            .map(Counters::lifetime)
+            .unwrap_or_default()
+    }
+
+    /// Sum over every torrent this node has ever had
+    pub fn totals(&self) -> LifetimeStats {
+        let counters = self.counters.lock().unwrap();
+        counters
+            .values()
+            .map(Counters::lifetime)
+            .fold(LifetimeStats::default(), |total, stats| LifetimeStats {
+                uploaded_bytes: total.uploaded_bytes + stats.uploaded_bytes,
+                downloaded_bytes: total.downloaded_bytes + stats.downloaded_bytes,
+            })
+    }
+
+    fn update(&self, state: &AppState) {
+        let snapshots = torrent_snapshots(&state.bt_api);
+        let mut counters = self.counters.lock().unwrap();
+        for torrent in snapshots {
+            counters
+                .entry(torrent.info_hash)
+                .or_default()
+                .update(torrent.uploaded_bytes, torrent.fetched_bytes);
+        }
+    }
+
+    fn persist(&self) {
+        let content = {
+            let counters = self.counters.lock().unwrap();
+            serde_json::to_vec(&*counters)
+        };
+        let content = match content {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to serialize torrent stats: {}", e);
+                return;
+            }
+        };
+        let tmp_path = self.store_path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&tmp_path, content)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.store_path))
+        {
+            warn!(
+                "Failed to write torrent stats file {}: {}",
+                self.store_path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Fold librqbit's session counters into the lifetime totals and save them
+pub async fn persist_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(PERSIST_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        state.bt_stats.update(&state);
+        let stats = state.bt_stats.clone();
+        let _ = tokio::task::spawn_blocking(move || stats.persist()).await;
+    }
+}
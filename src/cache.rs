@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use moka::future::Cache;
 use tokio::time::Duration;
@@ -26,6 +26,7 @@ impl FileSystemCache {
             cache: Cache::builder()
                 .max_capacity(FS_CACHE_SIZE as u64)
                 .time_to_live(Duration::from_secs(FS_CACHE_TTL_SECS))
+                .support_invalidation_closures()
                 .build(),
         }
     }
@@ -38,6 +39,23 @@ impl FileSystemCache {
         self.cache.insert(path, status).await;
     }
 
+    pub async fn invalidate(&self, path: &PathBuf) {
+        self.cache.invalidate(path).await;
+    }
+
+    /// Drop the entry for `path` and every entry below it
+    pub fn invalidate_tree(&self, path: &Path) {
+        let path = path.to_path_buf();
+        // Only fails when closures aren't supported, which the builder enables
+        let _ = self
+            .cache
+            .invalidate_entries_if(move |key, _| key.starts_with(&path));
+    }
+
+    pub fn invalidate_all(&self) {
+        self.cache.invalidate_all();
+    }
+
     pub async fn get_or_fetch<F, Fut>(&self, path: PathBuf, fetch_fn: F) -> FileSystemStatus
     where
         F: FnOnce() -> Fut,
@@ -58,6 +76,12 @@ impl FileSystemCache {
     }
 }
 
+impl Default for FileSystemCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub async fn check_file_status(path: &PathBuf) -> FileSystemStatus {
     match tokio::fs::metadata(path).await {
         Ok(metadata) => {
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use moka::future::Cache;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::time::Duration;
+
+use crate::app::{AppState, STATE_DIR};
+use crate::jobs::JobHandle;
+use crate::manifest::walk_files;
+
+// Swapped view manifests take effect within this time
+const VIEW_CACHE_TTL_SECS: u64 = 10;
+const VIEW_CACHE_CAPACITY: u64 = 256;
+
+/// URL namespace of a path prefix, relative path -> sha256 of the content
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ViewManifest {
+    pub files: BTreeMap<String, String>,
+}
+
+/// Content-addressed object store under the state directory. Objects are
+/// immutable and shared by every view, so releases don't duplicate storage and
+/// a rollback is just pointing `cas_view` at an older manifest.
+pub struct CasStore {
+    state_dir: PathBuf,
+    views_dir: PathBuf,
+    views: Cache<String, Arc<ViewManifest>>,
+}
+
+impl CasStore {
+    pub fn new(state_dir: &Path) -> Self {
+        Self {
+            state_dir: state_dir.to_path_buf(),
+            views_dir: state_dir.join("views"),
+            views: Cache::builder()
+                .max_capacity(VIEW_CACHE_CAPACITY)
+                .time_to_live(Duration::from_secs(VIEW_CACHE_TTL_SECS))
+                .build(),
+        }
+    }
+
+    fn object_relative(hash: &str) -> String {
+        format!("cas/{}/{}", &hash[..2], hash)
+    }
+
+    /// URL path of the object serving `relative` in view `view`, under the state directory
+    pub async fn resolve(&self, view: &str, relative: &str) -> Option<String> {
+        let manifest = self.view(view).await?;
+        let hash = manifest.files.get(relative)?;
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(format!("/{}/{}", STATE_DIR, Self::object_relative(hash)))
+    }
+
+    async fn view(&self, view: &str) -> Option<Arc<ViewManifest>> {
+        if !is_valid_view_name(view) {
+            return None;
+        }
+        let path = self.views_dir.join(format!("{}.json", view));
+        self.views
+            .optionally_get_with(view.to_string(), async move {
+                let content = tokio::fs::read(&path).await.ok()?;
+                serde_json::from_slice(&content).ok().map(Arc::new)
+            })
+            .await
+    }
+
+    /// Add the files of `source` to the store and record them as view `view`
+    pub async fn import(
+        &self,
+        state: &AppState,
+        source: &Path,
+        view: &str,
+        job: &JobHandle,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(is_valid_view_name(view), "invalid view name {}", view);
+        let files = walk_files(source)
+            .await
+            .with_context(|| format!("Failed to list {}", source.display()))?;
+        let total = files.len() as u64;
+        let mut manifest = ViewManifest::default();
+        for (index, (relative, path, metadata)) in files.into_iter().enumerate() {
+            job.set_progress(index as u64, total);
+            let hashes = state.checksums.hashes(&path, &metadata).await?;
+            let object = self.state_dir.join(Self::object_relative(&hashes.sha256));
+            if tokio::fs::metadata(&object).await.is_err() {
+                store_object(&path, &object, &hashes.sha256).await?;
+            }
+            manifest.files.insert(relative, hashes.sha256.clone());
+        }
+        job.set_progress(total, total);
+
+        tokio::fs::create_dir_all(&self.views_dir).await?;
+        let view_path = self.views_dir.join(format!("{}.json", view));
+        let tmp_path = self.views_dir.join(format!(".{}.json.tmp", view));
+        tokio::fs::write(&tmp_path, serde_json::to_vec_pretty(&manifest)?).await?;
+        tokio::fs::rename(&tmp_path, &view_path).await?;
+        self.views.invalidate(view).await;
+        job.set_message(format!("View {} has {} files", view, manifest.files.len()));
+        Ok(())
+    }
+}
+
+// A private read-only copy, so editing or replacing the source later can't
+// change an object in place. The copy is a reflink where the filesystem
+// supports it, and checked against the hash in case the source changed.
+async fn store_object(source: &Path, object: &Path, sha256: &str) -> anyhow::Result<()> {
+    let dir = object.parent().context("object path has no parent")?;
+    tokio::fs::create_dir_all(dir).await?;
+    let mut tmp = object.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let _ = tokio::fs::remove_file(&tmp).await;
+    tokio::fs::copy(source, &tmp)
+        .await
+        .with_context(|| format!("Failed to copy {}", source.display()))?;
+    let hash_path = tmp.clone();
+    let copied = tokio::task::spawn_blocking(move || sha256_file(&hash_path)).await??;
+    if copied != sha256 {
+        let _ = tokio::fs::remove_file(&tmp).await;
+        anyhow::bail!("{} changed while it was imported", source.display());
+    }
+    let mut permissions = tokio::fs::metadata(&tmp).await?.permissions();
+    permissions.set_readonly(true);
+    tokio::fs::set_permissions(&tmp, permissions).await?;
+    tokio::fs::rename(&tmp, object).await?;
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 256 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn is_valid_view_name(view: &str) -> bool {
+    !view.is_empty()
+        && view
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+        && !view.starts_with('.')
+}
+
+pub fn spawn_import(state: &AppState, source: PathBuf, view: String) -> u64 {
+    let state = state.clone();
+    state
+        .jobs
+        .clone()
+        .spawn("cas_import", move |job| async move {
+            state.cas.import(&state, &source, &view, &job).await
+        })
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use hmac::{Hmac, Mac};
+use hyper::body::Bytes;
+use hyper::http::StatusCode;
+use hyper::{HeaderMap, Response};
+use sha2::Sha256;
+use tracing::warn;
+
+use crate::response::ResBody;
+use crate::signature::get_expire_time;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const CHALLENGE_COOKIE: &str = "dfs_challenge";
+// How long a passed challenge stays valid
+const CHALLENGE_TTL_SECS: u32 = 86400;
+
+// Served instead of the listing until the client comes back with the cookie.
+// Clients that don't keep cookies (most scrapers) never get past it.
+const CHALLENGE_PAGE: &str = "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+<meta http-equiv=\"refresh\" content=\"1\"><title>Loading...</title></head>\
+<body><p>Loading directory listing...</p></body></html>\n";
+
+/// Cookie challenge for autoindex pages, keyed by a per-node secret
+pub struct Challenge {
+    key: [u8; 32],
+}
+
+impl Challenge {
+    /// Load the node secret from `key_path`, generating it on first start so
+    /// cookies survive restarts
+    pub fn load(key_path: &Path) -> Self {
+        if let Ok(content) = std::fs::read(key_path) {
+            if let Ok(key) = <[u8; 32]>::try_from(content.as_slice()) {
+                return Self { key };
+            }
+            warn!("Invalid challenge key {}, regenerating", key_path.display());
+        }
+        let key: [u8; 32] = rand::random();
+        if let Err(e) = std::fs::write(key_path, key) {
+            warn!(
+                "Failed to write challenge key {}: {}",
+                key_path.display(),
+                e
+            );
+        }
+        Self { key }
+    }
+
+    fn sign(&self, expire_time: u32) -> String {
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(format!("challenge\n{:08x}", expire_time).as_bytes());
+        format!(
+            "{:08x}{}",
+            expire_time,
+            hex::encode(mac.finalize().into_bytes())
+        )
+    }
+
+    /// Whether the request carries an unexpired challenge cookie
+    pub fn verify(&self, headers: &HeaderMap) -> bool {
+        let Some(token) = headers
+            .get_all("cookie")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| pair.trim().split_once('='))
+            .find(|(name, _)| *name == CHALLENGE_COOKIE)
+            .map(|(_, value)| value)
+        else {
+            return false;
+        };
+        if token.len() != 72 || !token.is_ascii() {
+            return false;
+        }
+        let Ok(expire_time) = u32::from_str_radix(&token[..8], 16) else {
+            return false;
+        };
+        if expire_time < get_expire_time(0) {
+            return false;
+        }
+        let Ok(expected) = hex::decode(&token[8..]) else {
+            return false;
+        };
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(format!("challenge\n{:08x}", expire_time).as_bytes());
+        mac.verify_slice(&expected).is_ok()
+    }
+
+    /// Meta-refresh page that sets the challenge cookie
+    pub fn response(&self) -> Response<ResBody> {
+        let cookie = format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+            CHALLENGE_COOKIE,
+            self.sign(get_expire_time(CHALLENGE_TTL_SECS)),
+            CHALLENGE_TTL_SECS
+        );
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .header("Cache-Control", "no-store")
+            .header("Set-Cookie", cookie)
+            .body(ResBody::Bytes(Bytes::from_static(
+                CHALLENGE_PAGE.as_bytes(),
+            )))
+            .unwrap()
+    }
+}
@@ -0,0 +1,212 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use moka::future::Cache;
+use redb::{Database, ReadableTable, TableDefinition};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::time::Duration;
+use tracing::warn;
+
+use crate::logging::log_limited;
+use crate::manifest::CHUNK_SIZE;
+
+// path -> JSON encoded Record
+const CHECKSUM_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("checksums");
+const MEMORY_CACHE_CAPACITY: u64 = 100_000;
+const MEMORY_CACHE_TTI_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileHashes {
+    // sha256 hex of the whole file
+    pub sha256: String,
+    // sha256 hex of each CHUNK_SIZE chunk
+    pub chunks: Vec<String>,
+}
+
+// A file version, hashes are only valid while size and mtime are unchanged
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FileVersion {
+    path: PathBuf,
+    size: u64,
+    mtime_ns: Option<u128>,
+}
+
+impl FileVersion {
+    fn new(path: &Path, metadata: &std::fs::Metadata) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            mtime_ns: metadata
+                .modified()
+                .ok()
+                .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                .map(|mtime| mtime.as_nanos()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    size: u64,
+    mtime_ns: Option<u128>,
+    chunk_size: u64,
+    hashes: FileHashes,
+}
+
+/// On-disk database of file hashes keyed by path, so each file version is hashed
+/// at most once across restarts. A memory cache (including misses) sits in front.
+pub struct ChecksumDb {
+    db: Option<Arc<Database>>,
+    cache: Cache<FileVersion, Option<Arc<FileHashes>>>,
+}
+
+impl ChecksumDb {
+    /// Open or create the database, falling back to memory only if it can't be opened
+    pub fn open(path: &Path) -> Self {
+        let db = match Database::create(path) {
+            Ok(db) => Some(Arc::new(db)),
+            Err(e) => {
+                warn!(
+                    "Failed to open checksum database {}, hashes will not persist: {}",
+                    path.display(),
+                    e
+                );
+                None
+            }
+        };
+        Self {
+            db,
+            cache: Cache::builder()
+                .max_capacity(MEMORY_CACHE_CAPACITY)
+                .time_to_idle(Duration::from_secs(MEMORY_CACHE_TTI_SECS))
+                .build(),
+        }
+    }
+
+    /// Hashes of this file version if they are already known, without reading the file
+    pub async fn lookup(
+        &self,
+        path: &Path,
+        metadata: &std::fs::Metadata,
+    ) -> Option<Arc<FileHashes>> {
+        let version = FileVersion::new(path, metadata);
+        if let Some(cached) = self.cache.get(&version).await {
+            return cached;
+        }
+        let stored = match &self.db {
+            Some(db) => {
+                let db = db.clone();
+                let key = version.clone();
+                tokio::task::spawn_blocking(move || read_record(&db, &key))
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(Arc::new)
+            }
+            None => None,
+        };
+        self.cache.insert(version, stored.clone()).await;
+        stored
+    }
+
+    /// Hashes of this file version, hashing and storing them on first use
+    pub async fn hashes(
+        &self,
+        path: &Path,
+        metadata: &std::fs::Metadata,
+    ) -> std::io::Result<Arc<FileHashes>> {
+        if let Some(hashes) = self.lookup(path, metadata).await {
+            return Ok(hashes);
+        }
+        let version = FileVersion::new(path, metadata);
+        let db = self.db.clone();
+        let key = version.clone();
+        let hashes = tokio::task::spawn_blocking(move || {
+            let hashes = hash_file(&key.path)?;
+            if let Some(Err(e)) = db.map(|db| write_record(&db, &key, &hashes)) {
+                log_limited!(
+                    warn,
+                    "checksum_db",
+                    "Failed to store checksum of {}: {}",
+                    key.path.display(),
+                    e
+                );
+            }
+            Ok::<_, std::io::Error>(hashes)
+        })
+        .await
+        .map_err(std::io::Error::other)??;
+        let hashes = Arc::new(hashes);
+        self.cache.insert(version, Some(hashes.clone())).await;
+        Ok(hashes)
+    }
+}
+
+fn read_record(db: &Database, version: &FileVersion) -> Option<FileHashes> {
+    let txn = db.begin_read().ok()?;
+    // The table doesn't exist until the first write
+    let table = txn.open_table(CHECKSUM_TABLE).ok()?;
+    let value = table.get(version.path.to_string_lossy().as_ref()).ok()??;
+    let record: Record = serde_json::from_slice(value.value()).ok()?;
+    let current = record.size == version.size
+        && record.mtime_ns == version.mtime_ns
+        && record.chunk_size == CHUNK_SIZE;
+    current.then_some(record.hashes)
+}
+
+fn write_record(
+    db: &Database,
+    version: &FileVersion,
+    hashes: &FileHashes,
+) -> Result<(), redb::Error> {
+    let record = Record {
+        size: version.size,
+        mtime_ns: version.mtime_ns,
+        chunk_size: CHUNK_SIZE,
+        hashes: hashes.clone(),
+    };
+    let value = serde_json::to_vec(&record).expect("record serializes");
+    let txn = db.begin_write()?;
+    {
+        let mut table = txn.open_table(CHECKSUM_TABLE)?;
+        table.insert(version.path.to_string_lossy().as_ref(), value.as_slice())?;
+    }
+    txn.commit()?;
+    Ok(())
+}
+
+// Whole-file and per-chunk sha256 in a single pass
+fn hash_file(path: &Path) -> std::io::Result<FileHashes> {
+    let mut file = std::fs::File::open(path)?;
+    let mut file_hasher = Sha256::new();
+    let mut chunks = Vec::new();
+    let mut buf = vec![0u8; 256 * 1024];
+    loop {
+        let mut hasher = Sha256::new();
+        let mut chunk_len = 0u64;
+        while chunk_len < CHUNK_SIZE {
+            let want = buf.len().min((CHUNK_SIZE - chunk_len) as usize);
+            let n = file.read(&mut buf[..want])?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            file_hasher.update(&buf[..n]);
+            chunk_len += n as u64;
+        }
+        if chunk_len == 0 {
+            break;
+        }
+        chunks.push(hex::encode(hasher.finalize()));
+        if chunk_len < CHUNK_SIZE {
+            break;
+        }
+    }
+    Ok(FileHashes {
+        sha256: hex::encode(file_hasher.finalize()),
+        chunks,
+    })
+}
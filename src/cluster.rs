@@ -0,0 +1,142 @@
+// Consistent-hash placement across a cluster: every path has one owning node,
+// nodes redirect requests for content they don't hold to its owner instead of
+// each keeping the whole catalog
+use hyper::{Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppState;
+use crate::gossip::holders_of;
+use crate::logging::log_limited;
+use crate::metrics::CLUSTER_REDIRECTS_TOTAL;
+use crate::redirect::{REDIRECTED_PARAM, alternate_path};
+use crate::response::ResBody;
+
+const DEFAULT_VIRTUAL_NODES: u32 = 100;
+const DEFAULT_REDIRECT_EXPIRE_SECS: u32 = 300;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClusterConfig {
+    pub nodes: Vec<ClusterNode>,
+    // This node's id in `nodes`, defaults to the server id of the central URL
+    pub self_id: Option<String>,
+    // Points per node on the hash ring, more spread paths more evenly, default 100
+    pub virtual_nodes: Option<u32>,
+    // Lifetime of the re-issued signature, default 300 seconds
+    pub expire_seconds: Option<u32>,
+    // Shared by the members to sign gossip, no gossip without it
+    pub gossip_secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClusterNode {
+    pub id: String,
+    // Base URL, e.g. https://node2.example.com
+    pub url: String,
+    // Relative share of paths, default 1
+    pub weight: Option<u32>,
+}
+
+/// Hash ring built from the cluster membership
+#[derive(Debug)]
+pub struct HashRing {
+    // Sorted by hash, pointing into `nodes`
+    points: Vec<(u64, usize)>,
+    nodes: Vec<ClusterNode>,
+}
+
+impl HashRing {
+    pub fn new(config: &ClusterConfig) -> Self {
+        let virtual_nodes = config.virtual_nodes.unwrap_or(DEFAULT_VIRTUAL_NODES);
+        let mut points = Vec::new();
+        for (index, node) in config.nodes.iter().enumerate() {
+            let count = virtual_nodes * node.weight.unwrap_or(1);
+            for replica in 0..count {
+                let key = format!("{}#{}", node.id, replica);
+                points.push((xxhash_rust::xxh3::xxh3_64(key.as_bytes()), index));
+            }
+        }
+        points.sort_unstable();
+        Self {
+            points,
+            nodes: config.nodes.clone(),
+        }
+    }
+
+    /// Node responsible for `path`, the first point at or after its hash
+    pub fn owner(&self, path: &str) -> Option<&ClusterNode> {
+        self.owner_where(path, |_| true)
+    }
+
+    /// First node accepted by `eligible` walking the ring from `path`'s hash,
+    /// so the choice among eligible nodes is as stable as `owner`
+    pub fn owner_where(
+        &self,
+        path: &str,
+        eligible: impl Fn(&ClusterNode) -> bool,
+    ) -> Option<&ClusterNode> {
+        let hash = xxhash_rust::xxh3::xxh3_64(path.as_bytes());
+        let position = self.points.partition_point(|(point, _)| *point < hash);
+        (0..self.points.len())
+            .map(|step| self.points[(position + step) % self.points.len()].1)
+            .filter_map(|index| self.nodes.get(index))
+            .find(|node| eligible(node))
+    }
+}
+
+/// This node's id in the cluster, `self_id` or the server id of the central URL
+pub fn self_id<'a>(cluster: &'a ClusterConfig, state: &'a AppState) -> Option<&'a str> {
+    cluster.self_id.as_deref().or(state.server_id.as_deref())
+}
+
+/// 302 to the node the ring assigns `req`'s path to, when that isn't this
+/// node. Nodes gossiping that they finished the torrent covering the path are
+/// preferred over the ring owner. Requests already redirected once are
+/// answered here, so nodes with different memberships can't bounce a client
+/// between them. Signed links are re-signed with `expire_seconds` at most,
+/// never past the caller's own expiry.
+pub fn cluster_redirect<B>(state: &AppState, req: &Request<B>) -> Option<Response<ResBody>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return None;
+    }
+    if req
+        .uri()
+        .query()
+        .is_some_and(|query| query.split('&').any(|pair| pair == REDIRECTED_PARAM))
+    {
+        return None;
+    }
+    let config = state.config.load();
+    let (cluster, ring) = (config.cluster.as_ref()?, config.cluster_ring.as_ref()?);
+    let Some(self_id) = self_id(cluster, state) else {
+        log_limited!(
+            warn,
+            "cluster_self_id",
+            "Cluster configured without self_id or a central server id, not redirecting"
+        );
+        return None;
+    };
+    let path = req.uri().path();
+    let holders = holders_of(state, path);
+    let owner = ring
+        .owner_where(path, |node| holders.contains(&node.id))
+        .or_else(|| ring.owner(path))?;
+    if owner.id == self_id {
+        return None;
+    }
+    let expire_seconds = cluster
+        .expire_seconds
+        .unwrap_or(DEFAULT_REDIRECT_EXPIRE_SECS);
+    let path = alternate_path(&config, req, expire_seconds, Some(REDIRECTED_PARAM))?;
+
+    CLUSTER_REDIRECTS_TOTAL.inc();
+    let response = Response::builder()
+        .status(StatusCode::FOUND)
+        .header(
+            "Location",
+            format!("{}{}", owner.url.trim_end_matches('/'), path),
+        )
+        .header("Cache-Control", "no-store")
+        .body(ResBody::Empty)
+        .unwrap();
+    Some(response)
+}
@@ -0,0 +1,176 @@
+use std::pin::Pin;
+
+use async_compression::Level;
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use futures_util::{Stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::http::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use crate::metrics::{COMPRESSION_INPUT_BYTES_TOTAL, COMPRESSION_OUTPUT_BYTES_TOTAL};
+use crate::response::FileBody;
+
+// Smaller bodies gain less than the encoding overhead
+const DEFAULT_MIN_SIZE: u64 = 1024;
+// Text-like types; archives, images and media are already compressed
+const DEFAULT_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "application/x-ndjson",
+    "image/svg+xml",
+];
+// Brotli's default quality is far too slow for on-the-fly compression
+const BROTLI_QUALITY: i32 = 4;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CompressionConfig {
+    // Files smaller than this are sent as-is, default 1024 bytes
+    pub min_size: Option<u64>,
+    // Content types to compress, entries ending in `/` match the whole type
+    pub types: Option<Vec<String>>,
+    // Bodies compressed at the same time, later responses go out uncompressed.
+    // Default: the number of CPUs
+    pub max_concurrent: Option<u32>,
+}
+
+impl CompressionConfig {
+    pub fn max_concurrent(&self) -> u32 {
+        self.max_concurrent.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, |cpus| cpus.get() as u32)
+        })
+    }
+
+    // Why the policy leaves a body of this type and size uncompressed, if it does
+    fn exclusion(&self, content_type: &str, len: u64) -> Option<&'static str> {
+        if len < self.min_size.unwrap_or(DEFAULT_MIN_SIZE) {
+            return Some("too_small");
+        }
+        let essence = content_type.split(';').next().unwrap_or("").trim();
+        let matches = |allowed: &str| {
+            if allowed.ends_with('/') {
+                essence.starts_with(allowed)
+            } else {
+                essence.eq_ignore_ascii_case(allowed)
+            }
+        };
+        let allowed = match &self.types {
+            Some(types) => types.iter().any(|allowed| matches(allowed)),
+            None => DEFAULT_TYPES.iter().any(|allowed| matches(allowed)),
+        };
+        (!allowed).then_some("type")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Variant ETag, the compressed body is a different representation
+    pub fn etag(self, etag: &HeaderValue) -> Option<HeaderValue> {
+        let etag = etag.to_str().ok()?.trim_end_matches('"');
+        HeaderValue::from_str(&format!("{}-{}\"", etag, self.name())).ok()
+    }
+}
+
+/// Best encoding the client accepts, preferring zstd, then brotli, then gzip.
+/// `q=0` refuses a coding even when `*` would otherwise allow it.
+fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut wildcard = false;
+    let mut accepted = Vec::new();
+    let mut refused = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let encoding = match coding.as_str() {
+            "*" => {
+                wildcard = quality > 0.0;
+                continue;
+            }
+            "zstd" => Encoding::Zstd,
+            "br" => Encoding::Brotli,
+            "gzip" | "x-gzip" => Encoding::Gzip,
+            _ => continue,
+        };
+        if quality > 0.0 {
+            accepted.push(encoding);
+        } else {
+            refused.push(encoding);
+        }
+    }
+    [Encoding::Zstd, Encoding::Brotli, Encoding::Gzip]
+        .into_iter()
+        .filter(|encoding| !refused.contains(encoding))
+        .find(|encoding| accepted.contains(encoding) || (wildcard && *encoding == Encoding::Gzip))
+}
+
+/// Why a full response with these headers is never compressed: "encoded",
+/// "too_small" or "type". None when it may be, in which case caches must keep
+/// variants apart by Accept-Encoding.
+pub fn exclusion(config: &CompressionConfig, headers: &HeaderMap) -> Option<&'static str> {
+    let content_type = headers
+        .get("content-type")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("");
+    let len = headers
+        .get("content-length")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse().ok())
+        .unwrap_or(0);
+    if headers.contains_key("content-encoding") {
+        return Some("encoded");
+    }
+    config.exclusion(content_type, len)
+}
+
+pub fn choose_encoding(accept_encoding: Option<&HeaderValue>) -> Option<Encoding> {
+    negotiate(accept_encoding?.to_str().ok()?)
+}
+
+/// Stream `file` through the encoder, counting bytes in and out for the
+/// compression ratio metrics
+pub fn compress_file(file: tokio::fs::File, encoding: Encoding) -> FileBody {
+    let name = encoding.name();
+    let input = ReaderStream::new(file).inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            COMPRESSION_INPUT_BYTES_TOTAL
+                .with_label_values(&[name])
+                .inc_by(chunk.len() as u64);
+        }
+    });
+    let reader = StreamReader::new(input);
+    let output: Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>> = match encoding {
+        Encoding::Zstd => Box::pin(ReaderStream::new(ZstdEncoder::new(reader))),
+        Encoding::Brotli => Box::pin(ReaderStream::new(BrotliEncoder::with_quality(
+            reader,
+            Level::Precise(BROTLI_QUALITY),
+        ))),
+        Encoding::Gzip => Box::pin(ReaderStream::new(GzipEncoder::new(reader))),
+    };
+    FileBody::Stream(Box::pin(output.inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            COMPRESSION_OUTPUT_BYTES_TOTAL
+                .with_label_values(&[name])
+                .inc_by(chunk.len() as u64);
+        }
+    })))
+}
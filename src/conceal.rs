@@ -0,0 +1,88 @@
+use std::time::Instant;
+
+use hyper::Response;
+use hyper::http::{HeaderValue, StatusCode};
+use rand::Rng;
+use rand::distr::Alphanumeric;
+use tokio::time::{Duration, sleep};
+
+use crate::config::PathConfig;
+use crate::response::ResBody;
+
+const PADDING_HEADER: &str = "X-Dfs-Padding";
+// Proxies and clients reject oversized header sections (nginx buffers 4KB by
+// default), larger responses aren't padded further
+const MAX_PADDING: u64 = 4 * 1024;
+
+/// Per-path measures against enumerating files behind a prefix: client errors
+/// become one indistinguishable 404 answered no sooner than a fixed delay, and
+/// small responses are padded so sizes don't give away which file was hit
+pub struct Conceal {
+    error_floor: Option<Duration>,
+    pad_to: Option<u64>,
+}
+
+impl Conceal {
+    pub fn from_path_config(path_config: &PathConfig) -> Option<Self> {
+        let conceal = Self {
+            error_floor: path_config.uniform_errors_ms.map(Duration::from_millis),
+            pad_to: path_config
+                .pad_response_bytes
+                .map(|bytes| bytes.min(MAX_PADDING)),
+        };
+        (conceal.error_floor.is_some() || conceal.pad_to.is_some()).then_some(conceal)
+    }
+
+    pub async fn apply(
+        &self,
+        mut response: Response<ResBody>,
+        start: Instant,
+    ) -> Response<ResBody> {
+        // 429 carries Retry-After and 416 tells a resuming client its copy is
+        // complete, neither answers whether a file exists
+        let status = response.status();
+        let uniform_error = status.is_client_error()
+            && status != StatusCode::TOO_MANY_REQUESTS
+            && status != StatusCode::RANGE_NOT_SATISFIABLE;
+        if let Some(floor) = self.error_floor.filter(|_| uniform_error) {
+            response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(ResBody::Empty)
+                .unwrap();
+            let elapsed = start.elapsed();
+            if elapsed < floor {
+                sleep(floor - elapsed).await;
+            }
+        }
+        if let Some(pad_to) = self.pad_to {
+            let len = body_len(&response);
+            if let Some(padding) = len.filter(|len| *len < pad_to).map(|len| pad_to - len) {
+                // Random, so HPACK/QPACK and compressing proxies can't shrink it away
+                let filler: String = rand::rng()
+                    .sample_iter(Alphanumeric)
+                    .take(padding as usize)
+                    .map(char::from)
+                    .collect();
+                response.headers_mut().insert(
+                    PADDING_HEADER,
+                    HeaderValue::from_str(&filler).expect("ASCII padding is a valid header value"),
+                );
+            }
+        }
+        response
+    }
+}
+
+// Bytes the body will carry, None for streams of unknown length
+fn body_len(response: &Response<ResBody>) -> Option<u64> {
+    let content_length = response
+        .headers()
+        .get("content-length")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse().ok());
+    content_length.or(match response.body() {
+        ResBody::Bytes(bytes) => Some(bytes.len() as u64),
+        ResBody::Empty => Some(0),
+        _ => None,
+    })
+}
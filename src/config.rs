@@ -1,34 +1,166 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use anyhow::{Context, Result};
 use arc_swap::ArcSwap;
 use bytes::Bytes;
 use librqbit::dht::Id20;
+use notify::{EventKind, RecursiveMode, Watcher};
 use radix_trie::Trie;
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 use tokio::time::{Duration, interval};
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
-use crate::app::AppState;
-use crate::metrics::CONFIG_VERSION;
+use crate::accesslog::AccessLogConfig;
+use crate::app::{AppState, STATE_DIR};
+use crate::assist::{AssistedTorrent, PeerAssistConfig};
+use crate::auth::{AuthChain, AuthConfig};
+use crate::cluster::{ClusterConfig, HashRing, self_id};
+use crate::compression::CompressionConfig;
+use crate::jobs::JobHandle;
+use crate::logging::{Sampler, log_limited};
+use crate::metrics::{CONFIG_ISSUES, CONFIG_VERSION};
+use crate::mirror::MirrorConfig;
+use crate::net::IpRule;
+use crate::realip::ForwardedHeader;
+use crate::redirect::OverflowRedirectConfig;
+use crate::report::ErrorReportConfig;
+use crate::rollout::RolloutConfig;
+use crate::tls::{TlsConfig, check_files};
+use crate::torrents::{TorrentFile, TorrentLayout, torrent_snapshots};
+use crate::urlpath::{decode_path, is_within};
+use crate::webhook::{WebhookConfig, WebhookEvent, emit};
+
+// Config bundles with embedded torrents can be large, allow slow links more time
+const CONFIG_FETCH_TIMEOUT_SECS: u64 = 120;
+// Upper bound for paginated central configs
+const MAX_CONFIG_PAGES: usize = 1000;
+const HASH_CHECK_POLL_SECS: u64 = 5;
+const STATUS_REPORT_INTERVAL_SECS: u64 = 30;
+const STATUS_REPORT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_DAV_PREFIX: &str = "/-/dav";
+// librqbit state of torrents waiting for or running their initial hash check
+const HASH_CHECK_STATE: &str = "initializing";
+// Last config restored through /-/config/restore, under the state directory
+const SNAPSHOT_FILE: &str = "config-snapshot.json";
+// Quiet period after a change in the --config directory before the file is read
+const CONFIG_WATCH_DEBOUNCE_MS: u64 = 200;
+
+static HASH_CHECK_RUNNING: AtomicBool = AtomicBool::new(false);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PathConfig {
     pub autoindex: Option<bool>,
-    pub signature: Option<String>,
+    // 签名密钥，轮换时可为列表：新签名用第一个，校验时依次尝试
+    pub signature: Option<SignatureTokens>,
     pub signature_expire_seconds: Option<u32>, // 签名过期时间，默认3600秒(1小时)
+    pub available_after: Option<i64>,          // 解禁时间(unix秒)，之前的请求即使签名有效也拒绝
+    pub embargo_status: Option<u16>,           // 解禁前返回的状态码，默认403，可设为404等4xx
+    pub verify_hash: Option<bool>,             // 完整响应时边发送边校验sha256(来自 <file>.sha256)
+    pub access_log: Option<bool>,              // 记录该路径的访问日志
+    pub access_log_sample_rate: Option<f64>,   // 访问日志采样比例 0.0-1.0，默认全部记录
+    pub autoindex_challenge: Option<bool>,     // 目录列表需先通过cookie挑战，不影响文件下载
+    pub max_concurrent: Option<u32>,           // 该路径前缀下同时进行的最大传输数，超出返回503
+    pub autoindex_timezone: Option<String>,    // 目录列表时间所用时区(IANA名称)，默认UTC
+    pub autoindex_time_format: Option<String>, // 目录列表时间格式(strftime)，默认 %Y-%m-%d %H:%M:%S
+    pub autoindex_locale: Option<String>,      // 目录列表语言(en/zh/ja)，默认按 Accept-Language
+    pub autoindex_expire_step: Option<u32>,    // 目录列表签名过期时间取整到该秒数的倍数，可被缓存
+    pub mmap_max_size: Option<u64>,            // 不超过该大小的热点文件读入内存提供
+    pub fallback_file: Option<String>,         // 文件不存在时返回的文件，相对路径前缀或以/开头
+    pub cas_view: Option<String>,              // 按内容寻址存储中的视图清单提供该前缀下的文件
+    pub mirror_links: Option<bool>,            // 响应带其他节点的 Link 与 Digest 头
+    pub origin: Option<String>,                // 文件不存在时从该源站拉取并缓存到本地
+    pub rate_limit_bytes_per_sec: Option<u64>, // 该路径下每个下载的速度上限(字节/秒)
+    pub signature_exempt: Option<Vec<String>>, // 签名路径下无需签名的文件(通配符，如 *.json)
+    pub signature_params: Option<Vec<String>>, // 一并签名的查询参数(如 filename、dl)，签名后不可增删改
+    pub compress: Option<bool>,                // 按 Accept-Encoding 实时压缩文本类文件
+    pub max_per_signature: Option<u32>,        // 同一签名同时进行的最大传输数，超出返回429
+    pub uniform_errors_ms: Option<u64>,        // 客户端错误统一为404并至少延迟该毫秒数
+    pub pad_response_bytes: Option<u64>,       // 小于该大小的响应用随机填充头补齐(最多4KB)
+    pub grow_wait_ms: Option<u64>,             // Range超出仍在写入的文件末尾时等待数据的毫秒数
+    pub auth: Option<Vec<AuthConfig>>,         // 访问认证，全部通过才放行，默认按 signature 校验
+    pub root: Option<PathBuf>, // 该前缀的文件所在的数据目录，替代全局 --dir(如挂载在其他磁盘)
+    pub rollout: Option<RolloutConfig>, // 按比例让匹配请求头的客户端从另一个目录获取文件(灰度发布)
+    pub cors_origins: Option<Vec<String>>, // 允许跨域读取的来源，"*" 为任意来源，[] 关闭；默认 "*"，带认证的路径从不允许
+    // Built from `auth` when the path table is
+    #[serde(skip)]
+    pub authorizer: Option<Arc<AuthChain>>,
+    // Samples this path's access log at access_log_sample_rate, set when the path table is built
+    #[serde(skip)]
+    pub access_log_sampler: Arc<Sampler>,
+}
+
+impl PathConfig {
+    /// Token this node signs the path's URLs with, the first of `signature`
+    pub fn signing_token(&self) -> Option<&str> {
+        self.signature_tokens().first().map(String::as_str)
+    }
+
+    /// Tokens a signature of the path may be made with, tried in order
+    pub fn signature_tokens(&self) -> &[String] {
+        self.signature
+            .as_ref()
+            .map(SignatureTokens::all)
+            .unwrap_or_default()
+    }
+}
+
+/// `signature` of a path: a token, or a list of them while keys are rotated
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum SignatureTokens {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl SignatureTokens {
+    pub fn all(&self) -> &[String] {
+        match self {
+            SignatureTokens::One(token) => std::slice::from_ref(token),
+            SignatureTokens::Many(tokens) => tokens,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TorrentConfig {
     pub path: String,
-    #[serde(with = "base64_serde")]
+    // Inline .torrent content, may be omitted when torrent_url is set
+    #[serde(default, with = "base64_serde")]
     pub torrent: Bytes,
+    // .torrent download URL, fetched once and cached under the state directory
+    pub torrent_url: Option<String>,
     #[serde(default)]
     pub initial_peers: Vec<SocketAddr>,
+    // Arbitrary labels (release, platform, channel...) attached to metrics and status output
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    // Super-seeding for origin nodes. librqbit has no super-seed mode yet, such
+    // torrents are seeded normally with a warning.
+    #[serde(default)]
+    pub super_seed: bool,
+    // Unix timestamp before which the torrent is not added (pre-launch download window)
+    pub start_after: Option<i64>,
+    // Unix timestamp before which the torrent's path answers 404
+    pub release_at: Option<i64>,
+    // Answer small Range requests for pieces not downloaded yet by fetching them from peers
+    pub peer_assist: Option<PeerAssistConfig>,
+}
+
+impl TorrentConfig {
+    // Torrent path as a URL prefix, always "/..." with a trailing slash
+    pub fn url_prefix(&self) -> String {
+        format!("/{}/", self.path.trim_matches('/'))
+    }
+
+    pub fn is_deferred(&self, now: i64) -> bool {
+        self.start_after.is_some_and(|start| now < start)
+    }
 }
 
 mod base64_serde {
@@ -54,51 +186,197 @@ mod base64_serde {
     }
 }
 
+// Path configs, rebuilt whenever the config carries a `paths` section
+#[derive(Debug, Clone, Default)]
+pub struct PathTable {
+    pub path_trie: Trie<String, PathConfig>,
+}
+
+impl PathTable {
+    pub fn new(paths: &HashMap<String, PathConfig>) -> Self {
+        let mut path_trie = Trie::new();
+        // 将路径配置插入前缀树
+        for (path, path_config) in paths {
+            let mut path_config = path_config.clone();
+            let chain = AuthChain::build(path, &path_config);
+            path_config.authorizer = (!chain.is_empty()).then(|| Arc::new(chain));
+            path_config.access_log_sampler = Arc::new(Sampler::new());
+            path_trie.insert(path.clone(), path_config);
+        }
+        Self { path_trie }
+    }
+}
+
+// Parsed torrents, rebuilt only when the torrent list itself changes
+#[derive(Debug, Clone, Default)]
+pub struct TorrentTable {
+    pub version: Option<u64>,
+    pub torrents: HashMap<Id20, TorrentConfig>,
+    pub release_trie: Trie<String, i64>, // 未到发布时间的torrent路径
+    // URL prefixes of all torrents
+    pub torrent_trie: Trie<String, Id20>,
+    // URL prefixes of torrents with peer_assist
+    pub assist_trie: Trie<String, Arc<AssistedTorrent>>,
+    // URL prefixes of all torrents, with where their files sit in the pieces
+    pub layout_trie: Trie<String, Arc<TorrentLayout>>,
+}
+
+impl TorrentTable {
+    pub fn new(torrent_configs: &[TorrentConfig], version: Option<u64>) -> Self {
+        // 将 TorrentConfig 数组转换为 HashMap<Id20, TorrentConfig>
+        let mut torrents = HashMap::new();
+        for torrent_config in torrent_configs {
+            let torrent_info: Result<librqbit::TorrentMetaV1Borrowed> =
+                librqbit::torrent_from_bytes(&torrent_config.torrent);
+            if let Ok(torrent_info) = torrent_info {
+                torrents.insert(torrent_info.info_hash, torrent_config.clone());
+            } else {
+                warn!(
+                    "Failed to parse torrent {}: {}",
+                    torrent_config.path,
+                    torrent_info.unwrap_err()
+                );
+            }
+        }
+
+        // 记录torrent路径的发布时间
+        let mut release_trie = Trie::new();
+        let mut torrent_trie = Trie::new();
+        for (info_hash, torrent_config) in &torrents {
+            if let Some(release_at) = torrent_config.release_at {
+                release_trie.insert(torrent_config.url_prefix(), release_at);
+            }
+            torrent_trie.insert(torrent_config.url_prefix(), *info_hash);
+        }
+
+        let mut layout_trie = Trie::new();
+        let mut assist_trie = Trie::new();
+        for torrent_config in torrents.values() {
+            let Some(layout) = TorrentLayout::new(&torrent_config.torrent).map(Arc::new) else {
+                warn!(
+                    "Failed to read the file list of torrent {}, peer_assist and grow_wait_ms are off",
+                    torrent_config.path
+                );
+                continue;
+            };
+            if let Some(assist) = &torrent_config.peer_assist {
+                let assisted = AssistedTorrent::new(layout.clone(), assist);
+                assist_trie.insert(torrent_config.url_prefix(), Arc::new(assisted));
+            }
+            layout_trie.insert(torrent_config.url_prefix(), layout);
+        }
+
+        Self {
+            version,
+            torrents,
+            release_trie,
+            torrent_trie,
+            assist_trie,
+            layout_trie,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct OptimizedConfig {
     pub version: u64,
-    pub path_trie: Trie<String, PathConfig>,
-    pub torrents: HashMap<Id20, TorrentConfig>,
+    pub paths: Arc<PathTable>,
+    pub torrent_table: Arc<TorrentTable>,
+    pub management_token: Option<String>,
     pub prometheus_auth_header: Option<String>, // 预计算的认证头
+    pub mirror: Option<MirrorConfig>,
+    pub webhooks: Option<Vec<WebhookConfig>>,
+    pub disk_alert_percent: Option<f64>,
+    pub error_report: Option<ErrorReportConfig>,
+    pub overflow_redirect: Option<OverflowRedirectConfig>,
+    pub alternate_nodes: Option<Vec<String>>,
+    pub cluster: Option<ClusterConfig>,
+    // Built from `cluster` with the config
+    pub cluster_ring: Option<Arc<HashRing>>,
+    pub tls: Option<TlsConfig>,
+    pub max_disk_bytes: Option<u64>,
+    pub cache_max_bytes: Option<u64>,
+    pub max_transfers: Option<u32>,
+    pub max_transfers_per_ip: Option<u32>,
+    pub compression: Option<CompressionConfig>,
+    pub tombstone_secs: Option<u64>,
+    pub access_log: Option<AccessLogConfig>,
+    // Proxies whose forwarding header and PROXY protocol header name the client
+    pub trusted_proxies: Vec<IpRule>,
+    pub forwarded_header: ForwardedHeader,
+    // WebDAV URL prefix without a trailing slash
+    pub dav_prefix: String,
+    // Mismatches between path prefixes and torrent outputs, found when applied
+    pub issues: Vec<String>,
 }
 
 impl Default for OptimizedConfig {
     fn default() -> Self {
         Self {
             version: 0,
-            path_trie: Trie::new(),
-            torrents: HashMap::new(),
+            paths: Arc::new(PathTable::default()),
+            torrent_table: Arc::new(TorrentTable::default()),
+            management_token: None,
             prometheus_auth_header: None,
+            mirror: None,
+            webhooks: None,
+            disk_alert_percent: None,
+            error_report: None,
+            overflow_redirect: None,
+            alternate_nodes: None,
+            cluster: None,
+            cluster_ring: None,
+            tls: None,
+            max_disk_bytes: None,
+            cache_max_bytes: None,
+            max_transfers: None,
+            max_transfers_per_ip: None,
+            compression: None,
+            tombstone_secs: None,
+            access_log: None,
+            trusted_proxies: Vec::new(),
+            forwarded_header: ForwardedHeader::default(),
+            dav_prefix: DEFAULT_DAV_PREFIX.to_string(),
+            issues: Vec::new(),
         }
     }
 }
 
 impl OptimizedConfig {
-    pub fn from_config(config: Config) -> Self {
-        let mut path_trie = Trie::new();
+    /// Build from a (possibly partial) config. A missing `paths` keeps the
+    /// paths of `previous`. A missing `torrents` means no torrents, unless
+    /// `torrents_version` equals the current one: that keeps the current
+    /// torrents, which are then not re-parsed even when sent.
+    pub fn from_config(config: Config, previous: &OptimizedConfig) -> Self {
+        let paths = match &config.paths {
+            Some(paths) => Arc::new(PathTable::new(paths)),
+            None => previous.paths.clone(),
+        };
 
-        // 将路径配置插入前缀树
-        for (path, path_config) in &config.paths {
-            path_trie.insert(path.clone(), path_config.clone());
-        }
+        let torrents_unchanged = config.torrents_version.is_some()
+            && config.torrents_version == previous.torrent_table.version;
+        let torrent_table = match &config.torrents {
+            _ if torrents_unchanged => previous.torrent_table.clone(),
+            Some(torrent_configs) => {
+                Arc::new(TorrentTable::new(torrent_configs, config.torrents_version))
+            }
+            // Already none, nothing to resync
+            None if previous.torrents().is_empty() => previous.torrent_table.clone(),
+            None => Arc::new(TorrentTable::new(&[], config.torrents_version)),
+        };
 
-        // 将 TorrentConfig 数组转换为 HashMap<String, Vec<u8>>
-        let mut torrents = HashMap::new();
-        if let Some(torrent_configs) = &config.torrents {
-            for torrent_config in torrent_configs {
-                let torrent_info: Result<librqbit::TorrentMetaV1Borrowed> =
-                    librqbit::torrent_from_bytes(&torrent_config.torrent);
-                if let Ok(torrent_info) = torrent_info {
-                    torrents.insert(torrent_info.info_hash, torrent_config.clone());
-                } else {
-                    warn!(
-                        "Failed to parse torrent {}: {}",
-                        torrent_config.path,
-                        torrent_info.unwrap_err()
-                    );
+        let trusted_proxies = config
+            .trusted_proxies
+            .iter()
+            .flatten()
+            .filter_map(|rule| {
+                let parsed = IpRule::parse(rule);
+                if parsed.is_none() {
+                    warn!("Ignoring invalid trusted proxy {}", rule);
                 }
-            }
-        }
+                parsed
+            })
+            .collect();
 
         // 预计算 Prometheus 认证头
         let prometheus_auth_header = config
@@ -108,28 +386,221 @@ impl OptimizedConfig {
 
         Self {
             version: config.version.unwrap_or(0),
-            path_trie,
-            torrents,
+            paths,
+            torrent_table,
+            management_token: config.management_token,
             prometheus_auth_header,
+            mirror: config.mirror,
+            webhooks: config.webhooks,
+            disk_alert_percent: config.disk_alert_percent,
+            error_report: config.error_report,
+            overflow_redirect: config.overflow_redirect,
+            alternate_nodes: config.alternate_nodes,
+            cluster_ring: config
+                .cluster
+                .as_ref()
+                .map(|cluster| Arc::new(HashRing::new(cluster))),
+            cluster: config.cluster,
+            tls: config.tls,
+            max_disk_bytes: config.max_disk_bytes,
+            cache_max_bytes: config.cache_max_bytes,
+            max_transfers: config.max_transfers,
+            max_transfers_per_ip: config.max_transfers_per_ip,
+            compression: config.compression,
+            tombstone_secs: config.tombstone_secs,
+            access_log: config.access_log,
+            trusted_proxies,
+            forwarded_header: config.forwarded_header.unwrap_or_default(),
+            dav_prefix: config
+                .dav_prefix
+                .map(|prefix| format!("/{}", prefix.trim_matches('/')))
+                .unwrap_or_else(|| DEFAULT_DAV_PREFIX.to_string()),
+            issues: Vec::new(),
         }
     }
 
+    pub fn torrents(&self) -> &HashMap<Id20, TorrentConfig> {
+        &self.torrent_table.torrents
+    }
+
     pub fn find_path_config(&self, path: &str) -> Option<&PathConfig> {
         // 使用前缀树查找最长匹配的路径
-        self.path_trie.get_ancestor_value(path)
+        self.paths.path_trie.get(self.path_ancestor(path)?)
+    }
+
+    /// Configured path prefix that `path` falls under
+    pub fn find_path_prefix(&self, path: &str) -> Option<String> {
+        self.path_ancestor(path).map(str::to_string)
+    }
+
+    // Longest configured path covering `path` on segment boundaries. The trie
+    // matches plain string prefixes, so "/game" would also be found for
+    // "/gamex/a"; such a key is skipped for the next shorter one.
+    fn path_ancestor<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let mut probe = path;
+        loop {
+            let prefix_len = self.paths.path_trie.get_ancestor(probe)?.key()?.len();
+            let prefix = &path[..prefix_len];
+            if is_within(path, prefix) {
+                return Some(prefix);
+            }
+            let mut shorter = prefix.chars();
+            shorter.next_back()?;
+            probe = shorter.as_str();
+        }
+    }
+
+    /// Directory the files under `path` are kept in: the covering path's
+    /// `root`, or `data_dir`. Request paths are joined to it unchanged.
+    pub fn data_root<'a>(&'a self, path: &str, data_dir: &'a Path) -> &'a Path {
+        self.find_path_config(path)
+            .and_then(|path_config| path_config.root.as_deref())
+            .unwrap_or(data_dir)
+    }
+
+    /// Distinct `root`s of configured paths, besides the data directory
+    pub fn extra_roots(&self) -> Vec<PathBuf> {
+        let mut roots: Vec<PathBuf> = self
+            .paths
+            .path_trie
+            .values()
+            .filter_map(|path_config| path_config.root.clone())
+            .collect();
+        roots.sort();
+        roots.dedup();
+        roots
+    }
+
+    /// Info hash of the torrent whose output contains `path`
+    pub fn find_torrent(&self, path: &str) -> Option<Id20> {
+        self.torrent_table
+            .torrent_trie
+            .get_ancestor_value(path)
+            .copied()
+    }
+
+    /// Peer-assisted torrent covering `path`, and `path` below its URL prefix
+    pub fn find_assisted_torrent<'a>(
+        &self,
+        path: &'a str,
+    ) -> Option<(Arc<AssistedTorrent>, &'a str)> {
+        let subtrie = self.torrent_table.assist_trie.get_ancestor(path)?;
+        let prefix_len = subtrie.key()?.len();
+        Some((subtrie.value()?.clone(), &path[prefix_len..]))
+    }
+
+    /// Torrent covering `path` and the file of it at `path`, which is
+    /// percent-decoded to match the names in the .torrent
+    pub fn find_torrent_file(&self, path: &str) -> Option<(Arc<TorrentLayout>, TorrentFile)> {
+        let subtrie = self.torrent_table.layout_trie.get_ancestor(path)?;
+        let layout = subtrie.value()?;
+        let relative = decode_path(&path[subtrie.key()?.len()..])?;
+        Some((layout.clone(), layout.file(&relative)?))
+    }
+
+    /// Release time of the torrent covering `path`, if it hasn't been reached yet
+    pub fn pending_release(&self, path: &str, now: i64) -> Option<i64> {
+        let release_trie = &self.torrent_table.release_trie;
+        release_trie
+            .get_ancestor_value(path)
+            .or_else(|| release_trie.get_ancestor_value(&format!("{}/", path)))
+            .copied()
+            .filter(|release_at| now < *release_at)
     }
 
     pub fn get_version(&self) -> u64 {
         self.version
     }
+
+    /// The applied config as one complete `Config`, torrents carrying their
+    /// resolved .torrent content so no torrent_url has to be fetched again
+    pub fn snapshot(&self) -> Config {
+        let paths = self
+            .paths
+            .path_trie
+            .iter()
+            .map(|(prefix, path_config)| (prefix.clone(), path_config.clone()))
+            .collect();
+        let mut torrents: Vec<TorrentConfig> = self.torrents().values().cloned().collect();
+        torrents.sort_by(|a, b| a.path.cmp(&b.path));
+        Config {
+            version: Some(self.version),
+            paths: Some(paths),
+            torrents: Some(torrents),
+            torrents_version: self.torrent_table.version,
+            management_token: self.management_token.clone(),
+            mirror: self.mirror.clone(),
+            webhooks: self.webhooks.clone(),
+            disk_alert_percent: self.disk_alert_percent,
+            error_report: self.error_report.clone(),
+            overflow_redirect: self.overflow_redirect.clone(),
+            alternate_nodes: self.alternate_nodes.clone(),
+            cluster: self.cluster.clone(),
+            tls: self.tls.clone(),
+            dav_prefix: Some(self.dav_prefix.clone()),
+            max_disk_bytes: self.max_disk_bytes,
+            cache_max_bytes: self.cache_max_bytes,
+            max_transfers: self.max_transfers,
+            max_transfers_per_ip: self.max_transfers_per_ip,
+            compression: self.compression.clone(),
+            tombstone_secs: self.tombstone_secs,
+            access_log: self.access_log.clone(),
+            trusted_proxies: (!self.trusted_proxies.is_empty()).then(|| {
+                self.trusted_proxies
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            }),
+            forwarded_header: Some(self.forwarded_header),
+            next_page: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Config {
     pub version: Option<u64>,
-    pub paths: HashMap<String, PathConfig>,
+    // 省略 paths 时保留当前值；省略 torrents 表示没有 torrent，除非 torrents_version 与当前一致
+    pub paths: Option<HashMap<String, PathConfig>>,
     pub torrents: Option<Vec<TorrentConfig>>, // torrent配置数组
+    pub torrents_version: Option<u64>, // torrent列表版本，与当前一致时保留当前torrent并跳过解析
     pub management_token: Option<String>,
+    pub mirror: Option<MirrorConfig>, // 采样镜像请求到调试地址
+    pub webhooks: Option<Vec<WebhookConfig>>, // 事件通知 webhook
+    pub disk_alert_percent: Option<f64>, // 磁盘使用率超过该百分比时发送通知
+    pub error_report: Option<ErrorReportConfig>, // 错误上报（Sentry 或通用地址）
+    pub overflow_redirect: Option<OverflowRedirectConfig>, // 过载时重定向到其他节点
+    pub alternate_nodes: Option<Vec<String>>, // 其他节点地址，按优先级排列
+    pub cluster: Option<ClusterConfig>, // 集群成员，本地没有的内容按一致性哈希重定向到负责的节点
+    pub tls: Option<TlsConfig>,       // HTTPS 证书与私钥，命令行参数优先
+    pub dav_prefix: Option<String>,   // WebDAV 路径前缀，默认 /-/dav
+    pub max_disk_bytes: Option<u64>,  // 数据目录容量上限，超出时淘汰最久未访问的文件
+    pub cache_max_bytes: Option<u64>, // 回源文件与 thin torrent 的写穿缓存上限，写入后按最近访问淘汰
+    pub max_transfers: Option<u32>,   // 全局同时进行的文件传输数上限，超出返回503
+    pub max_transfers_per_ip: Option<u32>, // 每个客户端IP同时进行的文件传输数上限，超出返回429
+    pub compression: Option<CompressionConfig>, // 压缩策略(最小大小、类型、并发)，由路径 compress 开启
+    pub tombstone_secs: Option<u64>, // 从配置移除的torrent/路径内容保留的秒数，期间返回410，重新加入即恢复
+    pub access_log: Option<AccessLogConfig>, // JSON 访问日志文件与轮转，命令行 --access-log 优先，修改后需重启
+    pub trusted_proxies: Option<Vec<String>>, // 可信代理(IP或CIDR)，来自这些地址的请求按 forwarded_header 或 PROXY 协议头取客户端IP
+    pub forwarded_header: Option<ForwardedHeader>, // 可信代理设置的转发头(x_forwarded_for 或 forwarded)，默认 x_forwarded_for，只读取这一个
+    pub next_page: Option<String>,                 // 分页下发时下一页的游标
+}
+
+impl Config {
+    // Merge a follow-up page of a paginated central config into this one
+    fn merge_page(&mut self, page: Config) {
+        if let Some(paths) = page.paths {
+            self.paths.get_or_insert_with(HashMap::new).extend(paths);
+        }
+        if let Some(torrents) = page.torrents {
+            self.torrents.get_or_insert_with(Vec::new).extend(torrents);
+        }
+    }
+}
+
+async fn read_config_file(config_path: &Path) -> Result<Config> {
+    let content = tokio::fs::read_to_string(config_path).await?;
+    Ok(serde_yml::from_str(&content)?)
 }
 
 pub async fn load_config_from_file(
@@ -137,31 +608,289 @@ pub async fn load_config_from_file(
     config_path: &str,
     state: &AppState,
 ) -> Result<()> {
-    let content = tokio::fs::read_to_string(config_path).await?;
-    let config_data: Config = serde_yml::from_str(&content)?;
-    let optimized_config = OptimizedConfig::from_config(config_data.clone());
-    let new_torrents = optimized_config.torrents.clone();
+    let config_data = read_config_file(Path::new(config_path)).await?;
     let version = config_data.version.unwrap_or(0);
 
-    config.store(Arc::new(optimized_config));
+    apply_config(config, config_data, state).await?;
 
-    // 更新配置版本指标
-    CONFIG_VERSION.set(version);
+    info!(
+        "Loaded config from file: {} (version: {})",
+        config_path, version
+    );
+    Ok(())
+}
 
-    let state_cl = state.clone();
+/// Apply a snapshot taken with `/-/config/snapshot` and save it under the state
+/// directory, so the node can start from it while its config source is down
+pub async fn restore_config(state: &AppState, snapshot: Config) -> Result<()> {
+    let snapshot_path = state.state_dir.join(SNAPSHOT_FILE);
+    let tmp_path = snapshot_path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_vec(&snapshot)?).await?;
+    tokio::fs::rename(&tmp_path, &snapshot_path).await?;
+    let version = snapshot.version.unwrap_or(0);
+    apply_config(&state.config, snapshot, state).await?;
+    info!("Restored config snapshot (version: {})", version);
+    Ok(())
+}
+
+/// Apply the snapshot saved by the last restore, returning its version, or
+/// None if this node never had one restored
+pub async fn load_saved_snapshot(state: &AppState) -> Result<Option<u64>> {
+    let content = match tokio::fs::read(state.state_dir.join(SNAPSHOT_FILE)).await {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let snapshot: Config =
+        serde_json::from_slice(&content).context("Failed to parse saved config snapshot")?;
+    let version = snapshot.version.unwrap_or(0);
+    apply_config(&state.config, snapshot, state).await?;
+    Ok(Some(version))
+}
+
+/// Watch the --config file and load it again when it changes. As with the
+/// central server, the new file is only applied when its version differs.
+pub fn start_config_watcher(state: &AppState) -> Result<()> {
+    let Some(config_path) = &state.config_path else {
+        return Ok(());
+    };
+    let config_path = std::path::absolute(config_path)?;
+    // Editors, deploy tools and mounted ConfigMaps replace the file rather than
+    // write it in place, so watch the directory it is in
+    let dir = config_path
+        .parent()
+        .context("Config file has no parent directory")?
+        .to_path_buf();
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = sender.send(event);
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    info!("Watching {} for config changes", config_path.display());
+
+    let state = state.clone();
     tokio::spawn(async move {
-        if let Err(e) = sync_torrents(&state_cl.bt_api, &new_torrents, &state_cl.data_dir).await {
-            warn!("Failed to sync torrents: {}", e);
+        // Dropping the watcher stops it
+        let _watcher = watcher;
+        while let Some(event) = receiver.recv().await {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Access(_)) => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    log_limited!(warn, "config_watch", "Config file watcher error: {}", e);
+                    continue;
+                }
+            }
+            // One load for a burst of events, after the writer is done
+            tokio::time::sleep(Duration::from_millis(CONFIG_WATCH_DEBOUNCE_MS)).await;
+            while receiver.try_recv().is_ok() {}
+            if let Err(e) = reload_changed_config_file(&state, &config_path).await {
+                warn!(
+                    "Failed to reload config file {}: {:#}",
+                    config_path.display(),
+                    e
+                );
+            }
         }
     });
+    Ok(())
+}
 
+async fn reload_changed_config_file(state: &AppState, config_path: &Path) -> Result<()> {
+    let config_data = read_config_file(config_path).await?;
+    let new_version = config_data.version.unwrap_or(0);
+    let current_version = state.config.load().get_version();
+    if new_version == current_version {
+        debug!(
+            "Config file version unchanged ({}), skipping update",
+            current_version
+        );
+        return Ok(());
+    }
+    apply_config(&state.config, config_data, state).await?;
     info!(
-        "Loaded config from file: {} (version: {})",
-        config_path, version
+        "Reloaded config file {} (version: {} -> {})",
+        config_path.display(),
+        current_version,
+        new_version
     );
     Ok(())
 }
 
+// Swap in a new config and resync torrents if the torrent table changed. A
+// config is refused as a whole when one of its torrents can't be fetched, the
+// next load tries it again.
+async fn apply_config(
+    config: &Arc<ArcSwap<OptimizedConfig>>,
+    mut config_data: Config,
+    state: &AppState,
+) -> Result<()> {
+    for torrent in config_data.torrents.iter().flatten() {
+        if torrent.super_seed {
+            warn!(
+                "Torrent {} requests super_seed, which the BitTorrent engine does not support, seeding it normally",
+                torrent.path
+            );
+        }
+    }
+    // Any other status would tell clients and caches something other than "not yet"
+    if let Some((path, status)) = config_data.paths.iter().flatten().find_map(|(path, pc)| {
+        pc.embargo_status
+            .filter(|status| !(400..500).contains(status))
+            .map(|status| (path, status))
+    }) {
+        anyhow::bail!(
+            "path {} has embargo_status {}, which must be a 4xx status",
+            path,
+            status
+        );
+    }
+    let previous = config.load_full();
+    // Caught here rather than at the next certificate reload, which would
+    // only log it and keep the old files
+    if let Some(files) = config_data
+        .tls
+        .clone()
+        .filter(|files| previous.tls.as_ref() != Some(files))
+    {
+        tokio::task::spawn_blocking(move || check_files(&files))
+            .await?
+            .context("Invalid tls section")?;
+    }
+    let torrents_unchanged = config_data.torrents_version.is_some()
+        && config_data.torrents_version == previous.torrent_table.version;
+    if !torrents_unchanged {
+        if let Some(torrents) = config_data.torrents.as_mut() {
+            resolve_torrent_urls(torrents, &previous, state).await?;
+        }
+    }
+    let version = config_data.version.unwrap_or(0);
+    let mut optimized_config = OptimizedConfig::from_config(config_data, &previous);
+    optimized_config.issues = consistency_issues(&optimized_config, state).await;
+    for issue in &optimized_config.issues {
+        warn!("Config version {}: {}", version, issue);
+    }
+    CONFIG_ISSUES.set(optimized_config.issues.len() as i64);
+    let torrents_changed = !Arc::ptr_eq(&optimized_config.torrent_table, &previous.torrent_table);
+    update_path_tombstones(&previous, &optimized_config, state);
+    let new_torrents = optimized_config.torrents().clone();
+
+    config.store(Arc::new(optimized_config));
+
+    // 更新配置版本指标
+    CONFIG_VERSION.set(version);
+    emit(WebhookEvent::ConfigApplied { version });
+
+    // 新建一个job来同步torrents
+    if torrents_changed {
+        spawn_torrent_sync(state, new_torrents);
+    }
+    Ok(())
+}
+
+// Path prefixes dropped from the config, and not covered by a parent prefix, are
+// gone for tombstone_secs. Re-added ones are back. Their files are left alone,
+// only torrent outputs are deleted when a tombstone expires.
+fn update_path_tombstones(previous: &OptimizedConfig, config: &OptimizedConfig, state: &AppState) {
+    for (prefix, _) in config.paths.path_trie.iter() {
+        state.tombstones.revive(prefix);
+    }
+    let Some(grace_secs) = config.tombstone_secs else {
+        return;
+    };
+    for (prefix, _) in previous.paths.path_trie.iter() {
+        if config.find_path_config(prefix).is_none() {
+            state.tombstones.bury(prefix, grace_secs, None);
+        }
+    }
+}
+
+// Torrents that would never be served and path prefixes with nothing behind them,
+// which otherwise only show up as 404s in production
+async fn consistency_issues(config: &OptimizedConfig, state: &AppState) -> Vec<String> {
+    let mut issues = Vec::new();
+    let has_paths = !config.paths.path_trie.is_empty();
+    let torrent_prefixes: Vec<String> = config
+        .torrents()
+        .values()
+        .map(TorrentConfig::url_prefix)
+        .collect();
+
+    for prefix in &torrent_prefixes {
+        if prefix.trim_start_matches('/').split('/').next() == Some(STATE_DIR) {
+            issues.push(format!(
+                "torrent {} is inside the node state directory and is never served",
+                prefix
+            ));
+            continue;
+        }
+        match config.find_path_config(prefix) {
+            None if has_paths => issues.push(format!(
+                "torrent {} is not under any configured path prefix",
+                prefix
+            )),
+            Some(path_config) if path_config.cas_view.is_some() => issues.push(format!(
+                "torrent {} is under a cas_view prefix, its files are not served",
+                prefix
+            )),
+            _ => {}
+        }
+    }
+
+    for (prefix, path_config) in config.paths.path_trie.iter() {
+        let auth_error = path_config
+            .authorizer
+            .as_ref()
+            .and_then(|chain| chain.error());
+        if let Some(error) = auth_error {
+            issues.push(format!("{}, every request is refused", error));
+        }
+        if let Some(rollout) = &path_config.rollout {
+            let dir = rollout.root.join(prefix.trim_matches('/'));
+            if tokio::fs::metadata(&dir).await.is_err() {
+                issues.push(format!(
+                    "rollout of {} has no {}, its share of requests gets 404",
+                    prefix,
+                    dir.display()
+                ));
+            }
+        }
+        if path_config.cas_view.is_some() {
+            continue;
+        }
+        let covered_by_torrent = torrent_prefixes
+            .iter()
+            .any(|torrent| is_within(torrent, prefix) || is_within(prefix, torrent));
+        if covered_by_torrent {
+            continue;
+        }
+        let root = path_config.root.as_deref().unwrap_or(&state.data_dir);
+        let dir = root.join(prefix.trim_matches('/'));
+        if tokio::fs::metadata(&dir).await.is_err() {
+            issues.push(format!(
+                "path prefix {} has no torrent and {} does not exist",
+                prefix,
+                dir.display()
+            ));
+        }
+    }
+
+    if let Some(cluster) = &config.cluster {
+        match self_id(cluster, state) {
+            None => issues.push(
+                "cluster has no self_id and there is no central server id, nothing is redirected"
+                    .to_string(),
+            ),
+            Some(id) if !cluster.nodes.iter().any(|node| node.id == id) => issues.push(format!(
+                "cluster nodes don't include this node ({}), every missing file is redirected",
+                id
+            )),
+            _ => {}
+        }
+    }
+    issues
+}
+
 pub async fn load_config_from_central(
     config: &Arc<ArcSwap<OptimizedConfig>>,
     central_url: &str,
@@ -176,35 +905,25 @@ pub async fn load_config_from_central(
         format!("{}/config", central_url)
     };
 
-    let mut request = http_client.get(&config_url);
-
-    if let Some(auth) = auth_header {
-        request = request.header("Authorization", auth);
-    }
-
-    let response = request.send().await?;
-    let config_text = response.text().await?;
-    let config_data: Config = serde_yml::from_str(&config_text)?;
-
+    let mut config_data = fetch_config_page(http_client, &config_url, auth_header, None).await?;
     let new_version = config_data.version.unwrap_or(0);
     let current_version = config.load().get_version(); // 仅在版本号更新时才解析并替换配置
     if new_version != current_version {
-        let optimized_config = OptimizedConfig::from_config(config_data);
-        let new_torrents = optimized_config.torrents.clone();
-
-        config.store(Arc::new(optimized_config));
-
-        // 更新配置版本指标
-        CONFIG_VERSION.set(new_version);
-
-        // 新建一个task来同步torrents
-        let state_cl = state.clone();
-        tokio::spawn(async move {
-            if let Err(e) = sync_torrents(&state_cl.bt_api, &new_torrents, &state_cl.data_dir).await
-            {
-                warn!("Failed to sync torrents: {}", e);
+        // Only fetch the remaining pages once we know the version changed
+        let mut next_page = config_data.next_page.take();
+        let mut pages = 1;
+        while let Some(cursor) = next_page {
+            if pages >= MAX_CONFIG_PAGES {
+                anyhow::bail!("Central config has more than {} pages", MAX_CONFIG_PAGES);
             }
-        });
+            let mut page =
+                fetch_config_page(http_client, &config_url, auth_header, Some(&cursor)).await?;
+            next_page = page.next_page.take();
+            config_data.merge_page(page);
+            pages += 1;
+        }
+
+        apply_config(config, config_data, state).await?;
 
         info!(
             "Updated config from central server (version: {} -> {})",
@@ -220,6 +939,131 @@ pub async fn load_config_from_central(
     Ok(())
 }
 
+// Fill in `torrent` for entries that only reference a torrent_url. One that
+// can't be fetched keeps the content the applied config has for the URL; with
+// none to keep this fails, as an empty torrent would remove it and its data.
+async fn resolve_torrent_urls(
+    torrents: &mut [TorrentConfig],
+    previous: &OptimizedConfig,
+    state: &AppState,
+) -> Result<()> {
+    let cache_dir = state.state_dir.join("torrents");
+    for torrent in torrents.iter_mut() {
+        let Some(url) = torrent.torrent_url.as_deref() else {
+            continue;
+        };
+        if !torrent.torrent.is_empty() {
+            continue;
+        }
+        match fetch_torrent_file(url, &cache_dir, state).await {
+            Ok(bytes) => torrent.torrent = bytes,
+            Err(e) => {
+                let kept = previous
+                    .torrents()
+                    .values()
+                    .find(|applied| applied.torrent_url.as_deref() == Some(url))
+                    .map(|applied| applied.torrent.clone());
+                let Some(kept) = kept else {
+                    return Err(e.context(format!(
+                        "Failed to fetch torrent {} from {}",
+                        torrent.path, url
+                    )));
+                };
+                warn!(
+                    "Failed to fetch torrent {} from {}, keeping the applied one: {}",
+                    torrent.path, url, e
+                );
+                torrent.torrent = kept;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn fetch_torrent_file(
+    url: &str,
+    cache_dir: &std::path::Path,
+    state: &AppState,
+) -> Result<Bytes> {
+    use sha2::{Digest, Sha256};
+
+    // Torrent URLs are expected to be immutable, so the cache is keyed by URL
+    let cache_path = cache_dir.join(format!(
+        "{}.torrent",
+        hex::encode(Sha256::digest(url.as_bytes()))
+    ));
+    if let Ok(content) = tokio::fs::read(&cache_path).await {
+        return Ok(Bytes::from(content));
+    }
+
+    let mut request = state
+        .http_client
+        .get(url)
+        .timeout(Duration::from_secs(CONFIG_FETCH_TIMEOUT_SECS));
+    // Only hand the central credentials to the central server itself
+    if let (Some(auth), Some(central_url)) = (&state.auth_header, &state.central_url) {
+        let same_origin = match (reqwest::Url::parse(url), reqwest::Url::parse(central_url)) {
+            (Ok(url), Ok(central)) => url.origin() == central.origin(),
+            _ => false,
+        };
+        if same_origin {
+            request = request.header("Authorization", auth);
+        }
+    }
+    let content = request.send().await?.error_for_status()?.bytes().await?;
+
+    tokio::fs::create_dir_all(cache_dir).await?;
+    let tmp_path = cache_path.with_extension("tmp");
+    tokio::fs::write(&tmp_path, &content).await?;
+    tokio::fs::rename(&tmp_path, &cache_path).await?;
+    Ok(content)
+}
+
+// Fetch one page of the central config. Responses may be gzip/zstd encoded,
+// reqwest negotiates and decodes them transparently.
+async fn fetch_config_page(
+    http_client: &reqwest::Client,
+    config_url: &str,
+    auth_header: Option<&str>,
+    page: Option<&str>,
+) -> Result<Config> {
+    let mut request = http_client
+        .get(config_url)
+        .timeout(Duration::from_secs(CONFIG_FETCH_TIMEOUT_SECS));
+
+    if let Some(auth) = auth_header {
+        request = request.header("Authorization", auth);
+    }
+    if let Some(page) = page {
+        request = request.query(&[("page", page)]);
+    }
+
+    let response = request.send().await?.error_for_status()?;
+    let config_text = response.text().await?;
+    let config_data: Config = serde_yml::from_str(&config_text)?;
+    Ok(config_data)
+}
+
+/// Load the config again from the source given at startup, --config or the
+/// central server
+pub async fn reload_config(state: &AppState) -> Result<()> {
+    match (&state.config_path, &state.central_url) {
+        (Some(config_path), _) => load_config_from_file(&state.config, config_path, state).await,
+        (None, Some(central_url)) => {
+            load_config_from_central(
+                &state.config,
+                central_url,
+                state.server_id.as_deref(),
+                state.auth_header.as_deref(),
+                &state.http_client,
+                state,
+            )
+            .await
+        }
+        (None, None) => anyhow::bail!("No config source"),
+    }
+}
+
 pub async fn config_refresh_task(
     config: Arc<ArcSwap<OptimizedConfig>>,
     central_url: String,
@@ -250,16 +1094,261 @@ pub async fn config_refresh_task(
     }
 }
 
+#[derive(Debug, Serialize)]
+pub struct TorrentStatus {
+    info_hash: String,
+    path: String,
+    state: String,
+    finished: bool,
+    progress_bytes: u64,
+    total_bytes: u64,
+    // Downloaded from peers since the torrent was added
+    downloaded_bytes: u64,
+    completion_percent: f64,
+    live_peers: u64,
+    download_bytes_per_sec: f64,
+    upload_bytes_per_sec: f64,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeStatus {
+    server_id: Option<String>,
+    config_version: u64,
+    timestamp: i64,
+    pub torrents: Vec<TorrentStatus>,
+    // Lifetime totals over every torrent this node has had
+    uploaded_bytes_total: u64,
+    downloaded_bytes_total: u64,
+}
+
+// Per-torrent progress of the configured torrents
+pub fn node_status(state: &AppState) -> NodeStatus {
+    let config = state.config.load();
+    let torrents = config.torrents();
+    let torrents = torrent_snapshots(&state.bt_api)
+        .into_iter()
+        .filter_map(|torrent| {
+            let info_hash = Id20::from_str(&torrent.info_hash).ok()?;
+            let path = torrents.get(&info_hash)?.path.clone();
+            let completion_percent = if torrent.total_bytes > 0 {
+                torrent.progress_bytes as f64 * 100.0 / torrent.total_bytes as f64
+            } else {
+                0.0
+            };
+            Some(TorrentStatus {
+                path,
+                completion_percent,
+                downloaded_bytes: torrent.fetched_bytes,
+                live_peers: torrent.live_peers,
+                info_hash: torrent.info_hash,
+                state: torrent.state,
+                finished: torrent.finished,
+                progress_bytes: torrent.progress_bytes,
+                total_bytes: torrent.total_bytes,
+                download_bytes_per_sec: torrent.download_bytes_per_sec,
+                upload_bytes_per_sec: torrent.upload_bytes_per_sec,
+                error: torrent.error,
+            })
+        })
+        .collect();
+    let totals = state.bt_stats.totals();
+    NodeStatus {
+        server_id: state.server_id.clone(),
+        config_version: config.get_version(),
+        timestamp: chrono::Utc::now().timestamp(),
+        torrents,
+        uploaded_bytes_total: totals.uploaded_bytes,
+        downloaded_bytes_total: totals.downloaded_bytes,
+    }
+}
+
+// Tell the central server which content this node actually has, so users are
+// only routed to nodes that finished downloading it
+pub async fn status_report_task(state: AppState) {
+    let Some(central_url) = state.central_url.clone() else {
+        return;
+    };
+    let status_url = match &state.server_id {
+        Some(id) => format!("{}/{}/status", central_url, id),
+        None => format!("{}/status", central_url),
+    };
+    let mut interval = interval(Duration::from_secs(STATUS_REPORT_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let mut request = state
+            .http_client
+            .post(&status_url)
+            .timeout(Duration::from_secs(STATUS_REPORT_TIMEOUT_SECS))
+            .json(&node_status(&state));
+        if let Some(auth) = state.auth_header.as_deref() {
+            request = request.header("Authorization", auth);
+        }
+        let sent = match request.send().await {
+            Ok(response) => response.error_for_status().map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = sent {
+            log_limited!(warn, "status_report", "Failed to report status: {}", e);
+        }
+    }
+}
+
+// Add torrents whose start_after time has passed since the last sync
+pub async fn torrent_schedule_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        let config = state.config.load_full();
+        let now = chrono::Utc::now().timestamp();
+        if has_due_missing_torrent(&state, config.torrents(), now) {
+            spawn_torrent_sync(&state, config.torrents().clone());
+        }
+    }
+}
+
+fn has_due_missing_torrent(
+    state: &AppState,
+    torrents: &HashMap<Id20, TorrentConfig>,
+    now: i64,
+) -> bool {
+    let existing = state.bt_api.api_torrent_list();
+    torrents.iter().any(|(info_hash, torrent)| {
+        torrent.start_after.is_some()
+            && !torrent.is_deferred(now)
+            && !existing
+                .torrents
+                .iter()
+                .any(|t| t.info_hash == info_hash.as_string())
+    })
+}
+
+// Run sync_torrents as a tracked job so progress shows up in /-/jobs
+pub fn spawn_torrent_sync(state: &AppState, torrents: HashMap<Id20, TorrentConfig>) -> u64 {
+    let state_cl = state.clone();
+    state.jobs.spawn("torrent_sync", move |job| async move {
+        let result = sync_torrents(&state_cl, &torrents, &job).await;
+        spawn_hash_check(&state_cl);
+        result
+    })
+}
+
+/// Forget a configured torrent and add it back so its files on disk are hash
+/// checked again, as a tracked job. None if `info_hash` isn't configured.
+pub fn spawn_torrent_verify(state: &AppState, info_hash: Id20) -> Option<u64> {
+    let torrents = state.config.load().torrents().clone();
+    if !torrents.contains_key(&info_hash) {
+        return None;
+    }
+    let state_cl = state.clone();
+    let job_id = state.jobs.spawn("torrent_verify", move |job| async move {
+        let added = state_cl
+            .bt_api
+            .api_torrent_list()
+            .torrents
+            .iter()
+            .any(|t| t.info_hash == info_hash.as_string());
+        if added {
+            job.set_message(format!("Forgetting {}", torrents[&info_hash].path));
+            // Files stay on disk, adding the torrent again checks them
+            state_cl
+                .bt_api
+                .api_torrent_action_forget(librqbit::api::TorrentIdOrHash::Hash(info_hash))
+                .await?;
+        }
+        let result = sync_torrents(&state_cl, &torrents, &job).await;
+        spawn_hash_check(&state_cl);
+        result
+    });
+    Some(job_id)
+}
+
+// Added torrents hash check in the background, at most --bt-hash-check-concurrency
+// at a time. Track the queue as a job until every torrent is through it.
+fn spawn_hash_check(state: &AppState) {
+    let checking = torrent_snapshots(&state.bt_api)
+        .iter()
+        .any(|torrent| torrent.state == HASH_CHECK_STATE);
+    if !checking || HASH_CHECK_RUNNING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let state_cl = state.clone();
+    state.jobs.spawn("hash_check", move |job| async move {
+        let result = watch_hash_checks(&state_cl, &job).await;
+        HASH_CHECK_RUNNING.store(false, Ordering::Release);
+        result
+    });
+}
+
+async fn watch_hash_checks(state: &AppState, job: &JobHandle) -> Result<()> {
+    let mut interval = interval(Duration::from_secs(HASH_CHECK_POLL_SECS));
+    // Total bytes of every torrent seen checking, keyed by info hash
+    let mut seen: HashMap<String, u64> = HashMap::new();
+    loop {
+        interval.tick().await;
+        let snapshots = torrent_snapshots(&state.bt_api);
+        let checking: Vec<_> = snapshots
+            .iter()
+            .filter(|torrent| torrent.state == HASH_CHECK_STATE)
+            .collect();
+        for torrent in &checking {
+            seen.entry(torrent.info_hash.clone())
+                .or_insert(torrent.total_bytes);
+        }
+
+        let total: u64 = seen.values().sum();
+        let remaining: u64 = checking
+            .iter()
+            .map(|torrent| torrent.total_bytes.saturating_sub(torrent.progress_bytes))
+            .sum();
+        job.set_progress(total.saturating_sub(remaining), total);
+        if checking.is_empty() {
+            job.set_message(format!("Hash checked {} torrents", seen.len()));
+            return Ok(());
+        }
+        job.set_message(format!(
+            "{} of {} torrents queued or hash checking",
+            checking.len(),
+            seen.len()
+        ));
+    }
+}
+
 // Torrent管理功能
 pub async fn sync_torrents(
-    bt_api: &librqbit::Api,
+    state: &AppState,
     torrents: &HashMap<Id20, TorrentConfig>,
-    data_dir: &std::path::Path,
+    job: &JobHandle,
 ) -> Result<()> {
+    let bt_api = &state.bt_api;
+    let data_dir = state.data_dir.as_path();
     let data_dir_abs =
         std::path::absolute(data_dir).context("Failed to get absolute data directory path")?;
+    let config = state.config.load_full();
+    // Torrent outputs go below the root of their path, removed ones are looked up
+    // in every root, the data directory last as roots may be inside it
+    let mut roots_abs = Vec::new();
+    for root in config.extra_roots() {
+        roots_abs.push(std::path::absolute(&root).context("Failed to get absolute root path")?);
+    }
+    roots_abs.push(data_dir_abs);
     let pre_torrents = bt_api.api_torrent_list();
-    for (info_hash, torrent) in torrents {
+    let total = torrents.len() as u64;
+    let now = chrono::Utc::now().timestamp();
+    for (done, (info_hash, torrent)) in torrents.iter().enumerate() {
+        job.set_progress(done as u64, total);
+        job.set_message(format!("Syncing {}", torrent.path));
+        // Configured again, content kept under a tombstone is served as before
+        state.tombstones.revive(&torrent.path);
+        if torrent.is_deferred(now) {
+            info!(
+                "Torrent {} is scheduled to start at {:?}, skipping",
+                torrent.path, torrent.start_after
+            );
+            continue;
+        }
         // 检查是否已存在相同info_hash的torrent
         if pre_torrents
             .torrents
@@ -274,7 +1363,8 @@ pub async fn sync_torrents(
         } else {
             torrent.path.to_string()
         };
-        let torrent_path = data_dir_abs.join(path_with_dot);
+        let root = config.data_root(&torrent.url_prefix(), data_dir);
+        let torrent_path = std::path::absolute(root)?.join(path_with_dot);
         let torrent_path_str = std::path::absolute(torrent_path)?
             .to_string_lossy()
             .to_string();
@@ -287,13 +1377,21 @@ pub async fn sync_torrents(
                     sub_folder: None,
                     overwrite: true,
                     initial_peers: Some(torrent.initial_peers.clone()),
+                    // Thin nodes only fetch the pieces clients ask for
+                    only_files: torrent
+                        .peer_assist
+                        .as_ref()
+                        .filter(|assist| assist.thin)
+                        .map(|_| Vec::new()),
                     ..Default::default()
                 }),
             )
             .await;
         if let Err(e) = res {
             warn!("Failed to add torrent {}: {}", torrent.path, e);
+            crate::report::torrent_failure(&torrent.path, &e.to_string());
         } else {
+            crate::report::torrent_added(&torrent.path);
             info!(
                 "Added torrent {}: {:?}",
                 torrent.path,
@@ -301,16 +1399,38 @@ pub async fn sync_torrents(
             );
         }
     }
+    job.set_progress(total, total);
+    job.set_message("Removing stale torrents");
     // 删除不存在的torrent
+    let tombstone_secs = config.tombstone_secs;
     for pre_torrent in &pre_torrents.torrents {
         let id20 = Id20::from_str(&pre_torrent.info_hash);
         if let Ok(id20) = id20 {
             if !torrents.contains_key(&id20) {
                 info!("Removing torrent {}", pre_torrent.info_hash);
-                if let Err(e) = bt_api
-                    .api_torrent_action_delete(librqbit::api::TorrentIdOrHash::Hash(id20))
-                    .await
+                // With a grace period the files stay until the tombstone expires
+                let buried = tombstone_secs.and_then(|_| {
+                    let output = serde_json::to_value(pre_torrent).ok()?["output_folder"]
+                        .as_str()
+                        .map(std::path::PathBuf::from)?;
+                    roots_abs.iter().find_map(|root| {
+                        let relative = output.strip_prefix(root).ok()?;
+                        Some((relative.to_string_lossy().replace('\\', "/"), root))
+                    })
+                });
+                let id = librqbit::api::TorrentIdOrHash::Hash(id20);
+                let removed = match &buried {
+                    Some(_) => bt_api.api_torrent_action_forget(id).await,
+                    None => bt_api.api_torrent_action_delete(id).await,
+                };
+                if let (Ok(_), Some((prefix, root)), Some(grace_secs)) =
+                    (&removed, &buried, tombstone_secs)
                 {
+                    state
+                        .tombstones
+                        .bury(prefix, grace_secs, Some(root.as_path()));
+                }
+                if let Err(e) = removed {
                     warn!("Failed to remove torrent {}: {}", pre_torrent.info_hash, e);
                 } else {
                     info!("Removed torrent {}", pre_torrent.info_hash);
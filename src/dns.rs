@@ -0,0 +1,114 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::bail;
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::{Resolver, TokioResolver};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+// Which address families outbound connections use, in order of preference
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum IpPreference {
+    #[default]
+    Auto,
+    Ipv4,
+    Ipv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl IpPreference {
+    fn strategy(self) -> LookupIpStrategy {
+        match self {
+            IpPreference::Auto => LookupIpStrategy::Ipv4AndIpv6,
+            IpPreference::Ipv4 => LookupIpStrategy::Ipv4thenIpv6,
+            IpPreference::Ipv6 => LookupIpStrategy::Ipv6thenIpv4,
+            IpPreference::Ipv4Only => LookupIpStrategy::Ipv4Only,
+            IpPreference::Ipv6Only => LookupIpStrategy::Ipv6Only,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DnsOptions {
+    // Plain DNS servers, system resolv.conf is used when empty
+    pub servers: Vec<SocketAddr>,
+    // DNS-over-HTTPS provider: cloudflare, google or quad9
+    pub doh: Option<String>,
+    pub ip_preference: IpPreference,
+}
+
+impl DnsOptions {
+    fn is_default(&self) -> bool {
+        self.servers.is_empty() && self.doh.is_none() && self.ip_preference == IpPreference::Auto
+    }
+}
+
+/// Parse `ip` or `ip:port` (port defaults to 53), for `--dns-server`
+pub fn parse_dns_server(value: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    value
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .map_err(|_| format!("invalid DNS server address: {}", value))
+}
+
+/// reqwest resolver backed by hickory, so broken host resolver setups can be bypassed
+pub struct DnsResolver {
+    resolver: Arc<TokioResolver>,
+}
+
+impl DnsResolver {
+    /// Build a resolver for `options`, `None` when the system resolver should be kept
+    pub fn from_options(options: &DnsOptions) -> anyhow::Result<Option<Self>> {
+        if options.is_default() {
+            return Ok(None);
+        }
+
+        let mut builder = if let Some(doh) = &options.doh {
+            if !options.servers.is_empty() {
+                bail!("DNS servers and DNS-over-HTTPS cannot be combined");
+            }
+            let config = match doh.as_str() {
+                "cloudflare" => ResolverConfig::cloudflare_https(),
+                "google" => ResolverConfig::google_https(),
+                "quad9" => ResolverConfig::quad9_https(),
+                other => bail!("Unknown DNS-over-HTTPS provider: {}", other),
+            };
+            Resolver::builder_with_config(config, TokioConnectionProvider::default())
+        } else if !options.servers.is_empty() {
+            let mut group = NameServerConfigGroup::new();
+            for server in &options.servers {
+                group.merge(NameServerConfigGroup::from_ips_clear(
+                    &[server.ip()],
+                    server.port(),
+                    true,
+                ));
+            }
+            let config = ResolverConfig::from_parts(None, vec![], group);
+            Resolver::builder_with_config(config, TokioConnectionProvider::default())
+        } else {
+            Resolver::builder_tokio()?
+        };
+        builder.options_mut().ip_strategy = options.ip_preference.strategy();
+
+        Ok(Some(Self {
+            resolver: Arc::new(builder.build()),
+        }))
+    }
+}
+
+impl Resolve for DnsResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.resolver.clone();
+        Box::pin(async move {
+            let lookup = resolver.lookup_ip(name.as_str()).await?;
+            let addrs: Vec<IpAddr> = lookup.iter().collect();
+            let addrs: Addrs = Box::new(addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
@@ -0,0 +1,159 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+use hyper::http::{HeaderMap, HeaderValue};
+use moka::future::Cache;
+use tokio::time::Duration;
+
+use crate::checksums::ChecksumDb;
+
+const ETAG_CACHE_CAPACITY: u64 = 100_000;
+const ETAG_CACHE_IDLE_SECS: u64 = 3600;
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+#[derive(Clone)]
+struct CachedValidator {
+    size: u64,
+    modified: SystemTime,
+    etag: HeaderValue,
+}
+
+/// Strong validators of a file version
+#[derive(Clone)]
+pub struct Validators {
+    pub etag: HeaderValue,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+impl Validators {
+    pub fn last_modified_header(&self) -> Option<HeaderValue> {
+        let formatted = self.last_modified?.format(HTTP_DATE_FORMAT).to_string();
+        HeaderValue::from_str(&formatted).ok()
+    }
+}
+
+/// Strong ETags per file version. Files whose sha256 is already known (torrent
+/// output that was synced, CAS objects...) get the content hash, others an
+/// xxh3 of size, mtime and inode, which changes with every rewrite.
+pub struct EtagCache {
+    cache: Cache<PathBuf, CachedValidator>,
+}
+
+impl EtagCache {
+    pub fn new() -> Self {
+        Self {
+            cache: Cache::builder()
+                .max_capacity(ETAG_CACHE_CAPACITY)
+                .time_to_idle(Duration::from_secs(ETAG_CACHE_IDLE_SECS))
+                .build(),
+        }
+    }
+
+    pub async fn validators(
+        &self,
+        checksums: &ChecksumDb,
+        path: &Path,
+        metadata: &std::fs::Metadata,
+    ) -> Validators {
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let last_modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+        let cached = self
+            .cache
+            .get(path)
+            .await
+            .filter(|cached| cached.size == metadata.len() && cached.modified == modified);
+        if let Some(cached) = cached {
+            return Validators {
+                etag: cached.etag,
+                last_modified,
+            };
+        }
+
+        // Only already known hashes, never hash a file on the request path
+        let etag = match checksums.lookup(path, metadata).await {
+            Some(hashes) => format!("\"{}\"", hashes.sha256),
+            None => format!("\"{:016x}\"", version_hash(metadata, modified)),
+        };
+        let etag = HeaderValue::from_str(&etag).expect("hex ETag is a valid header value");
+        self.cache
+            .insert(
+                path.to_path_buf(),
+                CachedValidator {
+                    size: metadata.len(),
+                    modified,
+                    etag: etag.clone(),
+                },
+            )
+            .await;
+        Validators {
+            etag,
+            last_modified,
+        }
+    }
+}
+
+impl Default for EtagCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn version_hash(metadata: &std::fs::Metadata, modified: SystemTime) -> u64 {
+    let mtime = modified
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |mtime| mtime.as_nanos() as u64);
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+    #[cfg(not(unix))]
+    let inode = 0u64;
+    let mut input = [0u8; 24];
+    input[..8].copy_from_slice(&metadata.len().to_le_bytes());
+    input[8..16].copy_from_slice(&mtime.to_le_bytes());
+    input[16..].copy_from_slice(&inode.to_le_bytes());
+    xxhash_rust::xxh3::xxh3_64(&input)
+}
+
+// Entity tags listed in If-None-Match / If-Range, compared weakly: W/ is ignored
+fn etag_matches(list: &str, etag: &HeaderValue) -> bool {
+    let etag = etag.to_str().unwrap_or("");
+    list.split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|h| h.to_str().ok())
+}
+
+/// Evaluate If-None-Match (or, without it, If-Modified-Since) against our own
+/// validators, true when the client's copy is current
+pub fn is_not_modified(headers: &HeaderMap, validators: &Validators) -> bool {
+    match header_str(headers, "if-none-match") {
+        Some(list) => etag_matches(list, &validators.etag),
+        None => header_str(headers, "if-modified-since")
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .zip(validators.last_modified)
+            // HTTP dates have second precision
+            .is_some_and(|(since, modified)| modified.timestamp() <= since.timestamp()),
+    }
+}
+
+/// Strip the conditionals already evaluated against our validators, so
+/// hyper_staticfile, which has its own weak ETags, doesn't evaluate them
+/// differently. An If-Range entity tag that is no longer current drops the
+/// Range, unless the range is `signed` and the only part allowed.
+pub fn strip_conditionals(headers: &mut HeaderMap, validators: &Validators, signed: bool) {
+    // If-Range dates are still left to hyper_staticfile
+    let if_range_etag = header_str(headers, "if-range")
+        .filter(|value| value.starts_with('"') || value.starts_with("W/"))
+        .map(|value| !value.starts_with("W/") && etag_matches(value, &validators.etag));
+    if let Some(current) = if_range_etag {
+        headers.remove("if-range");
+        if !current && !signed {
+            headers.remove("range");
+        }
+    }
+    headers.remove("if-none-match");
+    headers.remove("if-modified-since");
+}
@@ -0,0 +1,319 @@
+// Cluster nodes tell each other which torrents they have finished. The
+// central server's view lags by a status report interval, gossip lets the
+// cluster redirect and overflow redirect send clients to a node that already
+// holds the content within seconds of it finishing.
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use hyper::body::Bytes;
+use hyper::http::StatusCode;
+use hyper::{Method, Request, Response};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::time::{Duration, interval};
+
+use crate::app::AppState;
+use crate::cluster::self_id;
+use crate::handlers::read_body_limited;
+use crate::logging::log_limited;
+use crate::metrics::{GOSSIP_EXCHANGES_TOTAL, GOSSIP_PEERS};
+use crate::response::ResBody;
+use crate::torrents::torrent_snapshots;
+
+const GOSSIP_INTERVAL_SECS: u64 = 10;
+const GOSSIP_TIMEOUT_SECS: u64 = 5;
+// Peers contacted per round, announcements spread to the rest through them
+const GOSSIP_FANOUT: usize = 3;
+// A node that hasn't announced for this long is assumed gone
+const ANNOUNCEMENT_TTL_SECS: i64 = 45;
+const MAX_GOSSIP_BODY_BYTES: u64 = 4 * 1024 * 1024;
+// Signed messages older or newer than this are refused, bounding replays
+const MAX_GOSSIP_SKEW_SECS: i64 = 60;
+/// `{unix time:08x}{hex hmac}` of the body, on requests and replies alike
+pub const GOSSIP_SIGNATURE_HEADER: &str = "X-Dfs-Gossip-Signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What one node has finished, as of `sent_at`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Announcement {
+    pub id: String,
+    // Info hashes of finished torrents
+    pub completed: Vec<String>,
+    // Unix time on the announcing node, the newest announcement of a node wins
+    pub sent_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct GossipMessage {
+    pub announcements: Vec<Announcement>,
+}
+
+/// Latest announcement heard from each other cluster node
+#[derive(Debug, Default)]
+pub struct GossipTable {
+    announcements: Mutex<HashMap<String, Announcement>>,
+}
+
+impl GossipTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep the newer of each node's announcements, ignoring this node's own
+    /// (relayed back by peers) and ones already expired
+    pub fn merge(&self, received: Vec<Announcement>, self_id: &str) {
+        let cutoff = chrono::Utc::now().timestamp() - ANNOUNCEMENT_TTL_SECS;
+        let mut announcements = self.announcements.lock().unwrap();
+        for announcement in received {
+            if announcement.id == self_id || announcement.sent_at < cutoff {
+                continue;
+            }
+            let newer = announcements
+                .get(&announcement.id)
+                .is_none_or(|known| known.sent_at < announcement.sent_at);
+            if newer {
+                announcements.insert(announcement.id.clone(), announcement);
+            }
+        }
+    }
+
+    /// Announcements that haven't expired, dropping the rest
+    pub fn fresh(&self) -> Vec<Announcement> {
+        let cutoff = chrono::Utc::now().timestamp() - ANNOUNCEMENT_TTL_SECS;
+        let mut announcements = self.announcements.lock().unwrap();
+        announcements.retain(|_, announcement| announcement.sent_at >= cutoff);
+        GOSSIP_PEERS.set(announcements.len() as i64);
+        announcements.values().cloned().collect()
+    }
+
+    /// Ids of the nodes that announced `info_hash` as finished
+    pub fn holders(&self, info_hash: &str) -> HashSet<String> {
+        let cutoff = chrono::Utc::now().timestamp() - ANNOUNCEMENT_TTL_SECS;
+        self.announcements
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|announcement| announcement.sent_at >= cutoff)
+            .filter(|announcement| announcement.completed.iter().any(|hash| hash == info_hash))
+            .map(|announcement| announcement.id.clone())
+            .collect()
+    }
+}
+
+/// Cluster nodes other than this one known to hold the torrent covering
+/// `path`. Empty when the path isn't part of a torrent, or nobody has said.
+pub fn holders_of(state: &AppState, path: &str) -> HashSet<String> {
+    let config = state.config.load();
+    match config.find_torrent(path) {
+        Some(info_hash) => state.gossip.holders(&info_hash.as_string()),
+        None => HashSet::new(),
+    }
+}
+
+// This node's announcement, from the torrents librqbit reports finished
+fn own_announcement(state: &AppState, id: &str) -> Announcement {
+    let completed = torrent_snapshots(&state.bt_api)
+        .into_iter()
+        .filter(|torrent| torrent.finished)
+        .map(|torrent| torrent.info_hash)
+        .collect();
+    Announcement {
+        id: id.to_string(),
+        completed,
+        sent_at: chrono::Utc::now().timestamp(),
+    }
+}
+
+fn gossip_mac(secret: &str, signed_at: u32, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("gossip\n{:08x}\n", signed_at).as_bytes());
+    mac.update(body);
+    mac
+}
+
+/// Signature of a gossip body for GOSSIP_SIGNATURE_HEADER, made at `signed_at`
+pub fn gossip_signature(secret: &str, signed_at: u32, body: &[u8]) -> String {
+    format!(
+        "{:08x}{}",
+        signed_at,
+        hex::encode(gossip_mac(secret, signed_at, body).finalize().into_bytes())
+    )
+}
+
+// Whether `signature` is a recent signature of `body` under `secret`
+fn verify_gossip_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> bool {
+    let Some(signature) = signature else {
+        return false;
+    };
+    if signature.len() != 72 || !signature.is_ascii() {
+        return false;
+    }
+    let Ok(signed_at) = u32::from_str_radix(&signature[..8], 16) else {
+        return false;
+    };
+    if (chrono::Utc::now().timestamp() - signed_at as i64).abs() > MAX_GOSSIP_SKEW_SECS {
+        return false;
+    }
+    let Ok(expected) = hex::decode(&signature[8..]) else {
+        return false;
+    };
+    gossip_mac(secret, signed_at, body)
+        .verify_slice(&expected)
+        .is_ok()
+}
+
+fn sign_now(secret: &str, body: &[u8]) -> String {
+    gossip_signature(secret, chrono::Utc::now().timestamp() as u32, body)
+}
+
+// Own announcement followed by everything still fresh heard from others
+fn outgoing_message(state: &AppState, id: &str) -> GossipMessage {
+    let mut announcements = vec![own_announcement(state, id)];
+    announcements.extend(state.gossip.fresh());
+    GossipMessage { announcements }
+}
+
+/// Every GOSSIP_INTERVAL_SECS exchange announcements with a few random
+/// cluster members, merging what they know in return. Membership is the
+/// `cluster` section of the config, as sent by the central server.
+pub async fn gossip_task(state: AppState) {
+    let mut ticker = interval(Duration::from_secs(GOSSIP_INTERVAL_SECS));
+    loop {
+        ticker.tick().await;
+        let (id, mut peers, secret) = {
+            let config = state.config.load();
+            let Some(cluster) = config.cluster.as_ref() else {
+                continue;
+            };
+            let Some(secret) = cluster.gossip_secret.clone() else {
+                continue;
+            };
+            let Some(id) = self_id(cluster, &state) else {
+                continue;
+            };
+            let peers: Vec<String> = cluster
+                .nodes
+                .iter()
+                .filter(|node| node.id != id)
+                .map(|node| node.url.trim_end_matches('/').to_string())
+                .collect();
+            (id.to_string(), peers, secret)
+        };
+        if peers.is_empty() {
+            continue;
+        }
+        peers.shuffle(&mut rand::rng());
+        peers.truncate(GOSSIP_FANOUT);
+
+        let body = match serde_json::to_vec(&outgoing_message(&state, &id)) {
+            Ok(body) => Bytes::from(body),
+            Err(e) => {
+                tracing::warn!("Failed to encode gossip message: {}", e);
+                continue;
+            }
+        };
+        let exchanges = peers
+            .iter()
+            .map(|url| exchange(&state, url, body.clone(), &secret));
+        for (url, result) in peers.iter().zip(join_all(exchanges).await) {
+            match result {
+                Ok(reply) => {
+                    GOSSIP_EXCHANGES_TOTAL.with_label_values(&["ok"]).inc();
+                    state.gossip.merge(reply.announcements, &id);
+                }
+                Err(e) => {
+                    GOSSIP_EXCHANGES_TOTAL.with_label_values(&["error"]).inc();
+                    log_limited!(warn, "gossip", "Gossip with {} failed: {:#}", url, e);
+                }
+            }
+        }
+    }
+}
+
+async fn exchange(
+    state: &AppState,
+    url: &str,
+    body: Bytes,
+    secret: &str,
+) -> anyhow::Result<GossipMessage> {
+    let reply = state
+        .http_client
+        .post(format!("{}/-/gossip", url))
+        .header("Content-Type", "application/json")
+        .header(GOSSIP_SIGNATURE_HEADER, sign_now(secret, &body))
+        .timeout(Duration::from_secs(GOSSIP_TIMEOUT_SECS))
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    let signature = reply
+        .headers()
+        .get(GOSSIP_SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = reply.bytes().await?;
+    if !verify_gossip_signature(secret, signature.as_deref(), &body) {
+        anyhow::bail!("reply signature invalid");
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// POST /-/gossip: merge a peer's announcements and answer with this node's.
+/// Both directions are signed with the cluster's `gossip_secret`.
+pub async fn handle_gossip_request(
+    state: &AppState,
+    mut req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    let status_response = |status: StatusCode| {
+        Ok(Response::builder()
+            .status(status)
+            .body(ResBody::Empty)
+            .unwrap())
+    };
+    if req.method() != Method::POST {
+        return status_response(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let (id, secret) = {
+        let config = state.config.load();
+        let Some(cluster) = config.cluster.as_ref() else {
+            return status_response(StatusCode::NOT_FOUND);
+        };
+        match (self_id(cluster, state), cluster.gossip_secret.clone()) {
+            (Some(id), Some(secret)) => (id.to_string(), secret),
+            _ => return status_response(StatusCode::NOT_FOUND),
+        }
+    };
+    let signature = req
+        .headers()
+        .get(GOSSIP_SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let body = match read_body_limited(req.body_mut(), MAX_GOSSIP_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(status) => return status_response(status),
+    };
+    if !verify_gossip_signature(&secret, signature.as_deref(), &body) {
+        return status_response(StatusCode::UNAUTHORIZED);
+    }
+    let message: GossipMessage = match serde_json::from_slice(&body) {
+        Ok(message) => message,
+        Err(_) => return status_response(StatusCode::BAD_REQUEST),
+    };
+    // Answer with what was known before the merge, the sender knows its own news
+    let reply = outgoing_message(state, &id);
+    state.gossip.merge(message.announcements, &id);
+
+    let body = serde_json::to_vec(&reply).unwrap_or_default();
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .header(GOSSIP_SIGNATURE_HEADER, sign_now(&secret, &body))
+        .body(ResBody::Bytes(Bytes::from(body)))
+        .unwrap();
+    Ok(response)
+}
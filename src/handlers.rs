@@ -1,24 +1,53 @@
-use std::sync::Arc;
-use std::time::Instant;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
 
+use dav_server::localfs::LocalFs;
 use hyper::body::Bytes;
 use hyper::http::StatusCode;
 use hyper::{Method, Request, Response};
+use librqbit::dht::Id20;
 use prometheus::{Encoder, TextEncoder};
 
-use crate::app::AppState;
-use crate::autoindex::generate_directory_listing;
-use crate::cache::{FileSystemStatus, check_file_status};
+use crate::admin::{handle_admin_request, handle_config_request};
+use crate::app::{AppState, STATE_DIR};
+use crate::assets::find_asset;
+use crate::auth::{AuthRequest, Authorizer, redact_secrets};
+use crate::config::PathConfig;
+use crate::gossip::handle_gossip_request;
+use crate::manifest::{Manifest, ManifestFile, ManifestHashes, manifest_files};
+use crate::metrics::{gather_torrent_metrics, update_bt_metrics};
+use crate::mirror::mirror_request;
+use crate::pipeline::{check_access, serve_path};
+use crate::reannounce::reannounce_all;
 use crate::response::ResBody;
-use crate::signature::verify_signature;
+use crate::server::{client_ip, connection_ip};
+use crate::signature::{constant_time_eq, is_signature_exempt, verify_signature_with_params};
+use crate::signing::{MAX_SIGN_PATHS, SignRequest, sign_paths};
+use crate::sync::plan_sync;
+use crate::torrents::piece_availability;
+use crate::urlpath::decode_path;
 
-pub async fn handle_request(
+// Largest client manifest accepted by /-/sync
+const MAX_MANIFEST_BODY_BYTES: u64 = 16 * 1024 * 1024;
+// Largest path list accepted by /-/sign
+const MAX_SIGN_BODY_BYTES: u64 = 16 * 1024 * 1024;
+// Largest GET/HEAD request body read and discarded before answering 413
+const MAX_DISCARDED_BODY_BYTES: u64 = 16 * 1024;
+const DISCARD_BODY_TIMEOUT_SECS: u64 = 5;
+
+/// Answer the node's own endpoints, everything else is served from the data
+/// directory by the pipeline
+pub async fn route_request(
     state: AppState,
-    req: Request<hyper::body::Incoming>,
+    mut req: Request<hyper::body::Incoming>,
 ) -> Result<Response<ResBody>, std::io::Error> {
-    let method = req.method();
-    let uri = req.uri();
-    let path = uri.path();
+    // GET/HEAD bodies are never used, don't let clients stream data into the node
+    if let Err(response) = discard_request_body(&mut req).await {
+        return Ok(response);
+    }
+
+    let path = req.uri().path();
 
     // Handle Prometheus metrics endpoint
     if path == "/-/metrics" {
@@ -28,143 +57,81 @@ pub async fn handle_request(
     if path == "/-/synctasks" {
         return handle_bt_request(&state, req).await;
     }
+    // Handle torrent admin endpoints /-/torrents/{info_hash}/...
+    if path.starts_with("/-/torrents/") {
+        return handle_torrents_request(&state, req).await;
+    }
+    // Handle background jobs endpoint /-/jobs and /-/jobs/{id}
+    if path == "/-/jobs" || path.starts_with("/-/jobs/") {
+        return handle_jobs_request(&state, req).await;
+    }
 
-    // handle webdav requests /-/dav/*
-    if path.starts_with("/-/dav/") {
-        // verify auth header
-        if let Err(response) = management_validation(&state, &req).await {
-            return Ok(response);
-        }
-        return Ok(state
-            .dav_server
-            .handle(req)
-            .await
-            .map(ResBody::Dav));
+    // Node management API /-/admin/...
+    if path == "/-/admin" || path.starts_with("/-/admin/") {
+        return handle_admin_request(&state, req).await;
     }
 
-    if method != Method::GET && method != Method::HEAD {
-        let response = Response::builder()
-            .status(StatusCode::METHOD_NOT_ALLOWED)
-            .body(ResBody::Empty)
-            .unwrap();
-        return Ok(response);
+    // Config snapshot and restore /-/config/{snapshot,restore}
+    if path.starts_with("/-/config/") {
+        return handle_config_request(&state, req).await;
     }
 
-    // Find matching path config - use optimized path lookup
-    let path_config = {
-        let config = state.config.load();
-        config.find_path_config(path).cloned()
-    };
-    // Check signature if required
-    if let Some(ref path_cfg) = path_config {
-        if let Some(ref signature_token) = path_cfg.signature {
-            let range_header = req.headers().get("range").and_then(|h| h.to_str().ok());
-            if let Err(err_msg) = verify_signature(path, uri.query(), signature_token, range_header)
-            {
-                let response = Response::builder()
-                    .status(err_msg)
-                    .body(ResBody::Empty)
-                    .unwrap();
-                return Ok(response);
-            }
-        }
+    // Manifest based client sync /-/sync/{dir}
+    if path.starts_with("/-/sync/") {
+        return handle_sync_request(&state, req).await;
     }
 
-    // Check if autoindex is enabled for directory listing
-    let enable_autoindex = path_config
-        .as_ref()
-        .and_then(|pc| pc.autoindex)
-        .unwrap_or(false);
+    // Bulk signed URL issuance /-/sign
+    if path == "/-/sign" {
+        return handle_sign_request(&state, req).await;
+    }
 
-    // Use cached file system status check
-    let file_path = state.data_dir.join(path.trim_start_matches('/'));
-    let file_path_clone = file_path.clone();
+    // Content-addressed store import /-/cas/import?dir=...&view=...
+    if path == "/-/cas/import" {
+        return handle_cas_import_request(&state, req).await;
+    }
 
-    let fs_status = state
-        .fs_cache
-        .get_or_fetch(file_path.clone(), || async move {
-            check_file_status(&file_path_clone).await
-        })
-        .await;
+    // Data directory change journal /-/changes?since=N
+    if path == "/-/changes" {
+        return handle_changes_request(&state, req).await;
+    }
 
-    // Handle different file system statuses
-    match fs_status {
-        FileSystemStatus::NotExists => {
-            let response = Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(ResBody::Empty)
-                .unwrap();
-            return Ok(response);
-        }
-        FileSystemStatus::Directory => {
-            if !enable_autoindex {
-                let response = Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(ResBody::Empty)
-                    .unwrap();
-                return Ok(response);
-            }
-            // Generate directory listing with signatures if required
-            let signature_token = path_config.as_ref().and_then(|pc| pc.signature.as_deref());
-
-            let signature_expire_seconds = path_config
-                .as_ref()
-                .and_then(|pc| pc.signature_expire_seconds)
-                .unwrap_or(3600); // 默认1小时
-
-            match generate_directory_listing(
-                &file_path,
-                path,
-                signature_token,
-                signature_expire_seconds,
-            )
-            .await
-            {
-                Ok(html) => {
-                    let response = Response::builder()
-                        .status(StatusCode::OK)
-                        .header("Content-Type", "text/html; charset=utf-8")
-                        .body(ResBody::Bytes(Bytes::from(html)))
-                        .unwrap();
-                    return Ok(response);
-                }
-                Err(status) => {
-                    let response = Response::builder()
-                        .status(status)
-                        .body(ResBody::Empty)
-                        .unwrap();
-                    return Ok(response);
-                }
-            }
-        }
-        FileSystemStatus::File => {
-            // File exists, continue to serve
-        }
+    // Cluster availability gossip /-/gossip
+    if path == "/-/gossip" {
+        return handle_gossip_request(&state, req).await;
     }
 
-    // 在调用 serve 前克隆需要的信息
-    let method_for_logging = method.clone();
-    let uri_for_logging = uri.clone();
-
-    // Use hyper_staticfile to serve the file/directory
-    match state.static_service.serve(req).await {
-        Ok(response) => {
-            let status = response.status();
-            // 对于文件响应，使用带日志的包装器来记录完整下载时间
-            let response = response.map(|res| ResBody::Static {
-                inner: res,
-                start_time: Instant::now(), // 记录开始时间
-                metrics: Arc::new(crate::response::StaticMetrics {
-                    method: method_for_logging,
-                    uri: uri_for_logging,
-                    status,
-                }),
-                bytes_sent: 0, // 初始化字节数为0
-            });
-            Ok(response)
-        }
-        Err(err) => Err(err),
+    // Page cache warmup /-/warm?path=...
+    if path == "/-/warm" {
+        return handle_warm_request(&state, req).await;
+    }
+
+    // Request echo for debugging client and proxy issues /-/debug/echo?path=...
+    if path == "/-/debug/echo" {
+        return handle_debug_echo_request(&state, req).await;
+    }
+
+    // Embedded autoindex assets, public
+    if let Some(name) = path.strip_prefix("/-/assets/") {
+        return Ok(asset_response(name));
+    }
+
+    // handle webdav requests under dav_prefix (default /-/dav)
+    let dav_prefix = {
+        let config = state.config.load();
+        let under_prefix = path
+            .strip_prefix(config.dav_prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        under_prefix.then(|| config.dav_prefix.clone())
+    };
+    if let Some(dav_prefix) = dav_prefix {
+        return handle_dav_request(&state, req, &dav_prefix).await;
     }
+
+    // Sample traffic to the debug mirror, if configured
+    mirror_request(&state, &req);
+
+    serve_path(state, req).await
 }
 
 pub async fn management_validation(
@@ -178,8 +145,7 @@ pub async fn management_validation(
             let auth_header = req.headers().get("Authorization");
             match auth_header {
                 Some(header_value) => {
-                    let header_str = header_value.to_str().unwrap_or("");
-                    header_str == expected_auth
+                    constant_time_eq(header_value.as_bytes(), expected_auth.as_bytes())
                 }
                 None => false,
             }
@@ -200,6 +166,17 @@ pub async fn management_validation(
     Ok(())
 }
 
+/// Whether the request carries the configured management token, false on
+/// nodes without one. For expensive work otherwise open to everyone.
+pub fn has_management_token<B>(state: &AppState, req: &Request<B>) -> bool {
+    let config = state.config.load();
+    let expected = config.prometheus_auth_header.as_ref();
+    let header = req.headers().get("authorization");
+    expected
+        .zip(header)
+        .is_some_and(|(expected, header)| constant_time_eq(header.as_bytes(), expected.as_bytes()))
+}
+
 pub async fn handle_metrics_request(
     state: &AppState,
     req: Request<hyper::body::Incoming>,
@@ -209,9 +186,15 @@ pub async fn handle_metrics_request(
         return Ok(response);
     }
 
+    update_bt_metrics(&state.bt_api);
+
     // Generate metrics
     let encoder = TextEncoder::new();
-    let metric_families = prometheus::gather();
+    let mut metric_families = crate::metrics::gather();
+    match gather_torrent_metrics(&state.bt_api, &state.config.load(), &state.bt_stats) {
+        Ok(families) => metric_families.extend(families),
+        Err(e) => tracing::warn!("Failed to collect torrent metrics: {}", e),
+    }
     let mut buffer = Vec::new();
 
     if encoder.encode(&metric_families, &mut buffer).is_err() {
@@ -247,9 +230,43 @@ pub async fn handle_bt_request(
         .api_torrent_list_ext(librqbit::api::ApiTorrentListOpts { with_stats: true });
 
     let total_stats = state.bt_api.api_session_stats();
+    // Attach configured path and labels to each torrent
+    let config = state.config.load();
+    let torrents: Vec<serde_json::Value> = bt_api
+        .torrents
+        .iter()
+        .map(|torrent| {
+            let torrent_config = Id20::from_str(&torrent.info_hash)
+                .ok()
+                .and_then(|id| config.torrents().get(&id));
+            let mut value = serde_json::to_value(torrent).unwrap_or_default();
+            let lifetime = state.bt_stats.lifetime(&torrent.info_hash);
+            let total_bytes = value["stats"]["total_bytes"].as_u64().unwrap_or(0);
+            if let Some(obj) = value.as_object_mut() {
+                obj.insert(
+                    "lifetime".to_string(),
+                    serde_json::json!({
+                        "uploaded_bytes": lifetime.uploaded_bytes,
+                        "downloaded_bytes": lifetime.downloaded_bytes,
+                        "ratio": lifetime.ratio(total_bytes),
+                    }),
+                );
+            }
+            if let (Some(obj), Some(torrent_config)) = (value.as_object_mut(), torrent_config) {
+                obj.insert("path".to_string(), torrent_config.path.clone().into());
+                obj.insert(
+                    "labels".to_string(),
+                    serde_json::to_value(&torrent_config.labels).unwrap_or_default(),
+                );
+            }
+            value
+        })
+        .collect();
     let resp_json = serde_json::json!({
-        "torrents": bt_api.torrents,
-        "session": total_stats
+        "torrents": torrents,
+        "session": total_stats,
+        "lifetime": state.bt_stats.totals(),
+        "config_issues": config.issues,
     });
     let resp_bytes = Bytes::from(serde_json::to_vec(&resp_json).unwrap());
     let response = Response::builder()
@@ -259,3 +276,688 @@ pub async fn handle_bt_request(
         .unwrap();
     Ok(response)
 }
+
+pub fn embargo_response(path_config: &PathConfig) -> Option<Response<ResBody>> {
+    let available_after = path_config.available_after?;
+    let now = chrono::Utc::now().timestamp();
+    if now >= available_after {
+        return None;
+    }
+    let status = path_config
+        .embargo_status
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .unwrap_or(StatusCode::FORBIDDEN);
+    let response = Response::builder()
+        .status(status)
+        .header("Retry-After", (available_after - now).to_string())
+        .body(ResBody::Empty)
+        .unwrap();
+    Some(response)
+}
+
+fn asset_response(name: &str) -> Response<ResBody> {
+    match find_asset(name) {
+        Some((content_type, content)) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", content_type)
+            // Assets only change with the binary
+            .header("Cache-Control", "public, max-age=86400")
+            .body(ResBody::Bytes(Bytes::from_static(content)))
+            .unwrap(),
+        None => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(ResBody::Empty)
+            .unwrap(),
+    }
+}
+
+// Authenticated WebDAV access to the data directory, for pushing files to a node directly
+async fn handle_dav_request(
+    state: &AppState,
+    req: Request<hyper::body::Incoming>,
+    dav_prefix: &str,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // verify auth header
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+    let mutating = !is_read_method(req.method());
+    if state.read_only && mutating {
+        return Ok(read_only_response());
+    }
+    // Target and Destination of MOVE/COPY as paths relative to the data
+    // directory, decoded the way the file system will see them
+    let relative = decode_path(&req.uri().path()[dav_prefix.len()..]);
+    let destination = req
+        .headers()
+        .get("destination")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<hyper::Uri>().ok())
+        .and_then(|uri| uri.path().strip_prefix(dav_prefix).map(decode_path));
+    let undecodable = relative.is_none() || destination.as_ref().is_some_and(Option::is_none);
+    let relative = relative.unwrap_or_default();
+    let destination = destination.flatten();
+    // Node-internal state is not writable over WebDAV
+    if mutating
+        && (undecodable
+            || is_state_dir_path(&relative)
+            || destination.as_deref().is_some_and(is_state_dir_path))
+    {
+        let response = Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(ResBody::Empty)
+            .unwrap();
+        return Ok(response);
+    }
+
+    // Files go where they are served from, the root of the path's config
+    let config = state.config.load();
+    let root = config.data_root(&relative, &state.data_dir).to_path_buf();
+    if destination
+        .as_deref()
+        .is_some_and(|destination| config.data_root(destination, &state.data_dir) != root)
+    {
+        // RFC 4918: the destination is on another server, as far as the file system goes
+        let response = Response::builder()
+            .status(StatusCode::BAD_GATEWAY)
+            .body(ResBody::Empty)
+            .unwrap();
+        return Ok(response);
+    }
+    drop(config);
+
+    let mut dav_config = dav_server::DavConfig::new().strip_prefix(dav_prefix);
+    if root != state.data_dir {
+        dav_config = dav_config.filesystem(LocalFs::new(&root, true, false, false));
+    }
+    let response = state.dav_server.handle_with(dav_config, req).await;
+    // Uploaded and removed files must not be answered from stale fs_cache entries
+    if mutating && response.status().is_success() {
+        for changed in std::iter::once(relative).chain(destination) {
+            let file_path = root.join(changed.trim_start_matches('/'));
+            state.fs_cache.invalidate(&file_path).await;
+        }
+    }
+    Ok(response.map(ResBody::Dav))
+}
+
+// Methods that never modify data, the only ones allowed in --read-only mode
+fn is_read_method(method: &Method) -> bool {
+    matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS" | "PROPFIND")
+}
+
+pub fn read_only_response() -> Response<ResBody> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(ResBody::Bytes(Bytes::from_static(b"node is read-only")))
+        .unwrap()
+}
+
+pub fn overloaded_response() -> Response<ResBody> {
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header("Retry-After", "1")
+        .body(ResBody::Empty)
+        .unwrap()
+}
+
+// Read and drop a small GET/HEAD body; larger or slow ones get an error and the connection is closed
+async fn discard_request_body(
+    req: &mut Request<hyper::body::Incoming>,
+) -> Result<(), Response<ResBody>> {
+    use hyper::body::Body;
+
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Ok(());
+    }
+    let body = req.body_mut();
+    if body.is_end_stream() {
+        return Ok(());
+    }
+    let reject = |status: StatusCode| {
+        Response::builder()
+            .status(status)
+            .header("Connection", "close")
+            .body(ResBody::Empty)
+            .unwrap()
+    };
+    if body.size_hint().lower() > MAX_DISCARDED_BODY_BYTES {
+        return Err(reject(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    let drain = async {
+        let mut received = 0u64;
+        while let Some(frame) =
+            std::future::poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_frame(cx)).await
+        {
+            let frame = frame.map_err(|_| StatusCode::BAD_REQUEST)?;
+            if let Some(data) = frame.data_ref() {
+                received += data.len() as u64;
+                if received > MAX_DISCARDED_BODY_BYTES {
+                    return Err(StatusCode::PAYLOAD_TOO_LARGE);
+                }
+            }
+        }
+        Ok(())
+    };
+    match tokio::time::timeout(Duration::from_secs(DISCARD_BODY_TIMEOUT_SECS), drain).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(status)) => Err(reject(status)),
+        Err(_) => Err(reject(StatusCode::REQUEST_TIMEOUT)),
+    }
+}
+
+pub fn is_state_dir_path(path: &str) -> bool {
+    path.trim_start_matches('/')
+        .split('/')
+        .next()
+        .is_some_and(|first| first == STATE_DIR)
+}
+
+pub async fn handle_jobs_request(
+    state: &AppState,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // auth
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+
+    let job_id = req.uri().path()["/-/jobs".len()..].trim_matches('/');
+    let resp_json = if job_id.is_empty() {
+        serde_json::to_vec(&state.jobs.list())
+    } else {
+        match job_id.parse::<u64>().ok().and_then(|id| state.jobs.get(id)) {
+            Some(job) => serde_json::to_vec(&job),
+            None => {
+                let response = Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(ResBody::Empty)
+                    .unwrap();
+                return Ok(response);
+            }
+        }
+    };
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .body(ResBody::Bytes(Bytes::from(resp_json.unwrap())))
+        .unwrap();
+    Ok(response)
+}
+
+pub async fn handle_torrents_request(
+    state: &AppState,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // auth
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+
+    let rest = req.uri().path()["/-/torrents/".len()..].trim_matches('/');
+    // Reconnect every torrent's swarm, e.g. after connectivity or a tracker came back
+    if rest == "reannounce" {
+        if req.method() != Method::POST {
+            let response = Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", "POST")
+                .body(ResBody::Empty)
+                .unwrap();
+            return Ok(response);
+        }
+        let results = reannounce_all(state).await;
+        let resp_json = serde_json::json!({ "torrents": results });
+        let response = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(ResBody::Bytes(Bytes::from(resp_json.to_string())))
+            .unwrap();
+        return Ok(response);
+    }
+    let (info_hash, action) = rest.split_once('/').unwrap_or((rest, ""));
+    let result = match (Id20::from_str(info_hash), action) {
+        (Ok(info_hash), "pieces") => piece_availability(&state.bt_api, info_hash)
+            .map(|pieces| serde_json::to_vec(&pieces).unwrap()),
+        _ => {
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(ResBody::Empty)
+                .unwrap();
+            return Ok(response);
+        }
+    };
+
+    let response = match result {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json; charset=utf-8")
+            .body(ResBody::Bytes(Bytes::from(body)))
+            .unwrap(),
+        Err(e) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(ResBody::Bytes(Bytes::from(e.to_string())))
+            .unwrap(),
+    };
+    Ok(response)
+}
+
+pub async fn handle_warm_request(
+    state: &AppState,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // auth
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+
+    let query = req.uri().query().unwrap_or("");
+    let parsed = serde_querystring::DuplicateQS::parse(query.as_bytes());
+    let paths: Vec<String> = parsed
+        .values(b"path")
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .map(|value| String::from_utf8_lossy(&value).into_owned())
+        .collect();
+    if paths.is_empty() {
+        let response = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(ResBody::Empty)
+            .unwrap();
+        return Ok(response);
+    }
+
+    let job_id = crate::warm::spawn_warm(state, paths);
+    let resp_json = serde_json::json!({ "job_id": job_id });
+    let response = Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .body(ResBody::Bytes(Bytes::from(resp_json.to_string())))
+        .unwrap();
+    Ok(response)
+}
+
+// Echo what the node sees of a request to `?path=...`: headers, client IP and
+// the path config it matches. Needs the management token, or a valid signature
+// for `path` when the path is signed, so end users can check their own links.
+pub async fn handle_debug_echo_request(
+    state: &AppState,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    let query = req.uri().query();
+    let parsed = serde_querystring::DuplicateQS::parse(query.unwrap_or("").as_bytes());
+    let path = parsed
+        .values(b"path")
+        .and_then(|v| v.first().cloned().unwrap_or(None))
+        .map(|value| {
+            format!(
+                "/{}",
+                String::from_utf8_lossy(&value).trim_start_matches('/')
+            )
+        })
+        .unwrap_or_else(|| "/".to_string());
+
+    let (path_config, matched_prefix) = {
+        let config = state.config.load();
+        (
+            config.find_path_config(&path).cloned(),
+            config.find_path_prefix(&path),
+        )
+    };
+    let signature_exempt = path_config
+        .as_ref()
+        .and_then(|pc| pc.signature_exempt.as_deref())
+        .is_some_and(|patterns| {
+            let prefix_len = matched_prefix.as_ref().map_or(0, String::len);
+            is_signature_exempt(patterns, &path[prefix_len..])
+        });
+    let range_header = req.headers().get("range").and_then(|h| h.to_str().ok());
+    // Exempt files need no signature, so one can't authorize the echo either
+    let signature = path_config
+        .as_ref()
+        .filter(|pc| pc.signature.is_some() && !signature_exempt)
+        .map(|pc| {
+            let signed_params = pc.signature_params.as_deref().unwrap_or_default();
+            verify_signature_with_params(
+                &path,
+                query,
+                pc.signature_tokens(),
+                range_header,
+                signed_params,
+                client_ip(&req),
+            )
+        });
+
+    match management_validation(state, &req).await {
+        Err(response) if !matches!(signature, Some(Ok(_))) => return Ok(response),
+        _ => {}
+    }
+
+    let headers: Vec<serde_json::Value> = req
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = if name == "authorization" {
+                "<redacted>".into()
+            } else {
+                String::from_utf8_lossy(value.as_bytes())
+            };
+            serde_json::json!([name.as_str(), value])
+        })
+        .collect();
+    let mut path_config_json = serde_json::to_value(&path_config).unwrap_or_default();
+    redact_secrets(&mut path_config_json);
+    let signature_json = signature.map(|result| match result {
+        Ok(ranges) => serde_json::json!({ "valid": true, "ranges": ranges }),
+        Err(status) => serde_json::json!({ "valid": false, "status": status.as_u16() }),
+    });
+    // Every authorizer of the path, not just the signature
+    let auth_request = AuthRequest {
+        path: &path,
+        query,
+        headers: req.headers(),
+        client_ip: client_ip(&req),
+    };
+    let auth_json = path_config
+        .as_ref()
+        .and_then(|pc| pc.authorizer.as_ref())
+        .map(|chain| match chain.authorize(&auth_request) {
+            Ok(_) => serde_json::json!({ "valid": true }),
+            Err(denial) => serde_json::json!({ "valid": false, "status": denial.status.as_u16() }),
+        });
+
+    let resp_json = serde_json::json!({
+        "method": req.method().as_str(),
+        "uri": req.uri().to_string(),
+        "version": format!("{:?}", req.version()),
+        "client_ip": client_ip(&req).map(|ip| ip.to_string()),
+        "connection_ip": connection_ip(&req).map(|ip| ip.to_string()),
+        "headers": headers,
+        "path": path,
+        "matched_prefix": matched_prefix,
+        "path_config": path_config_json,
+        "signature": signature_json,
+        "signature_exempt": signature_exempt,
+        "auth": auth_json,
+    });
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .header("Cache-Control", "no-store")
+        .body(ResBody::Bytes(Bytes::from(resp_json.to_string())))
+        .unwrap();
+    Ok(response)
+}
+
+// POST a JSON list of paths, get signed URLs back as JSON or, with
+// `?format=text`, one URL per line
+pub async fn handle_sign_request(
+    state: &AppState,
+    mut req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // auth
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+    let status_response = |status: StatusCode| {
+        Ok(Response::builder()
+            .status(status)
+            .body(ResBody::Empty)
+            .unwrap())
+    };
+    if req.method() != Method::POST {
+        return status_response(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let text = req
+        .uri()
+        .query()
+        .is_some_and(|query| query.split('&').any(|pair| pair == "format=text"));
+
+    let body = match read_body_limited(req.body_mut(), MAX_SIGN_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(status) => return status_response(status),
+    };
+    let Ok(sign_request) = serde_json::from_slice::<SignRequest>(&body) else {
+        return status_response(StatusCode::BAD_REQUEST);
+    };
+    if sign_request.paths().len() > MAX_SIGN_PATHS {
+        return status_response(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    if let Err(message) = sign_request.binding() {
+        let response = Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(ResBody::Bytes(Bytes::from(message)))
+            .unwrap();
+        return Ok(response);
+    }
+
+    let urls = sign_paths(&state.config.load(), &sign_request);
+    let (content_type, content) = if text {
+        let mut lines = String::new();
+        for signed in &urls {
+            lines.push_str(&signed.url);
+            lines.push('\n');
+        }
+        ("text/plain; charset=utf-8", lines)
+    } else {
+        let resp_json = serde_json::json!({ "urls": urls });
+        ("application/json; charset=utf-8", resp_json.to_string())
+    };
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", content_type)
+        .body(ResBody::Bytes(Bytes::from(content)))
+        .unwrap();
+    Ok(response)
+}
+
+// Changes since sequence `since` (optionally under `path`), requires --watch.
+// Clients pass back the `epoch` they got, a `reset` answer means re-walk.
+pub async fn handle_changes_request(
+    state: &AppState,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // auth
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+    let Some(journal) = state.journal.as_ref() else {
+        let response = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(ResBody::Empty)
+            .unwrap();
+        return Ok(response);
+    };
+
+    let query = req.uri().query().unwrap_or("");
+    let parsed = serde_querystring::DuplicateQS::parse(query.as_bytes());
+    let param = |name: &[u8]| {
+        parsed
+            .values(name)
+            .and_then(|values| values.first().cloned().unwrap_or(None))
+            .map(|value| String::from_utf8_lossy(&value).into_owned())
+    };
+    let since = param(b"since")
+        .and_then(|since| since.parse().ok())
+        .unwrap_or(0);
+    let prefix = param(b"path").unwrap_or_default();
+    let changes = journal.since(param(b"epoch").as_deref(), since, &prefix);
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .body(ResBody::Bytes(Bytes::from(
+            serde_json::to_vec(&changes).unwrap(),
+        )))
+        .unwrap();
+    Ok(response)
+}
+
+// Import a data directory into the CAS as a view, runs as a job
+pub async fn handle_cas_import_request(
+    state: &AppState,
+    req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // auth
+    if let Err(response) = management_validation(state, &req).await {
+        return Ok(response);
+    }
+    if state.read_only {
+        return Ok(read_only_response());
+    }
+    let status_response = |status: StatusCode| {
+        Ok(Response::builder()
+            .status(status)
+            .body(ResBody::Empty)
+            .unwrap())
+    };
+    if req.method() != Method::POST {
+        return status_response(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    let query = req.uri().query().unwrap_or("");
+    let parsed = serde_querystring::DuplicateQS::parse(query.as_bytes());
+    let param = |name: &[u8]| {
+        parsed
+            .values(name)
+            .and_then(|values| values.first().cloned().unwrap_or(None))
+            .map(|value| String::from_utf8_lossy(&value).into_owned())
+    };
+    let (Some(dir), Some(view)) = (param(b"dir"), param(b"view")) else {
+        return status_response(StatusCode::BAD_REQUEST);
+    };
+    let dir = format!("/{}", dir.trim_matches('/'));
+    if dir == "/" || is_state_dir_path(&dir) || dir.split('/').any(|part| part == "..") {
+        return status_response(StatusCode::BAD_REQUEST);
+    }
+
+    let source = state.data_path(&dir);
+    let job_id = crate::cas::spawn_import(state, source, view);
+    let resp_json = serde_json::json!({ "job_id": job_id });
+    let response = Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .body(ResBody::Bytes(Bytes::from(resp_json.to_string())))
+        .unwrap();
+    Ok(response)
+}
+
+// Read a request body into memory, failing with 413 past `limit` bytes
+pub async fn read_body_limited(
+    body: &mut hyper::body::Incoming,
+    limit: u64,
+) -> Result<Bytes, StatusCode> {
+    use hyper::body::Body;
+
+    if body.size_hint().lower() > limit {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let mut content = Vec::new();
+    while let Some(frame) =
+        std::future::poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_frame(cx)).await
+    {
+        let frame = frame.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if let Some(data) = frame.data_ref() {
+            if content.len() as u64 + data.len() as u64 > limit {
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            content.extend_from_slice(data);
+        }
+    }
+    Ok(Bytes::from(content))
+}
+
+pub async fn handle_sync_request(
+    state: &AppState,
+    mut req: Request<hyper::body::Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    let status_response = |status: StatusCode| {
+        Ok(Response::builder()
+            .status(status)
+            .body(ResBody::Empty)
+            .unwrap())
+    };
+
+    // Directory names as on disk, and checked once decoded so no encoding
+    // reaches the state directory
+    let Some(dir_path) = decode_path(&req.uri().path()["/-/sync/".len()..]) else {
+        return status_response(StatusCode::NOT_FOUND);
+    };
+    if is_state_dir_path(&dir_path) {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    if req.method() != Method::GET && req.method() != Method::POST {
+        return status_response(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    // Same access rules as the directory itself, a removed one is gone
+    // rather than empty
+    if let Err(response) = check_access(state, &req, &dir_path, true) {
+        return Ok(response);
+    }
+
+    let root = state.data_path(&dir_path);
+    if !tokio::fs::metadata(&root)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    // Hashing a whole tree on demand is for management requests, others get
+    // the hashes already known
+    let hashes = if has_management_token(state, &req) {
+        ManifestHashes::Compute
+    } else {
+        ManifestHashes::Known
+    };
+    let auth_request = AuthRequest {
+        path: &dir_path,
+        query: req.uri().query(),
+        headers: req.headers(),
+        client_ip: client_ip(&req),
+    };
+    let files = match manifest_files(state, &root, &dir_path, &auth_request, hashes).await {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::warn!("Failed to build manifest for {}: {}", dir_path, e);
+            return status_response(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let server_manifest = Manifest::from_files(&files);
+
+    let resp_json = if req.method() == Method::GET {
+        serde_json::to_vec(&server_manifest)
+    } else {
+        let body = match read_body_limited(req.body_mut(), MAX_MANIFEST_BODY_BYTES).await {
+            Ok(body) => body,
+            Err(status) => return status_response(status),
+        };
+        let Ok(client_manifest) = serde_json::from_slice::<Manifest>(&body) else {
+            return status_response(StatusCode::BAD_REQUEST);
+        };
+        let by_path: HashMap<&str, &ManifestFile> = files
+            .iter()
+            .map(|file| (file.path.as_str(), file))
+            .collect();
+        serde_json::to_vec(&plan_sync(
+            &server_manifest,
+            &client_manifest,
+            |path, ranges| {
+                let file = by_path[path];
+                file.signer().url(&file.url_path, ranges)
+            },
+        ))
+    };
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json; charset=utf-8")
+        .body(ResBody::Bytes(Bytes::from(resp_json.unwrap())))
+        .unwrap();
+    Ok(response)
+}
@@ -0,0 +1,153 @@
+use std::hash::{BuildHasher, Hash};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use bytes::Bytes;
+use moka::future::Cache;
+use tokio::time::{Duration, interval};
+
+use crate::metrics::{HOT_CACHE_BYTES, HOT_CACHE_HITS_TOTAL};
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_WIDTH: usize = 1 << 16;
+// Counters are halved every period, so estimates track recent request rates
+const DECAY_INTERVAL_SECS: u64 = 60;
+// Requests within roughly one decay period that make a file hot
+const PROMOTE_THRESHOLD: u32 = 32;
+// Cached files whose estimate falls below this are dropped
+const DEMOTE_THRESHOLD: u32 = PROMOTE_THRESHOLD / 4;
+
+/// Approximate per-key counters in fixed memory. Estimates never undercount.
+struct CountMinSketch {
+    counters: Vec<AtomicU32>,
+    hasher: std::collections::hash_map::RandomState,
+}
+
+impl CountMinSketch {
+    fn new() -> Self {
+        Self {
+            counters: (0..SKETCH_DEPTH * SKETCH_WIDTH)
+                .map(|_| AtomicU32::new(0))
+                .collect(),
+            hasher: Default::default(),
+        }
+    }
+
+    fn slots<K: Hash>(&self, key: &K) -> [usize; SKETCH_DEPTH] {
+        // Rows use independent bits of one 64-bit hash
+        let hash = self.hasher.hash_one(key);
+        std::array::from_fn(|row| {
+            let column = (hash.rotate_left(row as u32 * 16) as usize) % SKETCH_WIDTH;
+            row * SKETCH_WIDTH + column
+        })
+    }
+
+    /// Count one occurrence and return the new estimate
+    fn increment<K: Hash>(&self, key: &K) -> u32 {
+        self.slots(key)
+            .iter()
+            .map(|&slot| {
+                self.counters[slot]
+                    .fetch_add(1, Ordering::Relaxed)
+                    .saturating_add(1)
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn estimate<K: Hash>(&self, key: &K) -> u32 {
+        self.slots(key)
+            .iter()
+            .map(|&slot| self.counters[slot].load(Ordering::Relaxed))
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn decay(&self) {
+        for counter in &self.counters {
+            // Racing increments may be lost, which only makes the estimate a bit lower
+            counter.store(counter.load(Ordering::Relaxed) / 2, Ordering::Relaxed);
+        }
+    }
+}
+
+// (path, size, Last-Modified), a replaced file gets a new entry
+type HotKey = (PathBuf, u64, String);
+
+/// Keeps the contents of frequently requested files in memory. Files are
+/// promoted once their request rate crosses a threshold and demoted when it
+/// drops, so what's "hot" follows the traffic instead of static config.
+pub struct HotFileCache {
+    sketch: CountMinSketch,
+    files: Cache<HotKey, Bytes>,
+    max_file_size: u64,
+}
+
+impl HotFileCache {
+    pub fn new(capacity_bytes: u64, max_file_size: u64) -> Arc<Self> {
+        Arc::new(Self {
+            sketch: CountMinSketch::new(),
+            files: Cache::builder()
+                .max_capacity(capacity_bytes)
+                .weigher(|_: &HotKey, bytes: &Bytes| bytes.len().try_into().unwrap_or(u32::MAX))
+                .build(),
+            max_file_size,
+        })
+    }
+
+    /// Record a full-file request, returning the contents if the file is cached.
+    /// A file that just became hot is loaded in the background for later requests.
+    pub async fn get(
+        self: &Arc<Self>,
+        path: &Path,
+        size: u64,
+        last_modified: &str,
+    ) -> Option<Bytes> {
+        if size > self.max_file_size {
+            return None;
+        }
+        let key = (path.to_path_buf(), size, last_modified.to_string());
+        let hits = self.sketch.increment(&key.0);
+        if let Some(bytes) = self.files.get(&key).await {
+            HOT_CACHE_HITS_TOTAL.inc();
+            return Some(bytes);
+        }
+        // Retried every PROMOTE_THRESHOLD requests if the cache didn't admit it
+        if hits >= PROMOTE_THRESHOLD && hits.is_multiple_of(PROMOTE_THRESHOLD) {
+            let cache = self.clone();
+            tokio::spawn(async move {
+                let (path, size, _) = key.clone();
+                // Concurrent loads of the same key are coalesced
+                cache
+                    .files
+                    .optionally_get_with(key, async move {
+                        let content = tokio::fs::read(&path).await.ok()?;
+                        (content.len() as u64 == size).then(|| Bytes::from(content))
+                    })
+                    .await;
+            });
+        }
+        None
+    }
+
+    /// Age the counters and drop files that are no longer hot
+    pub async fn decay_task(self: Arc<Self>) {
+        let mut interval = interval(Duration::from_secs(DECAY_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            self.sketch.decay();
+            let cold: Vec<HotKey> = self
+                .files
+                .iter()
+                .filter(|(key, _)| self.sketch.estimate(&key.0) < DEMOTE_THRESHOLD)
+                .map(|(key, _)| key.as_ref().clone())
+                .collect();
+            for key in cold {
+                self.files.invalidate(&key).await;
+            }
+            self.files.run_pending_tasks().await;
+            HOT_CACHE_BYTES.set(self.files.weighted_size() as i64);
+        }
+    }
+}
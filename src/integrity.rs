@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use crate::metrics::HASH_MISMATCH_TOTAL;
+use crate::webhook::{WebhookEvent, emit};
+
+/// Hashes a full-file response while it streams and checks it against the known hash
+pub struct HashVerifier {
+    hasher: Sha256,
+    expected: [u8; 32],
+    remaining: u64,
+}
+
+impl HashVerifier {
+    pub fn new(expected: [u8; 32], content_length: u64) -> Self {
+        Self {
+            hasher: Sha256::new(),
+            expected,
+            remaining: content_length,
+        }
+    }
+
+    /// Feed the next chunk. Returns false if the content doesn't match; for the
+    /// last chunk this is decided before it is sent, so the client never gets a
+    /// complete corrupted file.
+    pub fn update(&mut self, chunk: &[u8], uri: &hyper::Uri) -> bool {
+        let len = chunk.len() as u64;
+        if len > self.remaining {
+            return self.mismatch(uri, "response longer than expected");
+        }
+        self.hasher.update(chunk);
+        self.remaining -= len;
+        if self.remaining == 0 {
+            let actual = std::mem::take(&mut self.hasher).finalize();
+            if actual.as_slice() != self.expected {
+                return self.mismatch(uri, &hex::encode(actual));
+            }
+        }
+        true
+    }
+
+    fn mismatch(&self, uri: &hyper::Uri, detail: &str) -> bool {
+        HASH_MISMATCH_TOTAL.inc();
+        error!(
+            "Hash mismatch serving {}: expected {}, got {}",
+            uri,
+            hex::encode(self.expected),
+            detail
+        );
+        emit(WebhookEvent::HashMismatch {
+            path: uri.path().to_string(),
+            detail: detail.to_string(),
+        });
+        false
+    }
+}
+
+/// Read the expected SHA-256 from a `<file>.sha256` sidecar (sha256sum format)
+pub async fn load_expected_hash(file_path: &Path) -> Option<[u8; 32]> {
+    let mut sidecar = file_path.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    let content = tokio::fs::read_to_string(&sidecar).await.ok()?;
+    let hex_hash = content.split_whitespace().next()?;
+    let mut expected = [0u8; 32];
+    hex::decode_to_slice(hex_hash, &mut expected).ok()?;
+    Some(expected)
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+// Jobs allowed to run at the same time, the rest wait in Queued state
+const MAX_RUNNING_JOBS: usize = 2;
+// Finished jobs kept in memory and in the job file
+const MAX_FINISHED_JOBS: usize = 256;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    // Job was queued or running when the process stopped
+    Interrupted,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub kind: String,
+    pub status: JobStatus,
+    pub progress_done: u64,
+    pub progress_total: u64,
+    pub message: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// Tracked background jobs, persisted to a JSON file in the state directory
+pub struct JobManager {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+    store_path: PathBuf,
+    // Held from snapshot to rename, so concurrent jobs don't share the tmp
+    // file and the last snapshot taken is the one left on disk
+    persist_lock: Mutex<()>,
+    run_permits: Arc<Semaphore>,
+}
+
+impl JobManager {
+    /// Load job history from `store_path`, marking unfinished jobs as interrupted
+    pub fn load(store_path: PathBuf) -> Self {
+        let mut jobs: BTreeMap<u64, Job> = match std::fs::read(&store_path) {
+            Ok(content) => serde_json::from_slice::<Vec<Job>>(&content)
+                .map(|list| list.into_iter().map(|job| (job.id, job)).collect())
+                .unwrap_or_else(|e| {
+                    warn!("Failed to parse job file {}: {}", store_path.display(), e);
+                    BTreeMap::new()
+                }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        let now = Utc::now();
+        for job in jobs.values_mut() {
+            if !job.status.is_finished() {
+                job.status = JobStatus::Interrupted;
+                job.finished_at = Some(now);
+            }
+        }
+
+        let next_id = jobs.keys().next_back().map(|id| id + 1).unwrap_or(1);
+        let manager = Self {
+            jobs: Mutex::new(jobs),
+            next_id: AtomicU64::new(next_id),
+            store_path,
+            persist_lock: Mutex::new(()),
+            run_permits: Arc::new(Semaphore::new(MAX_RUNNING_JOBS)),
+        };
+        manager.persist();
+        manager
+    }
+
+    /// Queue a job and run it in the background, returns the job id
+    pub fn spawn<F, Fut>(self: &Arc<Self>, kind: &str, f: F) -> u64
+    where
+        F: FnOnce(JobHandle) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let job = Job {
+            id,
+            kind: kind.to_string(),
+            status: JobStatus::Queued,
+            progress_done: 0,
+            progress_total: 0,
+            message: None,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+        };
+        self.jobs.lock().unwrap().insert(id, job);
+        self.persist();
+
+        let fut = f(JobHandle {
+            id,
+            manager: self.clone(),
+        });
+        let manager = self.clone();
+        let kind = kind.to_string();
+        tokio::spawn(async move {
+            let _permit = manager.run_permits.clone().acquire_owned().await;
+            manager.update(id, |job| {
+                job.status = JobStatus::Running;
+                job.started_at = Some(Utc::now());
+            });
+            manager.persist();
+
+            let result = fut.await;
+            manager.update(id, |job| {
+                job.finished_at = Some(Utc::now());
+                match &result {
+                    Ok(()) => job.status = JobStatus::Completed,
+                    Err(e) => {
+                        job.status = JobStatus::Failed;
+                        job.message = Some(e.to_string());
+                    }
+                }
+            });
+            match result {
+                Ok(()) => info!("Job {} ({}) completed", id, kind),
+                Err(e) => warn!("Job {} ({}) failed: {}", id, kind, e),
+            }
+            manager.prune();
+            manager.persist();
+        });
+
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.jobs.lock().unwrap().get(&id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Job> {
+        self.jobs.lock().unwrap().values().rev().cloned().collect()
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            f(job);
+        }
+    }
+
+    fn prune(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let finished: Vec<u64> = jobs
+            .values()
+            .filter(|job| job.status.is_finished())
+            .map(|job| job.id)
+            .collect();
+        if finished.len() > MAX_FINISHED_JOBS {
+            for id in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+                jobs.remove(id);
+            }
+        }
+    }
+
+    // Only called on status transitions, one write at a time
+    fn persist(&self) {
+        let _writing = self.persist_lock.lock().unwrap();
+        let content = {
+            let jobs = self.jobs.lock().unwrap();
+            serde_json::to_vec(&jobs.values().collect::<Vec<_>>())
+        };
+        let content = match content {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to serialize jobs: {}", e);
+                return;
+            }
+        };
+        let tmp_path = self.store_path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&tmp_path, content)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.store_path))
+        {
+            warn!(
+                "Failed to write job file {}: {}",
+                self.store_path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JobHandle {
+    id: u64,
+    manager: Arc<JobManager>,
+}
+
+impl JobHandle {
+    pub fn set_progress(&self, done: u64, total: u64) {
+        self.manager.update(self.id, |job| {
+            job.progress_done = done;
+            job.progress_total = total;
+        });
+    }
+
+    pub fn set_message(&self, message: impl Into<String>) {
+        let message = message.into();
+        self.manager
+            .update(self.id, |job| job.message = Some(message));
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, interval};
+use tracing::{info, warn};
+
+use crate::app::{AppState, STATE_DIR};
+use crate::logging::log_limited;
+
+// Changes kept in memory, older ones require a full re-walk
+const JOURNAL_CAPACITY: usize = 100_000;
+// Changes returned by one /-/changes request
+const MAX_CHANGES_PER_REQUEST: usize = 10_000;
+// How often roots added to the config are picked up
+const ROOTS_CHECK_INTERVAL_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    // Created or modified, fetch it again
+    Modified,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub seq: u64,
+    // URL path, '/' separated
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangesSince {
+    // Changes since a different epoch (node restart) can't be resumed
+    pub epoch: String,
+    // Latest sequence, pass as `since` next time
+    pub seq: u64,
+    // The requested sequence is no longer in the journal, re-walk instead
+    pub reset: bool,
+    pub changes: Vec<Change>,
+}
+
+struct JournalInner {
+    // Replaced whenever changes may have gone unrecorded
+    epoch: String,
+    entries: VecDeque<Change>,
+    last_seq: u64,
+}
+
+/// In-memory journal of data directory changes reported by the watcher
+pub struct ChangeJournal {
+    inner: Mutex<JournalInner>,
+}
+
+fn new_epoch() -> String {
+    hex::encode(rand::random::<[u8; 8]>())
+}
+
+impl ChangeJournal {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(JournalInner {
+                epoch: new_epoch(),
+                entries: VecDeque::new(),
+                last_seq: 0,
+            }),
+        }
+    }
+
+    // The watcher failed or dropped events, clients must re-walk
+    fn lost_changes(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.epoch = new_epoch();
+        inner.entries.clear();
+    }
+
+    fn record(&self, path: String, kind: ChangeKind) {
+        let mut inner = self.inner.lock().unwrap();
+        // Editors and copies emit bursts of events for the same file
+        if inner
+            .entries
+            .back()
+            .is_some_and(|last| last.path == path && last.kind == kind)
+        {
+            return;
+        }
+        inner.last_seq += 1;
+        let seq = inner.last_seq;
+        inner.entries.push_back(Change { seq, path, kind });
+        if inner.entries.len() > JOURNAL_CAPACITY {
+            inner.entries.pop_front();
+        }
+    }
+
+    /// Changes after `since` under `prefix`, from a journal started in `epoch`
+    pub fn since(&self, epoch: Option<&str>, since: u64, prefix: &str) -> ChangesSince {
+        let inner = self.inner.lock().unwrap();
+        let oldest = inner
+            .entries
+            .front()
+            .map_or(inner.last_seq, |first| first.seq - 1);
+        let reset = epoch.is_some_and(|epoch| epoch != inner.epoch) || since < oldest;
+        let prefix = prefix.trim_end_matches('/');
+        let changes: Vec<Change> = if reset {
+            Vec::new()
+        } else {
+            inner
+                .entries
+                .iter()
+                .skip_while(|change| change.seq <= since)
+                .filter(|change| {
+                    prefix.is_empty()
+                        || change.path == prefix
+                        || change.path.starts_with(&format!("{}/", prefix))
+                })
+                .take(MAX_CHANGES_PER_REQUEST)
+                .cloned()
+                .collect()
+        };
+        // A truncated page resumes after its last change
+        let seq = if changes.len() == MAX_CHANGES_PER_REQUEST {
+            changes.last().map_or(inner.last_seq, |change| change.seq)
+        } else {
+            inner.last_seq
+        };
+        ChangesSince {
+            epoch: inner.epoch.clone(),
+            seq,
+            reset,
+            changes,
+        }
+    }
+}
+
+impl Default for ChangeJournal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Watch the data directory and the paths' other roots, recording changes
+/// and dropping stale fs_cache entries
+pub fn start_watcher(state: &AppState) -> anyhow::Result<()> {
+    let Some(journal) = state.journal.clone() else {
+        return Ok(());
+    };
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = sender.send(event);
+    })?;
+    // Events carry paths as watched, strip the root to get URL paths
+    let data_dir = std::path::absolute(&state.data_dir)?;
+    watcher.watch(&data_dir, RecursiveMode::Recursive)?;
+    info!("Watching {} for changes", data_dir.display());
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        // Dropping the watcher stops it
+        let mut roots = vec![data_dir];
+        let mut check_roots = interval(Duration::from_secs(ROOTS_CHECK_INTERVAL_SECS));
+        loop {
+            let event = tokio::select! {
+                event = receiver.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = check_roots.tick() => {
+                    watch_new_roots(&state, &mut watcher, &mut roots);
+                    continue;
+                }
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    log_limited!(warn, "fs_watch", "File watcher error: {}", e);
+                    lost_changes(&state, &journal);
+                    continue;
+                }
+            };
+            // The kernel queue overflowed, events are missing
+            if event.need_rescan() {
+                log_limited!(warn, "fs_watch", "File watcher dropped events");
+                lost_changes(&state, &journal);
+                continue;
+            }
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+            for path in event.paths {
+                let Some((root, url_path)) = url_path(&roots, &path) else {
+                    continue;
+                };
+                // Under a prefix served from another root, so not a served file
+                let config = state.config.load();
+                let served_root = std::path::absolute(config.data_root(&url_path, &state.data_dir));
+                if served_root.ok().as_deref() != Some(root) {
+                    continue;
+                }
+                drop(config);
+                let file_path = root.join(url_path.trim_start_matches('/'));
+                state.fs_cache.invalidate(&file_path).await;
+                let kind = if tokio::fs::metadata(&path).await.is_ok() {
+                    ChangeKind::Modified
+                } else {
+                    ChangeKind::Removed
+                };
+                journal.record(url_path, kind);
+            }
+        }
+    });
+    Ok(())
+}
+
+// Neither the journal nor the cached file states can be trusted after missed
+// events: a new epoch makes clients re-walk, and the cache is dropped
+fn lost_changes(state: &AppState, journal: &ChangeJournal) {
+    journal.lost_changes();
+    state.fs_cache.invalidate_all();
+}
+
+// Roots configured since the last check are watched from now on. Roots inside
+// a watched one already report through it.
+fn watch_new_roots(state: &AppState, watcher: &mut impl Watcher, roots: &mut Vec<PathBuf>) {
+    for root in state.config.load().extra_roots() {
+        let Ok(root) = std::path::absolute(&root) else {
+            continue;
+        };
+        if roots.contains(&root) {
+            continue;
+        }
+        if !roots.iter().any(|watched| root.starts_with(watched)) {
+            if let Err(e) = watcher.watch(&root, RecursiveMode::Recursive) {
+                log_limited!(
+                    warn,
+                    "fs_watch",
+                    "Failed to watch {}: {}",
+                    root.display(),
+                    e
+                );
+                continue;
+            }
+            info!("Watching {} for changes", root.display());
+        }
+        roots.push(root);
+    }
+}
+
+// The innermost root containing a watched path and the path's URL path below
+// it. None for the state directory and paths outside every root.
+fn url_path<'a>(roots: &'a [PathBuf], path: &Path) -> Option<(&'a Path, String)> {
+    let root = roots
+        .iter()
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.as_os_str().len())?;
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Vec<String> = relative
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if parts.is_empty() || parts[0] == STATE_DIR {
+        return None;
+    }
+    Some((root, format!("/{}", parts.join("/"))))
+}
@@ -0,0 +1,62 @@
+// dfsnode: file distribution node serving a data directory over HTTP, kept in
+// sync through BitTorrent. The binary in main.rs parses the command line and
+// wires these together; `testing` runs whole nodes in-process for tests.
+pub mod accesslog;
+pub mod admin;
+pub mod app;
+pub mod assets;
+pub mod assist;
+pub mod auth;
+pub mod autoindex;
+pub mod btsession;
+pub mod btstats;
+pub mod cache;
+pub mod cas;
+pub mod challenge;
+pub mod checksums;
+pub mod cluster;
+pub mod compression;
+pub mod conceal;
+pub mod config;
+pub mod dns;
+pub mod etag;
+pub mod gossip;
+pub mod handlers;
+pub mod hotcache;
+pub mod integrity;
+pub mod jobs;
+pub mod journal;
+pub mod limits;
+pub mod logging;
+pub mod manifest;
+pub mod metrics;
+pub mod mirror;
+pub mod mmap;
+pub mod net;
+pub mod origin;
+pub mod outliers;
+pub mod pipeline;
+pub mod pool;
+pub mod quota;
+pub mod range;
+pub mod readahead;
+pub mod realip;
+pub mod reannounce;
+pub mod redirect;
+pub mod report;
+pub mod resources;
+pub mod response;
+pub mod rollout;
+pub mod selftest;
+pub mod server;
+pub mod signature;
+pub mod signing;
+pub mod sync;
+pub mod testing;
+pub mod throttle;
+pub mod tls;
+pub mod tombstone;
+pub mod torrents;
+pub mod urlpath;
+pub mod warm;
+pub mod webhook;
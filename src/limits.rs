@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use moka::future::Cache;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Duration;
+
+// Idle limiter keys are dropped after this long
+const LIMITER_IDLE_SECS: u64 = 3600;
+const LIMITER_CAPACITY: u64 = 65536;
+// Key of the node-wide max_transfers limit, never a valid IP address
+pub const GLOBAL_LIMIT_KEY: &str = "*";
+// Key of the compression max_concurrent limit
+pub const COMPRESSION_LIMIT_KEY: &str = "compression";
+
+struct LimitEntry {
+    limit: u32,
+    semaphore: Arc<Semaphore>,
+}
+
+impl LimitEntry {
+    fn new(limit: u32) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            semaphore: Arc::new(Semaphore::new(limit as usize)),
+        })
+    }
+}
+
+/// Concurrency limits keyed by string (path prefix, client IP, ...).
+/// Permits are held for the lifetime of a transfer.
+pub struct KeyedLimiter {
+    entries: Cache<String, Arc<LimitEntry>>,
+}
+
+impl KeyedLimiter {
+    pub fn new() -> Self {
+        Self {
+            entries: Cache::builder()
+                .max_capacity(LIMITER_CAPACITY)
+                .time_to_idle(Duration::from_secs(LIMITER_IDLE_SECS))
+                .build(),
+        }
+    }
+
+    /// Take a permit for `key` without waiting, `None` if `limit` transfers are already running
+    pub async fn try_acquire(&self, key: &str, limit: u32) -> Option<OwnedSemaphorePermit> {
+        let mut entry = self
+            .entries
+            .get_with_by_ref(key, async { LimitEntry::new(limit) })
+            .await;
+        // Limit changed in config, start over with the new size
+        if entry.limit != limit {
+            entry = LimitEntry::new(limit);
+            self.entries.insert(key.to_string(), entry.clone()).await;
+        }
+        entry.semaphore.clone().try_acquire_owned().ok()
+    }
+}
+
+impl Default for KeyedLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
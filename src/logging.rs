@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use tokio::time::{Duration, interval};
+use tracing::warn;
+
+// Each log key may emit LOG_BURST messages per window, the rest are counted and summarized
+const LOG_WINDOW_SECS: u64 = 10;
+const LOG_BURST: u32 = 5;
+
+lazy_static::lazy_static! {
+    pub static ref LOG_LIMITER: LogLimiter =
+        LogLimiter::new(Duration::from_secs(LOG_WINDOW_SECS), LOG_BURST);
+}
+
+// Deterministic 1-in-N sampling
+#[derive(Debug)]
+pub struct Sampler {
+    counter: AtomicU64,
+}
+
+impl Sampler {
+    pub const fn new() -> Self {
+        Self {
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// `rate` is the fraction of calls to accept, 0.0 - 1.0
+    pub fn sample(&self, rate: f64) -> bool {
+        if rate <= 0.0 {
+            return false;
+        }
+        let every = (1.0 / rate.min(1.0)).round().max(1.0) as u64;
+        self.counter.fetch_add(1, Ordering::Relaxed) % every == 0
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct LimitEntry {
+    window_start: Instant,
+    emitted: u32,
+    suppressed: u64,
+}
+
+pub struct LogLimiter {
+    window: Duration,
+    burst: u32,
+    entries: Mutex<HashMap<&'static str, LimitEntry>>,
+}
+
+impl LogLimiter {
+    pub fn new(window: Duration, burst: u32) -> Self {
+        Self {
+            window,
+            burst,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `Some(suppressed)` if a message for `key` should be emitted now,
+    /// where `suppressed` is the number of messages dropped since the last one
+    pub fn check(&self, key: &'static str) -> Option<u64> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(key).or_insert(LimitEntry {
+            window_start: now,
+            emitted: 0,
+            suppressed: 0,
+        });
+        if now.duration_since(entry.window_start) >= self.window {
+            entry.window_start = now;
+            entry.emitted = 0;
+        }
+        if entry.emitted < self.burst {
+            entry.emitted += 1;
+            Some(std::mem::take(&mut entry.suppressed))
+        } else {
+            entry.suppressed += 1;
+            None
+        }
+    }
+
+    // Take suppressed counts of keys whose window has ended
+    fn drain_expired(&self) -> Vec<(&'static str, u64)> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let mut drained = Vec::new();
+        entries.retain(|key, entry| {
+            if now.duration_since(entry.window_start) < self.window {
+                return true;
+            }
+            if entry.suppressed > 0 {
+                drained.push((*key, entry.suppressed));
+            }
+            false
+        });
+        drained
+    }
+}
+
+/// Log through `tracing` with per-key rate limiting, e.g.
+/// `log_limited!(error, "serve_connection", "Error serving connection: {:?}", err)`
+macro_rules! log_limited {
+    ($level:ident, $key:expr, $($arg:tt)+) => {
+        if let Some(suppressed) = $crate::logging::LOG_LIMITER.check($key) {
+            if suppressed > 0 {
+                tracing::$level!(
+                    "{} (previous message repeated {} times)",
+                    format_args!($($arg)+),
+                    suppressed
+                );
+            } else {
+                tracing::$level!($($arg)+);
+            }
+        }
+    };
+}
+pub(crate) use log_limited;
+
+// Report suppressed messages even when the error burst stopped
+pub async fn log_flush_task() {
+    let mut interval = interval(Duration::from_secs(LOG_WINDOW_SECS));
+    loop {
+        interval.tick().await;
+        for (key, suppressed) in LOG_LIMITER.drain_expired() {
+            warn!("{}: previous message repeated {} times", key, suppressed);
+        }
+    }
+}
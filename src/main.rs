@@ -1,30 +1,31 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use hyper_util::rt::TokioIo;
 use tokio::net::TcpListener;
-use tokio::sync::Semaphore;
-use tracing::{error, info, warn};
-
-mod app;
-mod autoindex;
-mod cache;
-mod config;
-mod handlers;
-mod metrics;
-mod response;
-mod signature;
-
-use app::{AppState, parse_central_url};
-use config::{config_refresh_task, load_config_from_central, load_config_from_file};
-use handlers::handle_request;
-use metrics::{ACTIVE_CONNECTIONS, register_metrics};
-
-// Connection pool to limit concurrent connections
-const MAX_CONNECTIONS: usize = 2048;
+use tracing::{info, warn};
+
+use dfsnode::accesslog::{AccessLog, AccessLogConfig};
+use dfsnode::app::{AppState, HttpClientOptions, parse_central_url};
+use dfsnode::config::{config_refresh_task, reload_config, torrent_schedule_task};
+use dfsnode::dns::{DnsOptions, IpPreference, parse_dns_server};
+use dfsnode::hotcache::HotFileCache;
+use dfsnode::journal::ChangeJournal;
+use dfsnode::logging::log_flush_task;
+use dfsnode::metrics::{MetricsOptions, parse_metric_label, register_metrics};
+use dfsnode::net::{TcpTuning, reuseport_listeners};
+use dfsnode::outliers::{OUTLIER_TRACKER, OutlierTracker};
+use dfsnode::resources::{ResourceLimits, ResourceMonitor};
+use dfsnode::server::{Acceptor, serve_listeners};
+use dfsnode::throttle::ByteBudget;
+use dfsnode::tls::{Tls, TlsConfig};
+use dfsnode::{
+    btsession, btstats, config, gossip, journal, quota, reannounce, report, selftest, tls,
+    tombstone, webhook,
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "dfsnode")]
@@ -45,9 +46,168 @@ struct Args {
     #[arg(long, default_value = "8093")]
     port: u16,
 
+    /// PEM certificate chain, serves HTTPS instead of HTTP on --port.
+    /// Reloaded automatically when the file changes
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Write one JSON line per completed response to this file, rotated by
+    /// size. Overrides the config's access_log section
+    #[arg(long)]
+    access_log: Option<PathBuf>,
+
+    /// Size in bytes at which --access-log is rotated (default 100MB)
+    #[arg(long, requires = "access_log")]
+    access_log_max_bytes: Option<u64>,
+
+    /// Rotated --access-log files kept (default 5)
+    #[arg(long, requires = "access_log")]
+    access_log_keep: Option<u32>,
+
+    /// Ask the node already running on --port to re-announce all torrents and
+    /// reconnect their initial peers, then exit
+    #[arg(long)]
+    reannounce: bool,
+
+    /// Management token for --reannounce
+    #[arg(long)]
+    management_token: Option<String>,
+
+    /// Start without the self-test (data directory write/read, loopback fetch
+    /// of a signed file, metrics registration) run before listening
+    #[arg(long)]
+    skip_self_test: bool,
+
     /// BitTorrent port to listen on (0 for random port)
     #[arg(long, default_value = "0")]
     bt_port: u16,
+
+    /// Torrents hash checked at the same time, the rest wait in a queue
+    /// (default: librqbit's limit)
+    #[arg(long)]
+    bt_hash_check_concurrency: Option<usize>,
+
+    /// Disable all mutating admin endpoints (WebDAV writes etc.) regardless of config
+    #[arg(long)]
+    read_only: bool,
+
+    /// Proxy for central and origin requests (http://, https:// or socks5://)
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Comma separated hosts that bypass --proxy
+    #[arg(long)]
+    no_proxy: Option<String>,
+
+    /// SOCKS5 proxy for outgoing BitTorrent peer connections
+    #[arg(long)]
+    bt_proxy: Option<String>,
+
+    /// DNS server for outbound HTTP requests (ip or ip:port), can be repeated
+    #[arg(long, value_parser = parse_dns_server)]
+    dns_server: Vec<SocketAddr>,
+
+    /// Resolve outbound HTTP hosts via DNS-over-HTTPS (cloudflare, google, quad9)
+    #[arg(long)]
+    dns_over_https: Option<String>,
+
+    /// Address family preference for outbound HTTP requests
+    #[arg(long, value_enum, default_value_t = IpPreference::Auto)]
+    ip_preference: IpPreference,
+
+    /// Source IP for outbound central/origin requests
+    #[arg(long)]
+    bind_address: Option<IpAddr>,
+
+    /// Network interface for outbound central/origin requests and BitTorrent connections
+    #[arg(long)]
+    bind_interface: Option<String>,
+
+    /// Disk read budget for HTTP file responses in bytes per second, leaves
+    /// disk bandwidth for BitTorrent hash checks and writes
+    #[arg(long)]
+    disk_read_limit: Option<u64>,
+
+    /// Watch the data directory for changes, keeping caches fresh and serving
+    /// incremental change lists at /-/changes
+    #[arg(long)]
+    watch: bool,
+
+    /// Memory in bytes for keeping frequently requested files cached, files are
+    /// promoted and demoted automatically by request rate
+    #[arg(long)]
+    hot_cache_size: Option<u64>,
+
+    /// Largest file in bytes eligible for the hot file cache
+    #[arg(long, default_value_t = 8 * 1024 * 1024)]
+    hot_file_max_size: u64,
+
+    /// Prefix for all exported metric names, e.g. "cdn" exports cdn_dfs_requests_total
+    #[arg(long)]
+    metrics_prefix: Option<String>,
+
+    /// Static label added to all exported metrics (key=value), can be repeated
+    #[arg(long, value_parser = parse_metric_label)]
+    metrics_label: Vec<(String, String)>,
+
+    /// Soft RSS limit in bytes, new connections get 503 above it
+    #[arg(long)]
+    max_rss: Option<u64>,
+
+    /// Soft open file descriptor limit, new connections get 503 above it
+    #[arg(long)]
+    max_open_fds: Option<u64>,
+
+    /// Soft tokio task count limit, new connections get 503 above it
+    #[arg(long)]
+    max_tasks: Option<u64>,
+
+    /// Log the slowest PERCENT of file transfers every --slow-transfer-interval seconds
+    #[arg(long)]
+    slow_transfer_percent: Option<f64>,
+
+    /// Reporting interval for --slow-transfer-percent
+    #[arg(long, default_value = "60")]
+    slow_transfer_interval: u64,
+
+    /// SO_SNDBUF for accepted connections, in bytes
+    #[arg(long)]
+    tcp_send_buffer: Option<usize>,
+
+    /// SO_RCVBUF for accepted connections, in bytes
+    #[arg(long)]
+    tcp_recv_buffer: Option<usize>,
+
+    /// TCP_NOTSENT_LOWAT for accepted connections, in bytes
+    #[arg(long)]
+    tcp_notsent_lowat: Option<u32>,
+
+    /// Idle seconds before TCP keepalive probes start on accepted connections
+    #[arg(long)]
+    tcp_keepalive_time: Option<u64>,
+
+    /// Seconds between TCP keepalive probes
+    #[arg(long)]
+    tcp_keepalive_interval: Option<u64>,
+
+    /// Unanswered TCP keepalive probes before the connection is dropped
+    #[arg(long)]
+    tcp_keepalive_retries: Option<u32>,
+
+    /// Expect a HAProxy PROXY protocol (v1 or v2) header on every connection
+    /// and use the client address it carries. Only for ports reachable solely
+    /// by the load balancer
+    #[arg(long)]
+    proxy_protocol: bool,
+
+    /// Accept loops, each on its own SO_REUSEPORT socket so the kernel spreads
+    /// new connections over them (Unix only above 1)
+    #[arg(long, default_value_t = 1)]
+    acceptors: usize,
 }
 
 #[tokio::main]
@@ -62,11 +222,23 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    // Register metrics
-    register_metrics()?;
-
     let args = Args::parse();
 
+    if args.reannounce {
+        return reannounce::request_reannounce(
+            args.port,
+            args.tls_cert.is_some(),
+            args.management_token.as_deref(),
+        )
+        .await;
+    }
+
+    // Register metrics
+    register_metrics(MetricsOptions {
+        prefix: args.metrics_prefix.clone(),
+        labels: args.metrics_label.iter().cloned().collect(),
+    })?;
+
     // Validate arguments
     if args.central.is_some() && args.config.is_some() {
         anyhow::bail!("Cannot specify both --central and --config");
@@ -85,8 +257,11 @@ async fn main() -> Result<()> {
         (None, None, None)
     };
 
+    let session_dir = btsession::prepare_session_dir(&data_dir)
+        .await
+        .context("Failed to prepare BitTorrent session directory")?;
     let bt_session = librqbit::Session::new_with_opts(
-        std::env::temp_dir(),
+        session_dir,
         librqbit::SessionOptions {
             disable_dht: true,
             listen: Some(librqbit::ListenerOptions {
@@ -95,29 +270,75 @@ async fn main() -> Result<()> {
                 enable_upnp_port_forwarding: false,
                 utp_opts: None,
             }),
+            bind_device_name: args.bind_interface.clone(),
+            concurrent_init_limit: args.bt_hash_check_concurrency,
+            connect: Some(librqbit::ConnectionOptions {
+                proxy_url: args.bt_proxy.clone(),
+                ..Default::default()
+            }),
             ..Default::default()
         },
     )
     .await
     .context("Failed to create BitTorrent session")?;
 
-    let state = AppState::new(data_dir, central_url, auth_header, server_id, bt_session);
+    let http_options = HttpClientOptions {
+        proxy: args.proxy,
+        no_proxy: args.no_proxy,
+        dns: DnsOptions {
+            servers: args.dns_server,
+            doh: args.dns_over_https,
+            ip_preference: args.ip_preference,
+        },
+        local_address: args.bind_address,
+        interface: args.bind_interface,
+    };
+    let mut state = AppState::new(
+        data_dir,
+        central_url,
+        auth_header,
+        server_id,
+        bt_session,
+        &http_options,
+        args.read_only,
+    )?;
+    state.config_path = args.config.clone();
+    if let Some(bytes_per_sec) = args.disk_read_limit.filter(|limit| *limit > 0) {
+        let budget = ByteBudget::new(bytes_per_sec);
+        tokio::spawn(budget.clone().refill_task());
+        state.disk_budget = Some(budget);
+    }
+    if let Some(capacity) = args.hot_cache_size.filter(|size| *size > 0) {
+        let hot_files = HotFileCache::new(capacity, args.hot_file_max_size);
+        tokio::spawn(hot_files.clone().decay_task());
+        state.hot_files = Some(hot_files);
+    }
+    if args.watch {
+        state.journal = Some(Arc::new(ChangeJournal::new()));
+        journal::start_watcher(&state).context("Failed to watch data directory")?;
+    }
+
+    // Webhook delivery, events are only sent once webhooks are configured
+    webhook::start(&state);
+    report::install_panic_hook(state.state_dir.clone());
 
-    // Load initial config
-    if let Some(config_path) = args.config {
-        load_config_from_file(&state.config, &config_path, &state).await?;
-    } else {
-        load_config_from_central(
-            &state.config,
-            state.central_url.as_ref().unwrap(),
-            state.server_id.as_deref(),
-            state.auth_header.as_deref(),
-            &state.http_client,
-            &state,
-        )
-        .await?;
+    // Load initial config, falling back to a restored snapshot while the source is down
+    if let Err(e) = reload_config(&state).await {
+        match config::load_saved_snapshot(&state).await? {
+            Some(version) => warn!(
+                "Failed to load config ({:#}), starting from the restored snapshot (version: {})",
+                e, version
+            ),
+            None => return Err(e),
+        }
     }
 
+    // Pick up edits to --config without a restart
+    config::start_config_watcher(&state).context("Failed to watch config file")?;
+
+    // Started after the initial config so a crash from the previous run can be reported
+    report::start(&state);
+
     // Start config refresh task if using central server
     if state.central_url.is_some() {
         let config_clone = state.config.clone();
@@ -139,46 +360,95 @@ async fn main() -> Result<()> {
         });
     }
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
-    let listener = TcpListener::bind(addr).await?;
+    // Per-torrent progress for the central scheduler
+    tokio::spawn(config::status_report_task(state.clone()));
 
-    info!("Gateway listening on {}", addr);
+    // Add scheduled torrents once their download window opens
+    tokio::spawn(torrent_schedule_task(state.clone()));
+    tokio::spawn(btstats::persist_task(state.clone()));
+    tokio::spawn(quota::quota_task(state.clone()));
+    tokio::spawn(tombstone::expiry_task(state.clone()));
+    tokio::spawn(gossip::gossip_task(state.clone()));
 
-    // Semaphore to limit concurrent connections
-    let semaphore = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    let tcp_tuning = TcpTuning {
+        send_buffer: args.tcp_send_buffer,
+        recv_buffer: args.tcp_recv_buffer,
+        notsent_lowat: args.tcp_notsent_lowat,
+        keepalive_time: args.tcp_keepalive_time.map(Duration::from_secs),
+        keepalive_interval: args.tcp_keepalive_interval.map(Duration::from_secs),
+        keepalive_retries: args.tcp_keepalive_retries,
+    };
+
+    if let Some(percent) = args.slow_transfer_percent.filter(|percent| *percent > 0.0) {
+        let tracker = OUTLIER_TRACKER.get_or_init(|| OutlierTracker::new(percent));
+        tokio::spawn(tracker.report_task(Duration::from_secs(args.slow_transfer_interval.max(1))));
+    }
 
-    loop {
-        let (stream, _) = listener.accept().await?;
+    let resource_monitor = Arc::new(ResourceMonitor::new(ResourceLimits {
+        max_rss_bytes: args.max_rss,
+        max_open_fds: args.max_open_fds,
+        max_tasks: args.max_tasks,
+    }));
+    tokio::spawn(resource_monitor.clone().run());
 
-        // Acquire semaphore permit
-        let permit = match semaphore.clone().acquire_owned().await {
-            Ok(permit) => permit,
-            Err(_) => {
-                warn!("Failed to acquire connection permit, dropping connection");
-                continue;
-            }
-        };
+    // Command line certificate paths win over the config's tls section
+    let cli_tls = args
+        .tls_cert
+        .zip(args.tls_key)
+        .map(|(cert, key)| TlsConfig { cert, key });
+    let tls = match cli_tls.clone().or_else(|| state.config.load().tls.clone()) {
+        Some(files) => {
+            let tls = Tls::new(files).context("Failed to load TLS certificate")?;
+            tokio::spawn(tls::reload_task(tls.clone(), state.clone(), cli_tls));
+            Some(tls)
+        }
+        None => None,
+    };
 
-        let io = TokioIo::new(stream);
-        let state = state.clone();
+    // Refuse to start rather than serve broken traffic
+    if !args.skip_self_test {
+        selftest::run(&state).await?;
+    }
 
-        ACTIVE_CONNECTIONS.inc();
+    // Started after the self-test so its loopback requests stay out of the log
+    let access_log = args
+        .access_log
+        .map(|path| AccessLogConfig {
+            path,
+            max_bytes: args.access_log_max_bytes,
+            keep: args.access_log_keep,
+        })
+        .or_else(|| state.config.load().access_log.clone());
+    if let Some(access_log) = access_log {
+        AccessLog::start(&access_log)
+            .await
+            .context("Failed to open access log")?;
+    }
 
-        tokio::task::spawn(async move {
-            let _permit = permit; // Hold permit for connection lifetime
+    let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
+    let listeners = if args.acceptors > 1 {
+        reuseport_listeners(addr, args.acceptors)
+            .context("Failed to bind SO_REUSEPORT listeners")?
+    } else {
+        vec![TcpListener::bind(addr).await?]
+    };
 
-            let result = hyper::server::conn::http1::Builder::new()
-                .serve_connection(
-                    io,
-                    hyper::service::service_fn(move |req| handle_request(state.clone(), req)),
-                )
-                .await;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    info!(
+        "Gateway listening on {} ({}, {} acceptors)",
+        addr,
+        scheme,
+        listeners.len()
+    );
 
-            ACTIVE_CONNECTIONS.dec();
+    tokio::spawn(log_flush_task());
 
-            if let Err(err) = result {
-                error!("Error serving connection: {:?}", err);
-            }
-        });
-    }
+    let acceptor = Acceptor {
+        tls,
+        tcp_tuning,
+        resource_monitor,
+        proxy_protocol: args.proxy_protocol,
+    };
+    serve_listeners(state, listeners, acceptor).await;
+    Ok(())
 }
@@ -0,0 +1,254 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppState;
+use crate::auth::{AuthRequest, Authorizer};
+use crate::checksums::FileHashes;
+use crate::sync::UrlSigner;
+
+// Files are compared in chunks of this size
+pub const CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+// Refuse to walk trees larger than this
+const MAX_MANIFEST_FILES: usize = 200_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileManifest {
+    // Relative to the manifest root, '/' separated
+    pub path: String,
+    pub size: u64,
+    // sha256 hex of the whole file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    // sha256 hex of each CHUNK_SIZE chunk
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub chunk_size: u64,
+    pub files: Vec<FileManifest>,
+}
+
+/// Regular files under `root`, as (relative path, absolute path, metadata).
+/// Hidden entries (starting with '.') are skipped like in autoindex.
+pub async fn walk_files(root: &Path) -> std::io::Result<Vec<(String, PathBuf, std::fs::Metadata)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![(String::new(), root.to_path_buf())];
+    while let Some((prefix, dir)) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') {
+                continue;
+            }
+            let relative = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                pending.push((relative, entry.path()));
+            } else if metadata.is_file() {
+                files.push((relative, entry.path(), metadata));
+                if files.len() > MAX_MANIFEST_FILES {
+                    return Err(std::io::Error::other("too many files for a manifest"));
+                }
+            }
+        }
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(files)
+}
+
+/// Which hashes a manifest carries. Hashing a whole tree is expensive, only
+/// management requests may have missing hashes computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestHashes {
+    None,
+    // Hashes already in the checksum database
+    Known,
+    Compute,
+}
+
+/// A file below a manifest's directory that the request may fetch
+pub struct ManifestFile {
+    // Relative to the manifest root, '/' separated
+    pub path: String,
+    // Request path of the file
+    pub url_path: String,
+    pub size: u64,
+    // Unix seconds
+    pub mtime: i64,
+    pub hashes: Option<Arc<FileHashes>>,
+    // URL signing of the path config the file falls under
+    signing_token: Option<String>,
+    expire_seconds: u32,
+}
+
+impl ManifestFile {
+    pub fn signer(&self) -> UrlSigner<'_> {
+        UrlSigner {
+            token: self.signing_token.as_deref(),
+            expire_seconds: self.expire_seconds,
+        }
+    }
+}
+
+/// Files under `dir`, served as `url_path`, that `request` may fetch. Each is
+/// checked against the path config it falls under, so nested prefixes keep
+/// their own removal, release, embargo and auth rules and sign their own URLs.
+/// `request` has already passed the authorizers of `url_path` itself.
+pub async fn manifest_files(
+    state: &AppState,
+    dir: &Path,
+    url_path: &str,
+    request: &AuthRequest<'_>,
+    hashes: ManifestHashes,
+) -> std::io::Result<Vec<ManifestFile>> {
+    let config = state.config.load_full();
+    let now = chrono::Utc::now().timestamp();
+    let dir_prefix = config.find_path_prefix(url_path);
+    let base = url_path.trim_end_matches('/');
+    let mut files = Vec::new();
+    for (relative, path, metadata) in walk_files(dir).await? {
+        let file_url = format!("{}/{}", base, relative);
+        // A nested prefix with its own root serves another file at this URL
+        let served = config
+            .data_root(&file_url, &state.data_dir)
+            .join(file_url.trim_start_matches('/'));
+        if served != path
+            || state.tombstones.find(&file_url).is_some()
+            || config.pending_release(&file_url, now).is_some()
+        {
+            continue;
+        }
+        let path_config = config.find_path_config(&file_url);
+        if path_config
+            .and_then(|pc| pc.available_after)
+            .is_some_and(|available_after| now < available_after)
+        {
+            continue;
+        }
+        let nested = config.find_path_prefix(&file_url) != dir_prefix;
+        let chain = path_config
+            .and_then(|pc| pc.authorizer.as_ref())
+            .filter(|_| nested);
+        if let Some(chain) = chain {
+            let file_request = AuthRequest {
+                path: &file_url,
+                query: request.query,
+                headers: request.headers,
+                client_ip: request.client_ip,
+            };
+            if chain.authorize(&file_request).is_err() {
+                continue;
+            }
+        }
+        let file_hashes = match hashes {
+            ManifestHashes::None => None,
+            ManifestHashes::Known => state.checksums.lookup(&path, &metadata).await,
+            ManifestHashes::Compute => Some(state.checksums.hashes(&path, &metadata).await?),
+        };
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(0, |mtime| mtime.as_secs() as i64);
+        files.push(ManifestFile {
+            path: relative,
+            url_path: file_url,
+            size: metadata.len(),
+            mtime,
+            hashes: file_hashes,
+            signing_token: path_config
+                .and_then(|pc| pc.signing_token())
+                .map(str::to_string),
+            expire_seconds: path_config
+                .and_then(|pc| pc.signature_expire_seconds)
+                .unwrap_or(3600),
+        });
+    }
+    Ok(files)
+}
+
+impl Manifest {
+    /// Sync manifest of `files`, files without known hashes have no chunks
+    pub fn from_files(files: &[ManifestFile]) -> Self {
+        let files = files
+            .iter()
+            .map(|file| FileManifest {
+                path: file.path.clone(),
+                size: file.size,
+                sha256: file.hashes.as_ref().map(|hashes| hashes.sha256.clone()),
+                chunks: file
+                    .hashes
+                    .as_ref()
+                    .map(|hashes| hashes.chunks.clone())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        Manifest {
+            chunk_size: CHUNK_SIZE,
+            files,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeManifestFormat {
+    Json,
+    // One entry per line, for streaming parsers
+    Ndjson,
+}
+
+impl TreeManifestFormat {
+    /// `?manifest=1` (or `json`) and `?manifest=ndjson` on an autoindex directory
+    pub fn from_query(query: Option<&str>) -> Option<Self> {
+        let parsed = serde_querystring::DuplicateQS::parse(query.unwrap_or("").as_bytes());
+        let manifest_param = parsed
+            .values(b"manifest")
+            .and_then(|v| v.first().cloned().unwrap_or(None))?;
+        match manifest_param.as_ref() {
+            b"ndjson" => Some(TreeManifestFormat::Ndjson),
+            b"0" => None,
+            _ => Some(TreeManifestFormat::Json),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            TreeManifestFormat::Json => "application/json; charset=utf-8",
+            TreeManifestFormat::Ndjson => "application/x-ndjson; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeEntry {
+    // Relative to the manifest root, '/' separated
+    pub path: String,
+    // URL to fetch the file, signed when the directory is
+    pub url: String,
+    pub size: u64,
+    // Unix seconds
+    pub mtime: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl TreeEntry {
+    /// Entry for `file` with a fresh URL, signed when its path is
+    pub fn new(file: &ManifestFile) -> Self {
+        Self {
+            path: file.path.clone(),
+            url: file.signer().url(&file.url_path, None),
+            size: file.size,
+            mtime: file.mtime,
+            sha256: file.hashes.as_ref().map(|hashes| hashes.sha256.clone()),
+        }
+    }
+}
@@ -1,28 +1,521 @@
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use hyper::Method;
+use hyper::http::StatusCode;
+use librqbit::dht::Id20;
 use prometheus::core::{AtomicU64, GenericCounter, GenericGauge};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry,
+};
+
+use crate::btstats::TorrentStatsStore;
+use crate::config::OptimizedConfig;
+use crate::torrents::{peer_connection_summary, torrent_snapshots};
 
 // Global metrics
 lazy_static::lazy_static! {
-    pub static ref HTTP_REQUESTS_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
-        "dfs_requests_total", "Total number of HTTP requests"
+    // prefix is the matched path prefix, "/-/" for node endpoints, "" for unmatched paths
+    pub static ref HTTP_REQUESTS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_requests_total", "HTTP requests by path prefix, method and status class"),
+        &["prefix", "method", "status"]
     ).expect("Failed to create counter");
 
-    pub static ref HTTP_BYTES_SENT_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
-        "dfs_bytes_sent_total", "Total bytes sent in HTTP responses"
+    // File responses are observed when the transfer ends
+    pub static ref HTTP_REQUEST_DURATION_SECONDS: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("dfs_request_duration_seconds", "HTTP request duration including the body transfer")
+            .buckets(vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0]),
+        &["prefix", "method", "status"]
+    ).expect("Failed to create histogram");
+
+    // response="full" for whole-file bodies, "range" for 206 responses
+    pub static ref HTTP_BYTES_SENT_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_bytes_sent_total", "Total bytes sent in HTTP file responses"),
+        &["prefix", "response"]
+    ).expect("Failed to create counter");
+
+    pub static ref FILE_RESPONSES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_file_responses_total", "File responses by status code"),
+        &["status"]
     ).expect("Failed to create counter");
 
     pub static ref ACTIVE_CONNECTIONS: GenericGauge<AtomicU64> = GenericGauge::new(
         "dfs_active_connections", "Number of active connections"
     ).expect("Failed to create gauge");
 
+    pub static ref CONNECTION_ERRORS_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_connection_errors_total", "Connection errors by kind (client_abort, protocol, internal)"),
+        &["kind"]
+    ).expect("Failed to create counter");
+
+    pub static ref BT_LIVE_PEERS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("dfs_bt_live_peers", "Live BitTorrent peer connections by direction and transport"),
+        &["direction", "transport"]
+    ).expect("Failed to create gauge");
+
+    pub static ref BT_CONNECTION_ATTEMPTS: IntGauge = IntGauge::new(
+        "dfs_bt_peer_connection_attempts", "Outbound BitTorrent connection attempts summed over known peers"
+    ).expect("Failed to create gauge");
+
+    pub static ref BT_HANDSHAKE_FAILURES: IntGauge = IntGauge::new(
+        "dfs_bt_peer_handshake_failures", "Outbound BitTorrent connection attempts that never completed, summed over known peers"
+    ).expect("Failed to create gauge");
+
+    pub static ref BT_PEER_ERRORS: IntGauge = IntGauge::new(
+        "dfs_bt_peer_errors", "Errors on established BitTorrent connections summed over known peers"
+    ).expect("Failed to create gauge");
+
+    pub static ref HASH_MISMATCH_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_hash_mismatch_total", "Full-file responses aborted because content did not match the known hash"
+    ).expect("Failed to create counter");
+
+    pub static ref RANGE_NOT_SATISFIABLE_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_range_not_satisfiable_total", "Requests rejected with 416 because no requested range overlaps the file"
+    ).expect("Failed to create counter");
+
+    pub static ref ROLLOUT_REQUESTS_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_rollout_requests_total", "Requests served from a path's rollout root"
+    ).expect("Failed to create counter");
+
+    pub static ref CLUSTER_REDIRECTS_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_cluster_redirects_total", "Requests for missing content redirected to the node owning the path"
+    ).expect("Failed to create counter");
+
+    // result: ok, error
+    pub static ref GOSSIP_EXCHANGES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_gossip_exchanges_total", "Availability announcements exchanged with cluster peers"),
+        &["result"]
+    ).expect("Failed to create counter");
+
+    pub static ref GOSSIP_PEERS: IntGauge = IntGauge::new(
+        "dfs_gossip_peers", "Cluster nodes with a current availability announcement"
+    ).expect("Failed to create gauge");
+
+    // result: served, timeout, error
+    pub static ref PEER_ASSIST_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_peer_assist_total", "Range requests for missing pieces fetched from the swarm on demand"),
+        &["result"]
+    ).expect("Failed to create counter");
+
+    pub static ref PEER_ASSIST_BYTES_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_peer_assist_bytes_total", "Bytes of ranges served from pieces fetched on demand"
+    ).expect("Failed to create counter");
+
+    pub static ref ACCESS_LOG_DROPPED_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_access_log_dropped_total", "Access log lines dropped because the writer fell behind"
+    ).expect("Failed to create counter");
+
+    pub static ref CONCURRENCY_REJECTED_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_concurrency_rejected_total", "Requests rejected by a concurrency limit, by scope (path, global: 503; ip, signature: 429)"),
+        &["scope"]
+    ).expect("Failed to create counter");
+
+    pub static ref TOKIO_ALIVE_TASKS: IntGauge = IntGauge::new(
+        "dfs_tokio_alive_tasks", "Number of alive tokio tasks"
+    ).expect("Failed to create gauge");
+
+    pub static ref LOAD_SHEDDING: IntGauge = IntGauge::new(
+        "dfs_load_shedding", "1 while a resource soft limit is exceeded and new connections are rejected"
+    ).expect("Failed to create gauge");
+
+    pub static ref SHED_CONNECTIONS_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_shed_connections_total", "Connections answered with 503 because of resource soft limits"
+    ).expect("Failed to create counter");
+
+    pub static ref HOT_CACHE_BYTES: IntGauge = IntGauge::new(
+        "dfs_hot_cache_bytes", "Bytes of frequently requested files held in memory"
+    ).expect("Failed to create gauge");
+
+    pub static ref HOT_CACHE_HITS_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_hot_cache_hits_total", "Full-file responses served from the hot file cache"
+    ).expect("Failed to create counter");
+
+    pub static ref OVERFLOW_REDIRECTS_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_overflow_redirects_total", "Requests redirected to an alternate node instead of 503 while over budget"
+    ).expect("Failed to create counter");
+
+    pub static ref BT_SESSION_RECLAIMED_BYTES: IntGauge = IntGauge::new(
+        "dfs_bt_session_reclaimed_bytes", "Bytes of stale BitTorrent session files removed at startup"
+    ).expect("Failed to create gauge");
+
+    pub static ref TLS_HANDSHAKE_ERRORS_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_tls_handshake_errors_total", "TLS handshakes that failed or timed out"
+    ).expect("Failed to create counter");
+
+    pub static ref TLS_CERT_EXPIRY_DAYS: Gauge = Gauge::new(
+        "dfs_tls_cert_expiry_days", "Days until the served TLS certificate expires, negative once expired"
+    ).expect("Failed to create gauge");
+
+    // result: stored, proxied, not_found, error
+    pub static ref ORIGIN_FETCHES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_origin_fetches_total", "Requests for missing files fetched from the path's origin"),
+        &["result"]
+    ).expect("Failed to create counter");
+
+    pub static ref DISK_USAGE_BYTES: IntGauge = IntGauge::new(
+        "dfs_disk_usage_bytes", "Bytes used by served files under the data directory"
+    ).expect("Failed to create gauge");
+
+    pub static ref CACHE_USAGE_BYTES: IntGauge = IntGauge::new(
+        "dfs_cache_usage_bytes", "Disk space taken by origin pulls and thin torrents, bounded by cache_max_bytes"
+    ).expect("Failed to create gauge");
+
+    pub static ref DISK_EVICTIONS_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_disk_evictions_total", "Files (or thin torrents) evicted to stay under max_disk_bytes or cache_max_bytes"
+    ).expect("Failed to create counter");
+
+    pub static ref DISK_EVICTED_BYTES_TOTAL: GenericCounter<AtomicU64> = GenericCounter::new(
+        "dfs_disk_evicted_bytes_total", "Bytes evicted to stay under max_disk_bytes or cache_max_bytes"
+    ).expect("Failed to create counter");
+
+    // Compression ratio per encoding = output / input
+    pub static ref COMPRESSION_INPUT_BYTES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_compression_input_bytes_total", "File bytes read into on-the-fly compression"),
+        &["encoding"]
+    ).expect("Failed to create counter");
+
+    pub static ref COMPRESSION_OUTPUT_BYTES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_compression_output_bytes_total", "Compressed bytes produced by on-the-fly compression"),
+        &["encoding"]
+    ).expect("Failed to create counter");
+
+    // result: the encoding used, "busy" when max_concurrent compressions were
+    // running, or why the policy excluded the file ("too_small", "type", "encoded")
+    pub static ref COMPRESSION_RESPONSES_TOTAL: IntCounterVec = IntCounterVec::new(
+        Opts::new("dfs_compression_responses_total", "Responses on compressing paths by outcome"),
+        &["result"]
+    ).expect("Failed to create counter");
+
+    pub static ref CONFIG_ISSUES: IntGauge = IntGauge::new(
+        "dfs_config_issues", "Path prefix and torrent output mismatches found in the current config"
+    ).expect("Failed to create gauge");
+
     pub static ref CONFIG_VERSION: GenericGauge<AtomicU64> = GenericGauge::new(
         "dfs_config_version", "Current configuration version"
     ).expect("Failed to create gauge");
 }
 
-pub fn register_metrics() -> anyhow::Result<()> {
-    prometheus::register(Box::new(HTTP_REQUESTS_TOTAL.clone()))?;
-    prometheus::register(Box::new(HTTP_BYTES_SENT_TOTAL.clone()))?;
-    prometheus::register(Box::new(ACTIVE_CONNECTIONS.clone()))?;
-    prometheus::register(Box::new(CONFIG_VERSION.clone()))?;
+// --metrics-prefix and --metrics-label, applied to every exported metric
+#[derive(Debug, Clone, Default)]
+pub struct MetricsOptions {
+    pub prefix: Option<String>,
+    pub labels: HashMap<String, String>,
+}
+
+/// Parse a `--metrics-label` value, `key=value`
+pub fn parse_metric_label(value: &str) -> Result<(String, String), String> {
+    let (key, label_value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got {}", value))?;
+    let valid_key = !key.is_empty()
+        && !key.starts_with(|c: char| c.is_ascii_digit())
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_key {
+        return Err(format!("invalid label name: {}", key));
+    }
+    Ok((key.to_string(), label_value.to_string()))
+}
+
+static METRICS_OPTIONS: OnceLock<MetricsOptions> = OnceLock::new();
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+// A registry applying the configured prefix and static labels
+fn new_registry() -> prometheus::Result<Registry> {
+    let options = METRICS_OPTIONS.get_or_init(MetricsOptions::default);
+    let labels = (!options.labels.is_empty()).then(|| options.labels.clone());
+    Registry::new_custom(options.prefix.clone(), labels)
+}
+
+/// All global metrics, for the /-/metrics endpoint
+pub fn gather() -> Vec<MetricFamily> {
+    REGISTRY.get().map(Registry::gather).unwrap_or_default()
+}
+
+pub fn register_metrics(options: MetricsOptions) -> anyhow::Result<()> {
+    METRICS_OPTIONS
+        .set(options)
+        .map_err(|_| anyhow::anyhow!("metrics already registered"))?;
+    let registry = new_registry()?;
+    #[cfg(target_os = "linux")]
+    registry.register(Box::new(
+        prometheus::process_collector::ProcessCollector::for_self(),
+    ))?;
+    registry.register(Box::new(HTTP_REQUESTS_TOTAL.clone()))?;
+    registry.register(Box::new(HTTP_REQUEST_DURATION_SECONDS.clone()))?;
+    registry.register(Box::new(HTTP_BYTES_SENT_TOTAL.clone()))?;
+    registry.register(Box::new(FILE_RESPONSES_TOTAL.clone()))?;
+    registry.register(Box::new(ACTIVE_CONNECTIONS.clone()))?;
+    registry.register(Box::new(CONFIG_VERSION.clone()))?;
+    registry.register(Box::new(CONFIG_ISSUES.clone()))?;
+    registry.register(Box::new(ORIGIN_FETCHES_TOTAL.clone()))?;
+    registry.register(Box::new(COMPRESSION_INPUT_BYTES_TOTAL.clone()))?;
+    registry.register(Box::new(COMPRESSION_OUTPUT_BYTES_TOTAL.clone()))?;
+    registry.register(Box::new(COMPRESSION_RESPONSES_TOTAL.clone()))?;
+    registry.register(Box::new(DISK_USAGE_BYTES.clone()))?;
+    registry.register(Box::new(CACHE_USAGE_BYTES.clone()))?;
+    registry.register(Box::new(DISK_EVICTIONS_TOTAL.clone()))?;
+    registry.register(Box::new(DISK_EVICTED_BYTES_TOTAL.clone()))?;
+    registry.register(Box::new(TLS_HANDSHAKE_ERRORS_TOTAL.clone()))?;
+    registry.register(Box::new(TLS_CERT_EXPIRY_DAYS.clone()))?;
+    registry.register(Box::new(CONNECTION_ERRORS_TOTAL.clone()))?;
+    registry.register(Box::new(HASH_MISMATCH_TOTAL.clone()))?;
+    registry.register(Box::new(RANGE_NOT_SATISFIABLE_TOTAL.clone()))?;
+    registry.register(Box::new(ACCESS_LOG_DROPPED_TOTAL.clone()))?;
+    registry.register(Box::new(ROLLOUT_REQUESTS_TOTAL.clone()))?;
+    registry.register(Box::new(CLUSTER_REDIRECTS_TOTAL.clone()))?;
+    registry.register(Box::new(GOSSIP_EXCHANGES_TOTAL.clone()))?;
+    registry.register(Box::new(GOSSIP_PEERS.clone()))?;
+    registry.register(Box::new(PEER_ASSIST_TOTAL.clone()))?;
+    registry.register(Box::new(PEER_ASSIST_BYTES_TOTAL.clone()))?;
+    registry.register(Box::new(CONCURRENCY_REJECTED_TOTAL.clone()))?;
+    registry.register(Box::new(TOKIO_ALIVE_TASKS.clone()))?;
+    registry.register(Box::new(LOAD_SHEDDING.clone()))?;
+    registry.register(Box::new(SHED_CONNECTIONS_TOTAL.clone()))?;
+    registry.register(Box::new(HOT_CACHE_BYTES.clone()))?;
+    registry.register(Box::new(HOT_CACHE_HITS_TOTAL.clone()))?;
+    registry.register(Box::new(OVERFLOW_REDIRECTS_TOTAL.clone()))?;
+    registry.register(Box::new(BT_SESSION_RECLAIMED_BYTES.clone()))?;
+    registry.register(Box::new(BT_LIVE_PEERS.clone()))?;
+    registry.register(Box::new(BT_CONNECTION_ATTEMPTS.clone()))?;
+    registry.register(Box::new(BT_HANDSHAKE_FAILURES.clone()))?;
+    registry.register(Box::new(BT_PEER_ERRORS.clone()))?;
+    REGISTRY
+        .set(registry)
+        .map_err(|_| anyhow::anyhow!("metrics already registered"))?;
     Ok(())
 }
+
+// Anything else is counted as "other", clients choose the method string
+const METRIC_METHODS: &[&str] = &[
+    "GET", "HEAD", "OPTIONS", "POST", "PUT", "DELETE", "PROPFIND", "MKCOL", "MOVE", "COPY",
+];
+
+/// Labels of dfs_requests_total and dfs_request_duration_seconds
+#[derive(Debug, Clone)]
+pub struct RequestLabels {
+    pub prefix: String,
+    pub method: Method,
+}
+
+impl RequestLabels {
+    pub fn new(prefix: Option<String>, path: &str, method: &Method) -> Self {
+        let prefix = prefix.unwrap_or_else(|| {
+            if path.starts_with("/-/") {
+                "/-/".to_string()
+            } else {
+                String::new()
+            }
+        });
+        Self {
+            prefix,
+            method: method.clone(),
+        }
+    }
+
+    /// Count a completed request
+    pub fn record(&self, status: StatusCode, elapsed: Duration) {
+        let method = self.method.as_str();
+        let method = if METRIC_METHODS.contains(&method) {
+            method
+        } else {
+            "other"
+        };
+        let labels = [self.prefix.as_str(), method, status_class(status)];
+        HTTP_REQUESTS_TOTAL.with_label_values(&labels).inc();
+        HTTP_REQUEST_DURATION_SECONDS
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+// librqbit state of a running torrent, as opposed to initializing, paused or error
+const TORRENT_LIVE_STATE: &str = "live";
+
+// Refresh BitTorrent peer gauges, called on each scrape
+pub fn update_bt_metrics(bt_api: &librqbit::Api) {
+    let summary = peer_connection_summary(bt_api);
+    BT_LIVE_PEERS.reset();
+    for ((direction, transport), count) in &summary.live {
+        BT_LIVE_PEERS
+            .with_label_values(&[*direction, transport.as_str()])
+            .set(*count);
+    }
+    BT_CONNECTION_ATTEMPTS.set(summary.attempts);
+    BT_HANDSHAKE_FAILURES.set(summary.failures);
+    BT_PEER_ERRORS.set(summary.errors);
+}
+
+// Prometheus label names must match [a-zA-Z_][a-zA-Z0-9_]*
+fn sanitize_label_name(name: &str) -> String {
+    let mut label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if label.is_empty() || label.starts_with(|c: char| c.is_ascii_digit()) {
+        label.insert(0, '_');
+    }
+    if label == "info_hash" || label == "path" {
+        label.insert_str(0, "label_");
+    }
+    label
+}
+
+/// Per-torrent gauges labeled by info_hash, path and the torrent's configured labels.
+/// The label set depends on the config, so they are built in a fresh registry per scrape.
+pub fn gather_torrent_metrics(
+    bt_api: &librqbit::Api,
+    config: &OptimizedConfig,
+    bt_stats: &TorrentStatsStore,
+) -> anyhow::Result<Vec<MetricFamily>> {
+    let label_keys: BTreeSet<String> = config
+        .torrents()
+        .values()
+        .flat_map(|t| t.labels.keys())
+        .map(|key| sanitize_label_name(key))
+        .collect();
+    let mut label_names = vec!["info_hash", "path"];
+    label_names.extend(label_keys.iter().map(String::as_str));
+
+    let download_rate = GaugeVec::new(
+        Opts::new(
+            "dfs_torrent_download_rate_bytes",
+            "Current torrent download rate in bytes per second",
+        ),
+        &label_names,
+    )?;
+    let upload_rate = GaugeVec::new(
+        Opts::new(
+            "dfs_torrent_upload_rate_bytes",
+            "Current torrent upload rate in bytes per second",
+        ),
+        &label_names,
+    )?;
+    let eta = GaugeVec::new(
+        Opts::new(
+            "dfs_torrent_eta_seconds",
+            "Estimated seconds until the torrent completes, absent when stalled",
+        ),
+        &label_names,
+    )?;
+    let progress = IntGaugeVec::new(
+        Opts::new(
+            "dfs_torrent_progress_bytes",
+            "Bytes of the torrent downloaded and verified",
+        ),
+        &label_names,
+    )?;
+    let total = IntGaugeVec::new(
+        Opts::new(
+            "dfs_torrent_total_bytes",
+            "Total size of the torrent in bytes",
+        ),
+        &label_names,
+    )?;
+    let peers = IntGaugeVec::new(
+        Opts::new(
+            "dfs_torrent_live_peers",
+            "Peers with an established connection to the torrent",
+        ),
+        &label_names,
+    )?;
+    let seeding = IntGaugeVec::new(
+        Opts::new(
+            "dfs_torrent_seeding",
+            "1 if the torrent is complete and live (seeding), 0 otherwise",
+        ),
+        &label_names,
+    )?;
+    let lifetime_uploaded = IntCounterVec::new(
+        Opts::new(
+            "dfs_torrent_lifetime_uploaded_bytes_total",
+            "Bytes uploaded to peers over the torrent's lifetime on this node, across restarts",
+        ),
+        &label_names,
+    )?;
+    let lifetime_downloaded = IntCounterVec::new(
+        Opts::new(
+            "dfs_torrent_lifetime_downloaded_bytes_total",
+            "Bytes downloaded from peers over the torrent's lifetime on this node, across restarts",
+        ),
+        &label_names,
+    )?;
+
+    for torrent in torrent_snapshots(bt_api) {
+        let torrent_config = Id20::from_str(&torrent.info_hash)
+            .ok()
+            .and_then(|id| config.torrents().get(&id));
+        let labels: HashMap<String, &str> = torrent_config
+            .map(|t| {
+                t.labels
+                    .iter()
+                    .map(|(key, value)| (sanitize_label_name(key), value.as_str()))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut values = vec![
+            torrent.info_hash.as_str(),
+            torrent_config.map(|t| t.path.as_str()).unwrap_or(""),
+        ];
+        values.extend(
+            label_keys
+                .iter()
+                .map(|key| labels.get(key).copied().unwrap_or("")),
+        );
+
+        download_rate
+            .with_label_values(&values)
+            .set(torrent.download_bytes_per_sec);
+        upload_rate
+            .with_label_values(&values)
+            .set(torrent.upload_bytes_per_sec);
+        progress
+            .with_label_values(&values)
+            .set(torrent.progress_bytes as i64);
+        total
+            .with_label_values(&values)
+            .set(torrent.total_bytes as i64);
+        peers
+            .with_label_values(&values)
+            .set(torrent.live_peers as i64);
+        seeding
+            .with_label_values(&values)
+            .set((torrent.finished && torrent.state == TORRENT_LIVE_STATE) as i64);
+        if let Some(seconds) = torrent.eta_seconds() {
+            eta.with_label_values(&values).set(seconds);
+        }
+        let lifetime = bt_stats.lifetime(&torrent.info_hash);
+        lifetime_uploaded
+            .with_label_values(&values)
+            .inc_by(lifetime.uploaded_bytes);
+        lifetime_downloaded
+            .with_label_values(&values)
+            .inc_by(lifetime.downloaded_bytes);
+    }
+
+    let registry = new_registry()?;
+    registry.register(Box::new(download_rate))?;
+    registry.register(Box::new(upload_rate))?;
+    registry.register(Box::new(eta))?;
+    registry.register(Box::new(progress))?;
+    registry.register(Box::new(total))?;
+    registry.register(Box::new(peers))?;
+    registry.register(Box::new(seeding))?;
+    registry.register(Box::new(lifetime_uploaded))?;
+    registry.register(Box::new(lifetime_downloaded))?;
+    Ok(registry.gather())
+}
@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use hyper::Request;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Semaphore;
+use tokio::time::Duration;
+
+use crate::app::AppState;
+use crate::logging::{Sampler, log_limited};
+
+// Mirrored requests in flight at once, samples beyond this are dropped
+const MAX_INFLIGHT_MIRRORS: usize = 32;
+const MIRROR_TIMEOUT_SECS: u64 = 5;
+
+// Headers that only make sense for the original hop
+const SKIPPED_HEADERS: &[&str] = &[
+    "host",
+    "connection",
+    "keep-alive",
+    "transfer-encoding",
+    "upgrade",
+    "proxy-connection",
+    "te",
+    "trailer",
+    // Credentials stay with this node, the sink is for debugging
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MirrorConfig {
+    // Base URL of the debug sink / staging node, request path and query are appended
+    pub url: String,
+    // Fraction of requests to mirror, 0.0 - 1.0
+    pub sample_rate: f64,
+}
+
+pub struct RequestMirror {
+    sampler: Sampler,
+    permits: Arc<Semaphore>,
+}
+
+impl RequestMirror {
+    pub fn new() -> Self {
+        Self {
+            sampler: Sampler::new(),
+            permits: Arc::new(Semaphore::new(MAX_INFLIGHT_MIRRORS)),
+        }
+    }
+}
+
+impl Default for RequestMirror {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Replay the request line and headers (never the body) to the configured
+/// sink, without credentials: auth headers, cookies, the `$` signature and the
+/// `dfs_*` parameters that bind or mark it
+pub fn mirror_request<B>(state: &AppState, req: &Request<B>) {
+    let config = state.config.load();
+    let Some(mirror) = config.mirror.as_ref() else {
+        return;
+    };
+    if !state.mirror.sampler.sample(mirror.sample_rate) {
+        return;
+    }
+    let Ok(permit) = state.mirror.permits.clone().try_acquire_owned() else {
+        return;
+    };
+
+    let query: Vec<&str> = req
+        .uri()
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| {
+            let name = percent_decode_str(pair.split('=').next().unwrap_or("")).decode_utf8_lossy();
+            !name.is_empty() && name != "$" && !name.starts_with("dfs_")
+        })
+        .collect();
+    let mut url = format!("{}{}", mirror.url.trim_end_matches('/'), req.uri().path());
+    if !query.is_empty() {
+        url.push('?');
+        url.push_str(&query.join("&"));
+    }
+    let mut request = state
+        .http_client
+        .request(req.method().clone(), &url)
+        .timeout(Duration::from_secs(MIRROR_TIMEOUT_SECS));
+    for (name, value) in req.headers() {
+        if !SKIPPED_HEADERS.contains(&name.as_str()) {
+            request = request.header(name, value);
+        }
+    }
+    if let Some(server_id) = &state.server_id {
+        request = request.header("X-Dfs-Mirrored-From", server_id);
+    }
+
+    tokio::spawn(async move {
+        let _permit = permit;
+        // The response body is dropped unread, only the request pattern matters
+        if let Err(e) = request.send().await {
+            log_limited!(
+                debug,
+                "mirror_request",
+                "Failed to mirror request to {}: {}",
+                url,
+                e
+            );
+        }
+    });
+}
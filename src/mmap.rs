@@ -0,0 +1,65 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use moka::future::Cache;
+use tokio::time::Duration;
+
+// Total bytes held, the files are copies rather than page cache mappings
+const MAPPED_FILES_CAPACITY_BYTES: u64 = 256 * 1024 * 1024;
+const MAPPED_FILES_TTI_SECS: u64 = 300;
+
+// (path, size, Last-Modified), a replaced file gets a new entry
+type MappedKey = (PathBuf, u64, String);
+
+/// Contents of small hot files held in memory, so repeated full responses are
+/// served without read syscalls. They are read once rather than memory mapped:
+/// torrents, WebDAV and origin fetches may write the files in place, and
+/// truncating a mapped file faults its readers.
+pub struct MappedFiles {
+    cache: Cache<MappedKey, Bytes>,
+}
+
+impl MappedFiles {
+    pub fn new() -> Self {
+        Self {
+            cache: Cache::builder()
+                .max_capacity(MAPPED_FILES_CAPACITY_BYTES)
+                .weigher(|_, bytes: &Bytes| u32::try_from(bytes.len()).unwrap_or(u32::MAX))
+                .time_to_idle(Duration::from_secs(MAPPED_FILES_TTI_SECS))
+                .build(),
+        }
+    }
+
+    /// Contents of the file, read on first use. None if it can't be read or
+    /// no longer has the expected size.
+    pub async fn get(&self, path: &Path, size: u64, last_modified: &str) -> Option<Bytes> {
+        let key = (path.to_path_buf(), size, last_modified.to_string());
+        if let Some(bytes) = self.cache.get(&key).await {
+            return Some(bytes);
+        }
+        let file_path = path.to_path_buf();
+        let bytes = tokio::task::spawn_blocking(move || read_file(&file_path, size))
+            .await
+            .ok()??;
+        self.cache.insert(key, bytes.clone()).await;
+        Some(bytes)
+    }
+}
+
+impl Default for MappedFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_file(path: &Path, size: u64) -> Option<Bytes> {
+    let file = std::fs::File::open(path).ok()?;
+    if file.metadata().ok()?.len() != size {
+        return None;
+    }
+    let mut contents = Vec::with_capacity(usize::try_from(size).ok()?);
+    // One byte past the size shows the file grew while being read
+    file.take(size + 1).read_to_end(&mut contents).ok()?;
+    (contents.len() as u64 == size).then(|| Bytes::from(contents))
+}
@@ -0,0 +1,181 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use tokio::net::{TcpListener, TcpStream};
+
+// Pending connections per listening socket
+const LISTEN_BACKLOG: i32 = 1024;
+
+// Socket options applied to every accepted connection, unset values keep the OS defaults
+#[derive(Debug, Clone, Default)]
+pub struct TcpTuning {
+    pub send_buffer: Option<usize>,
+    pub recv_buffer: Option<usize>,
+    // Limit unsent data queued in the kernel (Linux/macOS only)
+    pub notsent_lowat: Option<u32>,
+    pub keepalive_time: Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive_retries: Option<u32>,
+}
+
+impl TcpTuning {
+    pub fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let socket = SockRef::from(stream);
+        if let Some(size) = self.send_buffer {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if let Some(lowat) = self.notsent_lowat {
+            set_notsent_lowat(stream, lowat)?;
+        }
+        if let Some(keepalive) = self.keepalive() {
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
+    }
+
+    fn keepalive(&self) -> Option<TcpKeepalive> {
+        if self.keepalive_time.is_none()
+            && self.keepalive_interval.is_none()
+            && self.keepalive_retries.is_none()
+        {
+            return None;
+        }
+        let mut keepalive = TcpKeepalive::new();
+        if let Some(time) = self.keepalive_time {
+            keepalive = keepalive.with_time(time);
+        }
+        #[cfg(not(any(target_os = "openbsd", target_os = "redox", target_os = "solaris")))]
+        if let Some(interval) = self.keepalive_interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        #[cfg(not(any(
+            target_os = "openbsd",
+            target_os = "redox",
+            target_os = "solaris",
+            target_os = "windows"
+        )))]
+        if let Some(retries) = self.keepalive_retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+        Some(keepalive)
+    }
+}
+
+/// An address or CIDR network, as written in IP auth rules and trusted_proxies
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpRule {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRule {
+    // `10.0.0.0/8`, `2001:db8::/32` or a single address
+    pub fn parse(rule: &str) -> Option<Self> {
+        let (address, prefix_len) = match rule.trim().split_once('/') {
+            Some((address, len)) => (address, Some(len.parse::<u8>().ok()?)),
+            None => (rule.trim(), None),
+        };
+        let network: IpAddr = address.parse().ok()?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        (prefix_len <= max_len).then_some(Self {
+            network,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients on a dual-stack socket show up as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for IpRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos"))]
+fn set_notsent_lowat(stream: &TcpStream, lowat: u32) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value = lowat as libc::c_int;
+    // SAFETY: the fd is owned by `stream` for the duration of the call
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_NOTSENT_LOWAT,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn set_notsent_lowat(_stream: &TcpStream, _lowat: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "TCP_NOTSENT_LOWAT is not supported on this platform",
+    ))
+}
+
+/// `count` sockets listening on `addr` with SO_REUSEPORT, the kernel spreads
+/// incoming connections over them. With port 0 the first socket picks the
+/// port and the others join it.
+pub fn reuseport_listeners(addr: SocketAddr, count: usize) -> std::io::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::with_capacity(count);
+    let mut addr = addr;
+    for _ in 0..count.max(1) {
+        let listener = reuseport_listener(addr)?;
+        addr = listener.local_addr()?;
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn reuseport_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn reuseport_listener(_addr: SocketAddr) -> std::io::Result<TcpListener> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
@@ -0,0 +1,236 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
+
+use hyper::body::Bytes;
+use hyper::http::StatusCode;
+use hyper::{Method, Request, Response};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use crate::app::{AppState, STATE_DIR};
+use crate::logging::log_limited;
+use crate::metrics::ORIGIN_FETCHES_TOTAL;
+use crate::quota::tracks_access;
+use crate::response::{FileBody, ResBody, StaticMetrics};
+
+// Whole files are streamed, so only stalls should end a fetch
+const ORIGIN_TIMEOUT_SECS: u64 = 3600;
+// Chunks buffered between the origin download and a slow client
+const CLIENT_CHANNEL_CHUNKS: usize = 16;
+
+// Origin response headers passed on to the client
+const FORWARDED_HEADERS: &[&str] = &[
+    "content-type",
+    "content-length",
+    "content-range",
+    "accept-ranges",
+    "last-modified",
+    "etag",
+];
+
+// Files currently being downloaded into the data directory
+static IN_FLIGHT: LazyLock<Mutex<HashSet<PathBuf>>> = LazyLock::new(Default::default);
+
+// Removes the in-flight marker however the download ends
+struct InFlightGuard(PathBuf);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        IN_FLIGHT.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Fetch a file missing from the data directory from the path's origin.
+/// Full GETs are stored while streaming to the client, so later requests are
+/// served from disk; HEAD, Range and concurrent requests for a file that is
+/// already downloading are proxied without storing.
+pub async fn pull_through<B>(
+    state: &AppState,
+    req: &Request<B>,
+    origin: &str,
+    relative: &str,
+    file_path: &Path,
+) -> Response<ResBody> {
+    let status_response = |status: StatusCode| {
+        Response::builder()
+            .status(status)
+            .body(ResBody::Empty)
+            .unwrap()
+    };
+    let relative = relative.trim_start_matches('/');
+    if relative.is_empty()
+        || relative
+            .split('/')
+            .any(|part| part == ".." || part == STATE_DIR)
+    {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    let url = format!("{}/{}", origin.trim_end_matches('/'), relative);
+    let method = req.method().clone();
+    let range = req.headers().get("range").cloned();
+
+    let mut request = if method == Method::HEAD {
+        state.http_client.head(&url)
+    } else {
+        state.http_client.get(&url)
+    };
+    request = request.timeout(Duration::from_secs(ORIGIN_TIMEOUT_SECS));
+    if let Some(range) = &range {
+        request = request.header("Range", range.as_bytes());
+    }
+    let upstream = match request.send().await {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            ORIGIN_FETCHES_TOTAL.with_label_values(&["error"]).inc();
+            log_limited!(warn, "origin_fetch", "Failed to fetch {}: {}", url, e);
+            return status_response(StatusCode::BAD_GATEWAY);
+        }
+    };
+    let status = upstream.status();
+    if status == StatusCode::NOT_FOUND {
+        ORIGIN_FETCHES_TOTAL.with_label_values(&["not_found"]).inc();
+        return status_response(StatusCode::NOT_FOUND);
+    }
+    if !status.is_success() {
+        ORIGIN_FETCHES_TOTAL.with_label_values(&["error"]).inc();
+        log_limited!(
+            warn,
+            "origin_fetch",
+            "Origin returned {} for {}",
+            status,
+            url
+        );
+        return status_response(StatusCode::BAD_GATEWAY);
+    }
+
+    let mut builder = Response::builder().status(status);
+    for name in FORWARDED_HEADERS {
+        if let Some(value) = upstream.headers().get(*name) {
+            builder = builder.header(*name, value);
+        }
+    }
+
+    // Only one download per file writes to disk
+    let store = method == Method::GET
+        && status == StatusCode::OK
+        && IN_FLIGHT.lock().unwrap().insert(file_path.to_path_buf());
+    let guard = store.then(|| InFlightGuard(file_path.to_path_buf()));
+    ORIGIN_FETCHES_TOTAL
+        .with_label_values(&[if store { "stored" } else { "proxied" }])
+        .inc();
+
+    let expected_len = upstream.content_length();
+    let (sender, receiver) = mpsc::channel(CLIENT_CHANNEL_CHUNKS);
+    let state = state.clone();
+    let target = file_path.to_path_buf();
+    tokio::spawn(async move {
+        let _guard = guard;
+        let result = if store {
+            download_to(&state, upstream, &target, expected_len, sender).await
+        } else {
+            forward(upstream, sender).await
+        };
+        if let Err(e) = result {
+            log_limited!(
+                warn,
+                "origin_fetch",
+                "Origin transfer of {} failed: {}",
+                url,
+                e
+            );
+        }
+    });
+
+    let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    let body = ResBody::Static {
+        inner: FileBody::Stream(Box::pin(stream)),
+        start_time: Instant::now(),
+        metrics: Arc::new(StaticMetrics {
+            method,
+            uri: req.uri().clone(),
+            status,
+            access_log: false,
+            labels: None,
+            access_request: None,
+        }),
+        bytes_sent: 0,
+        verifier: None,
+        permits: Vec::new(),
+        throttle: None,
+        held: None,
+        rate_limit: None,
+    };
+    builder.body(body).unwrap()
+}
+
+type ChunkSender = mpsc::Sender<std::io::Result<Bytes>>;
+
+async fn forward(mut upstream: reqwest::Response, sender: ChunkSender) -> anyhow::Result<()> {
+    while let Some(chunk) = upstream.chunk().await? {
+        if sender.send(Ok(chunk)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Write the origin body to the data directory, feeding the client as it arrives.
+// The download continues if the client goes away, so the file still gets cached.
+async fn download_to(
+    state: &AppState,
+    mut upstream: reqwest::Response,
+    file_path: &Path,
+    expected_len: Option<u64>,
+    sender: ChunkSender,
+) -> anyhow::Result<()> {
+    let dir = file_path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("no parent directory"))?;
+    tokio::fs::create_dir_all(dir).await?;
+    let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    // Hidden, so listings never show a partial download
+    let tmp_path = dir.join(format!(".{}.origin-tmp", name));
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+
+    let mut written = 0u64;
+    let result: anyhow::Result<()> = async {
+        while let Some(chunk) = upstream.chunk().await? {
+            file.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+            // A closed channel means the client left, keep downloading
+            let _ = sender.send(Ok(chunk)).await;
+        }
+        file.flush().await?;
+        if let Some(expected) = expected_len.filter(|expected| *expected != written) {
+            anyhow::bail!("got {} of {} bytes", written, expected);
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        let _ = sender
+            .send(Err(std::io::Error::other("origin transfer failed")))
+            .await;
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&tmp_path, file_path).await?;
+    state.fs_cache.invalidate(&file_path.to_path_buf()).await;
+    if tracks_access(state) {
+        state.quota.touch(file_path);
+        state.quota.stored();
+    }
+    info!(
+        "Stored {} ({} bytes) from origin",
+        file_path.display(),
+        written
+    );
+    Ok(())
+}
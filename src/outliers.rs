@@ -0,0 +1,91 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::time::interval;
+use tracing::info;
+
+// Most outliers logged per interval, regardless of the configured percentage
+const MAX_LOGGED_OUTLIERS: usize = 50;
+
+// Set at startup when --slow-transfer-percent is given
+pub static OUTLIER_TRACKER: OnceLock<OutlierTracker> = OnceLock::new();
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Transfer {
+    duration: Duration,
+    bytes: u64,
+    path: String,
+}
+
+#[derive(Default)]
+struct Window {
+    count: u64,
+    // Min-heap on duration holding the slowest transfers seen so far
+    slowest: BinaryHeap<Reverse<Transfer>>,
+}
+
+/// Keeps the slowest transfers of each interval and logs the top `percent` of them
+pub struct OutlierTracker {
+    percent: f64,
+    window: Mutex<Window>,
+}
+
+impl OutlierTracker {
+    pub fn new(percent: f64) -> Self {
+        Self {
+            percent: percent.clamp(0.0, 100.0),
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    pub fn record(&self, path: &str, bytes: u64, duration: Duration) {
+        let mut window = self.window.lock().unwrap();
+        window.count += 1;
+        let faster_than_all = window.slowest.len() >= MAX_LOGGED_OUTLIERS
+            && window
+                .slowest
+                .peek()
+                .is_some_and(|Reverse(fastest)| duration <= fastest.duration);
+        if faster_than_all {
+            return;
+        }
+        window.slowest.push(Reverse(Transfer {
+            duration,
+            bytes,
+            path: path.to_string(),
+        }));
+        if window.slowest.len() > MAX_LOGGED_OUTLIERS {
+            window.slowest.pop();
+        }
+    }
+
+    pub async fn report_task(&'static self, period: Duration) {
+        let mut interval = interval(period);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let window = std::mem::take(&mut *self.window.lock().unwrap());
+            let wanted = ((window.count as f64) * self.percent / 100.0).ceil() as usize;
+            let mut slowest: Vec<Transfer> = window
+                .slowest
+                .into_iter()
+                .map(|Reverse(transfer)| transfer)
+                .collect();
+            slowest.sort_by(|a, b| b.duration.cmp(&a.duration));
+            for transfer in slowest.into_iter().take(wanted) {
+                let secs = transfer.duration.as_secs_f64();
+                info!(
+                    target: "slow_transfer",
+                    path = %transfer.path,
+                    bytes = transfer.bytes,
+                    duration_ms = transfer.duration.as_millis() as u64,
+                    bytes_per_sec = if secs > 0.0 { (transfer.bytes as f64 / secs) as u64 } else { 0 },
+                    transfers = window.count,
+                    "Slow transfer"
+                );
+            }
+        }
+    }
+}
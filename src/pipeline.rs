@@ -0,0 +1,1372 @@
+// Request pipeline. handle_request wraps every request in the observe layers
+// (metrics and access logs, then conceal), handlers::route_request answers the
+// node's own endpoints and hands data paths to serve_path, which runs them
+// through its stages in order:
+//
+//   admit    state dir, methods, removed or unreleased content, authorizers
+//   rewrite  CAS views map the URL onto the object to serve
+//   resolve  missing files, directories, growing files, 304 and 416
+//   limit    transfer slots, only taken once a file is actually going out
+//   serve    the file, with validators, compression, links and CORS headers
+//
+// A stage either hands the request on or answers it with `Err(response)`.
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hyper::body::{Bytes, Incoming};
+use hyper::http::{HeaderValue, StatusCode};
+use hyper::{Method, Request, Response};
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::accesslog::{ACCESS_LOG, AccessRequest};
+use crate::app::AppState;
+use crate::assist::AssistedRange;
+use crate::auth::{AuthRequest, Authorizer, Grant, signature_only};
+use crate::autoindex::{
+    ListingFormat, ListingOptions, Locale, directory_summary, generate_directory_listing,
+};
+use crate::cache::{FileSystemStatus, check_file_status};
+use crate::cluster::cluster_redirect;
+use crate::compression::{choose_encoding, compress_file, exclusion};
+use crate::conceal::Conceal;
+use crate::config::PathConfig;
+use crate::etag::{Validators, is_not_modified, strip_conditionals};
+use crate::handlers::{
+    embargo_response, has_management_token, is_state_dir_path, overloaded_response, route_request,
+};
+use crate::integrity::{HashVerifier, load_expected_hash};
+use crate::limits::{COMPRESSION_LIMIT_KEY, GLOBAL_LIMIT_KEY};
+use crate::manifest::{ManifestHashes, TreeEntry, TreeManifestFormat, manifest_files};
+use crate::metrics::{
+    COMPRESSION_RESPONSES_TOTAL, CONCURRENCY_REJECTED_TOTAL, RANGE_NOT_SATISFIABLE_TOTAL,
+    ROLLOUT_REQUESTS_TOTAL, RequestLabels,
+};
+use crate::origin::pull_through;
+use crate::pool::PooledFileStream;
+use crate::quota::tracks_access;
+use crate::range::{ByteRangeSpec, is_satisfiable, parse_byte_ranges};
+use crate::readahead::{prefetch_open_file, prefetch_ranges};
+use crate::realip::resolve_client_ip;
+use crate::redirect::{digest_header, duplicate_links, overflow_redirect};
+use crate::response::{FileBody, ResBody};
+use crate::server::{ConnectionContext, RangeCursor, client_ip};
+use crate::signature::{signed_range_header, signed_ranges_match};
+use crate::throttle::RateLimiter;
+use crate::torrents::{TorrentFile, TorrentLayout};
+use crate::urlpath::decode_path;
+
+// Methods accepted for regular file paths
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+const CORS_EXPOSED_HEADERS: &str = "Content-Length, Content-Range, Accept-Ranges, ETag, Last-Modified, Link, Digest, X-Dfs-Continue-Range, X-Dfs-Dir-Entries, X-Dfs-Dir-Size";
+const CORS_MAX_AGE_SECS: u64 = 86400;
+// Directory listings are negotiated on both
+const LISTING_VARY: &str = "Accept, Accept-Language";
+// Size checks of a growing file while a range waits for its data
+const GROW_POLL_MS: u64 = 100;
+// Clients over max_transfers_per_ip or max_per_signature usually just need one
+// of their transfers to finish
+const CLIENT_RETRY_AFTER_SECS: u64 = 5;
+
+type Flow<T = ()> = Result<T, Response<ResBody>>;
+
+fn status_response(status: StatusCode) -> Response<ResBody> {
+    Response::builder()
+        .status(status)
+        .body(ResBody::Empty)
+        .unwrap()
+}
+
+pub async fn handle_request(
+    state: AppState,
+    mut req: Request<Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    // Per-path access logging and metric labels, decided before the request is consumed
+    let (access_log, conceal, labels) = {
+        let config = state.config.load();
+        // Everything after this sees the client behind trusted proxies
+        resolve_client_ip(&mut req, &config.trusted_proxies, config.forwarded_header);
+        let path = req.uri().path();
+        let path_config = config.find_path_config(path);
+        let access_log = match path_config {
+            Some(pc) if pc.access_log.unwrap_or(false) => pc
+                .access_log_sampler
+                .sample(pc.access_log_sample_rate.unwrap_or(1.0)),
+            _ => false,
+        };
+        let labels = RequestLabels::new(config.find_path_prefix(path), path, req.method());
+        (
+            access_log,
+            path_config.and_then(Conceal::from_path_config),
+            labels,
+        )
+    };
+
+    let uri = access_log.then(|| req.uri().clone());
+    let access_request = ACCESS_LOG.get().map(|_| AccessRequest::new(&req));
+    let start_time = Instant::now();
+    let mut result = conceal_request(state, req, conceal).await;
+    match result.as_mut() {
+        Ok(response) => {
+            let status = response.status();
+            // File transfers are counted and logged when the body is dropped, with the bytes sent
+            let deferred = match response.body_mut() {
+                ResBody::Static { metrics, .. } => Arc::get_mut(metrics),
+                _ => None,
+            };
+            match deferred {
+                Some(metrics) => {
+                    metrics.access_log = access_log;
+                    metrics.labels = Some(labels);
+                    metrics.access_request = access_request;
+                }
+                None => {
+                    labels.record(status, start_time.elapsed());
+                    if let Some((log, request)) = ACCESS_LOG.get().zip(access_request.as_ref()) {
+                        let bytes = match response.body() {
+                            ResBody::Bytes(bytes) => bytes.len() as u64,
+                            _ => 0,
+                        };
+                        log.record(request, status, bytes, start_time.elapsed());
+                    }
+                    if let Some(uri) = &uri {
+                        tracing::info!(
+                            target: "access",
+                            "{} {} -> {} ({}ms)",
+                            labels.method,
+                            uri,
+                            status,
+                            start_time.elapsed().as_millis()
+                        );
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            if let Some(uri) = &uri {
+                tracing::info!(target: "access", "{} {} -> error: {}", labels.method, uri, e);
+            }
+        }
+    }
+    result
+}
+
+// Paths with uniform_errors_ms / pad_response_bytes get their responses normalized
+async fn conceal_request(
+    state: AppState,
+    req: Request<Incoming>,
+    conceal: Option<Conceal>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    let Some(conceal) = conceal else {
+        return route_request(state, req).await;
+    };
+    let start_time = Instant::now();
+    let response = route_request(state, req).await?;
+    Ok(conceal.apply(response, start_time).await)
+}
+
+/// Serve a file or directory of the data directory
+pub async fn serve_path(
+    state: AppState,
+    req: Request<Incoming>,
+) -> Result<Response<ResBody>, std::io::Error> {
+    let mut request = match PathRequest::admit(state, req) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+    let flow = async {
+        request.rewrite().await?;
+        request.resolve().await?;
+        request.limit().await
+    }
+    .await;
+    match flow {
+        Ok(()) => request.serve().await,
+        Err(response) => Ok(response),
+    }
+}
+
+// A data path request on its way through the stages
+struct PathRequest {
+    state: AppState,
+    req: Request<Incoming>,
+    path: String,
+    path_config: Option<PathConfig>,
+    // Signed ranges and the credential the authorizers accepted
+    grant: Grant,
+    // Directory the request URI is served from, the path's root or the data directory
+    root: PathBuf,
+    // The file served, `serve_uri` replaces the request URI when set
+    file_path: PathBuf,
+    serve_uri: Option<hyper::Uri>,
+    content_type: Option<mime_guess::Mime>,
+    validators: Option<Validators>,
+    // Opened for the validators and served from, so the body is the version they describe
+    file: Option<(tokio::fs::File, u64)>,
+    // Available size and wanted end of a range into a growing file
+    range_shortfall: Option<(u64, u64)>,
+    // Range of a torrent file fetched from peers instead of read from disk
+    assisted: Option<AssistedRange>,
+    // Released when the body is dropped
+    permits: Vec<OwnedSemaphorePermit>,
+}
+
+impl PathRequest {
+    fn admit(state: AppState, req: Request<Incoming>) -> Flow<Self> {
+        let path = req.uri().path().to_string();
+        // Never serve node-internal state from the data directory, however
+        // the path is encoded
+        if decode_path(&path).is_none_or(|decoded| is_state_dir_path(&decoded)) {
+            return Err(status_response(StatusCode::NOT_FOUND));
+        }
+        if req.method() == Method::OPTIONS {
+            let config = state.config.load();
+            return Err(options_response(&req, config.find_path_config(&path)));
+        }
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            let response = Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", ALLOWED_METHODS)
+                .body(ResBody::Empty)
+                .unwrap();
+            return Err(response);
+        }
+
+        let (path_config, grant) = check_access(&state, &req, &path, true)?;
+
+        let rollout = path_config
+            .as_ref()
+            .and_then(|pc| pc.rollout.as_ref())
+            .filter(|rollout| rollout.applies(&req));
+        let root = match rollout {
+            Some(rollout) => {
+                ROLLOUT_REQUESTS_TOTAL.inc();
+                rollout.root.clone()
+            }
+            None => state
+                .config
+                .load()
+                .data_root(&path, &state.data_dir)
+                .to_path_buf(),
+        };
+        Ok(Self {
+            file_path: root.join(path.trim_start_matches('/')),
+            root,
+            state,
+            req,
+            path,
+            path_config,
+            grant,
+            serve_uri: None,
+            content_type: None,
+            validators: None,
+            file: None,
+            range_shortfall: None,
+            assisted: None,
+            permits: Vec::new(),
+        })
+    }
+
+    // `path` below its configured prefix
+    fn relative_path(&self) -> &str {
+        let prefix_len = self
+            .state
+            .config
+            .load()
+            .find_path_prefix(&self.path)
+            .map_or(0, |prefix| prefix.len());
+        &self.path[prefix_len..]
+    }
+
+    // The Range header must ask for the signed ranges, compared by the bytes
+    // they cover when the size of the served file is known
+    fn check_signed_ranges(&mut self, size: Option<u64>) -> Flow {
+        let signed = &self.grant.signed_ranges;
+        let range_header = self
+            .req
+            .headers()
+            .get("range")
+            .and_then(|h| h.to_str().ok());
+        if !signed_ranges_match(signed, range_header, size) {
+            return Err(status_response(StatusCode::BAD_REQUEST));
+        }
+        // Serve the signed ranges as signed: `0-` matching a signed `0-999` of
+        // a shorter file must not run past byte 999 once the file has grown
+        if !signed.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&signed_range_header(signed)) {
+                self.req.headers_mut().insert("range", value);
+            }
+        }
+        Ok(())
+    }
+
+    // CAS views map the URL namespace onto content-addressed objects
+    async fn rewrite(&mut self) -> Flow {
+        let Some(view) = self.path_config.as_ref().and_then(|pc| pc.cas_view.clone()) else {
+            return Ok(());
+        };
+        let relative = self.relative_path().trim_start_matches('/');
+        let Some(object_uri) = self.state.cas.resolve(&view, relative).await else {
+            return Err(status_response(StatusCode::NOT_FOUND));
+        };
+        // Objects are kept in the data directory whatever the path's root
+        self.root = self.state.data_dir.clone();
+        self.file_path = self.root.join(object_uri.trim_start_matches('/'));
+        self.serve_uri = object_uri.parse().ok();
+        // Objects have no extension, the type comes from the view path
+        self.content_type = Some(mime_guess::from_path(&self.path).first_or_octet_stream());
+        Ok(())
+    }
+
+    // Settle what is served: a file, a pulled or fallback file, a listing, or
+    // an answer that needs no transfer at all
+    async fn resolve(&mut self) -> Flow {
+        // Pieces of torrents with peer_assist may not be on disk yet, or the file not exist at all
+        if self.req.method() == Method::GET && self.serve_uri.is_none() {
+            let assisted = self.state.config.load().find_assisted_torrent(&self.path);
+            self.assisted = assisted.and_then(|(torrent, relative)| {
+                torrent.missing_range(&self.state, relative, self.req.headers())
+            });
+            if self.assisted.is_some() {
+                return self.check_signed_ranges(None);
+            }
+        }
+
+        let file_path = self.file_path.clone();
+        let fs_status = self
+            .state
+            .fs_cache
+            .get_or_fetch(self.file_path.clone(), || async move {
+                check_file_status(&file_path).await
+            })
+            .await;
+
+        // Handle different file system statuses
+        match fs_status {
+            FileSystemStatus::NotExists => self.resolve_missing().await?,
+            FileSystemStatus::Directory => return Err(self.directory_response().await),
+            FileSystemStatus::File => {}
+        }
+
+        // Checked before waiting, the wait is for the signed bytes only
+        let size = tokio::fs::metadata(&self.file_path)
+            .await
+            .ok()
+            .filter(|metadata| metadata.is_file())
+            .map(|metadata| metadata.len());
+        self.check_signed_ranges(size)?;
+
+        // Files still being written (uploads, external writers): ranges past the current
+        // end wait for the data, up to grow_wait_ms
+        let grow_wait = self
+            .path_config
+            .as_ref()
+            .and_then(|pc| pc.grow_wait_ms)
+            .filter(|_| self.req.method() == Method::GET);
+        if let Some(wait_ms) = grow_wait {
+            let wait = Duration::from_millis(wait_ms);
+            let torrent_file = self.state.config.load().find_torrent_file(&self.path);
+            self.range_shortfall = match torrent_file {
+                Some((layout, file)) => {
+                    wait_for_pieces(&self.state, &mut self.req, &layout, &file, wait).await?
+                }
+                None => wait_for_range(&self.req, &self.file_path, wait).await,
+            };
+        }
+
+        let opened = match tokio::fs::File::open(&self.file_path).await {
+            Ok(file) => match file.metadata().await {
+                Ok(metadata) if metadata.is_file() => Some((file, metadata)),
+                _ => None,
+            },
+            Err(_) => None,
+        };
+
+        // Conditional requests are answered from our own strong validators,
+        // before any transfer slot is taken
+        self.validators = match opened {
+            Some((file, metadata)) => {
+                let validators = self
+                    .state
+                    .etags
+                    .validators(&self.state.checksums, &self.file_path, &metadata)
+                    .await;
+                self.file = Some((file, metadata.len()));
+                Some(validators)
+            }
+            None => None,
+        };
+        // A compressed copy is revalidated by its variant ETag
+        let compress = self
+            .path_config
+            .as_ref()
+            .and_then(|pc| pc.compress)
+            .unwrap_or(false);
+        let variant = self
+            .validators
+            .as_ref()
+            .filter(|_| compress)
+            .zip(choose_encoding(self.req.headers().get("accept-encoding")))
+            .and_then(|(validators, encoding)| {
+                Some(Validators {
+                    etag: encoding.etag(&validators.etag)?,
+                    ..validators.clone()
+                })
+            });
+        let not_modified = self
+            .validators
+            .iter()
+            .chain(variant.as_ref())
+            .find(|validators| is_not_modified(self.req.headers(), validators));
+        if let Some(validators) = not_modified {
+            let mut builder = Response::builder()
+                .status(StatusCode::NOT_MODIFIED)
+                .header("ETag", validators.etag.clone());
+            if let Some(last_modified) = validators.last_modified_header() {
+                builder = builder.header("Last-Modified", last_modified);
+            }
+            if compress {
+                builder = builder.header("Vary", "Accept-Encoding");
+            }
+            return Err(builder.body(ResBody::Empty).unwrap());
+        }
+
+        // Reject ranges entirely past the end of the file with a proper 416
+        if let Some(response) = unsatisfiable_range_response(&self.req, &self.file_path).await {
+            RANGE_NOT_SATISFIABLE_TOTAL.inc();
+            return Err(response);
+        }
+        Ok(())
+    }
+
+    // The cluster node owning the path, pull-through from the origin, or the
+    // path's fallback file
+    async fn resolve_missing(&mut self) -> Flow {
+        let owner_redirect = self
+            .serve_uri
+            .is_none()
+            .then(|| cluster_redirect(&self.state, &self.req))
+            .flatten();
+        if let Some(response) = owner_redirect {
+            return Err(response);
+        }
+        let origin = self
+            .path_config
+            .as_ref()
+            .and_then(|pc| pc.origin.clone())
+            .filter(|_| self.serve_uri.is_none());
+        if let Some(origin) = origin {
+            // The origin's file size isn't known here, the signed notation is required
+            self.check_signed_ranges(None)?;
+            let relative = self.relative_path().to_string();
+            let mut response =
+                pull_through(&self.state, &self.req, &origin, &relative, &self.file_path).await;
+            if let ResBody::Static { rate_limit, .. } = response.body_mut() {
+                *rate_limit = self
+                    .path_config
+                    .as_ref()
+                    .and_then(|pc| pc.rate_limit_bytes_per_sec)
+                    .map(RateLimiter::new);
+            }
+            return Err(response);
+        }
+        let fallback_file = self
+            .path_config
+            .as_ref()
+            .and_then(|pc| pc.fallback_file.as_deref());
+        match resolve_fallback(&self.state, &self.path, fallback_file).await {
+            Some((root, fallback_path, fallback)) => {
+                // The fallback may sit under another prefix, whose rules hold
+                // too. Its own auth only applies when that is a different
+                // prefix, this request's credentials were checked for its own.
+                let fallback_url = fallback.path();
+                let other_prefix = {
+                    let config = self.state.config.load();
+                    config.find_path_prefix(fallback_url) != config.find_path_prefix(&self.path)
+                };
+                let (_, grant) = check_access(&self.state, &self.req, fallback_url, other_prefix)?;
+                self.grant.merge(grant);
+                self.root = root;
+                self.file_path = fallback_path;
+                self.serve_uri = Some(fallback);
+                Ok(())
+            }
+            None => Err(status_response(StatusCode::NOT_FOUND)),
+        }
+    }
+
+    async fn directory_response(&self) -> Response<ResBody> {
+        let path_config = self.path_config.as_ref();
+        let (req, path) = (&self.req, self.path.as_str());
+        // Check if autoindex is enabled for directory listing
+        if !path_config.and_then(|pc| pc.autoindex).unwrap_or(false) {
+            return status_response(StatusCode::NOT_FOUND);
+        }
+        // Make clients prove they keep cookies before spending work on a listing
+        if path_config
+            .and_then(|pc| pc.autoindex_challenge)
+            .unwrap_or(false)
+            && !self.state.challenge.verify(req.headers())
+        {
+            return self.state.challenge.response();
+        }
+        // Whole tree in one response, so sync tools don't walk it listing by listing
+        if let Some(format) = TreeManifestFormat::from_query(req.uri().query()) {
+            return tree_manifest_response(&self.state, req, &self.file_path, path, format).await;
+        }
+        // Generate directory listing with signatures if required
+        let signature_token = path_config.and_then(|pc| pc.signing_token());
+
+        let signature_expire_seconds = path_config
+            .and_then(|pc| pc.signature_expire_seconds)
+            .unwrap_or(3600); // 默认1小时
+
+        let options = ListingOptions {
+            signature_token,
+            signature_expire_seconds,
+            expire_step: path_config.and_then(|pc| pc.autoindex_expire_step),
+            timezone: path_config.and_then(|pc| pc.autoindex_timezone.as_deref()),
+            time_format: path_config.and_then(|pc| pc.autoindex_time_format.as_deref()),
+            format: ListingFormat::from_request(
+                req.uri().query(),
+                req.headers().get("accept").and_then(|h| h.to_str().ok()),
+            ),
+            locale: Locale::select(
+                path_config.and_then(|pc| pc.autoindex_locale.as_deref()),
+                req.headers()
+                    .get("accept-language")
+                    .and_then(|h| h.to_str().ok()),
+            ),
+        };
+
+        // Sync tools check whether a directory changed before fetching the listing
+        if req.method() == Method::HEAD {
+            return directory_head_response(req, &self.file_path, options.format).await;
+        }
+
+        match generate_directory_listing(&self.file_path, path, &options).await {
+            Ok(body) => {
+                // The format follows Accept and the wording Accept-Language
+                let mut builder = Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", options.format.content_type())
+                    .header("Vary", LISTING_VARY);
+                // Same links until the quantized expiry moves on, a CDN may keep it that
+                // long. Not where the listing is behind the cookie challenge, and only
+                // the client may keep it where more than the URL authorizes it.
+                let challenged = path_config
+                    .and_then(|pc| pc.autoindex_challenge)
+                    .unwrap_or(false);
+                if let Some(max_age) = options.cache_seconds().filter(|_| !challenged) {
+                    let scope = if path_config.is_none_or(signature_only) {
+                        "public"
+                    } else {
+                        "private"
+                    };
+                    builder =
+                        builder.header("Cache-Control", format!("{}, max-age={}", scope, max_age));
+                }
+                builder.body(body).unwrap()
+            }
+            Err(status) => status_response(status),
+        }
+    }
+
+    // Node-wide, per-client, per-prefix and per-credential transfer slots
+    async fn limit(&mut self) -> Flow {
+        if self.req.method() != Method::GET {
+            return Ok(());
+        }
+        let state = &self.state;
+        let too_many_requests = || {
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("Retry-After", CLIENT_RETRY_AFTER_SECS.to_string())
+                .body(ResBody::Empty)
+                .unwrap()
+        };
+        let overloaded = |req: &Request<Incoming>| {
+            overflow_redirect(state, req).unwrap_or_else(overloaded_response)
+        };
+
+        // Node-wide and per-client transfer limits, so one client can't take every slot
+        let (max_transfers, max_per_ip) = {
+            let config = state.config.load();
+            (config.max_transfers, config.max_transfers_per_ip)
+        };
+        if let Some(limit) = max_transfers {
+            match state
+                .transfer_limiter
+                .try_acquire(GLOBAL_LIMIT_KEY, limit)
+                .await
+            {
+                Some(permit) => self.permits.push(permit),
+                None => {
+                    CONCURRENCY_REJECTED_TOTAL
+                        .with_label_values(&["global"])
+                        .inc();
+                    return Err(overloaded(&self.req));
+                }
+            }
+        }
+        if let (Some(limit), Some(ip)) = (max_per_ip, client_ip(&self.req)) {
+            match state
+                .transfer_limiter
+                .try_acquire(&ip.to_string(), limit)
+                .await
+            {
+                Some(permit) => self.permits.push(permit),
+                None => {
+                    CONCURRENCY_REJECTED_TOTAL.with_label_values(&["ip"]).inc();
+                    return Err(too_many_requests());
+                }
+            }
+        }
+
+        // Per-prefix transfer limit, protects slow disks from piling up readers
+        let limit = self.path_config.as_ref().and_then(|pc| pc.max_concurrent);
+        let prefix = limit.and_then(|_| state.config.load().find_path_prefix(&self.path));
+        if let (Some(limit), Some(prefix)) = (limit, prefix) {
+            match state.path_limiter.try_acquire(&prefix, limit).await {
+                Some(permit) => self.permits.push(permit),
+                None => {
+                    CONCURRENCY_REJECTED_TOTAL
+                        .with_label_values(&["path"])
+                        .inc();
+                    return Err(overloaded(&self.req));
+                }
+            }
+        }
+
+        // Download managers opening many connections per link get the parallelism it was issued for
+        let limit = self
+            .path_config
+            .as_ref()
+            .and_then(|pc| pc.max_per_signature);
+        if let (Some(limit), Some(credential)) = (limit, &self.grant.credential) {
+            match state.signature_limiter.try_acquire(credential, limit).await {
+                Some(permit) => self.permits.push(permit),
+                None => {
+                    CONCURRENCY_REJECTED_TOTAL
+                        .with_label_values(&["signature"])
+                        .inc();
+                    return Err(too_many_requests());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn serve(self) -> Result<Response<ResBody>, std::io::Error> {
+        let PathRequest {
+            state,
+            mut req,
+            path,
+            path_config,
+            grant,
+            root,
+            file_path,
+            serve_uri,
+            content_type,
+            validators,
+            mut file,
+            range_shortfall,
+            assisted,
+            mut permits,
+            ..
+        } = self;
+        if let Some(assisted) = assisted {
+            let content_type = content_type
+                .unwrap_or_else(|| mime_guess::from_path(&file_path).first_or_octet_stream());
+            let response = assisted.response(&state, content_type.as_ref()).await;
+            // The fetched pieces are on disk now, thin torrents count against the cache
+            if response.status() == StatusCode::PARTIAL_CONTENT && tracks_access(&state) {
+                state.quota.touch(&file_path);
+                state.quota.stored();
+            }
+            return Ok(response);
+        }
+        let signed_ranges = grant.signed_ranges;
+        let method = req.method().clone();
+
+        // Patchers read signed range sets in order, warm the later ranges while the first streams
+        if method == Method::GET && signed_ranges.len() > 1 {
+            let later_ranges = signed_ranges[1..].to_vec();
+            prefetch_ranges(file_path.clone(), later_ranges);
+        }
+
+        // Segmented downloaders fetch consecutive chunks over one connection, read the next one ahead
+        if method == Method::GET {
+            track_sequential_range(&req, &file_path, file.as_ref()).await;
+        }
+
+        // 在调用 serve 前克隆需要的信息
+        let uri_for_logging = req.uri().clone();
+        let allow_origin = req
+            .headers()
+            .get("origin")
+            .and_then(|origin| allowed_origin(&path, path_config.as_ref(), origin));
+
+        // Known hash for on-the-fly verification of full GET responses
+        let expected_hash = if method == Method::GET
+            && path_config
+                .as_ref()
+                .and_then(|pc| pc.verify_hash)
+                .unwrap_or(false)
+        {
+            load_expected_hash(&file_path).await
+        } else {
+            None
+        };
+
+        // Alternate node URLs for clients that fail over or download in parallel
+        let mirror_links = path_config
+            .as_ref()
+            .and_then(|pc| pc.mirror_links)
+            .unwrap_or(false);
+        let links = if mirror_links {
+            duplicate_links(&state, &req)
+        } else {
+            None
+        };
+
+        // Text-like files are compressed on the fly where the path opts in
+        let compress = path_config
+            .as_ref()
+            .and_then(|pc| pc.compress)
+            .unwrap_or(false);
+        let accept_encoding = req.headers().get("accept-encoding").cloned();
+
+        if let Some(validators) = &validators {
+            strip_conditionals(req.headers_mut(), validators, !signed_ranges.is_empty());
+        }
+
+        // Serve the CAS object or fallback file in place of the requested path
+        if let Some(serve_uri) = serve_uri {
+            *req.uri_mut() = serve_uri;
+        }
+
+        // Use hyper_staticfile to serve the file/directory
+        let mut response = state.static_service_for(&root).serve(req).await?;
+        let status = response.status();
+        let served = status == StatusCode::OK || status == StatusCode::PARTIAL_CONTENT;
+        if let Some(validators) = validators.filter(|_| served) {
+            response.headers_mut().insert("ETag", validators.etag);
+        }
+        // Only part of the range exists yet, tell the client where to continue
+        let continue_range = range_shortfall
+            .filter(|_| status == StatusCode::PARTIAL_CONTENT)
+            .and_then(|(available, end)| {
+                HeaderValue::from_str(&format!("bytes={}-{}", available, end)).ok()
+            });
+        if let Some(continue_range) = continue_range {
+            response
+                .headers_mut()
+                .insert("X-Dfs-Continue-Range", continue_range);
+        }
+        if served && tracks_access(&state) {
+            state.quota.touch(&file_path);
+            permits.extend(state.quota.open(&file_path));
+        }
+        if let Some(content_type) = content_type
+            .as_ref()
+            .and_then(|mime| HeaderValue::from_str(mime.as_ref()).ok())
+        {
+            response.headers_mut().insert("Content-Type", content_type);
+        }
+        // Only full responses, ranges address the file's bytes and verified
+        // bodies must be hashed as stored
+        let compression = state.config.load().compression.clone().unwrap_or_default();
+        let encoding = if compress && status == StatusCode::OK && expected_hash.is_none() {
+            match exclusion(&compression, response.headers()) {
+                None => {
+                    response
+                        .headers_mut()
+                        .insert("Vary", HeaderValue::from_static("Accept-Encoding"));
+                    choose_encoding(accept_encoding.as_ref())
+                }
+                // Counted where the client asked, to show what the policy turns away
+                Some(reason) => {
+                    if choose_encoding(accept_encoding.as_ref()).is_some() {
+                        COMPRESSION_RESPONSES_TOTAL
+                            .with_label_values(&[reason])
+                            .inc();
+                    }
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let mut compressed = None;
+        let encoding = match encoding {
+            Some(encoding) if method == Method::GET => {
+                // Past max_concurrent the CPU budget is spent, send the file as-is
+                match state
+                    .transfer_limiter
+                    .try_acquire(COMPRESSION_LIMIT_KEY, compression.max_concurrent())
+                    .await
+                {
+                    Some(permit) => match file.take() {
+                        Some((file, _)) => {
+                            permits.push(permit);
+                            compressed = Some(compress_file(file, encoding));
+                            Some(encoding)
+                        }
+                        None => None,
+                    },
+                    None => {
+                        COMPRESSION_RESPONSES_TOTAL
+                            .with_label_values(&["busy"])
+                            .inc();
+                        None
+                    }
+                }
+            }
+            encoding => encoding,
+        };
+        if let Some(encoding) = encoding {
+            // HEAD only announces the encoding, nothing is compressed
+            if compressed.is_some() {
+                COMPRESSION_RESPONSES_TOTAL
+                    .with_label_values(&[encoding.name()])
+                    .inc();
+            }
+            let headers = response.headers_mut();
+            headers.insert(
+                "Content-Encoding",
+                HeaderValue::from_static(encoding.name()),
+            );
+            headers.remove("content-length");
+            if let Some(etag) = headers.get("etag").and_then(|etag| encoding.etag(etag)) {
+                headers.insert("ETag", etag);
+            }
+        }
+        if mirror_links && served {
+            if let Some(links) = links {
+                response.headers_mut().insert("Link", links);
+            }
+            // Only already known hashes, never hash a file on the request path
+            let hashes = match tokio::fs::metadata(&file_path).await {
+                Ok(metadata) => state.checksums.lookup(&file_path, &metadata).await,
+                Err(_) => None,
+            };
+            // Digest covers the stored bytes, not a compressed body
+            if let Some(digest) = hashes
+                .as_deref()
+                .and_then(digest_header)
+                .filter(|_| encoding.is_none())
+            {
+                response.headers_mut().insert("Digest", digest);
+            }
+        }
+        // Both builds are served under the same URL, caches must keep them apart
+        if let Some(rollout) = path_config.as_ref().and_then(|pc| pc.rollout.as_ref()) {
+            let headers = response.headers_mut();
+            if let Ok(header) = HeaderValue::from_str(rollout.header()) {
+                headers.append("Vary", header);
+            }
+            if rollout.is_partial() {
+                headers.insert("Cache-Control", HeaderValue::from_static("private"));
+            }
+        }
+        // Allow browser-based downloaders to read files and range headers
+        if let Some(allow_origin) = allow_origin {
+            let headers = response.headers_mut();
+            if allow_origin != "*" {
+                headers.append("Vary", HeaderValue::from_static("Origin"));
+            }
+            headers.insert("Access-Control-Allow-Origin", allow_origin);
+            headers.insert(
+                "Access-Control-Expose-Headers",
+                HeaderValue::from_static(CORS_EXPOSED_HEADERS),
+            );
+        }
+        let content_length: Option<u64> = response
+            .headers()
+            .get("content-length")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        let verifier = expected_hash
+            .filter(|_| status == StatusCode::OK)
+            .zip(content_length)
+            .map(|(expected, len)| Box::new(HashVerifier::new(expected, len)));
+
+        let mmap_max_size = path_config.as_ref().and_then(|pc| pc.mmap_max_size);
+        let last_modified = response
+            .headers()
+            .get("last-modified")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        let full_get = method == Method::GET && status == StatusCode::OK;
+        let mapped = match (mmap_max_size, content_length) {
+            (Some(max), Some(len)) if full_get && len <= max => {
+                state.mapped_files.get(&file_path, len, last_modified).await
+            }
+            _ => None,
+        };
+        // Otherwise frequently requested files may already be held in memory
+        let mapped = match (mapped, &state.hot_files, content_length) {
+            (None, Some(hot_files), Some(len)) if full_get => {
+                hot_files.get(&file_path, len, last_modified).await
+            }
+            (mapped, ..) => mapped,
+        };
+
+        let (parts, body) = response.into_parts();
+        // Full-file bodies are re-read through the buffer pool to avoid per-chunk allocations
+        let inner = match compressed {
+            Some(compressed) => compressed,
+            None => match body {
+                hyper_staticfile::Body::Full(_) if mapped.is_some() => FileBody::Mapped(mapped),
+                // hyper_staticfile opened the file again for its headers, the
+                // handle validated earlier is only used while it's the same size
+                hyper_staticfile::Body::Full(_) if status == StatusCode::OK => {
+                    match (content_length, file) {
+                        (Some(len), Some((file, file_len))) if len == file_len => {
+                            FileBody::Pooled(PooledFileStream::new(file, len))
+                        }
+                        _ => FileBody::Static(body),
+                    }
+                }
+                body => FileBody::Static(body),
+            },
+        };
+
+        // 对于文件响应，使用带日志的包装器来记录完整下载时间
+        let body = ResBody::Static {
+            inner,
+            start_time: Instant::now(), // 记录开始时间
+            metrics: Arc::new(crate::response::StaticMetrics {
+                method,
+                uri: uri_for_logging,
+                status,
+                access_log: false,
+                labels: None,
+                access_request: None,
+            }),
+            bytes_sent: 0, // 初始化字节数为0
+            verifier,
+            permits,
+            throttle: state.disk_budget.as_ref().map(|budget| budget.throttle()),
+            held: None,
+            rate_limit: path_config
+                .as_ref()
+                .and_then(|pc| pc.rate_limit_bytes_per_sec)
+                .map(RateLimiter::new),
+        };
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+// Content-Type, Last-Modified and entry totals of a listed directory, 304 if unchanged
+async fn directory_head_response<B>(
+    req: &Request<B>,
+    dir_path: &Path,
+    format: ListingFormat,
+) -> Response<ResBody> {
+    let summary = match directory_summary(dir_path).await {
+        Ok(summary) => summary,
+        Err(status) => {
+            return Response::builder()
+                .status(status)
+                .body(ResBody::Empty)
+                .unwrap();
+        }
+    };
+    let if_modified_since = req
+        .headers()
+        .get("if-modified-since")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| chrono::DateTime::parse_from_rfc2822(h).ok());
+    let not_modified = summary
+        .last_modified
+        .zip(if_modified_since)
+        .is_some_and(|(modified, since)| modified <= since);
+
+    let mut builder = Response::builder()
+        .status(if not_modified {
+            StatusCode::NOT_MODIFIED
+        } else {
+            StatusCode::OK
+        })
+        .header("Content-Type", format.content_type())
+        .header("Vary", LISTING_VARY)
+        .header("X-Dfs-Dir-Entries", summary.entries)
+        .header("X-Dfs-Dir-Size", summary.total_size);
+    if let Some(modified) = summary.last_modified {
+        builder = builder.header(
+            "Last-Modified",
+            modified.format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
+        );
+    }
+    builder.body(ResBody::Empty).unwrap()
+}
+
+// `?manifest=`: every file below an autoindex directory the request may
+// fetch, `&hashes=1` adds sha256 (computed only for management requests)
+async fn tree_manifest_response<B>(
+    state: &AppState,
+    req: &Request<B>,
+    dir_path: &Path,
+    url_path: &str,
+    format: TreeManifestFormat,
+) -> Response<ResBody> {
+    if req.method() == Method::HEAD {
+        return Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", format.content_type())
+            .body(ResBody::Empty)
+            .unwrap();
+    }
+    let query = req.uri().query();
+    let hashes = if !query.is_some_and(|query| query.split('&').any(|pair| pair == "hashes=1")) {
+        ManifestHashes::None
+    } else if has_management_token(state, req) {
+        ManifestHashes::Compute
+    } else {
+        ManifestHashes::Known
+    };
+    let auth_request = AuthRequest {
+        path: url_path,
+        query,
+        headers: req.headers(),
+        client_ip: client_ip(req),
+    };
+    let files = match manifest_files(state, dir_path, url_path, &auth_request, hashes).await {
+        Ok(files) => files,
+        Err(e) => {
+            tracing::warn!("Failed to build manifest for {}: {}", url_path, e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(ResBody::Empty)
+                .unwrap();
+        }
+    };
+    let entries: Vec<TreeEntry> = files.iter().map(TreeEntry::new).collect();
+    let body = match format {
+        TreeManifestFormat::Json => serde_json::to_vec(&serde_json::json!({ "files": entries })),
+        TreeManifestFormat::Ndjson => entries.iter().try_fold(Vec::new(), |mut body, entry| {
+            serde_json::to_writer(&mut body, entry)?;
+            body.push(b'\n');
+            Ok::<_, serde_json::Error>(body)
+        }),
+    };
+    match body {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", format.content_type())
+            .body(ResBody::Bytes(Bytes::from(body)))
+            .unwrap(),
+        Err(_) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(ResBody::Empty)
+            .unwrap(),
+    }
+}
+
+// Access-Control-Allow-Origin for a request from `origin`: whatever the path's
+// `cors_origins` allow, any origin by default. Never for paths behind auth,
+// whose responses are for the credential holder, nor under the /-/ namespace.
+fn allowed_origin(
+    path: &str,
+    path_config: Option<&PathConfig>,
+    origin: &HeaderValue,
+) -> Option<HeaderValue> {
+    if path.starts_with("/-/") || path_config.is_some_and(|pc| pc.authorizer.is_some()) {
+        return None;
+    }
+    match path_config.and_then(|pc| pc.cors_origins.as_ref()) {
+        Some(origins) if !origins.iter().any(|allowed| allowed == "*") => origins
+            .iter()
+            .any(|allowed| allowed.as_bytes() == origin.as_bytes())
+            .then(|| origin.clone()),
+        _ => Some(HeaderValue::from_static("*")),
+    }
+}
+
+// Answer OPTIONS (including `OPTIONS *` and CORS preflight) instead of 405
+fn options_response<B>(req: &Request<B>, path_config: Option<&PathConfig>) -> Response<ResBody> {
+    let mut builder = Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header("Allow", ALLOWED_METHODS);
+    let headers = req.headers();
+    let allow_origin = headers
+        .get("origin")
+        .filter(|_| headers.contains_key("access-control-request-method"))
+        .and_then(|origin| allowed_origin(req.uri().path(), path_config, origin));
+    if let Some(allow_origin) = allow_origin {
+        if allow_origin != "*" {
+            builder = builder.header("Vary", "Origin");
+        }
+        builder = builder
+            .header("Access-Control-Allow-Origin", allow_origin)
+            .header("Access-Control-Allow-Methods", ALLOWED_METHODS)
+            .header("Access-Control-Max-Age", CORS_MAX_AGE_SECS.to_string());
+        if let Some(requested) = headers.get("access-control-request-headers") {
+            builder = builder.header("Access-Control-Allow-Headers", requested.clone());
+        }
+    }
+    builder.body(ResBody::Empty).unwrap()
+}
+
+async fn track_sequential_range<B>(
+    req: &Request<B>,
+    file_path: &Path,
+    file: Option<&(tokio::fs::File, u64)>,
+) {
+    let Some(context) = req.extensions().get::<Arc<ConnectionContext>>() else {
+        return;
+    };
+    let Some((file, size)) = file else {
+        return;
+    };
+    let ranges = req
+        .headers()
+        .get("range")
+        .and_then(|h| h.to_str().ok())
+        .and_then(parse_byte_ranges);
+    let Some([ByteRangeSpec::FromTo(start, Some(end))]) = ranges.as_deref() else {
+        return;
+    };
+    let (start, end, size) = (*start, *end, *size);
+
+    let cursor = context.range_cursor.lock().unwrap().take();
+    let sequential =
+        cursor.is_some_and(|cursor| cursor.path == file_path && cursor.next_offset == start);
+    if end >= size {
+        return;
+    }
+
+    // Read ahead through this request's own handle, the version being served
+    let next_start = end + 1;
+    if sequential && next_start < size {
+        let next_end = (next_start + (end - start)).min(size - 1);
+        if let Ok(file) = file.try_clone().await {
+            prefetch_open_file(
+                file_path.to_path_buf(),
+                Arc::new(file.into_std().await),
+                vec![(next_start, next_end)],
+            );
+        }
+    }
+    *context.range_cursor.lock().unwrap() = Some(RangeCursor {
+        path: file_path.to_path_buf(),
+        next_offset: next_start,
+    });
+}
+
+// Wait up to `wait` for a growing file to cover the requested ranges. Returns
+// (current size, inclusive end wanted) if it still falls short, in which case
+// the part that exists is served.
+async fn wait_for_range<B>(
+    req: &Request<B>,
+    file_path: &Path,
+    wait: Duration,
+) -> Option<(u64, u64)> {
+    if req.headers().contains_key("if-range") {
+        return None;
+    }
+    let range_header = req.headers().get("range")?.to_str().ok()?;
+    // Open-ended ranges only need their start, suffix ranges follow the end
+    let needed = parse_byte_ranges(range_header)?
+        .iter()
+        .filter_map(|range| match *range {
+            ByteRangeSpec::FromTo(start, end) => Some(end.unwrap_or(start)),
+            ByteRangeSpec::Suffix(_) => None,
+        })
+        .max()?;
+    let deadline = Instant::now() + wait;
+    loop {
+        // A directory in its place has no bytes to wait for
+        let metadata = tokio::fs::metadata(file_path).await.ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let size = metadata.len();
+        if size > needed {
+            return None;
+        }
+        if Instant::now() >= deadline {
+            return Some((size, needed));
+        }
+        tokio::time::sleep(Duration::from_millis(GROW_POLL_MS)).await;
+    }
+}
+
+// wait_for_range for a torrent's file, which has its full size from the
+// start: the downloaded pieces tell what exists. A single range still short at
+// the deadline is cut to the part downloaded, or answered 503 if none is.
+async fn wait_for_pieces<B>(
+    state: &AppState,
+    req: &mut Request<B>,
+    layout: &TorrentLayout,
+    file: &TorrentFile,
+    wait: Duration,
+) -> Result<Option<(u64, u64)>, Response<ResBody>> {
+    if req.headers().contains_key("if-range") {
+        return Ok(None);
+    }
+    let Some(ranges) = req
+        .headers()
+        .get("range")
+        .and_then(|h| h.to_str().ok())
+        .and_then(parse_byte_ranges)
+    else {
+        return Ok(None);
+    };
+    let [range] = ranges.as_slice() else {
+        return Ok(None);
+    };
+    let Some((start, end)) = range.resolve(file.length) else {
+        return Ok(None);
+    };
+    // Open-ended ranges only need their start, like growing files
+    let needed = match range {
+        ByteRangeSpec::FromTo(_, Some(_)) => end,
+        _ => start,
+    };
+    let deadline = Instant::now() + wait;
+    let available = loop {
+        let Some(available) = layout.downloaded_until(&state.bt_api, file, start, end) else {
+            return Ok(None);
+        };
+        if available > needed || Instant::now() >= deadline {
+            break available;
+        }
+        tokio::time::sleep(Duration::from_millis(GROW_POLL_MS)).await;
+    };
+    if available > end {
+        return Ok(None);
+    }
+    if available == start {
+        let response = Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header("Retry-After", CLIENT_RETRY_AFTER_SECS.to_string())
+            .body(ResBody::Empty)
+            .unwrap();
+        return Err(response);
+    }
+    let downloaded = format!("bytes={}-{}", start, available - 1);
+    req.headers_mut()
+        .insert("range", HeaderValue::from_str(&downloaded).unwrap());
+    Ok(Some((available, end)))
+}
+
+async fn unsatisfiable_range_response<B>(
+    req: &Request<B>,
+    file_path: &Path,
+) -> Option<Response<ResBody>> {
+    // With If-Range the range may be ignored in favor of a full response
+    if req.method() != Method::GET || req.headers().contains_key("if-range") {
+        return None;
+    }
+    let range_header = req.headers().get("range")?.to_str().ok()?;
+    let ranges = parse_byte_ranges(range_header)?;
+    // Directories are listed, not ranged
+    let size = tokio::fs::metadata(file_path)
+        .await
+        .ok()
+        .filter(|metadata| metadata.is_file())?
+        .len();
+    if is_satisfiable(&ranges, size) {
+        return None;
+    }
+    let response = Response::builder()
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header("Content-Range", format!("bytes */{}", size))
+        .body(ResBody::Empty)
+        .unwrap();
+    Some(response)
+}
+
+// Whether `path` may be served to `req` under its path config: not removed,
+// released, past its embargo and, with `authorize`, allowed by its auth rules.
+// Returns the path config and what the authorizers granted.
+pub(crate) fn check_access<B>(
+    state: &AppState,
+    req: &Request<B>,
+    path: &str,
+    authorize: bool,
+) -> Flow<(Option<PathConfig>, Grant)> {
+    // Removed from the config but kept for rollback, tell caches it's gone for good
+    if state.tombstones.find(path).is_some() {
+        return Err(status_response(StatusCode::GONE));
+    }
+
+    // Find matching path config - use optimized path lookup
+    let (path_config, pending_release) = {
+        let config = state.config.load();
+        let now = chrono::Utc::now().timestamp();
+        (
+            config.find_path_config(path).cloned(),
+            config.pending_release(path, now),
+        )
+    };
+    // Content downloaded ahead of its release time stays hidden
+    if pending_release.is_some() {
+        return Err(status_response(StatusCode::NOT_FOUND));
+    }
+    // Embargoed paths are rejected before signature checks
+    if let Some(response) = path_config.as_ref().and_then(embargo_response) {
+        return Err(response);
+    }
+    // Signature, JWT, basic auth and IP rules of the path, as configured
+    let mut grant = Grant::default();
+    let chain = path_config
+        .as_ref()
+        .and_then(|pc| pc.authorizer.as_ref())
+        .filter(|_| authorize);
+    if let Some(chain) = chain {
+        let auth_request = AuthRequest {
+            path,
+            query: req.uri().query(),
+            headers: req.headers(),
+            client_ip: client_ip(req),
+        };
+        grant = chain
+            .authorize(&auth_request)
+            .map_err(|denial| denial.into_response())?;
+    }
+    Ok((path_config, grant))
+}
+
+// Existing file for `fallback_file`, relative to the configured path prefix
+// unless it starts with '/'. Returns its root, data path and the URI to serve it by.
+async fn resolve_fallback(
+    state: &AppState,
+    path: &str,
+    fallback_file: Option<&str>,
+) -> Option<(PathBuf, PathBuf, hyper::Uri)> {
+    let fallback_file = fallback_file?;
+    let fallback_path = if fallback_file.starts_with('/') {
+        fallback_file.to_string()
+    } else {
+        let prefix = state.config.load().find_path_prefix(path)?;
+        format!("{}/{}", prefix.trim_end_matches('/'), fallback_file)
+    };
+    if fallback_path == path
+        || is_state_dir_path(&fallback_path)
+        || fallback_path.split('/').any(|part| part == "..")
+    {
+        return None;
+    }
+    let root = state
+        .config
+        .load()
+        .data_root(&fallback_path, &state.data_dir)
+        .to_path_buf();
+    let file_path = root.join(fallback_path.trim_start_matches('/'));
+    let file_path_clone = file_path.clone();
+    let status = state
+        .fs_cache
+        .get_or_fetch(file_path.clone(), || async move {
+            check_file_status(&file_path_clone).await
+        })
+        .await;
+    if !matches!(status, FileSystemStatus::File) {
+        return None;
+    }
+    let uri = fallback_path.parse().ok()?;
+    Some((root, file_path, uri))
+}
@@ -0,0 +1,99 @@
+use std::io::Error as IoError;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, ready};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::Stream;
+use tokio::fs::File;
+use tokio_util::io::poll_read_buf;
+
+// Read chunk size for full-file responses
+const POOL_BUFFER_SIZE: usize = 64 * 1024;
+// Idle buffers kept for reuse, the rest are freed
+const MAX_POOLED_BUFFERS: usize = 4096;
+
+lazy_static::lazy_static! {
+    pub static ref BUFFER_POOL: BufferPool = BufferPool::new();
+}
+
+/// Recycles read buffers between transfers instead of allocating one per stream
+pub struct BufferPool {
+    buffers: Mutex<Vec<BytesMut>>,
+}
+
+impl BufferPool {
+    fn new() -> Self {
+        Self {
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn get(&self) -> BytesMut {
+        self.buffers
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(POOL_BUFFER_SIZE))
+    }
+
+    fn put(&self, buf: BytesMut) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < MAX_POOLED_BUFFERS {
+            buffers.push(buf);
+        }
+    }
+}
+
+/// File body stream reading into a pooled buffer. Chunks are split off the
+/// buffer, and `reserve` reclaims the allocation once hyper has written them.
+pub struct PooledFileStream {
+    file: File,
+    buf: BytesMut,
+    remaining: u64,
+}
+
+impl PooledFileStream {
+    pub fn new(file: File, len: u64) -> Self {
+        Self {
+            file,
+            buf: BUFFER_POOL.get(),
+            remaining: len,
+        }
+    }
+}
+
+impl Stream for PooledFileStream {
+    type Item = Result<Bytes, IoError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        if this.buf.capacity() - this.buf.len() < POOL_BUFFER_SIZE {
+            this.buf.reserve(POOL_BUFFER_SIZE);
+        }
+        let max = this.remaining.min(POOL_BUFFER_SIZE as u64) as usize;
+        let read = ready!(poll_read_buf(
+            Pin::new(&mut this.file),
+            cx,
+            &mut (&mut this.buf).limit(max)
+        ));
+        match read {
+            // File got shorter than the advertised length
+            Ok(0) => Poll::Ready(Some(Err(IoError::from(std::io::ErrorKind::UnexpectedEof)))),
+            Ok(n) => {
+                this.remaining -= n as u64;
+                Poll::Ready(Some(Ok(this.buf.split().freeze())))
+            }
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+impl Drop for PooledFileStream {
+    fn drop(&mut self) {
+        BUFFER_POOL.put(std::mem::take(&mut self.buf));
+    }
+}
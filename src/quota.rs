@@ -0,0 +1,440 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use librqbit::api::TorrentIdOrHash;
+use librqbit::dht::Id20;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{Duration, interval};
+use tracing::{info, warn};
+
+use crate::app::AppState;
+use crate::config::spawn_torrent_sync;
+use crate::metrics::{
+    CACHE_USAGE_BYTES, DISK_EVICTED_BYTES_TOTAL, DISK_EVICTIONS_TOTAL, DISK_USAGE_BYTES,
+};
+
+const QUOTA_CHECK_INTERVAL_SECS: u64 = 60;
+// Evict down to this fraction of max_disk_bytes, so eviction doesn't run on every check
+const EVICT_TARGET_RATIO: f64 = 0.95;
+// Access times kept in memory, older entries fall back to the filesystem atime
+const MAX_TRACKED_FILES: usize = 1_000_000;
+// Checks after write-throughs run at most this often, however fast content arrives
+const STORED_CHECK_MIN_INTERVAL_SECS: u64 = 5;
+
+struct FileEntry {
+    path: PathBuf,
+    size: u64,
+    last_access: SystemTime,
+}
+
+// What the write-through cache evicts: a file pulled from an origin, or a file
+// of a thin torrent with the pieces holding it
+enum CacheUnit {
+    File(PathBuf),
+    TorrentFile(Id20, PathBuf),
+}
+
+impl CacheUnit {
+    fn path(&self) -> &Path {
+        match self {
+            CacheUnit::File(path) | CacheUnit::TorrentFile(_, path) => path,
+        }
+    }
+}
+
+struct CacheEntry {
+    unit: CacheUnit,
+    size: u64,
+    last_access: SystemTime,
+}
+
+/// Last access of served files, for evicting the least recently used ones
+/// once the data directory outgrows `max_disk_bytes` or write-through
+/// content outgrows `cache_max_bytes`
+pub struct DiskQuota {
+    access: Mutex<HashMap<PathBuf, SystemTime>>,
+    // Files being streamed, each response holds a permit until it ends
+    open: Mutex<HashMap<PathBuf, Arc<Semaphore>>>,
+    stored: Notify,
+}
+
+#[derive(Default)]
+struct Evicted {
+    files: Vec<PathBuf>,
+    // Files of thin torrents to drop, removed once librqbit let go of them
+    torrent_files: Vec<(Id20, PathBuf)>,
+}
+
+impl DiskQuota {
+    pub fn new() -> Self {
+        Self {
+            access: Mutex::new(HashMap::new()),
+            open: Mutex::new(HashMap::new()),
+            stored: Notify::new(),
+        }
+    }
+
+    /// Content was written through to disk, check the cache soon rather than
+    /// at the next minute
+    pub fn stored(&self) {
+        self.stored.notify_one();
+    }
+
+    pub fn touch(&self, path: &Path) {
+        let mut access = self.access.lock().unwrap();
+        if access.len() >= MAX_TRACKED_FILES && !access.contains_key(path) {
+            return;
+        }
+        access.insert(path.to_path_buf(), SystemTime::now());
+    }
+
+    /// Keep `path` from being evicted while the permit is held
+    pub fn open(&self, path: &Path) -> Option<OwnedSemaphorePermit> {
+        let semaphore = self
+            .open
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_insert_with(|| Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)))
+            .clone();
+        semaphore.try_acquire_owned().ok()
+    }
+
+    // Files with a response streaming them, entries of finished ones are dropped
+    fn open_files(&self) -> HashSet<PathBuf> {
+        let mut open = self.open.lock().unwrap();
+        open.retain(|_, semaphore| semaphore.available_permits() < Semaphore::MAX_PERMITS);
+        open.keys().cloned().collect()
+    }
+
+    fn last_access(&self, path: &Path, metadata: &std::fs::Metadata) -> SystemTime {
+        if let Some(time) = self.access.lock().unwrap().get(path) {
+            return *time;
+        }
+        // Since the last restart the atime (relatime) is the best we have
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        metadata
+            .accessed()
+            .map_or(modified, |accessed| accessed.max(modified))
+    }
+
+    fn forget(&self, path: &Path) {
+        self.access.lock().unwrap().remove(path);
+    }
+}
+
+impl Default for DiskQuota {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether served files' access times are needed, for either limit
+pub fn tracks_access(state: &AppState) -> bool {
+    let config = state.config.load();
+    config.max_disk_bytes.is_some() || config.cache_max_bytes.is_some()
+}
+
+/// Track data directory usage and evict least recently accessed files over quota.
+/// Files under torrent output paths are never evicted, except files of thin
+/// torrents already released when the write-through cache is over
+/// `cache_max_bytes`. Files a response is streaming and content kept under a
+/// tombstone are left alone.
+pub async fn quota_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(QUOTA_CHECK_INTERVAL_SECS));
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = state.quota.stored.notified() => {}
+        }
+        let blocking_state = state.clone();
+        match tokio::task::spawn_blocking(move || enforce_quota(&blocking_state)).await {
+            // Evicted files must not be answered from fs_cache
+            Ok(Ok(evicted)) => {
+                for path in evicted.files {
+                    state.fs_cache.invalidate(&path).await;
+                }
+                if !evicted.torrent_files.is_empty() {
+                    drop_torrent_files(&state, &evicted.torrent_files).await;
+                }
+            }
+            Ok(Err(e)) => warn!("Failed to check disk quota: {}", e),
+            Err(e) => warn!("Disk quota check panicked: {}", e),
+        }
+        tokio::time::sleep(Duration::from_secs(STORED_CHECK_MIN_INTERVAL_SECS)).await;
+    }
+}
+
+// librqbit keeps the files of its torrents open and their pieces marked as
+// downloaded. The torrents are forgotten with their files kept, the evicted
+// files removed and the torrents added again: the initial check keeps the
+// pieces of the other files, the removed ones refill on demand.
+async fn drop_torrent_files(state: &AppState, files: &[(Id20, PathBuf)]) {
+    let info_hashes: HashSet<Id20> = files.iter().map(|(info_hash, _)| *info_hash).collect();
+    let mut forgotten = HashSet::new();
+    for info_hash in info_hashes {
+        let id = TorrentIdOrHash::Hash(info_hash);
+        match state.bt_api.api_torrent_action_forget(id).await {
+            Ok(_) => {
+                forgotten.insert(info_hash);
+            }
+            Err(e) => warn!(
+                "Failed to evict from torrent {}: {}",
+                info_hash.as_string(),
+                e
+            ),
+        }
+    }
+    for (_, path) in files
+        .iter()
+        .filter(|(info_hash, _)| forgotten.contains(info_hash))
+    {
+        if let Err(e) = tokio::fs::remove_file(path).await {
+            warn!("Failed to evict {}: {}", path.display(), e);
+        }
+        state.quota.forget(path);
+        state.fs_cache.invalidate(path).await;
+    }
+    let torrents = state.config.load().torrents().clone();
+    spawn_torrent_sync(state, torrents);
+}
+
+fn enforce_quota(state: &AppState) -> std::io::Result<Evicted> {
+    let (max_bytes, cache_max_bytes, roots, protected, origin_dirs, thin_torrents) = {
+        let config = state.config.load();
+        let dir = |prefix: &str| {
+            config
+                .data_root(prefix, &state.data_dir)
+                .join(prefix.trim_matches('/'))
+        };
+        // Roots inside the data directory are scanned with it
+        let mut roots = vec![state.data_dir.clone()];
+        roots.extend(config.extra_roots().into_iter().filter(|root| {
+            !root.starts_with(&state.data_dir) && !state.data_dir.starts_with(root)
+        }));
+        // Torrent output, and content kept for a tombstone's grace period
+        let mut protected: Vec<PathBuf> = config
+            .torrents()
+            .values()
+            .map(|torrent| dir(&torrent.path))
+            .collect();
+        protected.extend(state.tombstones.content_dirs(&state.data_dir));
+        let origin_dirs: Vec<PathBuf> = config
+            .paths
+            .path_trie
+            .iter()
+            .filter(|(_, path_config)| path_config.origin.is_some())
+            .map(|(prefix, _)| dir(prefix))
+            .collect();
+        // Thin torrents not yet released are kept whole for their release
+        let now = chrono::Utc::now().timestamp();
+        let thin_torrents: Vec<(Id20, PathBuf)> = config
+            .torrents()
+            .iter()
+            .filter(|(_, torrent)| {
+                torrent
+                    .peer_assist
+                    .as_ref()
+                    .is_some_and(|assist| assist.thin)
+                    && config.pending_release(&torrent.url_prefix(), now).is_none()
+            })
+            .map(|(info_hash, torrent)| (*info_hash, dir(&torrent.path)))
+            .collect();
+        (
+            config.max_disk_bytes,
+            config.cache_max_bytes,
+            roots,
+            protected,
+            origin_dirs,
+            thin_torrents,
+        )
+    };
+
+    let mut files = Vec::new();
+    collect_files(&state.data_dir, &mut files)?;
+    for root in &roots[1..] {
+        if let Err(e) = collect_files(root, &mut files) {
+            warn!("Failed to scan data root {}: {}", root.display(), e);
+        }
+    }
+    let open = state.quota.open_files();
+    let mut evicted = Evicted::default();
+    if let Some(cache_max_bytes) = cache_max_bytes {
+        let entries = cache_entries(state, &files, &protected, &origin_dirs, &thin_torrents);
+        evict_cache(state, entries, cache_max_bytes, &open, &mut evicted);
+        files.retain(|(path, _)| {
+            !evicted.files.contains(path)
+                && !evicted.torrent_files.iter().any(|(_, file)| file == path)
+        });
+    }
+
+    let mut usage: u64 = files.iter().map(|(_, metadata)| metadata.len()).sum();
+    DISK_USAGE_BYTES.set(usage as i64);
+    let Some(max_bytes) = max_bytes.filter(|max| usage > *max) else {
+        return Ok(evicted);
+    };
+
+    let mut candidates: Vec<FileEntry> = files
+        .into_iter()
+        .filter(|(path, _)| {
+            !protected.iter().any(|dir| path.starts_with(dir)) && !open.contains(path)
+        })
+        .map(|(path, metadata)| FileEntry {
+            last_access: state.quota.last_access(&path, &metadata),
+            size: metadata.len(),
+            path,
+        })
+        .collect();
+    candidates.sort_by_key(|entry| entry.last_access);
+
+    let target = (max_bytes as f64 * EVICT_TARGET_RATIO) as u64;
+    let mut evicted_files = Vec::new();
+    let mut evicted_bytes = 0u64;
+    for entry in candidates {
+        if usage <= target {
+            break;
+        }
+        if let Err(e) = std::fs::remove_file(&entry.path) {
+            warn!("Failed to evict {}: {}", entry.path.display(), e);
+            continue;
+        }
+        state.quota.forget(&entry.path);
+        usage -= entry.size;
+        evicted_bytes += entry.size;
+        evicted_files.push(entry.path);
+    }
+    DISK_EVICTIONS_TOTAL.inc_by(evicted_files.len() as u64);
+    DISK_EVICTED_BYTES_TOTAL.inc_by(evicted_bytes);
+    DISK_USAGE_BYTES.set(usage as i64);
+    if usage > max_bytes {
+        warn!(
+            "Data directory uses {} bytes, over max_disk_bytes {} after evicting everything outside torrent paths",
+            usage, max_bytes
+        );
+    } else {
+        info!(
+            "Evicted {} files ({} bytes) to stay under max_disk_bytes {}",
+            evicted_files.len(),
+            evicted_bytes,
+            max_bytes
+        );
+    }
+    evicted.files.extend(evicted_files);
+    Ok(evicted)
+}
+
+// Files pulled from origins and files of thin torrents, sized by the disk space they take
+fn cache_entries(
+    state: &AppState,
+    files: &[(PathBuf, std::fs::Metadata)],
+    protected: &[PathBuf],
+    origin_dirs: &[PathBuf],
+    thin_torrents: &[(Id20, PathBuf)],
+) -> Vec<CacheEntry> {
+    let mut entries: Vec<CacheEntry> = files
+        .iter()
+        .filter(|(path, _)| {
+            origin_dirs.iter().any(|dir| path.starts_with(dir))
+                && !protected.iter().any(|dir| path.starts_with(dir))
+        })
+        .map(|(path, metadata)| CacheEntry {
+            unit: CacheUnit::File(path.clone()),
+            size: allocated_bytes(metadata),
+            last_access: state.quota.last_access(path, metadata),
+        })
+        .collect();
+    for (info_hash, dir) in thin_torrents {
+        for (path, metadata) in files.iter().filter(|(path, _)| path.starts_with(dir)) {
+            let size = allocated_bytes(metadata);
+            if size > 0 {
+                entries.push(CacheEntry {
+                    unit: CacheUnit::TorrentFile(*info_hash, path.clone()),
+                    size,
+                    last_access: state.quota.last_access(path, metadata),
+                });
+            }
+        }
+    }
+    entries
+}
+
+// Evict least recently used cache entries until they fit in `max_bytes`
+fn evict_cache(
+    state: &AppState,
+    mut entries: Vec<CacheEntry>,
+    max_bytes: u64,
+    open: &HashSet<PathBuf>,
+    evicted: &mut Evicted,
+) {
+    let mut usage: u64 = entries.iter().map(|entry| entry.size).sum();
+    CACHE_USAGE_BYTES.set(usage as i64);
+    if usage <= max_bytes {
+        return;
+    }
+    entries.sort_by_key(|entry| entry.last_access);
+    let target = (max_bytes as f64 * EVICT_TARGET_RATIO) as u64;
+    let mut evicted_count = 0u64;
+    let mut evicted_bytes = 0u64;
+    for entry in entries {
+        if usage <= target {
+            break;
+        }
+        if open.contains(entry.unit.path()) {
+            continue;
+        }
+        match entry.unit {
+            CacheUnit::File(path) => {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    warn!("Failed to evict {}: {}", path.display(), e);
+                    continue;
+                }
+                state.quota.forget(&path);
+                evicted.files.push(path);
+            }
+            // Removed once librqbit let go of the torrent
+            CacheUnit::TorrentFile(info_hash, path) => {
+                evicted.torrent_files.push((info_hash, path))
+            }
+        }
+        usage -= entry.size;
+        evicted_count += 1;
+        evicted_bytes += entry.size;
+    }
+    DISK_EVICTIONS_TOTAL.inc_by(evicted_count);
+    DISK_EVICTED_BYTES_TOTAL.inc_by(evicted_bytes);
+    CACHE_USAGE_BYTES.set(usage as i64);
+    info!(
+        "Evicted {} cache entries ({} bytes) to stay under cache_max_bytes {}",
+        evicted_count, evicted_bytes, max_bytes
+    );
+}
+
+// Torrent files are sparse until their pieces arrive, count what is written
+fn allocated_bytes(metadata: &std::fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    let allocated = std::os::unix::fs::MetadataExt::blocks(metadata) * 512;
+    #[cfg(not(unix))]
+    let allocated = metadata.len();
+    allocated
+}
+
+// Regular files under `dir`, skipping hidden entries (and so the state directory)
+fn collect_files(dir: &Path, files: &mut Vec<(PathBuf, std::fs::Metadata)>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if metadata.is_file() {
+            files.push((entry.path(), metadata));
+        }
+    }
+    Ok(())
+}
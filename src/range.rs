@@ -0,0 +1,70 @@
+// Byte range parsing (RFC 7233) with u64 offsets, used to validate Range headers
+// against the file size before the request reaches hyper_staticfile
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRangeSpec {
+    // bytes=start-end / bytes=start-
+    FromTo(u64, Option<u64>),
+    // bytes=-suffix_length
+    Suffix(u64),
+}
+
+impl ByteRangeSpec {
+    /// Resolve to an inclusive (start, end) range within a file of `size` bytes,
+    /// `None` if the range is unsatisfiable
+    pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRangeSpec::FromTo(start, end) => {
+                if start >= size {
+                    return None;
+                }
+                let end = end.map_or(size - 1, |end| end.min(size - 1));
+                Some((start, end))
+            }
+            ByteRangeSpec::Suffix(len) => {
+                if len == 0 || size == 0 {
+                    return None;
+                }
+                Some((size.saturating_sub(len), size - 1))
+            }
+        }
+    }
+}
+
+/// Parse a `Range` header value, `None` if it is syntactically invalid
+/// (such headers are ignored rather than rejected, per RFC 7233)
+pub fn parse_byte_ranges(header: &str) -> Option<Vec<ByteRangeSpec>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        let (start, end) = spec.split_once('-')?;
+        let range = if start.is_empty() {
+            ByteRangeSpec::Suffix(end.parse().ok()?)
+        } else {
+            let start: u64 = start.parse().ok()?;
+            let end: Option<u64> = if end.is_empty() {
+                None
+            } else {
+                Some(end.parse().ok()?)
+            };
+            if end.is_some_and(|end| end < start) {
+                return None;
+            }
+            ByteRangeSpec::FromTo(start, end)
+        };
+        ranges.push(range);
+    }
+    if ranges.is_empty() {
+        return None;
+    }
+    Some(ranges)
+}
+
+/// A range set is satisfiable if at least one of its ranges overlaps the file
+pub fn is_satisfiable(ranges: &[ByteRangeSpec], size: u64) -> bool {
+    ranges.iter().any(|range| range.resolve(size).is_some())
+}
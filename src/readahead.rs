@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+// Upper bound of bytes prefetched for a single request
+const MAX_READAHEAD_BYTES: u64 = 64 * 1024 * 1024;
+// Readahead tasks running at once, further requests skip prefetching
+const MAX_READAHEAD_TASKS: usize = 64;
+
+lazy_static::lazy_static! {
+    static ref READAHEAD_PERMITS: Arc<Semaphore> = Arc::new(Semaphore::new(MAX_READAHEAD_TASKS));
+}
+
+/// Pull inclusive byte `ranges` of `path` into the page cache in the background
+pub fn prefetch_ranges(path: PathBuf, ranges: Vec<(u64, u64)>) {
+    spawn_readahead(path, None, ranges);
+}
+
+/// Like prefetch_ranges, reusing an already open handle
+pub fn prefetch_open_file(path: PathBuf, file: Arc<std::fs::File>, ranges: Vec<(u64, u64)>) {
+    spawn_readahead(path, Some(file), ranges);
+}
+
+fn spawn_readahead(path: PathBuf, file: Option<Arc<std::fs::File>>, ranges: Vec<(u64, u64)>) {
+    let Ok(permit) = READAHEAD_PERMITS.clone().try_acquire_owned() else {
+        return;
+    };
+    tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let mut budget = MAX_READAHEAD_BYTES;
+        let file = match file {
+            Some(file) => file,
+            None => match std::fs::File::open(&path) {
+                Ok(file) => Arc::new(file),
+                Err(_) => return,
+            },
+        };
+        for (start, end) in ranges {
+            if budget == 0 {
+                break;
+            }
+            let len = end.saturating_sub(start).saturating_add(1).min(budget);
+            budget -= len;
+            if let Err(e) = readahead(&file, start, len) {
+                tracing::debug!("Readahead of {} failed: {}", path.display(), e);
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(target_os = "linux")]
+fn readahead(file: &std::fs::File, offset: u64, len: u64) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: the fd is owned by `file` for the duration of the call
+    let ret = unsafe {
+        libc::posix_fadvise(
+            file.as_raw_fd(),
+            offset as libc::off_t,
+            len as libc::off_t,
+            libc::POSIX_FADV_WILLNEED,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::from_raw_os_error(ret));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn readahead(file: &std::fs::File, offset: u64, len: u64) -> std::io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    std::io::copy(&mut file.take(len), &mut std::io::sink())?;
+    Ok(())
+}
@@ -0,0 +1,206 @@
+use std::io::{Error as IoError, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use hyper::Request;
+use hyper::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::time::{Duration, timeout};
+
+use crate::net::IpRule;
+use crate::server::{ClientIp, connection_ip};
+
+// Proxies send the header right away, don't let a silent peer hold a connection slot
+const PROXY_HEADER_TIMEOUT_SECS: u64 = 10;
+// "PROXY UNKNOWN\r\n", the shortest v1 header; every v2 header is longer
+const PROXY_HEADER_MIN_LEN: usize = 15;
+const PROXY_V1_MAX_LEN: usize = 107;
+const PROXY_V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+// Addresses from more hops than this are not looked at
+const MAX_FORWARDED_HOPS: usize = 32;
+
+/// The forwarding header trusted proxies set. Only that one is read, a client
+/// could send the other one itself and have the proxy pass it on untouched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForwardedHeader {
+    #[default]
+    XForwardedFor,
+    // RFC 7239 `Forwarded: for=`
+    Forwarded,
+}
+
+/// Whether `ip` is one of `trusted_proxies`
+pub fn is_trusted_proxy(trusted_proxies: &[IpRule], ip: IpAddr) -> bool {
+    trusted_proxies.iter().any(|rule| rule.contains(ip))
+}
+
+/// Read the HAProxy PROXY protocol (v1 or v2) header a load balancer sends
+/// before anything else, returning the client address it carries. None for
+/// health checks from the balancer itself (`UNKNOWN`, v2 `LOCAL`) and
+/// non-TCP sources.
+pub async fn read_proxy_header(stream: &mut TcpStream) -> std::io::Result<Option<SocketAddr>> {
+    match timeout(
+        Duration::from_secs(PROXY_HEADER_TIMEOUT_SECS),
+        read_header(stream),
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => Err(ErrorKind::TimedOut.into()),
+    }
+}
+
+async fn read_header(stream: &mut TcpStream) -> std::io::Result<Option<SocketAddr>> {
+    // Never read past the header, the rest belongs to TLS or HTTP
+    let mut header = vec![0u8; PROXY_HEADER_MIN_LEN];
+    stream.read_exact(&mut header).await?;
+    if header.starts_with(PROXY_V2_SIGNATURE) {
+        let mut fixed = [0u8; 16];
+        fixed[..PROXY_HEADER_MIN_LEN].copy_from_slice(&header);
+        stream
+            .read_exact(&mut fixed[PROXY_HEADER_MIN_LEN..])
+            .await?;
+        let len = u16::from_be_bytes([fixed[14], fixed[15]]) as usize;
+        let mut addresses = vec![0u8; len];
+        stream.read_exact(&mut addresses).await?;
+        return parse_v2(fixed[12], fixed[13], &addresses);
+    }
+    if !header.starts_with(b"PROXY ") {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+    while !header.ends_with(b"\r\n") {
+        if header.len() >= PROXY_V1_MAX_LEN {
+            return Err(invalid("PROXY protocol v1 header too long"));
+        }
+        header.push(stream.read_u8().await?);
+    }
+    parse_v1(&header[..header.len() - 2])
+}
+
+// "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443"
+fn parse_v1(line: &[u8]) -> std::io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("invalid PROXY v1 header"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("invalid PROXY v1 source address"))?;
+            let port: u16 = source_port
+                .parse()
+                .map_err(|_| invalid("invalid PROXY v1 source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("invalid PROXY v1 header")),
+    }
+}
+
+fn parse_v2(
+    version_command: u8,
+    family: u8,
+    addresses: &[u8],
+) -> std::io::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    match version_command & 0x0f {
+        0x0 => return Ok(None),
+        0x1 => {}
+        _ => return Err(invalid("invalid PROXY v2 command")),
+    }
+    // TLVs may follow the addresses, they are skipped
+    match family {
+        0x11 if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+        }
+        0x21 if addresses.len() >= 36 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(octets)),
+                port,
+            )))
+        }
+        0x11 | 0x21 => Err(invalid("truncated PROXY v2 addresses")),
+        // UDP and unix sockets, there's no client IP to use
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, message.to_string())
+}
+
+/// Record the request's client IP for `client_ip`. When the connection comes
+/// from a trusted proxy its forwarding `header` is walked from the nearest
+/// hop, skipping trusted proxies, and the first other address is the client.
+pub fn resolve_client_ip<B>(
+    req: &mut Request<B>,
+    trusted_proxies: &[IpRule],
+    header: ForwardedHeader,
+) {
+    let Some(peer) = connection_ip(req) else {
+        return;
+    };
+    let is_trusted = |ip: IpAddr| is_trusted_proxy(trusted_proxies, ip);
+    if !is_trusted(peer) {
+        return;
+    }
+    let hops = forwarded_for(req.headers(), header);
+    let mut client = peer;
+    for hop in hops.into_iter().rev().take(MAX_FORWARDED_HOPS) {
+        // Anything unparsable ends the walk, the address seen last is used
+        let Some(ip) = hop else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    req.extensions_mut().insert(ClientIp(client));
+}
+
+// Hops of `header`, client first. None for obfuscated and `unknown` hops.
+fn forwarded_for(headers: &HeaderMap, header: ForwardedHeader) -> Vec<Option<IpAddr>> {
+    if header == ForwardedHeader::Forwarded {
+        return headers
+            .get_all("forwarded")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.trim().split_once('=')?;
+                    name.eq_ignore_ascii_case("for")
+                        .then(|| parse_forwarded_node(value))
+                })
+            })
+            .collect();
+    }
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| parse_forwarded_node(hop.trim()))
+        .collect()
+}
+
+// `192.0.2.1`, `192.0.2.1:8080`, `"[2001:db8::1]:4711"` or a bare IPv6 address
+fn parse_forwarded_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    node.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
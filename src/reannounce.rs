@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use librqbit::api::TorrentIdOrHash;
+use librqbit::dht::Id20;
+use serde::Serialize;
+use tokio::time::{Duration, Instant, sleep};
+use tracing::{info, warn};
+
+use crate::app::AppState;
+use crate::torrents::torrent_snapshots;
+
+// Restarted torrents usually go live right away, they're already hash checked
+const LIVE_WAIT_SECS: u64 = 10;
+const LIVE_POLL_MS: u64 = 500;
+const LIVE_STATE: &str = "live";
+
+#[derive(Debug, Serialize)]
+pub struct ReannounceResult {
+    pub info_hash: String,
+    pub path: String,
+    // Initial peers queued for connection after the restart
+    pub initial_peers: usize,
+    pub error: Option<String>,
+}
+
+/// Reconnect the swarm of every running torrent: pausing and starting a torrent
+/// drops its peers and announces to trackers and the DHT again, then the
+/// configured initial peers are dialed on the fresh peer table.
+pub async fn reannounce_all(state: &AppState) -> Vec<ReannounceResult> {
+    let torrents = state.config.load().torrents().clone();
+    let running: Vec<Id20> = torrent_snapshots(&state.bt_api)
+        .into_iter()
+        .filter(|torrent| torrent.state == LIVE_STATE)
+        .filter_map(|torrent| torrent.info_hash.parse().ok())
+        .filter(|info_hash| torrents.contains_key(info_hash))
+        .collect();
+
+    let mut results = HashMap::new();
+    for info_hash in running {
+        let torrent = &torrents[&info_hash];
+        let restarted: anyhow::Result<()> = async {
+            state
+                .bt_api
+                .api_torrent_action_pause(TorrentIdOrHash::Hash(info_hash))
+                .await?;
+            state
+                .bt_api
+                .api_torrent_action_start(TorrentIdOrHash::Hash(info_hash))
+                .await?;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = &restarted {
+            warn!("Failed to re-announce torrent {}: {:#}", torrent.path, e);
+        }
+        results.insert(
+            info_hash,
+            ReannounceResult {
+                info_hash: info_hash.as_string(),
+                path: torrent.path.clone(),
+                initial_peers: 0,
+                error: restarted.err().map(|e| format!("{:#}", e)),
+            },
+        );
+    }
+
+    // Initial peers can only be added once a torrent is live again
+    let deadline = Instant::now() + Duration::from_secs(LIVE_WAIT_SECS);
+    let mut pending: Vec<Id20> = results
+        .iter()
+        .filter(|(info_hash, result)| {
+            result.error.is_none() && !torrents[*info_hash].initial_peers.is_empty()
+        })
+        .map(|(info_hash, _)| *info_hash)
+        .collect();
+    while !pending.is_empty() {
+        pending.retain(|info_hash| {
+            let live = state
+                .bt_api
+                .mgr_handle(TorrentIdOrHash::Hash(*info_hash))
+                .ok()
+                .and_then(|handle| handle.live());
+            let Some(live) = live else {
+                return true;
+            };
+            let result = results.get_mut(info_hash).unwrap();
+            for peer in &torrents[info_hash].initial_peers {
+                match live.add_peer_if_not_seen(*peer) {
+                    Ok(_) => result.initial_peers += 1,
+                    Err(e) => warn!("Failed to add peer {} to {}: {:#}", peer, result.path, e),
+                }
+            }
+            false
+        });
+        if Instant::now() >= deadline {
+            for info_hash in &pending {
+                let result = results.get_mut(info_hash).unwrap();
+                result.error = Some("torrent not live again in time for initial peers".to_string());
+            }
+            break;
+        }
+        if !pending.is_empty() {
+            sleep(Duration::from_millis(LIVE_POLL_MS)).await;
+        }
+    }
+
+    let results: Vec<ReannounceResult> = results.into_values().collect();
+    info!(
+        "Re-announced {} torrents ({} failed)",
+        results.len(),
+        results
+            .iter()
+            .filter(|result| result.error.is_some())
+            .count()
+    );
+    results
+}
+
+/// `--reannounce`: ask the node running on `port` to re-announce, print the result
+pub async fn request_reannounce(
+    port: u16,
+    tls: bool,
+    management_token: Option<&str>,
+) -> anyhow::Result<()> {
+    let scheme = if tls { "https" } else { "http" };
+    // The certificate is issued for the public name, not for localhost
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(tls)
+        .timeout(Duration::from_secs(LIVE_WAIT_SECS + 60))
+        .build()?;
+    let mut request = client.post(format!(
+        "{}://127.0.0.1:{}/-/torrents/reannounce",
+        scheme, port
+    ));
+    if let Some(token) = management_token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    anyhow::ensure!(status.is_success(), "node returned {}: {}", status, body);
+    println!("{}", body);
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use base64::Engine;
+use hyper::header::HeaderValue;
+use hyper::{Method, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppState;
+use crate::checksums::FileHashes;
+use crate::config::OptimizedConfig;
+use crate::gossip::holders_of;
+use crate::metrics::OVERFLOW_REDIRECTS_TOTAL;
+use crate::response::ResBody;
+use crate::server::client_ip;
+use crate::signature::{
+    IP_BINDING_PARAM, IpBinding, create_signature_with_params, get_expire_time,
+    signature_expire_time, signed_params_message, verify_signature_with_params,
+};
+
+// Added to redirect URLs so an equally busy node (or one with another cluster
+// membership) doesn't bounce the client back
+pub(crate) const REDIRECTED_PARAM: &str = "dfs_redirected=1";
+const DEFAULT_REDIRECT_EXPIRE_SECS: u32 = 300;
+// Alternates listed in Link headers, keeps the header size bounded
+const MAX_DUPLICATE_LINKS: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OverflowRedirectConfig {
+    // Base URLs of alternate nodes, e.g. https://node2.example.com
+    pub urls: Vec<String>,
+    // Lifetime of the re-issued signature, default 300 seconds
+    pub expire_seconds: Option<u32>,
+}
+
+// Path and query of `req` for another node, re-signed locally when the path is
+// signed. The query is carried over and the new signature keeps the original
+// ranges, network and parameters, expiring no later than the original. None if
+// the request's own signature doesn't verify.
+pub(crate) fn alternate_path<B>(
+    config: &OptimizedConfig,
+    req: &Request<B>,
+    expire_seconds: u32,
+    extra_param: Option<&str>,
+) -> Option<String> {
+    let path = req.uri().path();
+    let query = req.uri().query();
+    let mut params: Vec<String> = query
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split('=').next().unwrap_or("");
+            !name.is_empty() && name != "$" && name != IP_BINDING_PARAM
+        })
+        .map(str::to_string)
+        .collect();
+    params.extend(extra_param.map(str::to_string));
+    let path_config = config.find_path_config(path);
+    if let Some((path_config, token)) = path_config.and_then(|pc| Some((pc, pc.signing_token()?))) {
+        let signed_params = path_config.signature_params.as_deref().unwrap_or_default();
+        let range_header = req.headers().get("range").and_then(|h| h.to_str().ok());
+        let client_ip = client_ip(req);
+        let ranges = verify_signature_with_params(
+            path,
+            query,
+            path_config.signature_tokens(),
+            range_header,
+            signed_params,
+            client_ip,
+        )
+        .ok()?;
+        // The client is the same at the other node, so is its network
+        let binding = IpBinding::from_request(query, client_ip).ok()?;
+        // A redirect must not outlive the link it came from
+        let expire_time = signature_expire_time(query)?.min(get_expire_time(expire_seconds));
+        let signed_query = signed_params_message(Some(&params.join("&")), signed_params);
+        params.extend(binding.map(|binding| binding.query_pair()));
+        params.push(format!(
+            "$={}",
+            create_signature_with_params(
+                path,
+                expire_time,
+                token,
+                Some(&ranges),
+                &signed_query,
+                binding.as_ref(),
+            )
+        ));
+    }
+    if params.is_empty() {
+        Some(path.to_string())
+    } else {
+        Some(format!("{}?{}", path, params.join("&")))
+    }
+}
+
+/// 302 to an alternate node instead of a 503 while this node is over budget.
+/// Signed paths are re-signed locally with the same ranges, so the request
+/// must carry a valid signature to be redirected. Cluster nodes gossiping that
+/// they finished the path's torrent are picked first.
+pub fn overflow_redirect<B>(state: &AppState, req: &Request<B>) -> Option<Response<ResBody>> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return None;
+    }
+    if req
+        .uri()
+        .query()
+        .is_some_and(|query| query.split('&').any(|pair| pair == REDIRECTED_PARAM))
+    {
+        return None;
+    }
+    let config = state.config.load();
+    let redirect = config.overflow_redirect.as_ref()?;
+    if redirect.urls.is_empty() {
+        return None;
+    }
+    let holder_urls: Vec<&str> = match &config.cluster {
+        Some(cluster) => {
+            let holders = holders_of(state, req.uri().path());
+            cluster
+                .nodes
+                .iter()
+                .filter(|node| holders.contains(&node.id))
+                .map(|node| node.url.trim_end_matches('/'))
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    let preferred: Vec<&String> = redirect
+        .urls
+        .iter()
+        .filter(|url| holder_urls.contains(&url.trim_end_matches('/')))
+        .collect();
+    let base = if preferred.is_empty() {
+        &redirect.urls[rand::random_range(0..redirect.urls.len())]
+    } else {
+        preferred[rand::random_range(0..preferred.len())]
+    };
+    let expire_seconds = redirect
+        .expire_seconds
+        .unwrap_or(DEFAULT_REDIRECT_EXPIRE_SECS);
+    let path = alternate_path(&config, req, expire_seconds, Some(REDIRECTED_PARAM))?;
+
+    OVERFLOW_REDIRECTS_TOTAL.inc();
+    let response = Response::builder()
+        .status(StatusCode::FOUND)
+        .header(
+            "Location",
+            format!("{}{}", base.trim_end_matches('/'), path),
+        )
+        .header("Cache-Control", "no-store")
+        .body(ResBody::Empty)
+        .unwrap();
+    Some(response)
+}
+
+/// `Link: <url>; rel=duplicate` header (RFC 6249) listing this file on the
+/// configured alternate nodes, in the configured order of preference
+pub fn duplicate_links<B>(state: &AppState, req: &Request<B>) -> Option<HeaderValue> {
+    let config = state.config.load();
+    let nodes = config.alternate_nodes.as_ref()?;
+    if nodes.is_empty() {
+        return None;
+    }
+    let path = alternate_path(&config, req, DEFAULT_REDIRECT_EXPIRE_SECS, None)?;
+    let links: Vec<String> = nodes
+        .iter()
+        .take(MAX_DUPLICATE_LINKS)
+        .enumerate()
+        .map(|(index, base)| {
+            format!(
+                "<{}{}>; rel=duplicate; pri={}",
+                base.trim_end_matches('/'),
+                path,
+                index + 1
+            )
+        })
+        .collect();
+    HeaderValue::from_str(&links.join(", ")).ok()
+}
+
+/// `Digest` header (RFC 3230) with the whole-file sha256, as used by Metalink/HTTP
+pub fn digest_header(hashes: &FileHashes) -> Option<HeaderValue> {
+    let sha256 = hex::decode(&hashes.sha256).ok()?;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(sha256);
+    HeaderValue::from_str(&format!("SHA-256={}", encoded)).ok()
+}
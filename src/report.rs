@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::mpsc;
+use tokio::time::{Duration, interval};
+use tracing::{info, warn};
+
+use crate::app::AppState;
+use crate::logging::log_limited;
+use crate::metrics::CONNECTION_ERRORS_TOTAL;
+
+const REPORT_QUEUE_SIZE: usize = 64;
+const REPORT_TIMEOUT_SECS: u64 = 10;
+const SPIKE_WINDOW_SECS: u64 = 60;
+const DEFAULT_CONNECTION_ERROR_THRESHOLD: u64 = 100;
+const DEFAULT_TORRENT_FAILURE_THRESHOLD: u32 = 3;
+const CRASH_FILE: &str = "crash.json";
+
+static REPORT_SENDER: OnceLock<mpsc::Sender<ErrorReport>> = OnceLock::new();
+// Consecutive add failures per torrent path
+static TORRENT_FAILURES: OnceLock<Mutex<HashMap<String, u32>>> = OnceLock::new();
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErrorReportConfig {
+    // Sentry DSN, events are sent to its store endpoint
+    pub sentry_dsn: Option<String>,
+    // Generic endpoint receiving the report as JSON
+    pub url: Option<String>,
+    // Protocol + internal connection errors per minute considered a spike
+    pub connection_error_threshold: Option<u64>,
+    // Consecutive failures to add a torrent before it is reported
+    pub torrent_failure_threshold: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErrorReport {
+    // panic, torrent_failure or connection_errors
+    pub kind: String,
+    pub message: String,
+    pub timestamp: i64,
+    #[serde(default)]
+    pub extra: Value,
+}
+
+impl ErrorReportConfig {
+    fn should_send(&self, report: &ErrorReport) -> bool {
+        if report.kind != "torrent_failure" {
+            return true;
+        }
+        let threshold = self
+            .torrent_failure_threshold
+            .unwrap_or(DEFAULT_TORRENT_FAILURE_THRESHOLD);
+        report.extra["failures"].as_u64() == Some(threshold as u64)
+    }
+}
+
+impl ErrorReport {
+    fn new(kind: &str, message: String, extra: Value) -> Self {
+        Self {
+            kind: kind.to_string(),
+            message,
+            timestamp: chrono::Utc::now().timestamp(),
+            extra,
+        }
+    }
+}
+
+fn report(report: ErrorReport) {
+    let Some(sender) = REPORT_SENDER.get() else {
+        return;
+    };
+    if sender.try_send(report).is_err() {
+        log_limited!(
+            warn,
+            "error_report_queue",
+            "Error report queue full, dropping report"
+        );
+    }
+}
+
+/// Record panics to the state directory before the default hook runs. Release
+/// builds abort on panic, so the report is sent on the next start.
+pub fn install_panic_hook(state_dir: PathBuf) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic_info| {
+        let report = ErrorReport::new(
+            "panic",
+            panic_info.to_string(),
+            json!({ "backtrace": std::backtrace::Backtrace::force_capture().to_string() }),
+        );
+        if let Ok(content) = serde_json::to_vec(&report) {
+            let _ = std::fs::write(state_dir.join(CRASH_FILE), content);
+        }
+        self::report(report);
+        previous(panic_info);
+    }));
+}
+
+/// Count a failed torrent add, it is reported once the failures reach the threshold
+pub fn torrent_failure(path: &str, error: &str) {
+    let failures = {
+        let mut failures = TORRENT_FAILURES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap();
+        let count = failures.entry(path.to_string()).or_default();
+        *count += 1;
+        *count
+    };
+    report(ErrorReport::new(
+        "torrent_failure",
+        format!(
+            "Failed to add torrent {} {} times: {}",
+            path, failures, error
+        ),
+        json!({ "path": path, "failures": failures, "error": error }),
+    ));
+}
+
+pub fn torrent_added(path: &str) {
+    if let Some(failures) = TORRENT_FAILURES.get() {
+        failures.lock().unwrap().remove(path);
+    }
+}
+
+/// Start delivering reports, including a crash recorded by the previous run
+pub fn start(state: &AppState) {
+    let (sender, receiver) = mpsc::channel(REPORT_QUEUE_SIZE);
+    if REPORT_SENDER.set(sender).is_err() {
+        return;
+    }
+    let crash_path = state.state_dir.join(CRASH_FILE);
+    if let Some(crash) = std::fs::read(&crash_path)
+        .ok()
+        .and_then(|content| serde_json::from_slice::<ErrorReport>(&content).ok())
+    {
+        info!("Reporting crash from previous run: {}", crash.message);
+        report(crash);
+    }
+    tokio::spawn(dispatch_task(state.clone(), receiver));
+    tokio::spawn(connection_spike_task(state.clone()));
+}
+
+async fn dispatch_task(state: AppState, mut receiver: mpsc::Receiver<ErrorReport>) {
+    let crash_path = state.state_dir.join(CRASH_FILE);
+    while let Some(report) = receiver.recv().await {
+        let config = state.config.load_full();
+        let Some(config) = config.error_report.as_ref() else {
+            continue;
+        };
+        if !config.should_send(&report) {
+            continue;
+        }
+        let mut delivered = false;
+        if let Some(dsn) = &config.sentry_dsn {
+            delivered |= send_sentry(&state, dsn, &report).await;
+        }
+        if let Some(url) = &config.url {
+            delivered |= send_generic(&state, url, &report).await;
+        }
+        // Keep the crash file until it has been delivered somewhere
+        if delivered && report.kind == "panic" {
+            let _ = tokio::fs::remove_file(&crash_path).await;
+        }
+    }
+}
+
+fn node_metadata(state: &AppState) -> Value {
+    json!({
+        "server_id": state.server_id,
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+    })
+}
+
+async fn send_generic(state: &AppState, url: &str, report: &ErrorReport) -> bool {
+    let mut body = serde_json::to_value(report).unwrap_or(Value::Null);
+    body["node"] = node_metadata(state);
+    let request = state
+        .http_client
+        .post(url)
+        .timeout(Duration::from_secs(REPORT_TIMEOUT_SECS))
+        .json(&body);
+    send(request, url).await
+}
+
+// https://develop.sentry.dev/sdk/data-model/event-payloads/
+async fn send_sentry(state: &AppState, dsn: &str, report: &ErrorReport) -> bool {
+    let Some((store_url, public_key)) = parse_dsn(dsn) else {
+        log_limited!(warn, "sentry_dsn", "Invalid Sentry DSN");
+        return false;
+    };
+    let level = if report.kind == "panic" {
+        "fatal"
+    } else {
+        "error"
+    };
+    let event_id = hex::encode(rand::random::<[u8; 16]>());
+    let body = json!({
+        "event_id": event_id,
+        "timestamp": report.timestamp,
+        "platform": "other",
+        "level": level,
+        "logger": "dfsnode",
+        "server_name": state.server_id,
+        "release": concat!("dfsnode@", env!("CARGO_PKG_VERSION")),
+        "message": { "formatted": report.message },
+        "tags": {
+            "kind": report.kind,
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+        },
+        "extra": report.extra,
+    });
+    let auth = format!(
+        "Sentry sentry_version=7, sentry_client=dfsnode/{}, sentry_key={}",
+        env!("CARGO_PKG_VERSION"),
+        public_key
+    );
+    let request = state
+        .http_client
+        .post(&store_url)
+        .timeout(Duration::from_secs(REPORT_TIMEOUT_SECS))
+        .header("X-Sentry-Auth", auth)
+        .json(&body);
+    send(request, &store_url).await
+}
+
+// {scheme}://{public_key}@{host}/{project_id} -> store endpoint and key
+fn parse_dsn(dsn: &str) -> Option<(String, String)> {
+    let url = reqwest::Url::parse(dsn).ok()?;
+    let public_key = url.username();
+    if public_key.is_empty() {
+        return None;
+    }
+    let (prefix, project_id) = url.path().trim_end_matches('/').rsplit_once('/')?;
+    if project_id.is_empty() {
+        return None;
+    }
+    let port = url
+        .port()
+        .map(|port| format!(":{}", port))
+        .unwrap_or_default();
+    let store_url = format!(
+        "{}://{}{}{}/api/{}/store/",
+        url.scheme(),
+        url.host_str()?,
+        port,
+        prefix,
+        project_id
+    );
+    Some((store_url, public_key.to_string()))
+}
+
+async fn send(request: reqwest::RequestBuilder, url: &str) -> bool {
+    match request.send().await {
+        Ok(response) if response.status().is_success() => true,
+        Ok(response) => {
+            log_limited!(
+                warn,
+                "error_report_delivery",
+                "Error report endpoint {} returned {}",
+                url,
+                response.status()
+            );
+            false
+        }
+        Err(e) => {
+            log_limited!(
+                warn,
+                "error_report_delivery",
+                "Failed to send error report to {}: {}",
+                url,
+                e
+            );
+            false
+        }
+    }
+}
+
+// Report once per spike: when a window crosses the threshold after a quiet one
+async fn connection_spike_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(SPIKE_WINDOW_SECS));
+    let count = || {
+        ["protocol", "internal"]
+            .iter()
+            .map(|kind| CONNECTION_ERRORS_TOTAL.with_label_values(&[kind]).get())
+            .sum::<u64>()
+    };
+    let mut last = count();
+    let mut in_spike = false;
+    loop {
+        interval.tick().await;
+        let current = count();
+        let errors = current.saturating_sub(last);
+        last = current;
+
+        let config = state.config.load_full();
+        let Some(config) = config.error_report.as_ref() else {
+            continue;
+        };
+        let threshold = config
+            .connection_error_threshold
+            .unwrap_or(DEFAULT_CONNECTION_ERROR_THRESHOLD);
+        let spiking = errors >= threshold;
+        if spiking && !in_spike {
+            report(ErrorReport::new(
+                "connection_errors",
+                format!(
+                    "{} connection errors in the last {}s",
+                    errors, SPIKE_WINDOW_SECS
+                ),
+                json!({ "errors": errors, "window_seconds": SPIKE_WINDOW_SECS }),
+            ));
+        }
+        in_spike = spiking;
+    }
+}
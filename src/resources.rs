@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tokio::time::{Duration, interval};
+use tracing::{info, warn};
+
+use crate::metrics::{LOAD_SHEDDING, TOKIO_ALIVE_TASKS};
+
+const RESOURCE_CHECK_INTERVAL_SECS: u64 = 1;
+
+// Soft limits, new connections get 503 while any of them is exceeded
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLimits {
+    pub max_rss_bytes: Option<u64>,
+    pub max_open_fds: Option<u64>,
+    pub max_tasks: Option<u64>,
+}
+
+impl ResourceLimits {
+    fn is_empty(&self) -> bool {
+        self.max_rss_bytes.is_none() && self.max_open_fds.is_none() && self.max_tasks.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ResourceUsage {
+    rss_bytes: Option<u64>,
+    open_fds: Option<u64>,
+    tasks: u64,
+}
+
+impl ResourceUsage {
+    fn sample() -> Self {
+        Self {
+            rss_bytes: rss_bytes(),
+            open_fds: open_fds(),
+            tasks: tokio::runtime::Handle::current()
+                .metrics()
+                .num_alive_tasks() as u64,
+        }
+    }
+
+    // Name of the first limit exceeded
+    fn exceeded(&self, limits: &ResourceLimits) -> Option<&'static str> {
+        let over = |usage: Option<u64>, limit: Option<u64>| matches!((usage, limit), (Some(u), Some(l)) if u >= l);
+        if over(self.rss_bytes, limits.max_rss_bytes) {
+            Some("rss")
+        } else if over(self.open_fds, limits.max_open_fds) {
+            Some("open_fds")
+        } else if over(Some(self.tasks), limits.max_tasks) {
+            Some("tasks")
+        } else {
+            None
+        }
+    }
+}
+
+/// Periodically samples process resources and flips the shedding flag
+pub struct ResourceMonitor {
+    limits: ResourceLimits,
+    shedding: AtomicBool,
+}
+
+impl ResourceMonitor {
+    pub fn new(limits: ResourceLimits) -> Self {
+        Self {
+            limits,
+            shedding: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_shedding(&self) -> bool {
+        self.shedding.load(Ordering::Relaxed)
+    }
+
+    pub async fn run(self: std::sync::Arc<Self>) {
+        let mut interval = interval(Duration::from_secs(RESOURCE_CHECK_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let usage = ResourceUsage::sample();
+            TOKIO_ALIVE_TASKS.set(usage.tasks as i64);
+            if self.limits.is_empty() {
+                continue;
+            }
+
+            let exceeded = usage.exceeded(&self.limits);
+            let was_shedding = self.shedding.swap(exceeded.is_some(), Ordering::Relaxed);
+            LOAD_SHEDDING.set(exceeded.is_some() as i64);
+            match (exceeded, was_shedding) {
+                (Some(limit), false) => warn!(
+                    "Soft {} limit exceeded, shedding new connections ({:?})",
+                    limit, usage
+                ),
+                (None, true) => info!("Resource usage back under limits, accepting connections"),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn rss_bytes() -> Option<u64> {
+    // second field of statm is resident pages
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * page_size.max(0) as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rss_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn open_fds() -> Option<u64> {
+    Some(std::fs::read_dir("/proc/self/fd").ok()?.count() as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_fds() -> Option<u64> {
+    None
+}
@@ -8,23 +8,56 @@ use futures_util::Stream;
 use hyper::body::{Bytes, Frame};
 use hyper::http::StatusCode;
 use hyper::{Method, Uri};
+use tokio::sync::OwnedSemaphorePermit;
 
-use crate::metrics::{HTTP_BYTES_SENT_TOTAL, HTTP_REQUESTS_TOTAL};
+use crate::accesslog::{ACCESS_LOG, AccessRequest};
+use crate::integrity::HashVerifier;
+use crate::metrics::{FILE_RESPONSES_TOTAL, HTTP_BYTES_SENT_TOTAL, RequestLabels};
+use crate::outliers::OUTLIER_TRACKER;
+use crate::pool::PooledFileStream;
+use crate::throttle::{RateLimiter, Throttle};
 
 pub struct StaticMetrics {
     pub method: Method,
     pub uri: Uri,
     pub status: StatusCode,
+    // Emit an access log line when the transfer ends
+    pub access_log: bool,
+    // Set by the observe layer, the request is counted when the transfer ends
+    pub labels: Option<RequestLabels>,
+    // Set by the observe layer when the JSON access log is enabled
+    pub access_request: Option<AccessRequest>,
+}
+
+// File content source for ResBody::Static
+pub enum FileBody {
+    Static(hyper_staticfile::Body),
+    // Full-file responses read through the shared buffer pool
+    Pooled(PooledFileStream),
+    // Small files served from a memory map or the hot file cache, sent as a single chunk
+    Mapped(Option<Bytes>),
+    // Content arriving from elsewhere, e.g. an origin pull
+    Stream(Pin<Box<dyn Stream<Item = Result<Bytes, IoError>> + Send>>),
 }
 
 pub enum ResBody {
     Static {
-        inner: hyper_staticfile::Body,
+        inner: FileBody,
         start_time: Instant,
         bytes_sent: u32,
         metrics: Arc<StaticMetrics>,
+        verifier: Option<Box<HashVerifier>>,
+        // Concurrency permits released when the transfer ends
+        permits: Vec<OwnedSemaphorePermit>,
+        // Disk read budget, a chunk waits in `held` until its bytes are granted
+        throttle: Option<Throttle>,
+        held: Option<Frame<Bytes>>,
+        // Per-path rate_limit_bytes_per_sec for this transfer
+        rate_limit: Option<RateLimiter>,
     },
     Dav(dav_server::body::Body),
+    // Generated content sent as it is produced, e.g. large directory listings
+    Stream(Pin<Box<dyn Stream<Item = Result<Bytes, IoError>> + Send>>),
     Bytes(Bytes),
     Empty,
 }
@@ -41,20 +74,66 @@ impl hyper::body::Body for ResBody {
             ResBody::Static {
                 ref mut inner,
                 ref mut bytes_sent,
+                ref mut verifier,
+                ref metrics,
+                ref mut throttle,
+                ref mut held,
+                ref mut rate_limit,
                 ..
             } => {
-                let result = ready!(match inner {
-                    hyper_staticfile::Body::Empty => return Poll::Ready(None),
-                    hyper_staticfile::Body::Full(stream) => Pin::new(stream).poll_next(cx),
-                    hyper_staticfile::Body::Range(stream) => Pin::new(stream).poll_next(cx),
-                    hyper_staticfile::Body::MultiRange(stream) => Pin::new(stream).poll_next(cx),
-                });
-                let bytes = result.map(|res| res.map(Frame::data));
+                let bytes = match held.take() {
+                    Some(frame) => Some(Ok(frame)),
+                    None => {
+                        let result = ready!(match inner {
+                            FileBody::Pooled(stream) => Pin::new(stream).poll_next(cx),
+                            FileBody::Mapped(bytes) => Poll::Ready(bytes.take().map(Ok)),
+                            FileBody::Stream(stream) => stream.as_mut().poll_next(cx),
+                            FileBody::Static(hyper_staticfile::Body::Empty) => {
+                                return Poll::Ready(None);
+                            }
+                            FileBody::Static(hyper_staticfile::Body::Full(stream)) => {
+                                Pin::new(stream).poll_next(cx)
+                            }
+                            FileBody::Static(hyper_staticfile::Body::Range(stream)) => {
+                                Pin::new(stream).poll_next(cx)
+                            }
+                            FileBody::Static(hyper_staticfile::Body::MultiRange(stream)) => {
+                                Pin::new(stream).poll_next(cx)
+                            }
+                        });
+                        result.map(|res| res.map(Frame::data))
+                    }
+                };
+                // The rate limit only waits here, the chunk is charged once it goes out
+                if let (Some(limiter), Some(Ok(_))) = (rate_limit.as_mut(), &bytes) {
+                    if limiter.poll_ready(cx).is_pending() {
+                        *held = bytes.and_then(Result::ok);
+                        return Poll::Pending;
+                    }
+                }
+                // Hold the chunk back until the disk read budget covers it
+                if let (Some(throttle), Some(Ok(frame))) = (throttle.as_mut(), &bytes) {
+                    let len = frame.data_ref().map_or(0, |data| data.len());
+                    if throttle.poll_take(cx, len).is_pending() {
+                        *held = bytes.and_then(Result::ok);
+                        return Poll::Pending;
+                    }
+                }
                 if let Some(Ok(ref bytes)) = bytes {
-                    if bytes.is_data() {
+                    if let Some(data) = bytes.data_ref() {
+                        // Abort the transfer instead of sending the last chunk of a corrupted file
+                        if let Some(verifier) = verifier {
+                            if !verifier.update(data, &metrics.uri) {
+                                return Poll::Ready(Some(Err(IoError::other(
+                                    "content hash mismatch",
+                                ))));
+                            }
+                        }
+                        if let Some(limiter) = rate_limit {
+                            limiter.consume(data.len());
+                        }
                         // Update bytes sent count
-                        let add = bytes.data_ref().unwrap().len() as u32;
-                        *bytes_sent += add;
+                        *bytes_sent += data.len() as u32;
                     }
                 }
                 Poll::Ready(bytes)
@@ -63,6 +142,10 @@ impl hyper::body::Body for ResBody {
                 let result = ready!(Pin::new(dav_body).poll_next(cx));
                 Poll::Ready(result.map(|res| res.map(Frame::data)))
             }
+            ResBody::Stream(ref mut stream) => {
+                let result = ready!(stream.as_mut().poll_next(cx));
+                Poll::Ready(result.map(|res| res.map(Frame::data)))
+            }
             ResBody::Empty => return Poll::Ready(None),
             ResBody::Bytes(ref mut bytes) => {
                 if bytes.is_empty() {
@@ -88,12 +171,49 @@ impl Drop for ResBody {
         } = self
         {
             // 增加请求计数
-            HTTP_REQUESTS_TOTAL.inc();
-            // 记录发送的字节数到 metrics
-            HTTP_BYTES_SENT_TOTAL.inc_by(*bytes_sent as u64);
+            if let Some(labels) = &metrics.labels {
+                labels.record(metrics.status, start_time.elapsed());
+            }
+            // 记录发送的字节数到 metrics，区分完整文件与 Range 响应
+            let response = if metrics.status == StatusCode::PARTIAL_CONTENT {
+                "range"
+            } else {
+                "full"
+            };
+            let prefix = metrics
+                .labels
+                .as_ref()
+                .map_or("", |labels| labels.prefix.as_str());
+            HTTP_BYTES_SENT_TOTAL
+                .with_label_values(&[prefix, response])
+                .inc_by(*bytes_sent as u64);
+            FILE_RESPONSES_TOTAL
+                .with_label_values(&[metrics.status.as_str()])
+                .inc();
+            if let Some((log, request)) = ACCESS_LOG.get().zip(metrics.access_request.as_ref()) {
+                log.record(
+                    request,
+                    metrics.status,
+                    *bytes_sent as u64,
+                    start_time.elapsed(),
+                );
+            }
+            if let Some(tracker) = OUTLIER_TRACKER.get() {
+                tracker.record(metrics.uri.path(), *bytes_sent as u64, start_time.elapsed());
+            }
 
-            // 优化日志记录 - 只在debug模式下记录详细信息
-            if cfg!(debug_assertions) {
+            if metrics.access_log {
+                tracing::info!(
+                    target: "access",
+                    "{} {} -> {} ({}ms) {}b",
+                    metrics.method,
+                    metrics.uri,
+                    metrics.status,
+                    start_time.elapsed().as_millis(),
+                    bytes_sent
+                );
+            } else if cfg!(debug_assertions) {
+                // 优化日志记录 - 只在debug模式下记录详细信息
                 let est_speed = if start_time.elapsed().as_millis() > 0 {
                     *bytes_sent as f64 / start_time.elapsed().as_secs_f64()
                 } else {
@@ -0,0 +1,59 @@
+// Staged rollouts: a share of the clients matching a header get a path's files
+// from another root, e.g. a release-candidate build, under the same URLs
+use std::path::PathBuf;
+
+use hyper::Request;
+use serde::{Deserialize, Serialize};
+
+use crate::server::client_ip;
+
+const DEFAULT_HEADER: &str = "user-agent";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RolloutConfig {
+    // Served instead of the path's root, laid out the same way
+    pub root: PathBuf,
+    // Share of matching clients in percent, default 100
+    pub percent: Option<f64>,
+    // Header to match, default User-Agent
+    pub header: Option<String>,
+    // Text the header must contain (case-sensitive), every client when absent
+    pub contains: Option<String>,
+}
+
+impl RolloutConfig {
+    /// The header clients are matched on, responses vary on it
+    pub fn header(&self) -> &str {
+        self.header.as_deref().unwrap_or(DEFAULT_HEADER)
+    }
+
+    /// Whether only some of the matching clients get the rollout. They are
+    /// told apart by IP, which no `Vary` can express to a shared cache.
+    pub fn is_partial(&self) -> bool {
+        self.percent.unwrap_or(100.0) < 100.0
+    }
+
+    /// Whether `req` is served from the rollout root. Clients are bucketed by
+    /// IP, so one client stays on the same build from request to request.
+    pub fn applies<B>(&self, req: &Request<B>) -> bool {
+        let value = req
+            .headers()
+            .get(self.header())
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        if self
+            .contains
+            .as_deref()
+            .is_some_and(|contains| !value.contains(contains))
+        {
+            return false;
+        }
+        if !self.is_partial() {
+            return true;
+        }
+        let percent = self.percent.unwrap_or(100.0);
+        let key = client_ip(req).map(|ip| ip.to_string()).unwrap_or_default();
+        let bucket = xxhash_rust::xxh3::xxh3_64(key.as_bytes()) % 10_000;
+        (bucket as f64) < percent * 100.0
+    }
+}
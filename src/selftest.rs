@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail, ensure};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::app::AppState;
+use crate::assets::find_asset;
+use crate::auth::signature_suffices;
+use crate::metrics::gather;
+use crate::pipeline::handle_request;
+use crate::server::ConnectionContext;
+use crate::sync::UrlSigner;
+
+const PROBE_SIZE: usize = 64 * 1024;
+const LOOPBACK_TIMEOUT_SECS: u64 = 10;
+// Served from the binary, fetched instead of a probe file with --read-only
+const PROBE_ASSET: &str = "autoindex.css";
+// Registered at startup and always gathered, even before any traffic
+const PROBE_METRIC: &str = "bytes_sent_total";
+
+/// Check the node can actually serve before it starts listening: the data
+/// directory is writable, a file makes it through the whole HTTP stack (with
+/// a signature where the config has one) and metrics are registered.
+/// Every failed check is reported.
+pub async fn run(state: &AppState) -> Result<()> {
+    let started = Instant::now();
+    let mut failures = Vec::new();
+    let probe = if state.read_only {
+        info!("Self-test: --read-only, skipping the data directory write check");
+        None
+    } else {
+        match write_probe(state).await {
+            Ok(probe) => Some(probe),
+            Err(e) => {
+                failures.push(format!("data directory: {:#}", e));
+                None
+            }
+        }
+    };
+
+    let fetched = match &probe {
+        Some(Probe {
+            url: Some(url),
+            content,
+            ..
+        }) => loopback_fetch(state, url, content).await,
+        // --read-only, or no directory a signed URL alone gets into
+        _ if state.read_only || probe.is_some() => {
+            let (_, content) = find_asset(PROBE_ASSET).context("probe asset missing")?;
+            loopback_fetch(state, &format!("/-/assets/{}", PROBE_ASSET), content).await
+        }
+        None => Ok(()),
+    };
+    if let Err(e) = fetched {
+        failures.push(format!("loopback HTTP fetch: {:#}", e));
+    }
+    if let Some(probe) = probe {
+        let removed = tokio::fs::remove_file(&probe.path).await;
+        if let Err(e) = removed {
+            failures.push(format!(
+                "data directory: failed to delete {}: {}",
+                probe.path.display(),
+                e
+            ));
+        }
+    }
+
+    let registered = gather()
+        .iter()
+        .any(|family| family.name().ends_with(PROBE_METRIC));
+    if !registered {
+        failures.push("metrics: registry is empty or missing core metrics".to_string());
+    }
+
+    if !failures.is_empty() {
+        for failure in &failures {
+            warn!("Self-test failed: {}", failure);
+        }
+        bail!("self-test failed: {}", failures.join("; "));
+    }
+    info!("Self-test passed in {}ms", started.elapsed().as_millis());
+    Ok(())
+}
+
+struct Probe {
+    path: std::path::PathBuf,
+    // Signed where the directory requires it, None if other auth is required
+    url: Option<String>,
+    content: Vec<u8>,
+}
+
+// Write a hidden probe file into a served directory and read it back
+async fn write_probe(state: &AppState) -> Result<Probe> {
+    let (dir, token, servable) = probe_dir(state).await;
+    let name = format!(".selftest-{}", hex::encode(rand::random::<[u8; 8]>()));
+    let url_path = format!("{}/{}", dir.trim_end_matches('/'), name);
+    let path = state.data_path(&url_path);
+    let content: Vec<u8> = (0..PROBE_SIZE).map(|_| rand::random::<u8>()).collect();
+
+    tokio::fs::write(&path, &content)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    let read = tokio::fs::read(&path).await;
+    let read_back = match read {
+        Ok(read) => read == content,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(e).with_context(|| format!("failed to read {}", path.display()));
+        }
+    };
+    if !read_back {
+        let _ = tokio::fs::remove_file(&path).await;
+        bail!("{} read back different content", path.display());
+    }
+
+    let signer = UrlSigner {
+        token: token.as_deref(),
+        expire_seconds: LOOPBACK_TIMEOUT_SECS as u32 * 6,
+    };
+    Ok(Probe {
+        url: servable.then(|| signer.url(&url_path, None)),
+        path,
+        content,
+    })
+}
+
+// A configured prefix whose directory exists and is served as-is, preferring
+// one that requires a signature so verification is exercised too. The flag is
+// false when the fallback root needs more than a signed URL.
+async fn probe_dir(state: &AppState) -> (String, Option<String>, bool) {
+    let config = state.config.load_full();
+    let now = chrono::Utc::now().timestamp();
+    let mut candidates = Vec::new();
+    for (prefix, path_config) in config.paths.path_trie.iter() {
+        let usable = path_config.cas_view.is_none()
+            && path_config.available_after.is_none_or(|at| at <= now)
+            && config.pending_release(prefix, now).is_none()
+            && state.tombstones.find(prefix).is_none()
+            && signature_suffices(path_config);
+        let root = path_config.root.as_ref().unwrap_or(&state.data_dir);
+        let dir = root.join(prefix.trim_matches('/'));
+        if usable && tokio::fs::metadata(&dir).await.is_ok_and(|m| m.is_dir()) {
+            let token = path_config.signing_token().map(str::to_string);
+            candidates.push((prefix.clone(), token, true));
+        }
+    }
+    candidates.sort_by_key(|(_, token, _)| token.is_none());
+    candidates.into_iter().next().unwrap_or_else(|| {
+        let root = config.find_path_config("/");
+        let token = root.and_then(|pc| pc.signing_token().map(str::to_string));
+        ("/".to_string(), token, root.is_none_or(signature_suffices))
+    })
+}
+
+// Serve handle_request on an ephemeral loopback port and GET `url` from it
+async fn loopback_fetch(state: &AppState, url: &str, expected: &[u8]) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let addr = listener.local_addr()?;
+    let server_state = state.clone();
+    let server = tokio::spawn(async move {
+        while let Ok((stream, peer_addr)) = listener.accept().await {
+            let state = server_state.clone();
+            tokio::spawn(async move {
+                let context = Arc::new(ConnectionContext {
+                    peer_addr: Some(peer_addr),
+                    ..Default::default()
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(
+                        TokioIo::new(stream),
+                        hyper::service::service_fn(move |mut req| {
+                            req.extensions_mut().insert(context.clone());
+                            handle_request(state.clone(), req)
+                        }),
+                    )
+                    .await;
+            });
+        }
+    });
+
+    // Not state.http_client, its proxy must not see loopback traffic
+    let fetched: Result<()> = async {
+        let client = reqwest::Client::builder()
+            .no_proxy()
+            .timeout(Duration::from_secs(LOOPBACK_TIMEOUT_SECS))
+            .build()?;
+        let response = client.get(format!("http://{}{}", addr, url)).send().await?;
+        let status = response.status();
+        ensure!(status.is_success(), "{} returned {}", url, status);
+        let body = response.bytes().await?;
+        ensure!(
+            body.as_ref() == expected,
+            "{} returned {} bytes, not the {} expected",
+            url,
+            body.len(),
+            expected.len()
+        );
+        Ok(())
+    }
+    .await;
+    server.abort();
+    fetched
+}
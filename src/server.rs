@@ -0,0 +1,309 @@
+use std::error::Error as StdError;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::warn;
+
+use crate::app::AppState;
+use crate::handlers::overloaded_response;
+use crate::logging::log_limited;
+use crate::metrics::{
+    ACTIVE_CONNECTIONS, CONNECTION_ERRORS_TOTAL, SHED_CONNECTIONS_TOTAL, TLS_HANDSHAKE_ERRORS_TOTAL,
+};
+use crate::net::TcpTuning;
+use crate::pipeline::handle_request;
+use crate::realip::{is_trusted_proxy, read_proxy_header};
+use crate::redirect::overflow_redirect;
+use crate::resources::ResourceMonitor;
+use crate::tls::{self, Tls};
+
+// Connection pool to limit concurrent connections
+const MAX_CONNECTIONS: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionErrorKind {
+    // Client went away mid-transfer (reset, broken pipe, aborted download)
+    ClientAbort,
+    // Client sent something that isn't valid HTTP
+    Protocol,
+    // Everything else, including errors returned by our own service
+    Internal,
+}
+
+impl ConnectionErrorKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConnectionErrorKind::ClientAbort => "client_abort",
+            ConnectionErrorKind::Protocol => "protocol",
+            ConnectionErrorKind::Internal => "internal",
+        }
+    }
+}
+
+pub fn classify_connection_error(err: &hyper::Error) -> ConnectionErrorKind {
+    if err.is_incomplete_message()
+        || err.is_canceled()
+        || err.is_closed()
+        || err.is_body_write_aborted()
+        || err.is_timeout()
+    {
+        return ConnectionErrorKind::ClientAbort;
+    }
+    if err.is_parse() || err.is_parse_status() {
+        return ConnectionErrorKind::Protocol;
+    }
+
+    // Walk the source chain for socket level errors
+    let mut source = err.source();
+    while let Some(cause) = source {
+        if let Some(io_err) = cause.downcast_ref::<std::io::Error>() {
+            if matches!(
+                io_err.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::UnexpectedEof
+                    | ErrorKind::TimedOut
+            ) {
+                return ConnectionErrorKind::ClientAbort;
+            }
+        }
+        source = cause.source();
+    }
+
+    ConnectionErrorKind::Internal
+}
+
+/// Count the error by kind and log it at a level matching its severity
+pub fn report_connection_error(err: &hyper::Error) {
+    let kind = classify_connection_error(err);
+    CONNECTION_ERRORS_TOTAL
+        .with_label_values(&[kind.as_str()])
+        .inc();
+
+    match kind {
+        ConnectionErrorKind::ClientAbort => {
+            tracing::debug!("Client aborted connection: {:?}", err);
+        }
+        ConnectionErrorKind::Protocol => {
+            log_limited!(
+                warn,
+                "connection_protocol",
+                "Protocol error on connection: {:?}",
+                err
+            );
+        }
+        ConnectionErrorKind::Internal => {
+            log_limited!(
+                error,
+                "serve_connection",
+                "Error serving connection: {:?}",
+                err
+            );
+        }
+    }
+}
+
+/// State shared by the requests of one keep-alive connection, attached to each
+/// request as an extension
+#[derive(Default)]
+pub struct ConnectionContext {
+    pub peer_addr: Option<SocketAddr>,
+    // Client address from the PROXY protocol header, when the listener expects one
+    pub proxied_addr: Option<SocketAddr>,
+    pub range_cursor: Mutex<Option<RangeCursor>>,
+}
+
+/// Client IP resolved from a trusted proxy's forwarding headers, attached to
+/// the request by the observe layer
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Client IP of the request: from trusted proxies' forwarding headers when
+/// there are any, else the connection's
+pub fn client_ip<B>(req: &hyper::Request<B>) -> Option<IpAddr> {
+    match req.extensions().get::<ClientIp>() {
+        Some(ClientIp(ip)) => Some(*ip),
+        None => connection_ip(req),
+    }
+}
+
+/// Source of the connection the request arrived on, as given by the PROXY
+/// protocol header if there was one
+pub fn connection_ip<B>(req: &hyper::Request<B>) -> Option<IpAddr> {
+    req.extensions()
+        .get::<Arc<ConnectionContext>>()
+        .and_then(|context| context.proxied_addr.or(context.peer_addr))
+        .map(|addr| addr.ip())
+}
+
+// Last range served on the connection, to detect segmented sequential downloads
+pub struct RangeCursor {
+    pub path: PathBuf,
+    pub next_offset: u64,
+}
+
+/// How accepted connections are set up before requests are served
+#[derive(Clone)]
+pub struct Acceptor {
+    pub tls: Option<Arc<Tls>>,
+    pub tcp_tuning: TcpTuning,
+    pub resource_monitor: Arc<ResourceMonitor>,
+    // Every connection starts with a PROXY protocol header (--proxy-protocol)
+    pub proxy_protocol: bool,
+}
+
+/// Serve the node on `listener` until the task is dropped
+pub async fn serve(state: AppState, listener: TcpListener, acceptor: Acceptor) {
+    serve_listeners(state, vec![listener], acceptor).await;
+}
+
+/// Serve the node on every listener, e.g. the SO_REUSEPORT sockets of
+/// --acceptors, until the task is dropped. Each accept loop is a task of its
+/// own so the runtime spreads them over its workers; the connection limit is
+/// shared.
+pub async fn serve_listeners(state: AppState, listeners: Vec<TcpListener>, acceptor: Acceptor) {
+    // Semaphore to limit concurrent connections
+    let semaphore = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    // Dropping the set aborts the loops along with this future
+    let mut loops = JoinSet::new();
+    for listener in listeners {
+        loops.spawn(accept_loop(
+            state.clone(),
+            listener,
+            acceptor.clone(),
+            semaphore.clone(),
+        ));
+    }
+    while loops.join_next().await.is_some() {}
+}
+
+async fn accept_loop(
+    state: AppState,
+    listener: TcpListener,
+    acceptor: Acceptor,
+    semaphore: Arc<Semaphore>,
+) {
+    loop {
+        let (mut stream, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                // e.g. EMFILE during connection spikes, back off instead of exiting
+                log_limited!(error, "accept", "Failed to accept connection: {}", err);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                continue;
+            }
+        };
+
+        if let Err(err) = acceptor.tcp_tuning.apply(&stream) {
+            log_limited!(warn, "tcp_tuning", "Failed to apply TCP options: {}", err);
+        }
+
+        // Shed load before the OOM killer does it for us
+        if acceptor.resource_monitor.is_shedding() {
+            SHED_CONNECTIONS_TOTAL.inc();
+            let state = state.clone();
+            let tls = acceptor.tls.clone();
+            let proxy_protocol = acceptor.proxy_protocol;
+            tokio::task::spawn(async move {
+                if proxy_protocol && read_proxy_header(&mut stream).await.is_err() {
+                    return;
+                }
+                let Ok(io) = tls::accept(tls.as_deref(), stream).await else {
+                    return;
+                };
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .keep_alive(false)
+                    .serve_connection(
+                        TokioIo::new(io),
+                        hyper::service::service_fn(move |req| {
+                            let response =
+                                overflow_redirect(&state, &req).unwrap_or_else(overloaded_response);
+                            async move { Ok::<_, std::convert::Infallible>(response) }
+                        }),
+                    )
+                    .await;
+            });
+            continue;
+        }
+
+        // Acquire semaphore permit
+        let permit = match semaphore.clone().acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => {
+                warn!("Failed to acquire connection permit, dropping connection");
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        let tls = acceptor.tls.clone();
+        let proxy_protocol = acceptor.proxy_protocol;
+
+        tokio::task::spawn(async move {
+            let _permit = permit; // Hold permit for connection lifetime
+
+            let proxied_addr = if proxy_protocol {
+                match read_proxy_header(&mut stream).await {
+                    // Only a trusted proxy may name another source
+                    Ok(addr) => addr.filter(|_| {
+                        is_trusted_proxy(&state.config.load().trusted_proxies, peer_addr.ip())
+                    }),
+                    Err(err) => {
+                        CONNECTION_ERRORS_TOTAL
+                            .with_label_values(&[ConnectionErrorKind::Protocol.as_str()])
+                            .inc();
+                        log_limited!(
+                            warn,
+                            "proxy_protocol",
+                            "Invalid PROXY protocol header from {}: {}",
+                            peer_addr,
+                            err
+                        );
+                        return;
+                    }
+                }
+            } else {
+                None
+            };
+            // The handshake runs here so slow clients don't hold up the accept loop
+            let io = match tls::accept(tls.as_deref(), stream).await {
+                Ok(io) => TokioIo::new(io),
+                Err(err) => {
+                    TLS_HANDSHAKE_ERRORS_TOTAL.inc();
+                    tracing::debug!("TLS handshake failed: {}", err);
+                    return;
+                }
+            };
+            ACTIVE_CONNECTIONS.inc();
+
+            let context = Arc::new(ConnectionContext {
+                peer_addr: Some(peer_addr),
+                proxied_addr,
+                ..Default::default()
+            });
+            let result = hyper::server::conn::http1::Builder::new()
+                .serve_connection(
+                    io,
+                    hyper::service::service_fn(move |mut req| {
+                        req.extensions_mut().insert(context.clone());
+                        handle_request(state.clone(), req)
+                    }),
+                )
+                .await;
+
+            ACTIVE_CONNECTIONS.dec();
+
+            if let Err(err) = result {
+                report_connection_error(&err);
+            }
+        });
+    }
+}
@@ -1,30 +1,134 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use hmac::{Hmac, Mac};
 use hyper::http::StatusCode;
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, percent_encode};
 use sha2::Sha256;
 
+use crate::range::{ByteRangeSpec, parse_byte_ranges};
+use crate::urlpath::has_dot_segments;
+
 type HmacSha256 = Hmac<Sha256>;
 
-pub fn verify_signature(
+// v2 signatures start with this, v1 ones are plain hex
+const V2_PREFIX: &str = "2.";
+// v2 payload: 4 byte expire time and 32 byte HMAC, then 16 bytes per range
+const V2_MIN_PAYLOAD: usize = 36;
+const V2_RANGE_BYTES: usize = 16;
+// End of an open range (`bytes=N-`), written as ffffffff in v1 signatures
+pub const OPEN_RANGE_END: u64 = u64::MAX;
+// Query parameter of IP-bound signatures, the prefix length of the client
+// network they are valid for
+pub const IP_BINDING_PARAM: &str = "dfs_ip";
+// Escaped in signed parameter values: all but RFC 3986 unreserved characters
+const SIGNED_VALUE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+// Marker of links re-signed for another node, always covered by the signature
+// so it can't be stripped to bounce the client back
+pub const REDIRECTED_PARAM_NAME: &str = "dfs_redirected";
+
+/// Client network a signature is bound to, so a shared link only works from
+/// the address (or e.g. the /24) it was issued for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpBinding {
+    pub ip: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl IpBinding {
+    /// None when `prefix_len` is longer than `ip`. IPv4-mapped IPv6
+    /// addresses are bound as IPv4, whichever way the client connects.
+    pub fn new(ip: IpAddr, prefix_len: u8) -> Option<Self> {
+        let ip = ip.to_canonical();
+        let max_len = if ip.is_ipv4() { 32 } else { 128 };
+        (prefix_len <= max_len).then_some(Self { ip, prefix_len })
+    }
+
+    /// Binding to exactly `ip`
+    pub fn exact(ip: IpAddr) -> Self {
+        let ip = ip.to_canonical();
+        let prefix_len = if ip.is_ipv4() { 32 } else { 128 };
+        Self { ip, prefix_len }
+    }
+
+    /// The binding a request's signature was issued with: the `dfs_ip` prefix
+    /// length applied to the client IP (after trusted proxy headers). None for
+    /// unbound signatures, 402 if the client IP is unknown.
+    pub fn from_request(
+        query: Option<&str>,
+        client_ip: Option<IpAddr>,
+    ) -> Result<Option<Self>, StatusCode> {
+        let Some(value) = query_param(query, IP_BINDING_PARAM) else {
+            return Ok(None);
+        };
+        let prefix_len: u8 = value.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+        let ip = client_ip.ok_or(StatusCode::PAYMENT_REQUIRED)?;
+        Self::new(ip, prefix_len)
+            .map(Some)
+            .ok_or(StatusCode::BAD_REQUEST)
+    }
+
+    /// `dfs_ip=...` to put in the signed URL
+    pub fn query_pair(&self) -> String {
+        format!("{}={}", IP_BINDING_PARAM, self.prefix_len)
+    }
+
+    // "@203.0.113.0/24\n" in front of the HMAC message. Unbound messages start
+    // with the path or "2", so neither can be passed off as the other.
+    fn message_prefix(&self) -> String {
+        let network = match self.ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask.unwrap_or(0)))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask.unwrap_or(0)))
+            }
+        };
+        format!("@{}/{}\n", network, self.prefix_len)
+    }
+}
+
+fn binding_prefix(binding: Option<&IpBinding>) -> String {
+    binding.map(IpBinding::message_prefix).unwrap_or_default()
+}
+
+/// Verify the `$` signature of a request, returning the signed ranges on success.
+/// The path config's `signature_params` are covered by the HMAC so they can't
+/// be added, stripped or altered after signing. Signatures carrying `dfs_ip`
+/// only verify from `client_ip`'s network. Each of `sign_tokens` is tried, so
+/// URLs signed with the previous key keep working while keys are rotated.
+pub fn verify_signature_with_params(
     path: &str,
     query: Option<&str>,
-    sign_token: &str,
+    sign_tokens: &[String],
     range_header: Option<&str>,
-) -> Result<(), StatusCode> {
-    let query = query.unwrap_or("");
-    let parsed = serde_querystring::DuplicateQS::parse(query.as_bytes());
-
-    // Parse signature from query parameter $
-    let sign_param = parsed
-        .values(b"$")
-        .and_then(|v| v.first().cloned().unwrap_or(None));
+    signed_params: &[String],
+    client_ip: Option<IpAddr>,
+) -> Result<Vec<(u64, u64)>, StatusCode> {
+    let signed_query = signed_params_message(query, signed_params);
 
     // If no signature parameter is found, return an error
-    let sign_param = sign_param.ok_or(StatusCode::PAYMENT_REQUIRED)?;
-
-    // Extract the signature string - avoid extra allocation
-    let sign_bytes = sign_param.as_ref();
+    let sign_param = signature_param(query).ok_or(StatusCode::PAYMENT_REQUIRED)?;
+    let binding = IpBinding::from_request(query, client_ip)?;
+    if let Some(v2) = sign_param.strip_prefix(V2_PREFIX) {
+        return verify_v2(
+            path,
+            v2,
+            sign_tokens,
+            range_header,
+            &signed_query,
+            binding.as_ref(),
+        );
+    }
+    let sign_bytes = sign_param.as_bytes();
 
     // Parse signature components: {4byte hex unix过期时间}{hmac_sha256_hex}{4byte hex range start}{4byte hex range end}...
     // Minimum length: 8 (expire) + 64 (hmac) + 0 (no range) = 72 hex chars
@@ -65,39 +169,198 @@ pub fn verify_signature(
         let range_start = parse_hex_u32(&ranges_bytes[i..i + 8]).ok_or(StatusCode::BAD_REQUEST)?;
         let range_end =
             parse_hex_u32(&ranges_bytes[i + 8..i + 16]).ok_or(StatusCode::BAD_REQUEST)?;
-        ranges.push((range_start, range_end));
+        ranges.push((range_start as u64, from_v1_end(range_end)));
         i += 16;
-    } // Build HMAC message: /path/to/file\n{4byte hex unix过期时间}\n{ranges...}
-    let mut message = format!("{}\n{:08x}\n", path, expire_time as u32);
-    for (start, end) in &ranges {
-        message.push_str(&format!("{:08x}{:08x}", start, end));
-    }
+    } // Build HMAC message: [@{network}\n]/path/to/file\n{4byte hex unix过期时间}\n{ranges...}
+    let mut message = binding_prefix(binding.as_ref());
+    message.push_str(&format!("{}\n{:08x}\n", path, expire_time as u32));
+    message.push_str(&String::from_utf8_lossy(ranges_bytes).to_ascii_lowercase());
+    push_signed_query(&mut message, &signed_query);
 
     // Verify Range header matches signature ranges if provided
-    if let Some(range_header_value) = range_header {
-        let parsed_ranges = parse_range_header(range_header_value)?;
-        if parsed_ranges != ranges {
-            return Err(StatusCode::BAD_REQUEST);
+    check_range_header(&ranges, range_header)?;
+
+    // Verify HMAC
+    let mut received_hmac = [0u8; 32];
+    if hex::decode_to_slice(hmac_hex, &mut received_hmac).is_err()
+        || !hmac_matches(sign_tokens, message.as_bytes(), &received_hmac)
+    {
+        return Err(StatusCode::PAYMENT_REQUIRED);
+    }
+
+    Ok(ranges)
+}
+
+// Whether `received` is the HMAC of `message` under one of `sign_tokens`,
+// compared in constant time so the expected value can't be found byte by byte
+fn hmac_matches(sign_tokens: &[String], message: &[u8], received: &[u8]) -> bool {
+    sign_tokens.iter().any(|token| {
+        let mut mac = HmacSha256::new_from_slice(token.as_bytes()).unwrap();
+        mac.update(message);
+        mac.verify_slice(received).is_ok()
+    })
+}
+
+/// Compare secrets (tokens, passwords) without stopping at the first
+/// differing byte. Only the length can be learnt from the timing.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// `{base64url payload}` for exactly `path`, or `{depth}.{base64url payload}` for
+// every file below the directory made of the first `depth` segments of `path`
+fn verify_v2(
+    path: &str,
+    signature: &str,
+    sign_tokens: &[String],
+    range_header: Option<&str>,
+    signed_query: &str,
+    binding: Option<&IpBinding>,
+) -> Result<Vec<(u64, u64)>, StatusCode> {
+    let (scope, payload) = match signature.split_once('.') {
+        Some((depth, payload)) => {
+            let depth: usize = depth.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+            // Only files below the directory, not the directory itself, and
+            // nothing that could climb out of it once decoded
+            let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+            let climbs = has_dot_segments(&segments[depth.min(segments.len())..].join("/"));
+            if depth >= segments.len() || climbs {
+                return Err(StatusCode::PAYMENT_REQUIRED);
+            }
+            (directory_scope(&segments[..depth]), payload)
         }
-    } else if !ranges.is_empty() {
-        // If signature contains ranges but no Range header is provided, it's invalid
+        None => (path.to_string(), signature),
+    };
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload)
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    if payload.len() < V2_MIN_PAYLOAD || (payload.len() - V2_MIN_PAYLOAD) % V2_RANGE_BYTES != 0 {
         return Err(StatusCode::BAD_REQUEST);
     }
+    let expire_time = u32::from_be_bytes(payload[..4].try_into().unwrap());
+    let current_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    if current_time > expire_time as u64 {
+        return Err(StatusCode::PAYMENT_REQUIRED);
+    }
+    let be_u64 = |bytes: &[u8]| u64::from_be_bytes(bytes.try_into().unwrap());
+    let ranges: Vec<(u64, u64)> = payload[V2_MIN_PAYLOAD..]
+        .chunks_exact(V2_RANGE_BYTES)
+        .map(|range| (be_u64(&range[..8]), be_u64(&range[8..])))
+        .collect();
 
-    // Verify HMAC
+    check_range_header(&ranges, range_header)?;
+
+    let message = v2_message(&scope, expire_time, &ranges, signed_query, binding);
+    if !hmac_matches(sign_tokens, message.as_bytes(), &payload[4..V2_MIN_PAYLOAD]) {
+        return Err(StatusCode::PAYMENT_REQUIRED);
+    }
+    Ok(ranges)
+}
+
+// "/a/b/" for ["a", "b"], "/" for none
+fn directory_scope(segments: &[&str]) -> String {
+    let mut scope = String::from("/");
+    for segment in segments {
+        scope.push_str(segment);
+        scope.push('/');
+    }
+    scope
+}
+
+// [@{network}\n]2\n{path or directory/}\n{expire hex}\n{ranges, 16 hex digits each}[\n{params}]
+fn v2_message(
+    scope: &str,
+    expire_time: u32,
+    ranges: &[(u64, u64)],
+    signed_query: &str,
+    binding: Option<&IpBinding>,
+) -> String {
+    let mut message = binding_prefix(binding);
+    message.push_str(&format!("2\n{}\n{:08x}\n", scope, expire_time));
+    for (start, end) in ranges {
+        message.push_str(&format!("{:016x}{:016x}", start, end));
+    }
+    push_signed_query(&mut message, signed_query);
+    message
+}
+
+/// Create a v2 signature for `scope`: a file path, or a directory ending in
+/// `/` to authorize every file below it with the same signature. A bound
+/// signature needs `binding.query_pair()` in the URL as well.
+pub fn create_signature_v2(
+    scope: &str,
+    expire_time: u32,
+    sign_token: &str,
+    ranges: Option<&[(u64, u64)]>,
+    signed_query: &str,
+    binding: Option<&IpBinding>,
+) -> String {
+    let ranges = ranges.unwrap_or_default();
     let mut mac = HmacSha256::new_from_slice(sign_token.as_bytes()).unwrap();
-    mac.update(message.as_bytes());
-    let expected_hmac = mac.finalize().into_bytes();
+    mac.update(v2_message(scope, expire_time, ranges, signed_query, binding).as_bytes());
+
+    let mut payload = expire_time.to_be_bytes().to_vec();
+    payload.extend_from_slice(&mac.finalize().into_bytes());
+    for (start, end) in ranges {
+        payload.extend_from_slice(&start.to_be_bytes());
+        payload.extend_from_slice(&end.to_be_bytes());
+    }
+    let encoded = URL_SAFE_NO_PAD.encode(payload);
+    if scope.ends_with('/') {
+        let depth = scope
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .count();
+        format!("{}{}.{}", V2_PREFIX, depth, encoded)
+    } else {
+        format!("{}{}", V2_PREFIX, encoded)
+    }
+}
 
-    // Parse received HMAC from hex
-    let mut expected_hex = [0u8; 64];
-    hex::encode_to_slice(expected_hmac, &mut expected_hex).unwrap();
+/// Expire time of the `$` signature in `query`, without verifying it
+pub fn signature_expire_time(query: Option<&str>) -> Option<u32> {
+    decode_signature(&signature_param(query)?).map(|(expire_time, _)| expire_time)
+}
 
-    if hmac_hex != expected_hex {
-        return Err(StatusCode::PAYMENT_REQUIRED);
+/// The issued link a verified `$` signature stands for: its expire time and
+/// HMAC, the same however the hex digits or base64 of the URL are written
+pub fn signature_credential(query: Option<&str>) -> Option<String> {
+    let (expire_time, hmac) = decode_signature(&signature_param(query)?)?;
+    Some(format!("{:08x}{}", expire_time, hex::encode(hmac)))
+}
+
+// Expire time and HMAC bytes of a v1 or v2 signature
+fn decode_signature(sign_param: &str) -> Option<(u32, Vec<u8>)> {
+    match sign_param.strip_prefix(V2_PREFIX) {
+        Some(v2) => {
+            let payload = v2.split_once('.').map_or(v2, |(_, payload)| payload);
+            let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+            let expire_time = u32::from_be_bytes(payload.get(..4)?.try_into().ok()?);
+            Some((expire_time, payload.get(4..V2_MIN_PAYLOAD)?.to_vec()))
+        }
+        None => {
+            let sign_bytes = sign_param.as_bytes();
+            let expire_time = parse_hex_u32(sign_bytes.get(..8)?)?;
+            Some((expire_time, hex::decode(sign_bytes.get(8..72)?).ok()?))
+        }
     }
+}
+
+/// The `$` signature of a query, also identifies the issued link
+pub fn signature_param(query: Option<&str>) -> Option<String> {
+    query_param(query, "$")
+}
 
-    Ok(())
+// First value of `name` in `query`, decoded
+fn query_param(query: Option<&str>, name: &str) -> Option<String> {
+    let parsed = serde_querystring::DuplicateQS::parse(query.unwrap_or("").as_bytes());
+    parsed
+        .values(name.as_bytes())
+        .and_then(|v| v.first().cloned().unwrap_or(None))
+        .map(|value| String::from_utf8_lossy(&value).into_owned())
 }
 
 /// Create a signature string for a given path, expiration time and optional ranges
@@ -109,21 +372,48 @@ pub fn verify_signature(
 /// * `ranges` - Optional list of (start, end) byte ranges
 ///
 /// # Returns
-/// Returns a signature string in the format: {expire_time}{hmac}{ranges...}
+/// Returns a signature string in the format: {expire_time}{hmac}{ranges...},
+/// or a v2 signature when a range reaches past 4 GiB
 pub fn create_signature(
     path: &str,
     expire_time: u32,
     sign_token: &str,
-    ranges: Option<&[(u32, u32)]>,
+    ranges: Option<&[(u64, u64)]>,
 ) -> String {
-    // Build HMAC message: /path/to/file\n{4byte hex unix过期时间}\n{ranges...}
-    let mut message = format!("{}\n{:08x}\n", path, expire_time);
+    create_signature_with_params(path, expire_time, sign_token, ranges, "", None)
+}
 
-    if let Some(ranges) = ranges {
-        for (start, end) in ranges {
-            message.push_str(&format!("{:08x}{:08x}", start, end));
-        }
+/// Create a signature that also covers `signed_query`, as built by
+/// `signed_params_message` from the URL's query, and is only valid from
+/// `binding`'s network if given (the URL then needs `binding.query_pair()`)
+pub fn create_signature_with_params(
+    path: &str,
+    expire_time: u32,
+    sign_token: &str,
+    ranges: Option<&[(u64, u64)]>,
+    signed_query: &str,
+    binding: Option<&IpBinding>,
+) -> String {
+    // v1 range fields are 32-bit, larger offsets need the 64-bit fields of v2
+    let v1_ranges: Option<Vec<(u32, u32)>> = ranges
+        .unwrap_or_default()
+        .iter()
+        .map(|&(start, end)| Some((u32::try_from(start).ok()?, to_v1_end(end)?)))
+        .collect();
+    let Some(v1_ranges) = v1_ranges else {
+        return create_signature_v2(path, expire_time, sign_token, ranges, signed_query, binding);
+    };
+
+    // Build HMAC message: [@{network}\n]/path/to/file\n{4byte hex unix过期时间}\n{ranges...}[\n{params}]
+    let mut message = binding_prefix(binding);
+    message.push_str(&format!("{}\n{:08x}\n", path, expire_time));
+
+    let mut ranges_hex = String::new();
+    for (start, end) in &v1_ranges {
+        ranges_hex.push_str(&format!("{:08x}{:08x}", start, end));
     }
+    message.push_str(&ranges_hex);
+    push_signed_query(&mut message, signed_query);
 
     // Calculate HMAC
     let mut mac = HmacSha256::new_from_slice(sign_token.as_bytes()).unwrap();
@@ -134,15 +424,58 @@ pub fn create_signature(
     let hmac_hex = hex::encode(hmac_bytes);
 
     // Build signature string: {expire_time}{hmac}{ranges...}
-    let mut signature = format!("{:08x}{}", expire_time, hmac_hex);
+    format!("{:08x}{}{}", expire_time, hmac_hex, ranges_hex)
+}
+
+// v1 range ends are 32-bit, with ffffffff for an open range
+fn from_v1_end(end: u32) -> u64 {
+    if end == u32::MAX {
+        OPEN_RANGE_END
+    } else {
+        end as u64
+    }
+}
 
-    if let Some(ranges) = ranges {
-        for (start, end) in ranges {
-            signature.push_str(&format!("{:08x}{:08x}", start, end));
+fn to_v1_end(end: u64) -> Option<u32> {
+    if end == OPEN_RANGE_END {
+        Some(u32::MAX)
+    } else {
+        u32::try_from(end).ok().filter(|&end| end != u32::MAX)
+    }
+}
+
+/// The `signed_params` present in `query` as `name=value&...` (in config
+/// order, repeated names kept in query order), then `dfs_redirected`. Values
+/// are decoded and re-encoded with everything but unreserved characters
+/// escaped, so a value can't pass for a `&`-joined pair. Empty when none of
+/// them is present, so URLs without these parameters sign as before.
+pub fn signed_params_message(query: Option<&str>, signed_params: &[String]) -> String {
+    let Some(query) =
+        query.filter(|query| !signed_params.is_empty() || query.contains(REDIRECTED_PARAM_NAME))
+    else {
+        return String::new();
+    };
+    let parsed = serde_querystring::DuplicateQS::parse(query.as_bytes());
+    let names = signed_params
+        .iter()
+        .map(String::as_str)
+        .filter(|name| *name != "$" && *name != REDIRECTED_PARAM_NAME)
+        .chain([REDIRECTED_PARAM_NAME]);
+    let mut pairs = Vec::new();
+    for name in names {
+        for value in parsed.values(name.as_bytes()).unwrap_or_default() {
+            let value = value.as_deref().unwrap_or_default();
+            pairs.push(format!("{}={}", name, percent_encode(value, SIGNED_VALUE)));
         }
     }
+    pairs.join("&")
+}
 
-    signature
+fn push_signed_query(message: &mut String, signed_query: &str) {
+    if !signed_query.is_empty() {
+        message.push('\n');
+        message.push_str(signed_query);
+    }
 }
 
 /// Helper function to get current Unix timestamp + offset seconds
@@ -154,6 +487,62 @@ pub fn get_expire_time(offset_seconds: u32) -> u32 {
     (current_time + offset_seconds as u64) as u32
 }
 
+/// `get_expire_time` rounded up to a multiple of `step` seconds, so signatures
+/// issued within one step are identical. They stay valid for at least
+/// `offset_seconds` and at most `offset_seconds + step`.
+pub fn quantized_expire_time(offset_seconds: u32, step: u32) -> u32 {
+    let expire_time = get_expire_time(offset_seconds);
+    if step <= 1 {
+        return expire_time;
+    }
+    expire_time.div_ceil(step).saturating_mul(step)
+}
+
+/// Whether `relative` (the path below the signed prefix) matches one of the
+/// `signature_exempt` patterns. Patterns without a `/` match the file name in
+/// any directory, others the whole relative path; `*` and `?` don't cross `/`.
+pub fn is_signature_exempt(patterns: &[String], relative: &str) -> bool {
+    let relative = relative.trim_start_matches('/');
+    let name = relative.rsplit('/').next().unwrap_or(relative);
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.trim_start_matches('/');
+        let target = if pattern.contains('/') {
+            relative
+        } else {
+            name
+        };
+        wildcard_match(pattern.as_bytes(), target.as_bytes())
+    })
+}
+
+// Iterative glob match, backtracking to the last `*` without recursion
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if (c == b'?' && text[t] != b'/') || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character, but never a `/`
+                Some((star_p, star_t)) if text[star_t] != b'/' => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                _ => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
 // Helper function to parse hex without allocation
 fn parse_hex_u32(hex_bytes: &[u8]) -> Option<u32> {
     if hex_bytes.len() != 8 {
@@ -173,44 +562,70 @@ fn parse_hex_u32(hex_bytes: &[u8]) -> Option<u32> {
     Some(result)
 }
 
-// Helper function to parse Range header
-fn parse_range_header(range_header: &str) -> Result<Vec<(u32, u32)>, StatusCode> {
-    // Expected format: "bytes=start1-end1,start2-end2,..."
-    if !range_header.starts_with("bytes=") {
-        return Err(StatusCode::BAD_REQUEST);
+// The Range header spec a signed range stands for
+fn signed_spec(&(start, end): &(u64, u64)) -> ByteRangeSpec {
+    ByteRangeSpec::FromTo(start, (end != OPEN_RANGE_END).then_some(end))
+}
+
+// A signature with ranges needs a Range header naming them in order. Which
+// other notations ask for the same bytes (a suffix range, an end clipped to
+// the file) depends on the file size, those only need the same starts here
+// and are settled by `signed_ranges_match` once the file is known.
+fn check_range_header(signed: &[(u64, u64)], range_header: Option<&str>) -> Result<(), StatusCode> {
+    if signed.is_empty() {
+        return Ok(());
+    }
+    let requested = range_header
+        .and_then(parse_byte_ranges)
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let may_match = requested.len() == signed.len()
+        && requested
+            .iter()
+            .zip(signed)
+            .all(|(requested, &(start, _))| match *requested {
+                ByteRangeSpec::FromTo(requested_start, _) => requested_start == start,
+                ByteRangeSpec::Suffix(_) => true,
+            });
+    if may_match {
+        Ok(())
+    } else {
+        Err(StatusCode::BAD_REQUEST)
     }
+}
 
-    let ranges_str = &range_header[6..]; // Skip "bytes="
-    let mut ranges = Vec::new();
+/// Range header asking for exactly the `signed` ranges, an open end as `start-`
+pub fn signed_range_header(signed: &[(u64, u64)]) -> String {
+    let ranges: Vec<String> = signed
+        .iter()
+        .map(|&(start, end)| match end {
+            OPEN_RANGE_END => format!("{}-", start),
+            end => format!("{}-{}", start, end),
+        })
+        .collect();
+    format!("bytes={}", ranges.join(","))
+}
 
-    for range_part in ranges_str.split(',') {
-        let range_part = range_part.trim();
-        if let Some(dash_pos) = range_part.find('-') {
-            let start_str = &range_part[..dash_pos];
-            let end_str = &range_part[dash_pos + 1..];
-
-            // Parse start and end, handling empty values
-            let start = if start_str.is_empty() {
-                0
-            } else {
-                start_str
-                    .parse::<u32>()
-                    .map_err(|_| StatusCode::BAD_REQUEST)?
-            };
-
-            let end = if end_str.is_empty() {
-                u32::MAX
-            } else {
-                end_str
-                    .parse::<u32>()
-                    .map_err(|_| StatusCode::BAD_REQUEST)?
-            };
-
-            ranges.push((start, end));
-        } else {
-            return Err(StatusCode::BAD_REQUEST);
-        }
+/// Whether `range_header` asks for exactly the `signed` ranges. With the file
+/// size known ranges are compared by the bytes they cover, so `bytes=-500`
+/// or `bytes=500-999` satisfy a signed `500-` of a 1000 byte file; without it
+/// the header must spell out the signed ranges (an open end as `start-`).
+pub fn signed_ranges_match(
+    signed: &[(u64, u64)],
+    range_header: Option<&str>,
+    size: Option<u64>,
+) -> bool {
+    if signed.is_empty() {
+        return true;
     }
-
-    Ok(ranges)
+    let Some(requested) = range_header.and_then(parse_byte_ranges) else {
+        return false;
+    };
+    requested.len() == signed.len()
+        && requested.iter().zip(signed).all(|(requested, signed)| {
+            let signed = signed_spec(signed);
+            match size {
+                Some(size) => requested.resolve(size) == signed.resolve(size),
+                None => *requested == signed,
+            }
+        })
 }
@@ -0,0 +1,119 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::OptimizedConfig;
+use crate::signature::{IpBinding, create_signature_v2, get_expire_time, signed_params_message};
+use crate::sync::{UrlSigner, with_signature};
+
+// Paths accepted by one /-/sign request
+pub const MAX_SIGN_PATHS: usize = 100_000;
+
+/// Body of POST /-/sign: a plain list of paths, or an object with options
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SignRequest {
+    Paths(Vec<String>),
+    Options {
+        paths: Vec<String>,
+        // Overrides signature_expire_seconds of the path config
+        expire_seconds: Option<u32>,
+        // Prepended to every URL, e.g. https://cdn.example.com
+        base_url: Option<String>,
+        // Signature scheme, 2 for base64url signatures that can cover a directory
+        version: Option<u8>,
+        // Only valid from this client address, or its network with ip_prefix_len
+        client_ip: Option<IpAddr>,
+        ip_prefix_len: Option<u8>,
+    },
+}
+
+impl SignRequest {
+    pub fn paths(&self) -> &[String] {
+        match self {
+            SignRequest::Paths(paths) | SignRequest::Options { paths, .. } => paths,
+        }
+    }
+
+    /// Network the URLs are bound to, Err if the prefix doesn't fit the address
+    pub fn binding(&self) -> Result<Option<IpBinding>, String> {
+        let SignRequest::Options {
+            client_ip: Some(ip),
+            ip_prefix_len,
+            ..
+        } = self
+        else {
+            return Ok(None);
+        };
+        match ip_prefix_len {
+            Some(prefix_len) => IpBinding::new(*ip, *prefix_len)
+                .map(Some)
+                .ok_or_else(|| format!("prefix length {} is too long for {}", prefix_len, ip)),
+            None => Ok(Some(IpBinding::exact(*ip))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SignedUrl {
+    pub path: String,
+    pub url: String,
+}
+
+/// Sign each path with the token of its path config. Paths without a signature
+/// token are returned unsigned, as they can be fetched as-is.
+pub fn sign_paths(config: &OptimizedConfig, request: &SignRequest) -> Vec<SignedUrl> {
+    let (expire_seconds, base_url, version) = match request {
+        SignRequest::Paths(_) => (None, None, None),
+        SignRequest::Options {
+            expire_seconds,
+            base_url,
+            version,
+            ..
+        } => (*expire_seconds, base_url.as_deref(), *version),
+    };
+    let base_url = base_url.unwrap_or("").trim_end_matches('/');
+    let binding = request.binding().ok().flatten();
+    request
+        .paths()
+        .iter()
+        .map(|path| {
+            // Query parameters listed in signature_params are signed along with the path
+            let (path, query) = path.split_once('?').unwrap_or((path, ""));
+            let path = format!("/{}", path.trim_start_matches('/'));
+            let path_config = config.find_path_config(&path);
+            let signer = UrlSigner {
+                token: path_config.and_then(|pc| pc.signing_token()),
+                expire_seconds: expire_seconds
+                    .or_else(|| path_config.and_then(|pc| pc.signature_expire_seconds))
+                    .unwrap_or(3600),
+            };
+            let signed_params = path_config
+                .and_then(|pc| pc.signature_params.as_deref())
+                .unwrap_or_default();
+            let signed = match (version, signer.token) {
+                // A directory path signs every file below it
+                (Some(2), Some(token)) => {
+                    let signed_query = signed_params_message(Some(query), signed_params);
+                    let signature = create_signature_v2(
+                        &path,
+                        get_expire_time(signer.expire_seconds),
+                        token,
+                        None,
+                        &signed_query,
+                        binding.as_ref(),
+                    );
+                    with_signature(&path, query, binding.as_ref(), &signature)
+                }
+                _ => signer.bound_url(&path, query, signed_params, binding.as_ref()),
+            };
+            let url = format!("{}{}", base_url, signed);
+            let path = if query.is_empty() {
+                path
+            } else {
+                format!("{}?{}", path, query)
+            };
+            SignedUrl { path, url }
+        })
+        .collect()
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::manifest::{FileManifest, Manifest};
+use crate::signature::{
+    IpBinding, create_signature, create_signature_with_params, get_expire_time,
+    signed_params_message,
+};
+
+// Sync protocol (/-/sync/{dir}):
+//   GET  -> server Manifest of {dir}
+//   POST -> client sends its Manifest, receives a SyncPlan: the files and byte
+//           ranges that differ, each with a ready-to-use (signed) URL
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FetchEntry {
+    pub path: String,
+    pub size: u64,
+    pub url: String,
+    // Inclusive byte ranges to request with a single Range header, absent for a full download
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ranges: Option<Vec<(u64, u64)>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncPlan {
+    pub fetch: Vec<FetchEntry>,
+    // Client files that no longer exist on the server
+    pub delete: Vec<String>,
+}
+
+pub struct UrlSigner<'a> {
+    pub token: Option<&'a str>,
+    pub expire_seconds: u32,
+}
+
+impl UrlSigner<'_> {
+    pub fn url(&self, path: &str, ranges: Option<&[(u64, u64)]>) -> String {
+        match self.token {
+            Some(token) => {
+                let expire_time = get_expire_time(self.expire_seconds);
+                let signature = create_signature(path, expire_time, token, ranges);
+                format!("{}?$={}", path, signature)
+            }
+            None => path.to_string(),
+        }
+    }
+
+    /// URL for `path` with an existing query, whose `signed_params` are
+    /// covered by the signature
+    pub fn url_with_query(&self, path: &str, query: &str, signed_params: &[String]) -> String {
+        self.bound_url(path, query, signed_params, None)
+    }
+
+    /// `url_with_query` that only works from `binding`'s network
+    pub fn bound_url(
+        &self,
+        path: &str,
+        query: &str,
+        signed_params: &[String],
+        binding: Option<&IpBinding>,
+    ) -> String {
+        if query.is_empty() && binding.is_none() {
+            return self.url(path, None);
+        }
+        match self.token {
+            Some(token) => {
+                let expire_time = get_expire_time(self.expire_seconds);
+                let signed_query = signed_params_message(Some(query), signed_params);
+                let signature = create_signature_with_params(
+                    path,
+                    expire_time,
+                    token,
+                    None,
+                    &signed_query,
+                    binding,
+                );
+                with_signature(path, query, binding, &signature)
+            }
+            None if query.is_empty() => path.to_string(),
+            None => format!("{}?{}", path, query),
+        }
+    }
+}
+
+/// `path?{query}&dfs_ip=..&$={signature}`, leaving out what's absent
+pub fn with_signature(
+    path: &str,
+    query: &str,
+    binding: Option<&IpBinding>,
+    signature: &str,
+) -> String {
+    let mut params: Vec<String> = Vec::new();
+    if !query.is_empty() {
+        params.push(query.to_string());
+    }
+    params.extend(binding.map(IpBinding::query_pair));
+    params.push(format!("$={}", signature));
+    format!("{}?{}", path, params.join("&"))
+}
+
+/// Compare the client manifest against the server's. `url_for` gives the URL
+/// of a server file, by its manifest path and the ranges to fetch.
+pub fn plan_sync(
+    server: &Manifest,
+    client: &Manifest,
+    url_for: impl Fn(&str, Option<&[(u64, u64)]>) -> String,
+) -> SyncPlan {
+    let client_files: HashMap<&str, &FileManifest> = client
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    // Chunk hashes are only comparable with the same chunk size
+    let chunks_comparable = client.chunk_size == server.chunk_size;
+
+    let mut plan = SyncPlan::default();
+    for file in &server.files {
+        let ranges = match client_files.get(file.path.as_str()) {
+            Some(local) if local.size == file.size && local.chunks == file.chunks => continue,
+            Some(local)
+                if chunks_comparable && local.size == file.size && !local.chunks.is_empty() =>
+            {
+                differing_ranges(file, local, server.chunk_size)
+            }
+            _ => None,
+        };
+
+        plan.fetch.push(FetchEntry {
+            path: file.path.clone(),
+            size: file.size,
+            url: url_for(&file.path, ranges.as_deref()),
+            ranges,
+        });
+    }
+
+    let server_paths: std::collections::HashSet<&str> =
+        server.files.iter().map(|file| file.path.as_str()).collect();
+    plan.delete = client
+        .files
+        .iter()
+        .filter(|file| !server_paths.contains(file.path.as_str()))
+        .map(|file| file.path.clone())
+        .collect();
+    plan
+}
+
+// Merge consecutive differing chunks into inclusive byte ranges
+fn differing_ranges(
+    server: &FileManifest,
+    client: &FileManifest,
+    chunk_size: u64,
+) -> Option<Vec<(u64, u64)>> {
+    if server.size == 0 {
+        return None;
+    }
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for (index, hash) in server.chunks.iter().enumerate() {
+        if client.chunks.get(index) == Some(hash) {
+            continue;
+        }
+        let start = index as u64 * chunk_size;
+        let end = (start + chunk_size).min(server.size) - 1;
+        match ranges.last_mut() {
+            Some(last) if last.1 + 1 == start => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+    if ranges.is_empty() {
+        return None;
+    }
+    Some(ranges)
+}
@@ -0,0 +1,206 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::app::{AppState, HttpClientOptions};
+use crate::btsession;
+use crate::config::{reload_config, start_config_watcher};
+use crate::net::{TcpTuning, reuseport_listeners};
+use crate::resources::{ResourceLimits, ResourceMonitor};
+use crate::server::{Acceptor, serve_listeners};
+use crate::sync::UrlSigner;
+
+/// A whole node running in-process for integration tests: a temporary data
+/// directory, a config file written from `config`, HTTP on a random loopback
+/// port and a BitTorrent session listening on another.
+pub struct TestNode {
+    pub state: AppState,
+    pub addr: SocketAddr,
+    // BitTorrent listen address, for other nodes' initial_peers
+    pub bt_addr: SocketAddr,
+    root: PathBuf,
+    server: JoinHandle<()>,
+}
+
+#[derive(Default)]
+pub struct TestNodeBuilder {
+    config: Option<serde_json::Value>,
+    files: Vec<(String, Vec<u8>)>,
+    read_only: bool,
+    proxy_protocol: bool,
+    watch_config: bool,
+    acceptors: usize,
+}
+
+impl TestNodeBuilder {
+    /// Config file content, as it would be written in YAML
+    pub fn config(mut self, config: serde_json::Value) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// A file in the data directory, `path` as requested over HTTP
+    pub fn file(mut self, path: &str, content: impl Into<Vec<u8>>) -> Self {
+        self.files.push((path.to_string(), content.into()));
+        self
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Reload the config file when it changes, as a node started with --config does
+    pub fn watch_config(mut self) -> Self {
+        self.watch_config = true;
+        self
+    }
+
+    /// Expect a PROXY protocol header on every connection, as with --proxy-protocol
+    pub fn proxy_protocol(mut self) -> Self {
+        self.proxy_protocol = true;
+        self
+    }
+
+    /// Accept on this many SO_REUSEPORT sockets, as with --acceptors
+    pub fn acceptors(mut self, count: usize) -> Self {
+        self.acceptors = count;
+        self
+    }
+
+    pub async fn start(self) -> Result<TestNode> {
+        let root = std::env::temp_dir().join(format!(
+            "dfsnode-test-{}",
+            hex::encode(rand::random::<[u8; 8]>())
+        ));
+        let data_dir = root.join("data");
+        tokio::fs::create_dir_all(&data_dir).await?;
+        for (path, content) in &self.files {
+            let file_path = data_dir.join(path.trim_start_matches('/'));
+            if let Some(parent) = file_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&file_path, content)
+                .await
+                .with_context(|| format!("failed to write {}", file_path.display()))?;
+        }
+        let config_path = root.join("config.yml");
+        let config = self.config.unwrap_or_else(|| serde_json::json!({}));
+        tokio::fs::write(&config_path, serde_yml::to_string(&config)?).await?;
+
+        let bt_addr = SocketAddr::from((Ipv4Addr::LOCALHOST, free_port()?));
+        let session_dir = btsession::prepare_session_dir(&data_dir).await?;
+        let bt_session = librqbit::Session::new_with_opts(
+            session_dir,
+            librqbit::SessionOptions {
+                disable_dht: true,
+                listen: Some(librqbit::ListenerOptions {
+                    mode: librqbit::ListenerMode::TcpAndUtp,
+                    listen_addr: bt_addr,
+                    enable_upnp_port_forwarding: false,
+                    utp_opts: None,
+                }),
+                ..Default::default()
+            },
+        )
+        .await
+        .context("Failed to create BitTorrent session")?;
+
+        let mut state = AppState::new(
+            data_dir,
+            None,
+            None,
+            None,
+            bt_session,
+            &HttpClientOptions::default(),
+            self.read_only,
+        )?;
+        state.config_path = Some(config_path.to_string_lossy().into_owned());
+        reload_config(&state).await?;
+        if self.watch_config {
+            start_config_watcher(&state)?;
+        }
+
+        let listeners = if self.acceptors > 1 {
+            reuseport_listeners((Ipv4Addr::LOCALHOST, 0).into(), self.acceptors)?
+        } else {
+            vec![TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?]
+        };
+        let addr = listeners[0].local_addr()?;
+        let acceptor = Acceptor {
+            tls: None,
+            tcp_tuning: TcpTuning::default(),
+            resource_monitor: Arc::new(ResourceMonitor::new(ResourceLimits::default())),
+            proxy_protocol: self.proxy_protocol,
+        };
+        let server = tokio::spawn(serve_listeners(state.clone(), listeners, acceptor));
+        Ok(TestNode {
+            state,
+            addr,
+            bt_addr,
+            root,
+            server,
+        })
+    }
+}
+
+impl TestNode {
+    pub fn builder() -> TestNodeBuilder {
+        TestNodeBuilder::default()
+    }
+
+    pub fn url(&self, path_and_query: &str) -> String {
+        format!("http://{}{}", self.addr, path_and_query)
+    }
+
+    /// URL of `path` signed with `token`, optionally limited to `ranges`
+    pub fn signed_url(&self, path: &str, token: &str, ranges: Option<&[(u64, u64)]>) -> String {
+        let signer = UrlSigner {
+            token: Some(token),
+            expire_seconds: 3600,
+        };
+        self.url(&signer.url(path, ranges))
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.state.data_dir
+    }
+
+    /// Replace the config file and load it, as `POST /-/admin/config/refresh` would
+    pub async fn reconfigure(&self, config: serde_json::Value) -> Result<()> {
+        self.write_config(config).await?;
+        reload_config(&self.state).await
+    }
+
+    /// Replace the config file without loading it
+    pub async fn write_config(&self, config: serde_json::Value) -> Result<()> {
+        let config_path = self.root.join("config.yml");
+        tokio::fs::write(&config_path, serde_yml::to_string(&config)?).await?;
+        Ok(())
+    }
+}
+
+impl Drop for TestNode {
+    fn drop(&mut self) {
+        self.server.abort();
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Client for requests to test nodes, ignoring proxies from the environment
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .no_proxy()
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+// A port nothing listens on right now, for listeners that can't report theirs
+fn free_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    Ok(listener.local_addr()?.port())
+}
@@ -0,0 +1,126 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+
+use tokio::sync::Semaphore;
+use tokio::time::{Duration, Instant, Sleep, interval, sleep_until};
+use tokio_util::sync::PollSemaphore;
+
+// Budget refill period, the bucket holds at most BURST_PERIODS worth of bytes
+const REFILL_INTERVAL_MS: u64 = 50;
+const BURST_PERIODS: u64 = 4;
+
+/// Shared bytes-per-second budget implemented as a token bucket of semaphore permits.
+/// tokio's semaphore is FIFO, so waiting transfers are served in turn.
+pub struct ByteBudget {
+    semaphore: Arc<Semaphore>,
+    bytes_per_sec: u64,
+}
+
+impl ByteBudget {
+    pub fn new(bytes_per_sec: u64) -> Arc<Self> {
+        Arc::new(Self {
+            semaphore: Arc::new(Semaphore::new(0)),
+            bytes_per_sec,
+        })
+    }
+
+    fn burst(&self) -> u64 {
+        (self.bytes_per_sec * REFILL_INTERVAL_MS / 1000 * BURST_PERIODS).max(1)
+    }
+
+    pub fn throttle(self: &Arc<Self>) -> Throttle {
+        Throttle {
+            semaphore: PollSemaphore::new(self.semaphore.clone()),
+            max_take: self.burst().min(u32::MAX as u64) as u32,
+        }
+    }
+
+    /// Refill the bucket, runs for the lifetime of the process
+    pub async fn refill_task(self: Arc<Self>) {
+        let per_tick = (self.bytes_per_sec * REFILL_INTERVAL_MS / 1000).max(1);
+        let burst = self.burst();
+        let mut interval = interval(Duration::from_millis(REFILL_INTERVAL_MS));
+        loop {
+            interval.tick().await;
+            let available = self.semaphore.available_permits() as u64;
+            let add = per_tick.min(burst.saturating_sub(available));
+            if add > 0 {
+                self.semaphore.add_permits(add as usize);
+            }
+        }
+    }
+}
+
+/// Per-transfer handle on a ByteBudget
+pub struct Throttle {
+    semaphore: PollSemaphore,
+    max_take: u32,
+}
+
+impl Throttle {
+    /// Ready once `bytes` have been taken from the budget
+    pub fn poll_take(&mut self, cx: &mut Context<'_>, bytes: usize) -> Poll<()> {
+        let take = (bytes as u64).min(self.max_take as u64) as u32;
+        if take == 0 {
+            return Poll::Ready(());
+        }
+        if let Some(permit) = ready!(self.semaphore.poll_acquire_many(cx, take)) {
+            permit.forget();
+        }
+        Poll::Ready(())
+    }
+}
+
+/// Token bucket for a single transfer. Tokens may go negative by one chunk,
+/// the next chunk then waits until the debt is paid off.
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let mut limiter = Self {
+            bytes_per_sec: bytes_per_sec.max(1) as f64,
+            tokens: 0.0,
+            last_refill: Instant::now(),
+            sleep: None,
+        };
+        // Start with a full bucket so small files aren't delayed at all
+        limiter.tokens = limiter.burst();
+        limiter
+    }
+
+    fn burst(&self) -> f64 {
+        self.bytes_per_sec * REFILL_INTERVAL_MS as f64 / 1000.0 * BURST_PERIODS as f64
+    }
+
+    /// Ready once the bucket is out of debt; doesn't take anything, see `consume`
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            let now = Instant::now();
+            let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.burst());
+            self.last_refill = now;
+            if self.tokens >= 0.0 {
+                self.sleep = None;
+                return Poll::Ready(());
+            }
+            let wait = Duration::from_secs_f64(-self.tokens / self.bytes_per_sec);
+            let deadline = now + wait;
+            match self.sleep.as_mut() {
+                Some(sleep) => sleep.as_mut().reset(deadline),
+                None => self.sleep = Some(Box::pin(sleep_until(deadline))),
+            }
+            ready!(self.sleep.as_mut().unwrap().as_mut().poll(cx));
+        }
+    }
+
+    pub fn consume(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+    }
+}
@@ -0,0 +1,193 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, interval, timeout};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_rustls::server::TlsStream;
+use tokio_util::either::Either;
+use tracing::{info, warn};
+
+use crate::app::AppState;
+use crate::metrics::TLS_CERT_EXPIRY_DAYS;
+
+const RELOAD_CHECK_INTERVAL_SECS: u64 = 30;
+const SECS_PER_DAY: f64 = 86400.0;
+// Clients that don't finish the handshake in time release their connection slot
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    // PEM certificate chain and private key
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Plain or TLS connection, served the same way
+pub type ServerIo = Either<TlsStream<TcpStream>, TcpStream>;
+
+// Hands the current certificate to each new handshake, so a reload only
+// affects connections accepted afterwards
+#[derive(Debug)]
+struct ReloadableCert {
+    key: ArcSwap<CertifiedKey>,
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.load_full())
+    }
+}
+
+pub struct Tls {
+    acceptor: TlsAcceptor,
+    cert: Arc<ReloadableCert>,
+    // Files the current certificate was loaded from, and their mtimes
+    loaded: std::sync::Mutex<(TlsConfig, Option<SystemTime>)>,
+    // Unix notAfter of the current leaf certificate
+    not_after: AtomicI64,
+}
+
+impl Tls {
+    pub fn new(files: TlsConfig) -> anyhow::Result<Arc<Self>> {
+        let (key, not_after) = load_certified_key(&files)?;
+        let cert = Arc::new(ReloadableCert {
+            key: ArcSwap::from_pointee(key),
+        });
+        let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(cert.clone());
+        // The server only speaks HTTP/1.1
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let mtime = files_mtime(&files);
+        Ok(Arc::new(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            cert,
+            loaded: std::sync::Mutex::new((files, mtime)),
+            not_after: AtomicI64::new(not_after),
+        }))
+    }
+
+    fn update_expiry_metric(&self) {
+        let not_after = self.not_after.load(Ordering::Relaxed);
+        let now = chrono::Utc::now().timestamp();
+        TLS_CERT_EXPIRY_DAYS.set((not_after - now) as f64 / SECS_PER_DAY);
+    }
+
+    pub async fn accept(&self, stream: TcpStream) -> std::io::Result<ServerIo> {
+        let handshake = self.acceptor.accept(stream);
+        match timeout(Duration::from_secs(HANDSHAKE_TIMEOUT_SECS), handshake).await {
+            Ok(stream) => Ok(Either::Left(stream?)),
+            Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+        }
+    }
+
+    // Reload when the files (or, via config, their paths) changed; a broken
+    // renewal keeps the current certificate
+    fn reload_if_changed(&self, files: TlsConfig) {
+        let mtime = files_mtime(&files);
+        {
+            let loaded = self.loaded.lock().unwrap();
+            if loaded.0 == files && loaded.1 == mtime {
+                return;
+            }
+        }
+        match load_certified_key(&files) {
+            Ok((key, not_after)) => {
+                // Connections already established keep the certificate they negotiated
+                self.cert.key.store(Arc::new(key));
+                self.not_after.store(not_after, Ordering::Relaxed);
+                info!("Reloaded TLS certificate {}", files.cert.display());
+                *self.loaded.lock().unwrap() = (files, mtime);
+            }
+            Err(e) => warn!(
+                "Failed to reload TLS certificate {}, keeping the current one: {:#}",
+                files.cert.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Accept a connection, with the TLS handshake if TLS is enabled
+pub async fn accept(tls: Option<&Tls>, stream: TcpStream) -> std::io::Result<ServerIo> {
+    match tls {
+        Some(tls) => tls.accept(stream).await,
+        None => Ok(Either::Right(stream)),
+    }
+}
+
+/// Watch the certificate files for renewals. Paths given on the command line
+/// win over the config's `tls` section.
+pub async fn reload_task(tls: Arc<Tls>, state: AppState, cli_files: Option<TlsConfig>) {
+    tls.update_expiry_metric();
+    let mut interval = interval(Duration::from_secs(RELOAD_CHECK_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let files = cli_files
+            .clone()
+            .or_else(|| state.config.load().tls.clone());
+        if let Some(files) = files {
+            let tls = tls.clone();
+            let _ = tokio::task::spawn_blocking(move || tls.reload_if_changed(files)).await;
+        }
+        tls.update_expiry_metric();
+    }
+}
+
+/// Check that the files hold a usable certificate and its private key
+pub fn check_files(files: &TlsConfig) -> anyhow::Result<()> {
+    load_certified_key(files).map(|_| ())
+}
+
+fn files_mtime(files: &TlsConfig) -> Option<SystemTime> {
+    let mtime = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    mtime(&files.cert).max(mtime(&files.key))
+}
+
+// The certified key and the leaf certificate's notAfter
+fn load_certified_key(files: &TlsConfig) -> anyhow::Result<(CertifiedKey, i64)> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    };
+    let certs = rustls_pemfile::certs(&mut open(&files.cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid certificate {}", files.cert.display()))?;
+    anyhow::ensure!(
+        !certs.is_empty(),
+        "no certificate found in {}",
+        files.cert.display()
+    );
+    let (_, leaf) = x509_parser::parse_x509_certificate(&certs[0])
+        .map_err(|e| anyhow::anyhow!("Invalid certificate {}: {}", files.cert.display(), e))?;
+    let not_after = leaf.validity().not_after.timestamp();
+    let key = rustls_pemfile::private_key(&mut open(&files.key)?)
+        .with_context(|| format!("Invalid private key {}", files.key.display()))?
+        .with_context(|| format!("no private key found in {}", files.key.display()))?;
+    let signing_key = ring::sign::any_supported_type(&key)
+        .with_context(|| format!("Unsupported private key {}", files.key.display()))?;
+    let certified = CertifiedKey::new(certs, signing_key);
+    // A mismatched pair would load fine and only fail every handshake
+    certified.keys_match().with_context(|| {
+        format!(
+            "Private key {} does not belong to certificate {}",
+            files.key.display(),
+            files.cert.display()
+        )
+    })?;
+    Ok((certified, not_after))
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, interval};
+use tracing::{info, warn};
+
+use crate::app::AppState;
+
+const EXPIRY_CHECK_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub removed_at: i64,
+    // The content is deleted after this, requests get 404 again
+    pub expires_at: i64,
+    // Directory the content is kept in, the data directory when missing
+    #[serde(default)]
+    pub root: Option<PathBuf>,
+    // Only a removed torrent's output is deleted on expiry. Path rules never
+    // owned the files below them (uploads, origin pulls), those stay.
+    #[serde(default)]
+    pub owns_content: bool,
+}
+
+/// URL prefixes of torrents and paths removed from the config whose content
+/// is kept for `tombstone_secs`, answered with 410 Gone until it expires or
+/// the entry is configured again
+pub struct TombstoneStore {
+    entries: Mutex<HashMap<String, Tombstone>>,
+    store_path: PathBuf,
+    // Held from snapshot to rename, config applies and the expiry task may
+    // persist at the same time
+    persist_lock: Mutex<()>,
+}
+
+// "/game", "game/" and "/game/" are the same prefix, "" and "/" the root
+fn normalize(prefix: &str) -> String {
+    let trimmed = prefix.trim_matches('/');
+    if trimmed.is_empty() {
+        return "/".to_string();
+    }
+    format!("/{}/", trimmed)
+}
+
+impl TombstoneStore {
+    pub fn load(store_path: PathBuf) -> Self {
+        let entries = match std::fs::read(&store_path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                warn!(
+                    "Failed to parse tombstones file {}: {}",
+                    store_path.display(),
+                    e
+                );
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        Self {
+            entries: Mutex::new(entries),
+            store_path,
+            persist_lock: Mutex::new(()),
+        }
+    }
+
+    /// Mark `prefix` as removed for `grace_secs`. `content_root` is set for a
+    /// removed torrent, whose output below it is deleted once the time is up.
+    pub fn bury(&self, prefix: &str, grace_secs: u64, content_root: Option<&Path>) {
+        let now = chrono::Utc::now().timestamp();
+        let prefix = normalize(prefix);
+        // Would answer 410 for everything still configured below it
+        if prefix == "/" {
+            warn!("Not keeping a tombstone for the root prefix");
+            return;
+        }
+        info!("Removed {} is served as gone for {}s", prefix, grace_secs);
+        self.entries.lock().unwrap().insert(
+            prefix,
+            Tombstone {
+                removed_at: now,
+                expires_at: now + grace_secs as i64,
+                root: content_root.map(Path::to_path_buf),
+                owns_content: content_root.is_some(),
+            },
+        );
+        self.persist();
+    }
+
+    /// `prefix` is configured again, its kept content is live
+    pub fn revive(&self, prefix: &str) {
+        let prefix = normalize(prefix);
+        if self.entries.lock().unwrap().remove(&prefix).is_some() {
+            info!("Restored {} within its grace period", prefix);
+            self.persist();
+        }
+    }
+
+    /// Tombstone covering `path`, if any
+    pub fn find(&self, path: &str) -> Option<Tombstone> {
+        let entries = self.entries.lock().unwrap();
+        if entries.is_empty() {
+            return None;
+        }
+        let dir = normalize(path);
+        entries
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix.as_str()) || dir == **prefix)
+            .map(|(_, tombstone)| tombstone.clone())
+    }
+
+    /// Directories holding the kept content of every tombstone, which must
+    /// survive until the tombstone expires or the entry is configured again
+    pub fn content_dirs(&self, data_dir: &Path) -> Vec<PathBuf> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(prefix, tombstone)| {
+                tombstone
+                    .root
+                    .as_deref()
+                    .unwrap_or(data_dir)
+                    .join(prefix.trim_matches('/'))
+            })
+            .collect()
+    }
+
+    fn take_expired(&self, now: i64) -> Vec<(String, Tombstone)> {
+        let mut entries = self.entries.lock().unwrap();
+        let expired: Vec<String> = entries
+            .iter()
+            .filter(|(_, tombstone)| tombstone.expires_at <= now)
+            .map(|(prefix, _)| prefix.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|prefix| {
+                let tombstone = entries.remove(&prefix)?;
+                Some((prefix, tombstone))
+            })
+            .collect()
+    }
+
+    fn persist(&self) {
+        let _writing = self.persist_lock.lock().unwrap();
+        let content = {
+            let entries = self.entries.lock().unwrap();
+            serde_json::to_vec(&*entries)
+        };
+        let content = match content {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Failed to serialize tombstones: {}", e);
+                return;
+            }
+        };
+        let tmp_path = self.store_path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&tmp_path, content)
+            .and_then(|_| std::fs::rename(&tmp_path, &self.store_path))
+        {
+            warn!(
+                "Failed to write tombstones file {}: {}",
+                self.store_path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Delete the content of tombstones past their grace period
+pub async fn expiry_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(EXPIRY_CHECK_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let expired = state
+            .tombstones
+            .take_expired(chrono::Utc::now().timestamp());
+        if expired.is_empty() {
+            continue;
+        }
+        state.tombstones.persist();
+
+        // Content shared with a configured torrent or path prefix below it stays
+        let (torrents, paths): (Vec<String>, Vec<String>) = {
+            let config = state.config.load();
+            (
+                config
+                    .torrents()
+                    .values()
+                    .map(|torrent| torrent.url_prefix())
+                    .collect(),
+                config
+                    .paths
+                    .path_trie
+                    .iter()
+                    .map(|(prefix, _)| normalize(prefix))
+                    .collect(),
+            )
+        };
+        for (prefix, tombstone) in expired {
+            let in_use = torrents.iter().any(|torrent| {
+                torrent.starts_with(&prefix) || prefix.starts_with(torrent.as_str())
+            }) || paths.iter().any(|path| path.starts_with(&prefix));
+            if in_use {
+                info!("Tombstone {} expired, content still in use", prefix);
+                continue;
+            }
+            if !tombstone.owns_content {
+                info!("Tombstone {} expired", prefix);
+                continue;
+            }
+            let root = tombstone.root.unwrap_or_else(|| state.data_dir.clone());
+            let dir = root.join(prefix.trim_matches('/'));
+            if !is_removable(&prefix, &dir, &root, &state.state_dir) {
+                warn!(
+                    "Tombstone {} expired, not deleting {}",
+                    prefix,
+                    dir.display()
+                );
+                continue;
+            }
+            match tokio::task::spawn_blocking(move || remove_content(&dir)).await {
+                Ok(Ok(())) => info!("Tombstone {} expired, content deleted", prefix),
+                Ok(Err(e)) => warn!("Failed to delete content of {}: {}", prefix, e),
+                Err(e) => warn!("Deleting content of {} panicked: {}", prefix, e),
+            }
+        }
+    }
+}
+
+// A torrent output strictly below its root, never the root itself or
+// anything holding or inside the state directory
+fn is_removable(prefix: &str, dir: &Path, root: &Path, state_dir: &Path) -> bool {
+    let climbs = prefix.split('/').any(|part| part == "." || part == "..");
+    if prefix == "/" || climbs {
+        return false;
+    }
+    let (Ok(dir), Ok(root), Ok(state_dir)) = (
+        std::path::absolute(dir),
+        std::path::absolute(root),
+        std::path::absolute(state_dir),
+    ) else {
+        return false;
+    };
+    dir != root
+        && dir.starts_with(&root)
+        && !state_dir.starts_with(&dir)
+        && !dir.starts_with(&state_dir)
+}
+
+fn remove_content(dir: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
@@ -0,0 +1,262 @@
+// Read-only views over librqbit state. Stats are read through their serialized
+// (HTTP API) form so we only depend on field names, not internal types.
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use base64::Engine;
+use librqbit::api::TorrentIdOrHash;
+use librqbit::dht::Id20;
+use serde::Serialize;
+use serde_json::Value;
+
+// librqbit reports speeds in MiB/s
+const MIB: f64 = 1024.0 * 1024.0;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TorrentSnapshot {
+    pub info_hash: String,
+    pub name: Option<String>,
+    pub state: String,
+    pub finished: bool,
+    pub progress_bytes: u64,
+    pub total_bytes: u64,
+    pub uploaded_bytes: u64,
+    // Bytes downloaded from peers since the torrent was added
+    pub fetched_bytes: u64,
+    pub download_bytes_per_sec: f64,
+    pub upload_bytes_per_sec: f64,
+    // Peers with an established connection
+    pub live_peers: u64,
+    pub error: Option<String>,
+}
+
+impl TorrentSnapshot {
+    /// Estimated seconds until the download completes at the current rate
+    pub fn eta_seconds(&self) -> Option<f64> {
+        if self.finished {
+            return Some(0.0);
+        }
+        if self.download_bytes_per_sec <= 0.0 {
+            return None;
+        }
+        let remaining = self.total_bytes.saturating_sub(self.progress_bytes);
+        Some(remaining as f64 / self.download_bytes_per_sec)
+    }
+}
+
+pub fn torrent_snapshots(api: &librqbit::Api) -> Vec<TorrentSnapshot> {
+    let list = api.api_torrent_list_ext(librqbit::api::ApiTorrentListOpts { with_stats: true });
+    list.torrents
+        .iter()
+        .map(|torrent| {
+            let stats = torrent
+                .stats
+                .as_ref()
+                .and_then(|stats| serde_json::to_value(stats).ok())
+                .unwrap_or(Value::Null);
+            let live = &stats["live"];
+            let speed = |name: &str| live[name]["mbps"].as_f64().unwrap_or(0.0) * MIB;
+            TorrentSnapshot {
+                info_hash: torrent.info_hash.clone(),
+                name: torrent.name.clone(),
+                state: stats["state"].as_str().unwrap_or("unknown").to_string(),
+                finished: stats["finished"].as_bool().unwrap_or(false),
+                progress_bytes: stats["progress_bytes"].as_u64().unwrap_or(0),
+                total_bytes: stats["total_bytes"].as_u64().unwrap_or(0),
+                uploaded_bytes: stats["uploaded_bytes"].as_u64().unwrap_or(0),
+                fetched_bytes: live["snapshot"]["fetched_bytes"].as_u64().unwrap_or(0),
+                download_bytes_per_sec: speed("download_speed"),
+                upload_bytes_per_sec: speed("upload_speed"),
+                live_peers: live["snapshot"]["peer_stats"]["live"].as_u64().unwrap_or(0),
+                error: stats["error"].as_str().map(|e| e.to_string()),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Default)]
+pub struct PeerConnectionSummary {
+    // Live peers keyed by (direction, transport)
+    pub live: HashMap<(&'static str, String), i64>,
+    // Outbound connection attempts summed over known peers
+    pub attempts: i64,
+    // Outbound attempts that never became a connection
+    pub failures: i64,
+    // Errors on established connections
+    pub errors: i64,
+}
+
+pub fn peer_connection_summary(api: &librqbit::Api) -> PeerConnectionSummary {
+    let mut summary = PeerConnectionSummary::default();
+    for torrent in api.api_torrent_list().torrents {
+        let Ok(info_hash) = Id20::from_str(&torrent.info_hash) else {
+            continue;
+        };
+        let Ok(peer_stats) =
+            api.api_peer_stats(TorrentIdOrHash::Hash(info_hash), Default::default())
+        else {
+            continue;
+        };
+        let Ok(value) = serde_json::to_value(&peer_stats) else {
+            continue;
+        };
+        let Some(peers) = value.get("peers").and_then(Value::as_object) else {
+            continue;
+        };
+
+        for peer in peers.values() {
+            let counters = &peer["counters"];
+            let counter = |name: &str| counters[name].as_u64().unwrap_or(0) as i64;
+            let incoming = counter("incoming_connections");
+            let attempts = counter("connection_attempts");
+            let outbound_connections = (counter("connections") - incoming).max(0);
+
+            summary.attempts += attempts;
+            summary.failures += (attempts - outbound_connections).max(0);
+            summary.errors += counter("errors");
+
+            if peer["state"].as_str() == Some("live") {
+                let direction = if incoming > 0 { "inbound" } else { "outbound" };
+                let transport = peer["conn_kind"]
+                    .as_str()
+                    .map(|kind| kind.to_lowercase())
+                    .unwrap_or_else(|| "unknown".to_string());
+                *summary.live.entry((direction, transport)).or_default() += 1;
+            }
+        }
+    }
+    summary
+}
+
+#[derive(Debug, Serialize)]
+pub struct PieceAvailability {
+    pub info_hash: String,
+    pub total_pieces: u32,
+    pub have_pieces: u32,
+    // Ranges of missing piece indexes, [start, end) each
+    pub missing_ranges: Vec<(u32, u32)>,
+    // Standard BitTorrent bitfield (MSB first), base64 encoded
+    pub bitfield: String,
+}
+
+pub fn piece_availability(
+    api: &librqbit::Api,
+    info_hash: Id20,
+) -> anyhow::Result<PieceAvailability> {
+    let (haves, total_pieces) = api.api_dump_haves(TorrentIdOrHash::Hash(info_hash))?;
+    let bitfield = haves.as_raw_slice();
+    let has_piece = |index: u32| {
+        bitfield
+            .get((index / 8) as usize)
+            .is_some_and(|byte| byte & (0x80 >> (index % 8)) != 0)
+    };
+
+    let mut have_pieces = 0;
+    let mut missing_ranges = Vec::new();
+    let mut missing_start = None;
+    for index in 0..total_pieces {
+        if has_piece(index) {
+            have_pieces += 1;
+            if let Some(start) = missing_start.take() {
+                missing_ranges.push((start, index));
+            }
+        } else if missing_start.is_none() {
+            missing_start = Some(index);
+        }
+    }
+    if let Some(start) = missing_start {
+        missing_ranges.push((start, total_pieces));
+    }
+
+    Ok(PieceAvailability {
+        info_hash: info_hash.as_string(),
+        total_pieces,
+        have_pieces,
+        missing_ranges,
+        bitfield: base64::engine::general_purpose::STANDARD.encode(bitfield),
+    })
+}
+
+/// Where a torrent's files sit in its pieces, read from the .torrent.
+/// Single-file torrents are stored as their name, multi-file ones as the file
+/// paths without the torrent name, the way the torrents are added.
+#[derive(Debug)]
+pub struct TorrentLayout {
+    pub info_hash: Id20,
+    pub piece_length: u64,
+    // Keyed by path below the torrent's URL prefix
+    files: HashMap<String, TorrentFile>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TorrentFile {
+    // Position in the torrent's file list, as librqbit numbers them
+    pub index: usize,
+    // Offset of the file within the torrent's pieces
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl TorrentLayout {
+    /// Layout of `torrent`, None if it can't be parsed
+    pub fn new(torrent: &[u8]) -> Option<Self> {
+        let meta = librqbit::torrent_from_bytes(torrent).ok()?;
+        let piece_length = meta.info.piece_length as u64;
+        if piece_length == 0 {
+            return None;
+        }
+        let mut files = HashMap::new();
+        let mut offset = 0;
+        for (index, details) in meta.info.iter_file_details().ok()?.enumerate() {
+            let name = details.filename.to_vec().ok()?.join("/");
+            files.insert(
+                name,
+                TorrentFile {
+                    index,
+                    offset,
+                    length: details.len,
+                },
+            );
+            offset += details.len;
+        }
+        Some(Self {
+            info_hash: meta.info_hash,
+            piece_length,
+            files,
+        })
+    }
+
+    /// The file at `relative`, a decoded path below the torrent's URL prefix
+    pub fn file(&self, relative: &str) -> Option<TorrentFile> {
+        self.files.get(relative.trim_start_matches('/')).copied()
+    }
+
+    /// First byte of `file` in `start..=end` whose piece isn't downloaded yet,
+    /// `end + 1` when they all are. None if the session doesn't have the torrent.
+    pub fn downloaded_until(
+        &self,
+        api: &librqbit::Api,
+        file: &TorrentFile,
+        start: u64,
+        end: u64,
+    ) -> Option<u64> {
+        let (haves, _) = api
+            .api_dump_haves(TorrentIdOrHash::Hash(self.info_hash))
+            .ok()?;
+        let bitfield = haves.as_raw_slice();
+        let has_piece = |piece: u64| {
+            bitfield
+                .get((piece / 8) as usize)
+                .is_some_and(|byte| byte & (0x80 >> (piece % 8)) != 0)
+        };
+        let first_piece = (file.offset + start) / self.piece_length;
+        let last_piece = (file.offset + end) / self.piece_length;
+        let missing = (first_piece..=last_piece).find(|&piece| !has_piece(piece));
+        Some(match missing {
+            Some(piece) => (piece * self.piece_length)
+                .saturating_sub(file.offset)
+                .max(start),
+            None => end + 1,
+        })
+    }
+}
@@ -0,0 +1,38 @@
+use percent_encoding::percent_decode_str;
+
+/// A request path as the file system sees it: percent-decoded, without empty
+/// and `.` segments and with `..` applied, so "/a/%2e%2e//.dfs%2Fx" is
+/// "/.dfs/x". None if it climbs above the root or isn't UTF-8 without NULs.
+pub fn decode_path(path: &str) -> Option<String> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    if decoded.contains('\0') {
+        return None;
+    }
+    let mut segments = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// Whether `path` has `.` or `..` segments or an encoded `/` once decoded,
+/// anything that could make it leave a prefix it appears to be under
+pub fn has_dot_segments(path: &str) -> bool {
+    path.split('/').any(|segment| {
+        let decoded = percent_decode_str(segment).decode_utf8_lossy();
+        decoded == "." || decoded == ".." || decoded.contains('/')
+    })
+}
+
+/// Whether `path` is `prefix` or below it, on segment boundaries: "/game"
+/// covers "/game" and "/game/a" but not "/gamex"
+pub fn is_within(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
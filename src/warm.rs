@@ -0,0 +1,75 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Result, bail};
+use tokio::io::AsyncReadExt;
+
+use crate::app::AppState;
+use crate::cache::FileSystemStatus;
+use crate::jobs::JobHandle;
+
+const WARM_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Read `paths` through the OS page cache in a background job, returns the job id
+pub fn spawn_warm(state: &AppState, paths: Vec<String>) -> u64 {
+    let state = state.clone();
+    state.jobs.clone().spawn("warm", move |job| async move {
+        warm_files(&state, &paths, &job).await
+    })
+}
+
+async fn warm_files(state: &AppState, paths: &[String], job: &JobHandle) -> Result<()> {
+    let total = paths.len() as u64;
+    let mut failed = Vec::new();
+    for (done, path) in paths.iter().enumerate() {
+        job.set_progress(done as u64, total);
+        job.set_message(format!("Warming {}", path));
+        if let Err(e) = warm_file(state, path).await {
+            tracing::warn!("Failed to warm {}: {}", path, e);
+            failed.push(path.as_str());
+        }
+    }
+    job.set_progress(total, total);
+    if !failed.is_empty() {
+        bail!("Failed to warm {}", failed.join(", "));
+    }
+    Ok(())
+}
+
+async fn warm_file(state: &AppState, path: &str) -> Result<()> {
+    let root = state
+        .config
+        .load()
+        .data_root(path, &state.data_dir)
+        .to_path_buf();
+    let file_path = resolve_data_path(&root, path)?;
+    let mut file = tokio::fs::File::open(&file_path).await?;
+    if !file.metadata().await?.is_file() {
+        bail!("not a file");
+    }
+    // Shares the disk read budget with HTTP transfers
+    let mut throttle = state.disk_budget.as_ref().map(|budget| budget.throttle());
+    let mut buf = vec![0u8; WARM_CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        if let Some(throttle) = throttle.as_mut() {
+            std::future::poll_fn(|cx| throttle.poll_take(cx, n)).await;
+        }
+    }
+    state.fs_cache.put(file_path, FileSystemStatus::File).await;
+    Ok(())
+}
+
+// Map a request path below `data_dir` (or a path's root), refusing anything that escapes it
+pub fn resolve_data_path(data_dir: &Path, path: &str) -> Result<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        bail!("invalid path");
+    }
+    Ok(data_dir.join(relative))
+}
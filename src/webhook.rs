@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, interval};
+use tracing::{debug, warn};
+
+use crate::app::AppState;
+use crate::logging::log_limited;
+use crate::torrents::torrent_snapshots;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Events queued for delivery, newer events are dropped when the queue is full
+const EVENT_QUEUE_SIZE: usize = 256;
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+const MONITOR_INTERVAL_SECS: u64 = 30;
+
+static EVENT_SENDER: OnceLock<mpsc::Sender<WebhookEvent>> = OnceLock::new();
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    // HMAC-SHA256 key, the hex signature of the body is sent as X-Dfs-Signature
+    pub secret: Option<String>,
+    // Event names to deliver, all events when absent
+    pub events: Option<Vec<String>>,
+    // json (default), slack or discord
+    pub format: Option<WebhookFormat>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    #[default]
+    Json,
+    Slack,
+    Discord,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    TorrentComplete {
+        info_hash: String,
+        name: Option<String>,
+        total_bytes: u64,
+    },
+    HashMismatch {
+        path: String,
+        detail: String,
+    },
+    DiskThreshold {
+        used_percent: f64,
+        threshold_percent: f64,
+    },
+    ConfigApplied {
+        version: u64,
+    },
+}
+
+impl WebhookEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            WebhookEvent::TorrentComplete { .. } => "torrent_complete",
+            WebhookEvent::HashMismatch { .. } => "hash_mismatch",
+            WebhookEvent::DiskThreshold { .. } => "disk_threshold",
+            WebhookEvent::ConfigApplied { .. } => "config_applied",
+        }
+    }
+
+    // One-line summary for chat formats
+    fn summary(&self) -> String {
+        match self {
+            WebhookEvent::TorrentComplete {
+                info_hash, name, ..
+            } => format!(
+                "Torrent {} complete",
+                name.as_deref().unwrap_or(info_hash.as_str())
+            ),
+            WebhookEvent::HashMismatch { path, detail } => {
+                format!("Hash mismatch serving {}: {}", path, detail)
+            }
+            WebhookEvent::DiskThreshold {
+                used_percent,
+                threshold_percent,
+            } => format!(
+                "Disk usage {:.1}% exceeds {:.1}%",
+                used_percent, threshold_percent
+            ),
+            WebhookEvent::ConfigApplied { version } => {
+                format!("Config version {} applied", version)
+            }
+        }
+    }
+}
+
+/// Queue an event for the configured webhooks. No-op until the dispatcher is started.
+pub fn emit(event: WebhookEvent) {
+    let Some(sender) = EVENT_SENDER.get() else {
+        return;
+    };
+    if sender.try_send(event).is_err() {
+        log_limited!(warn, "webhook_queue", "Webhook queue full, dropping event");
+    }
+}
+
+/// Start delivering emitted events, plus the torrent and disk monitors that emit them
+pub fn start(state: &AppState) {
+    let (sender, receiver) = mpsc::channel(EVENT_QUEUE_SIZE);
+    if EVENT_SENDER.set(sender).is_err() {
+        return;
+    }
+    tokio::spawn(dispatch_task(state.clone(), receiver));
+    tokio::spawn(monitor_task(state.clone()));
+}
+
+async fn dispatch_task(state: AppState, mut receiver: mpsc::Receiver<WebhookEvent>) {
+    while let Some(event) = receiver.recv().await {
+        let config = state.config.load_full();
+        let Some(webhooks) = config.webhooks.as_ref() else {
+            continue;
+        };
+        for webhook in webhooks {
+            let wanted = webhook
+                .events
+                .as_ref()
+                .is_none_or(|events| events.iter().any(|e| e == event.name()));
+            if wanted {
+                deliver(&state, webhook, &event).await;
+            }
+        }
+    }
+}
+
+async fn deliver(state: &AppState, webhook: &WebhookConfig, event: &WebhookEvent) {
+    let payload = match webhook.format.unwrap_or_default() {
+        WebhookFormat::Json => {
+            let mut payload = serde_json::to_value(event).unwrap_or(Value::Null);
+            payload["node"] = json!(state.server_id);
+            payload["timestamp"] = json!(chrono::Utc::now().timestamp());
+            payload
+        }
+        WebhookFormat::Slack => json!({ "text": chat_text(state, event) }),
+        WebhookFormat::Discord => json!({ "content": chat_text(state, event) }),
+    };
+    let body = payload.to_string();
+
+    let mut request = state
+        .http_client
+        .post(&webhook.url)
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+        .header("Content-Type", "application/json")
+        .header("X-Dfs-Event", event.name());
+    if let Some(secret) = &webhook.secret {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key");
+        mac.update(body.as_bytes());
+        request = request.header(
+            "X-Dfs-Signature",
+            format!("sha256={}", hex::encode(mac.finalize().into_bytes())),
+        );
+    }
+
+    match request.body(body).send().await {
+        Ok(response) if response.status().is_success() => {
+            debug!("Delivered {} webhook to {}", event.name(), webhook.url)
+        }
+        Ok(response) => log_limited!(
+            warn,
+            "webhook_delivery",
+            "Webhook {} returned {}",
+            webhook.url,
+            response.status()
+        ),
+        Err(e) => log_limited!(
+            warn,
+            "webhook_delivery",
+            "Failed to deliver webhook to {}: {}",
+            webhook.url,
+            e
+        ),
+    }
+}
+
+fn chat_text(state: &AppState, event: &WebhookEvent) -> String {
+    match &state.server_id {
+        Some(server_id) => format!("[{}] {}", server_id, event.summary()),
+        None => event.summary(),
+    }
+}
+
+// Detects torrent completion and disk threshold crossings, each reported once per transition
+async fn monitor_task(state: AppState) {
+    let mut interval = interval(Duration::from_secs(MONITOR_INTERVAL_SECS));
+    // Torrents already finished at startup are not reported
+    let mut finished: Option<HashSet<String>> = None;
+    let mut disk_alerted = false;
+    loop {
+        interval.tick().await;
+        let config = state.config.load_full();
+        if config.webhooks.is_none() {
+            continue;
+        }
+
+        let snapshots = torrent_snapshots(&state.bt_api);
+        let now_finished: HashSet<String> = snapshots
+            .iter()
+            .filter(|torrent| torrent.finished)
+            .map(|torrent| torrent.info_hash.clone())
+            .collect();
+        if let Some(previous) = &finished {
+            for torrent in snapshots
+                .iter()
+                .filter(|torrent| torrent.finished && !previous.contains(&torrent.info_hash))
+            {
+                emit(WebhookEvent::TorrentComplete {
+                    info_hash: torrent.info_hash.clone(),
+                    name: torrent.name.clone(),
+                    total_bytes: torrent.total_bytes,
+                });
+            }
+        }
+        finished = Some(now_finished);
+
+        let Some(threshold) = config.disk_alert_percent else {
+            disk_alerted = false;
+            continue;
+        };
+        let Some(used_percent) = disk_used_percent(&state.data_dir) else {
+            continue;
+        };
+        let over = used_percent >= threshold;
+        if over && !disk_alerted {
+            emit(WebhookEvent::DiskThreshold {
+                used_percent,
+                threshold_percent: threshold,
+            });
+        }
+        disk_alerted = over;
+    }
+}
+
+#[cfg(unix)]
+fn disk_used_percent(path: &std::path::Path) -> Option<f64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is NUL terminated and stat is a valid out pointer
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // Same as df: space reserved for root is excluded from the total
+    let used = stat.f_blocks.saturating_sub(stat.f_bfree) as f64;
+    let usable = used + stat.f_bavail as f64;
+    if usable == 0.0 {
+        return None;
+    }
+    Some(used / usable * 100.0)
+}
+
+#[cfg(not(unix))]
+fn disk_used_percent(_path: &std::path::Path) -> Option<f64> {
+    None
+}
@@ -0,0 +1,79 @@
+use anyhow::Result;
+use dfsnode::testing::{TestNode, client};
+use serde_json::{Value, json};
+
+const MANAGEMENT_TOKEN: &str = "management-token";
+
+#[tokio::test]
+async fn config_snapshot_restores_on_a_replacement_node() -> Result<()> {
+    let source = TestNode::builder()
+        .config(json!({
+            "version": 7,
+            "management_token": MANAGEMENT_TOKEN,
+            "paths": { "/signed": { "signature": "signing-token" } }
+        }))
+        .start()
+        .await?;
+    let client = client();
+
+    let unauthorized = client.get(source.url("/-/config/snapshot")).send().await?;
+    assert_eq!(unauthorized.status(), 401);
+    let snapshot: Value = client
+        .get(source.url("/-/config/snapshot"))
+        .bearer_auth(MANAGEMENT_TOKEN)
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(snapshot["version"], 7);
+
+    // Nodes without a management token never hand out or take secrets
+    let open = TestNode::builder().start().await?;
+    let refused = client.get(open.url("/-/config/snapshot")).send().await?;
+    assert_eq!(refused.status(), 403);
+    let refused = client
+        .post(open.url("/-/config/restore"))
+        .body(snapshot.to_string())
+        .send()
+        .await?;
+    assert_eq!(refused.status(), 403);
+
+    let replacement = TestNode::builder()
+        .config(json!({ "management_token": MANAGEMENT_TOKEN }))
+        .file("/signed/a.bin", "signed content")
+        .start()
+        .await?;
+    let restored = client
+        .post(replacement.url("/-/config/restore"))
+        .bearer_auth(MANAGEMENT_TOKEN)
+        .body(snapshot.to_string())
+        .send()
+        .await?;
+    assert_eq!(restored.status(), 200);
+    assert_eq!(replacement.state.config.load().get_version(), 7);
+    assert!(
+        replacement
+            .data_dir()
+            .join(".dfsnode/config-snapshot.json")
+            .exists()
+    );
+
+    // The restored paths and management token are in force
+    let unsigned = client.get(replacement.url("/signed/a.bin")).send().await?;
+    assert_eq!(unsigned.status(), 402);
+    let unauthorized = client
+        .get(replacement.url("/-/config/snapshot"))
+        .send()
+        .await?;
+    assert_eq!(unauthorized.status(), 401);
+
+    // Partial configs are refused, they would merge into the current one
+    let partial = client
+        .post(replacement.url("/-/config/restore"))
+        .bearer_auth(MANAGEMENT_TOKEN)
+        .body(json!({ "version": 8, "paths": {} }).to_string())
+        .send()
+        .await?;
+    assert_eq!(partial.status(), 400);
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use dfsnode::quota::quota_task;
+use dfsnode::testing::{TestNode, client};
+use serde_json::json;
+
+const FILE_BYTES: usize = 64 * 1024;
+const EVICT_TIMEOUT_SECS: u64 = 15;
+
+async fn wait_until(what: &str, check: impl Fn() -> bool) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(EVICT_TIMEOUT_SECS);
+    while !check() {
+        if tokio::time::Instant::now() >= deadline {
+            bail!("{} not within {}s", what, EVICT_TIMEOUT_SECS);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn write_through_cache_keeps_recently_pulled_files() -> Result<()> {
+    let origin = TestNode::builder()
+        .file("/game/a.bin", vec![b'a'; FILE_BYTES])
+        .file("/game/b.bin", vec![b'b'; FILE_BYTES])
+        .file("/game/c.bin", vec![b'c'; FILE_BYTES])
+        .start()
+        .await?;
+    // Room for two of the three files
+    let edge = TestNode::builder()
+        .config(json!({
+            "cache_max_bytes": FILE_BYTES * 5 / 2,
+            "paths": { "/game": { "origin": origin.url("/game") } }
+        }))
+        .start()
+        .await?;
+    tokio::spawn(quota_task(edge.state.clone()));
+    let client = client();
+    let cached = |name: &str| edge.data_dir().join("game").join(name);
+
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        let response = client
+            .get(edge.url(&format!("/game/{}", name)))
+            .send()
+            .await?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.bytes().await?.len(), FILE_BYTES);
+        let path = cached(name);
+        wait_until("origin pull stored", || path.exists()).await?;
+        // Distinct access times
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let exists = |name: &str| cached(name).exists();
+    wait_until("least recently pulled file evicted", || !exists("a.bin")).await?;
+    assert!(exists("b.bin"));
+    assert!(exists("c.bin"));
+
+    // Evicted files are pulled again on request
+    let again = client.get(edge.url("/game/a.bin")).send().await?;
+    assert_eq!(again.status(), 200);
+    assert_eq!(again.bytes().await?.len(), FILE_BYTES);
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use dfsnode::testing::TestNode;
+use serde_json::json;
+
+const RELOAD_TIMEOUT_SECS: u64 = 10;
+
+async fn wait_for_version(node: &TestNode, version: u64) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(RELOAD_TIMEOUT_SECS);
+    while node.state.config.load().get_version() != version {
+        if tokio::time::Instant::now() >= deadline {
+            bail!(
+                "config version {} not loaded within {}s",
+                version,
+                RELOAD_TIMEOUT_SECS
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn config_file_changes_are_applied_when_the_version_changes() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({ "version": 1, "paths": { "/a": {} } }))
+        .watch_config()
+        .start()
+        .await?;
+
+    node.write_config(json!({ "version": 2, "paths": { "/b": {} } }))
+        .await?;
+    wait_for_version(&node, 2).await?;
+    assert!(node.state.config.load().find_path_config("/b/x").is_some());
+
+    // Same version, not applied
+    node.write_config(json!({ "version": 2, "paths": { "/c": {} } }))
+        .await?;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(node.state.config.load().find_path_config("/c/x").is_none());
+
+    node.write_config(json!({ "version": 3, "paths": { "/c": {} } }))
+        .await?;
+    wait_for_version(&node, 3).await?;
+    assert!(node.state.config.load().find_path_config("/c/x").is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn path_prefixes_match_on_segment_boundaries() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({ "version": 1, "paths": { "/": {}, "/game": {} } }))
+        .start()
+        .await?;
+
+    let config = node.state.config.load();
+    assert_eq!(config.find_path_prefix("/game/a").as_deref(), Some("/game"));
+    assert_eq!(config.find_path_prefix("/game").as_deref(), Some("/game"));
+    assert_eq!(config.find_path_prefix("/gamex/a").as_deref(), Some("/"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn config_with_missing_tls_files_is_refused() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({ "version": 1, "paths": { "/a": {} } }))
+        .watch_config()
+        .start()
+        .await?;
+
+    node.write_config(json!({
+        "version": 2,
+        "paths": { "/a": {} },
+        "tls": { "cert": "/nonexistent/cert.pem", "key": "/nonexistent/key.pem" }
+    }))
+    .await?;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(node.state.config.load().get_version(), 1);
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use anyhow::Result;
+use base64::Engine;
+use dfsnode::cluster::{ClusterConfig, HashRing};
+use dfsnode::gossip::{GOSSIP_SIGNATURE_HEADER, gossip_signature};
+use dfsnode::testing::{TestNode, client};
+use serde_json::{Value, json};
+use sha1::{Digest, Sha1};
+
+const GOSSIP_SECRET: &str = "cluster-secret";
+
+fn signed(message: &Value) -> (String, Vec<u8>) {
+    let body = serde_json::to_vec(message).unwrap();
+    let now = chrono::Utc::now().timestamp() as u32;
+    (gossip_signature(GOSSIP_SECRET, now, &body), body)
+}
+
+// Bencoded single-file .torrent and its info hash
+fn single_file_torrent(name: &str, content: &[u8]) -> (Vec<u8>, String) {
+    let mut info = Vec::new();
+    info.extend_from_slice(format!("d6:lengthi{}e", content.len()).as_bytes());
+    info.extend_from_slice(format!("4:name{}:{}", name.len(), name).as_bytes());
+    info.extend_from_slice(format!("12:piece lengthi{}e", 16 * 1024).as_bytes());
+    info.extend_from_slice(b"6:pieces20:");
+    info.extend_from_slice(&Sha1::digest(content));
+    info.extend_from_slice(b"e");
+    let info_hash = hex::encode(Sha1::digest(&info));
+    let mut torrent = b"d4:info".to_vec();
+    torrent.extend_from_slice(&info);
+    torrent.extend_from_slice(b"e");
+    (torrent, info_hash)
+}
+
+#[tokio::test]
+async fn gossiped_holders_are_preferred_over_the_ring_owner() -> Result<()> {
+    let ring = HashRing::new(&serde_json::from_value::<ClusterConfig>(json!({
+        "nodes": [{ "id": "a", "url": "" }, { "id": "b", "url": "" }, { "id": "c", "url": "" }]
+    }))?);
+    let path = (0..)
+        .map(|i| format!("/game/{}.bin", i))
+        .find(|path| ring.owner(path).is_some_and(|node| node.id == "b"))
+        .unwrap();
+
+    let (torrent, info_hash) = single_file_torrent("pack.bin", b"pack");
+    let holder = TestNode::builder()
+        .file(&path, "holder content")
+        .start()
+        .await?;
+    let node = TestNode::builder()
+        .config(json!({
+            "cluster": {
+                "self_id": "a",
+                "gossip_secret": GOSSIP_SECRET,
+                "nodes": [
+                    { "id": "a", "url": "http://127.0.0.1:1" },
+                    // The ring owner, never reached once c has announced
+                    { "id": "b", "url": "http://127.0.0.1:1" },
+                    { "id": "c", "url": holder.url("") }
+                ]
+            },
+            "paths": { "/game": {} },
+            "torrents": [{
+                "path": "/game",
+                "torrent": base64::engine::general_purpose::STANDARD.encode(&torrent)
+            }]
+        }))
+        .start()
+        .await?;
+    let client = client();
+    let announce = json!({
+        "announcements": [{
+            "id": "c",
+            "completed": [info_hash],
+            "sent_at": chrono::Utc::now().timestamp()
+        }]
+    });
+
+    let (signature, body) = signed(&announce);
+    let unsigned = client
+        .post(node.url("/-/gossip"))
+        .json(&announce)
+        .send()
+        .await?;
+    assert_eq!(unsigned.status(), 401);
+    // A signature of a different body doesn't carry over
+    let tampered = client
+        .post(node.url("/-/gossip"))
+        .header(GOSSIP_SIGNATURE_HEADER, &signature)
+        .json(&json!({ "announcements": [] }))
+        .send()
+        .await?;
+    assert_eq!(tampered.status(), 401);
+    let reply = client
+        .post(node.url("/-/gossip"))
+        .header(GOSSIP_SIGNATURE_HEADER, &signature)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await?;
+    assert!(reply.headers().contains_key(GOSSIP_SIGNATURE_HEADER));
+    let reply: Value = reply.json().await?;
+    assert_eq!(reply["announcements"][0]["id"], "a");
+
+    let redirected = client.get(node.url(&path)).send().await?;
+    assert_eq!(redirected.url().port(), Some(holder.addr.port()));
+    assert_eq!(redirected.text().await?, "holder content");
+
+    // Peers hear c's announcement from this node
+    let (signature, body) = signed(&json!({ "announcements": [] }));
+    let relayed: Value = client
+        .post(node.url("/-/gossip"))
+        .header(GOSSIP_SIGNATURE_HEADER, signature)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await?
+        .json()
+        .await?;
+    let ids: Vec<&str> = relayed["announcements"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|announcement| announcement["id"].as_str())
+        .collect();
+    assert!(ids.contains(&"c"));
+    Ok(())
+}
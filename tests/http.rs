@@ -0,0 +1,855 @@
+use anyhow::Result;
+use dfsnode::cluster::{ClusterConfig, HashRing};
+use dfsnode::signature::{
+    IpBinding, OPEN_RANGE_END, create_signature, create_signature_v2, create_signature_with_params,
+    get_expire_time,
+};
+use dfsnode::testing::{TestNode, client};
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const TOKEN: &str = "integration-token";
+
+#[tokio::test]
+async fn signed_paths_require_a_valid_signature() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({ "paths": { "/signed": { "signature": TOKEN } } }))
+        .file("/signed/a.bin", "signed content")
+        .file("/signed/b.bin", "other content")
+        .start()
+        .await?;
+    let client = client();
+
+    let unsigned = client.get(node.url("/signed/a.bin")).send().await?;
+    assert_eq!(unsigned.status(), 402);
+
+    let signed = client
+        .get(node.signed_url("/signed/a.bin", TOKEN, None))
+        .send()
+        .await?;
+    assert_eq!(signed.status(), 200);
+    assert_eq!(signed.text().await?, "signed content");
+
+    let wrong_token = client
+        .get(node.signed_url("/signed/a.bin", "not-the-token", None))
+        .send()
+        .await?;
+    assert_eq!(wrong_token.status(), 402);
+
+    // A signature only covers the path it was issued for
+    let other_path = node
+        .signed_url("/signed/a.bin", TOKEN, None)
+        .replace("/a.bin", "/b.bin");
+    assert_eq!(client.get(other_path).send().await?.status(), 402);
+    Ok(())
+}
+
+#[tokio::test]
+async fn range_signatures_bind_the_range_header() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({ "paths": { "/signed": { "signature": TOKEN } } }))
+        .file("/signed/a.bin", "0123456789")
+        .start()
+        .await?;
+    let client = client();
+    let url = node.signed_url("/signed/a.bin", TOKEN, Some(&[(2, 5)]));
+
+    let ranged = client.get(&url).header("Range", "bytes=2-5").send().await?;
+    assert_eq!(ranged.status(), 206);
+    assert_eq!(ranged.text().await?, "2345");
+
+    let other_range = client.get(&url).header("Range", "bytes=0-9").send().await?;
+    assert_eq!(other_range.status(), 400);
+
+    let no_range = client.get(&url).send().await?;
+    assert_eq!(no_range.status(), 400);
+    Ok(())
+}
+
+#[tokio::test]
+async fn signed_ranges_accept_equivalent_range_headers() -> Result<()> {
+    let content: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    let node = TestNode::builder()
+        .config(json!({ "paths": { "/signed": { "signature": TOKEN } } }))
+        .file("/signed/data.bin", content.clone())
+        .start()
+        .await?;
+    let client = client();
+    let open = node.signed_url("/signed/data.bin", TOKEN, Some(&[(500, OPEN_RANGE_END)]));
+
+    // The last 500 bytes of the file, however they are written
+    for range in ["bytes=500-", "bytes=-500", "bytes=500-999"] {
+        let response = client.get(&open).header("Range", range).send().await?;
+        assert_eq!(response.status(), 206, "{}", range);
+        assert_eq!(
+            response.bytes().await?.as_ref(),
+            &content[500..],
+            "{}",
+            range
+        );
+    }
+    for range in ["bytes=500-998", "bytes=-499", "bytes=0-"] {
+        let response = client.get(&open).header("Range", range).send().await?;
+        assert_eq!(response.status(), 400, "{}", range);
+    }
+
+    // Signatures without ranges allow any Range header
+    let whole = node.signed_url("/signed/data.bin", TOKEN, None);
+    let suffix = client
+        .get(&whole)
+        .header("Range", "bytes=-10")
+        .send()
+        .await?;
+    assert_eq!(suffix.status(), 206);
+    assert_eq!(suffix.bytes().await?.as_ref(), &content[990..]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn range_signatures_reach_past_4gib() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({ "paths": { "/signed": { "signature": TOKEN } } }))
+        .start()
+        .await?;
+    // Sparse, so the test doesn't write 5 GiB
+    let path = node.data_dir().join("signed/large.pak");
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let file = std::fs::File::create(&path)?;
+    file.set_len(5 << 30)?;
+    drop(file);
+    let client = client();
+
+    let start: u64 = (4 << 30) + 10;
+    let url = node.signed_url("/signed/large.pak", TOKEN, Some(&[(start, start + 3)]));
+    assert!(
+        url.contains("$=2."),
+        "wide ranges are signed as v2: {}",
+        url
+    );
+    let range = format!("bytes={}-{}", start, start + 3);
+    let ranged = client.get(&url).header("Range", &range).send().await?;
+    assert_eq!(ranged.status(), 206);
+    assert_eq!(ranged.bytes().await?.as_ref(), &[0u8; 4]);
+
+    // The same offsets modulo 2^32 are a different range
+    let wrapped = client
+        .get(&url)
+        .header("Range", "bytes=10-13")
+        .send()
+        .await?;
+    assert_eq!(wrapped.status(), 400);
+
+    // Ranges that fit keep the v1 encoding existing clients produce
+    let small = node.signed_url("/signed/large.pak", TOKEN, Some(&[(0, 3)]));
+    assert!(!small.contains("$=2."), "{}", small);
+    let ranged = client
+        .get(&small)
+        .header("Range", "bytes=0-3")
+        .send()
+        .await?;
+    assert_eq!(ranged.status(), 206);
+    Ok(())
+}
+
+#[tokio::test]
+async fn ranges_are_served_and_validated() -> Result<()> {
+    let content: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    let node = TestNode::builder()
+        .config(json!({ "paths": { "/files": {} } }))
+        .file("/files/data.bin", content.clone())
+        .start()
+        .await?;
+    let client = client();
+    let url = node.url("/files/data.bin");
+
+    let full = client.get(&url).send().await?;
+    assert_eq!(full.status(), 200);
+    assert_eq!(full.bytes().await?.as_ref(), content.as_slice());
+
+    let middle = client
+        .get(&url)
+        .header("Range", "bytes=10-19")
+        .send()
+        .await?;
+    assert_eq!(middle.status(), 206);
+    assert_eq!(
+        middle.headers()["content-range"].to_str()?,
+        "bytes 10-19/1000"
+    );
+    assert_eq!(middle.bytes().await?.as_ref(), &content[10..20]);
+
+    let suffix = client.get(&url).header("Range", "bytes=-10").send().await?;
+    assert_eq!(suffix.status(), 206);
+    assert_eq!(suffix.bytes().await?.as_ref(), &content[990..]);
+
+    let past_end = client
+        .get(&url)
+        .header("Range", "bytes=2000-")
+        .send()
+        .await?;
+    assert_eq!(past_end.status(), 416);
+    assert_eq!(
+        past_end.headers()["content-range"].to_str()?,
+        "bytes */1000"
+    );
+
+    // Conditional requests are answered from the node's own validators
+    let etag = client.head(&url).send().await?.headers()["etag"].clone();
+    let not_modified = client
+        .get(&url)
+        .header("If-None-Match", etag)
+        .send()
+        .await?;
+    assert_eq!(not_modified.status(), 304);
+    Ok(())
+}
+
+#[tokio::test]
+async fn autoindex_lists_enabled_directories_only() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({
+            "paths": {
+                "/listed": { "autoindex": true },
+                "/hidden": {}
+            }
+        }))
+        .file("/listed/a.txt", "a")
+        .file("/listed/sub/b.txt", "b")
+        .file("/hidden/c.txt", "c")
+        .start()
+        .await?;
+    let client = client();
+
+    let html = client.get(node.url("/listed/")).send().await?;
+    assert_eq!(html.status(), 200);
+    let html = html.text().await?;
+    assert!(html.contains("a.txt"), "listing names the file: {}", html);
+    assert!(
+        html.contains("sub"),
+        "listing names the subdirectory: {}",
+        html
+    );
+
+    let listing: serde_json::Value = client
+        .get(node.url("/listed/?format=json"))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert!(listing.to_string().contains("a.txt"), "{}", listing);
+
+    let hidden = client.get(node.url("/hidden/")).send().await?;
+    assert_eq!(hidden.status(), 404);
+    // Files below a directory without autoindex are still served
+    let file = client.get(node.url("/hidden/c.txt")).send().await?;
+    assert_eq!(file.text().await?, "c");
+    Ok(())
+}
+
+#[tokio::test]
+async fn large_listings_are_complete_and_sorted() -> Result<()> {
+    // Spans several streamed chunks
+    let mut builder = TestNode::builder()
+        .config(json!({ "paths": { "/big": { "autoindex": true } } }))
+        .file("/big/zdir/inner.txt", "inner");
+    for i in 0..600 {
+        builder = builder.file(&format!("/big/f{:04}.txt", i), "x");
+    }
+    let node = builder.start().await?;
+    let client = client();
+
+    let listing: Value = client
+        .get(node.url("/big/?format=json"))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(listing["path"], "/big/");
+    let names: Vec<&str> = listing["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|entry| entry["name"].as_str())
+        .collect();
+    assert_eq!(names.len(), 602);
+    // Parent link, then directories, then files by name
+    assert_eq!(&names[..3], &["../", "zdir", "f0000.txt"]);
+    assert_eq!(names[601], "f0599.txt");
+
+    let html = client.get(node.url("/big/")).send().await?.text().await?;
+    assert!(html.contains("f0599.txt"));
+    assert!(html.trim_end().ends_with("</html>"), "{}", html);
+    Ok(())
+}
+
+#[tokio::test]
+async fn quantized_listings_are_identical_and_cacheable() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({
+            "paths": {
+                "/signed": {
+                    "autoindex": true,
+                    "signature": TOKEN,
+                    "autoindex_expire_step": 86400
+                }
+            }
+        }))
+        .file("/signed/a.bin", "a")
+        .start()
+        .await?;
+    let client = client();
+    let listing_url = node.signed_url("/signed/", TOKEN, None);
+
+    let first = client.get(&listing_url).send().await?;
+    assert_eq!(first.status(), 200);
+    let cache_control = first.headers()["cache-control"].to_str()?.to_string();
+    let max_age: u32 = cache_control
+        .strip_prefix("public, max-age=")
+        .unwrap()
+        .parse()?;
+    assert!(max_age <= 86400, "{}", cache_control);
+    let first = first.text().await?;
+    let second = client.get(&listing_url).send().await?.text().await?;
+    assert_eq!(first, second);
+
+    // The quantized links still verify
+    let href = first
+        .split("href=\"")
+        .find(|part| part.starts_with("/signed/a.bin"))
+        .and_then(|part| part.split('"').next())
+        .unwrap();
+    let file = client.get(node.url(href)).send().await?;
+    assert_eq!(file.text().await?, "a");
+    Ok(())
+}
+
+#[tokio::test]
+async fn node_state_is_never_served() -> Result<()> {
+    let node = TestNode::builder().start().await?;
+    let client = client();
+    for path in [
+        "/.dfsnode/jobs.json",
+        "/%2Edfsnode/jobs.json",
+        "/a/%2e%2e/.dfsnode/jobs.json",
+        "//.dfsnode/jobs.json",
+    ] {
+        let state = client.get(node.url(path)).send().await?;
+        assert_eq!(state.status(), 404, "{}", path);
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn reuseport_acceptors_share_the_port() -> Result<()> {
+    let node = TestNode::builder()
+        .acceptors(4)
+        .file("/a.txt", "content")
+        .start()
+        .await?;
+    // Separate connections, so they are spread over the sockets
+    let fetches = (0..32).map(|_| async {
+        let response = client().get(node.url("/a.txt")).send().await?;
+        anyhow::Ok(response.text().await?)
+    });
+    for body in futures_util::future::join_all(fetches).await {
+        assert_eq!(body?, "content");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn forwarding_headers_are_trusted_from_trusted_proxies_only() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({ "trusted_proxies": ["127.0.0.1", "10.0.0.0/8"] }))
+        .start()
+        .await?;
+    let client = client();
+    let echo = |forwarded_for: &'static str| {
+        client
+            .get(node.url("/-/debug/echo"))
+            .header("X-Forwarded-For", forwarded_for)
+            .send()
+    };
+
+    // Trusted hops are skipped from the nearest one back
+    let echoed: Value = echo("198.51.100.1, 203.0.113.7, 10.1.2.3")
+        .await?
+        .json()
+        .await?;
+    assert_eq!(echoed["client_ip"], "203.0.113.7");
+    assert_eq!(echoed["connection_ip"], "127.0.0.1");
+
+    let forwarded = || {
+        client
+            .get(node.url("/-/debug/echo"))
+            .header("Forwarded", "for=\"[2001:db8::7]:4711\";proto=https")
+            .send()
+    };
+    // Only the configured header is read
+    let ignored: Value = forwarded().await?.json().await?;
+    assert_eq!(ignored["client_ip"], "127.0.0.1");
+    node.reconfigure(json!({
+        "trusted_proxies": ["127.0.0.1", "10.0.0.0/8"],
+        "forwarded_header": "forwarded"
+    }))
+    .await?;
+    let forwarded: Value = forwarded().await?.json().await?;
+    assert_eq!(forwarded["client_ip"], "2001:db8::7");
+    let ignored: Value = echo("203.0.113.7").await?.json().await?;
+    assert_eq!(ignored["client_ip"], "127.0.0.1");
+
+    node.reconfigure(json!({})).await?;
+    let untrusted: Value = echo("203.0.113.7").await?.json().await?;
+    assert_eq!(untrusted["client_ip"], "127.0.0.1");
+    Ok(())
+}
+
+#[tokio::test]
+async fn proxy_protocol_header_names_the_client() -> Result<()> {
+    let node = TestNode::builder()
+        .proxy_protocol()
+        .config(json!({ "trusted_proxies": ["127.0.0.1"] }))
+        .start()
+        .await?;
+    let request = "GET /-/debug/echo HTTP/1.1\r\nHost: node\r\nConnection: close\r\n\r\n";
+
+    let mut stream = TcpStream::connect(node.addr).await?;
+    stream
+        .write_all(format!("PROXY TCP4 198.51.100.9 127.0.0.1 50000 80\r\n{}", request).as_bytes())
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(
+        response.contains("\"client_ip\":\"198.51.100.9\""),
+        "{}",
+        response
+    );
+
+    // v2, TCP over IPv4
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    header.extend_from_slice(&[192, 0, 2, 44, 127, 0, 0, 1, 0xc3, 0x50, 0, 80]);
+    let mut stream = TcpStream::connect(node.addr).await?;
+    stream.write_all(&header).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    assert!(
+        response.contains("\"client_ip\":\"192.0.2.44\""),
+        "{}",
+        response
+    );
+
+    // Connections without the header are closed unanswered
+    let mut stream = TcpStream::connect(node.addr).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response).await;
+    assert!(response.is_empty(), "{}", response);
+
+    // Headers from untrusted peers are read but not believed
+    node.reconfigure(json!({})).await?;
+    let mut stream = TcpStream::connect(node.addr).await?;
+    stream
+        .write_all(format!("PROXY TCP4 198.51.100.9 127.0.0.1 50000 80\r\n{}", request).as_bytes())
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    assert!(
+        response.contains("\"client_ip\":\"127.0.0.1\""),
+        "{}",
+        response
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn paths_with_a_root_are_served_from_it() -> Result<()> {
+    let node = TestNode::builder()
+        .file("/archive/a.bin", "data directory copy")
+        .file("/hot/b.bin", "hot content")
+        .start()
+        .await?;
+    // Next to the data directory, removed with the node
+    let root = node.data_dir().with_file_name("hdd");
+    tokio::fs::create_dir_all(root.join("archive/old")).await?;
+    tokio::fs::write(root.join("archive/a.bin"), "archived content").await?;
+    node.reconfigure(json!({
+        "paths": { "/archive": { "root": root, "autoindex": true }, "/hot": {} }
+    }))
+    .await?;
+    let client = client();
+
+    let archived = client.get(node.url("/archive/a.bin")).send().await?;
+    assert_eq!(archived.status(), 200);
+    assert_eq!(archived.text().await?, "archived content");
+    let ranged = client
+        .get(node.url("/archive/a.bin"))
+        .header("Range", "bytes=0-7")
+        .send()
+        .await?;
+    assert_eq!(ranged.text().await?, "archived");
+    let listing = client
+        .get(node.url("/archive/"))
+        .header("Accept", "application/json")
+        .send()
+        .await?;
+    assert_eq!(listing.status(), 200);
+    assert!(listing.text().await?.contains("old"));
+
+    let hot = client.get(node.url("/hot/b.bin")).send().await?;
+    assert_eq!(hot.text().await?, "hot content");
+    Ok(())
+}
+
+#[tokio::test]
+async fn rollouts_serve_a_share_of_clients_from_another_root() -> Result<()> {
+    let node = TestNode::builder()
+        .file("/game/a.bin", "stable")
+        .start()
+        .await?;
+    let candidate = node.data_dir().with_file_name("rc");
+    tokio::fs::create_dir_all(candidate.join("game")).await?;
+    tokio::fs::write(candidate.join("game/a.bin"), "candidate").await?;
+    node.reconfigure(json!({
+        "trusted_proxies": ["127.0.0.1"],
+        "paths": { "/game": { "rollout": { "root": candidate, "contains": "beta" } } }
+    }))
+    .await?;
+    let client = client();
+    let fetch = |user_agent: &str, client_ip: &str| {
+        client
+            .get(node.url("/game/a.bin"))
+            .header("User-Agent", user_agent)
+            .header("X-Forwarded-For", client_ip)
+            .send()
+    };
+
+    let beta = fetch("launcher/2.0 beta", "10.0.0.1").await?;
+    assert_eq!(beta.headers()["vary"], "user-agent");
+    assert!(!beta.headers().contains_key("cache-control"));
+    assert_eq!(beta.text().await?, "candidate");
+    let stable = fetch("launcher/2.0", "10.0.0.1").await?;
+    assert_eq!(stable.text().await?, "stable");
+
+    node.reconfigure(json!({
+        "trusted_proxies": ["127.0.0.1"],
+        "paths": { "/game": { "rollout": { "root": candidate, "percent": 50 } } }
+    }))
+    .await?;
+    let bucketed = fetch("launcher", "10.0.1.0").await?;
+    assert_eq!(bucketed.headers()["cache-control"], "private");
+    let mut on_candidate = 0;
+    for i in 0..100 {
+        let client_ip = format!("10.0.1.{}", i);
+        let first = fetch("launcher", &client_ip).await?.text().await?;
+        // A client stays on the build it was given
+        let second = fetch("launcher", &client_ip).await?.text().await?;
+        assert_eq!(first, second);
+        if first == "candidate" {
+            on_candidate += 1;
+        }
+    }
+    assert!((20..=80).contains(&on_candidate), "{} of 100", on_candidate);
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_files_redirect_to_their_cluster_owner() -> Result<()> {
+    // Placement depends on the node ids only
+    let ring = HashRing::new(&serde_json::from_value::<ClusterConfig>(json!({
+        "nodes": [{ "id": "a", "url": "" }, { "id": "b", "url": "" }]
+    }))?);
+    let owned_by = |id: &str, skip: usize| {
+        (0..)
+            .map(|i| format!("/game/{}.bin", i))
+            .filter(|path| ring.owner(path).is_some_and(|node| node.id == id))
+            .nth(skip)
+            .unwrap()
+    };
+    let (remote, missing, mine) = (owned_by("b", 0), owned_by("b", 1), owned_by("a", 0));
+
+    let owner = TestNode::builder()
+        .file(&remote, "owner content")
+        .start()
+        .await?;
+    let node = TestNode::builder()
+        .file("/game/local.bin", "local content")
+        .start()
+        .await?;
+    node.reconfigure(json!({
+        "cluster": {
+            "self_id": "a",
+            "nodes": [
+                { "id": "a", "url": node.url("") },
+                { "id": "b", "url": owner.url("") }
+            ]
+        },
+        "paths": { "/game": {} }
+    }))
+    .await?;
+    let client = client();
+
+    // Content held locally is served whoever owns it
+    let local = client.get(node.url("/game/local.bin")).send().await?;
+    assert_eq!(local.text().await?, "local content");
+
+    let redirected = client.get(node.url(&remote)).send().await?;
+    assert_eq!(redirected.url().port(), Some(owner.addr.port()));
+    assert_eq!(redirected.text().await?, "owner content");
+    let elsewhere = client.get(node.url(&missing)).send().await?;
+    assert_eq!(elsewhere.url().port(), Some(owner.addr.port()));
+    assert_eq!(elsewhere.status(), 404);
+    let own = client.get(node.url(&mine)).send().await?;
+    assert_eq!(own.url().port(), Some(node.addr.port()));
+    assert_eq!(own.status(), 404);
+
+    // Redirected requests are never sent on again
+    let once = client
+        .get(node.url(&format!("{}?dfs_redirected=1", missing)))
+        .send()
+        .await?;
+    assert_eq!(once.url().port(), Some(node.addr.port()));
+    assert_eq!(once.status(), 404);
+    Ok(())
+}
+
+#[tokio::test]
+async fn cluster_redirects_never_extend_a_signature() -> Result<()> {
+    let ring = HashRing::new(&serde_json::from_value::<ClusterConfig>(json!({
+        "nodes": [{ "id": "a", "url": "" }, { "id": "b", "url": "" }]
+    }))?);
+    let remote = (0..)
+        .map(|i| format!("/dl/{}.bin", i))
+        .find(|path| ring.owner(path).is_some_and(|node| node.id == "b"))
+        .unwrap();
+    let node = TestNode::builder().start().await?;
+    node.reconfigure(json!({
+        "cluster": {
+            "self_id": "a",
+            "expire_seconds": 3600,
+            "nodes": [
+                { "id": "a", "url": node.url("") },
+                { "id": "b", "url": "http://node-b.example.com" }
+            ]
+        },
+        "paths": { "/dl": { "signature": TOKEN } }
+    }))
+    .await?;
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    let expire_time = get_expire_time(60);
+    let signature = create_signature(&remote, expire_time, TOKEN, Some(&[(0, 99)]));
+    let redirect = client
+        .get(node.url(&format!("{}?$={}&dl=1", remote, signature)))
+        .header("Range", "bytes=0-99")
+        .send()
+        .await?;
+    assert_eq!(redirect.status(), 302);
+    let location = redirect.headers()["location"].to_str()?.to_string();
+    let query = location.split_once('?').unwrap().1;
+    assert!(query.split('&').any(|pair| pair == "dl=1"), "{}", location);
+    let resigned = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("$="))
+        .unwrap();
+    // Same expiry and ranges, only the HMAC differs
+    assert_eq!(&resigned[..8], &signature[..8]);
+    assert_eq!(&resigned[72..], &signature[72..]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn v2_signatures_can_cover_a_directory() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({
+            "management_token": "management-token",
+            "paths": { "/hls": { "signature": TOKEN } }
+        }))
+        .file("/hls/s1/index.m3u8", "#EXTM3U")
+        .file("/hls/s1/seg1.ts", "segment one")
+        .file("/hls/s2/seg1.ts", "other stream")
+        .start()
+        .await?;
+    let client = client();
+    let get = |path: &str, signature: &str| {
+        client
+            .get(node.url(&format!("{}?$={}", path, signature)))
+            .send()
+    };
+    let expire_time = get_expire_time(600);
+
+    let directory = create_signature_v2("/hls/s1/", expire_time, TOKEN, None, "", None);
+    assert!(directory.starts_with("2.2."));
+    assert_eq!(get("/hls/s1/index.m3u8", &directory).await?.status(), 200);
+    let segment = get("/hls/s1/seg1.ts", &directory).await?;
+    assert_eq!(segment.text().await?, "segment one");
+    assert_eq!(get("/hls/s2/seg1.ts", &directory).await?.status(), 402);
+    let tampered = directory.replacen("2.2.", "2.1.", 1);
+    assert_eq!(get("/hls/s1/seg1.ts", &tampered).await?.status(), 402);
+
+    // Without a depth the signature covers one file, like v1
+    let file = create_signature_v2("/hls/s2/seg1.ts", expire_time, TOKEN, None, "", None);
+    assert_eq!(get("/hls/s2/seg1.ts", &file).await?.status(), 200);
+    assert_eq!(get("/hls/s1/seg1.ts", &file).await?.status(), 402);
+
+    let ranged = create_signature_v2("/hls/s1/", expire_time, TOKEN, Some(&[(0, 6)]), "", None);
+    let partial = client
+        .get(node.url(&format!("/hls/s1/seg1.ts?$={}", ranged)))
+        .header("Range", "bytes=0-6")
+        .send()
+        .await?;
+    assert_eq!(partial.text().await?, "segment");
+
+    // Issued through /-/sign for a directory, the query goes on every file URL
+    let signed: Value = client
+        .post(node.url("/-/sign"))
+        .bearer_auth("management-token")
+        .json(&json!({ "paths": ["/hls/s1/"], "version": 2 }))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let url = signed["urls"][0]["url"].as_str().unwrap();
+    let query = url.split_once('?').unwrap().1;
+    let listed = client
+        .get(node.url(&format!("/hls/s1/seg1.ts?{}", query)))
+        .send()
+        .await?;
+    assert_eq!(listed.status(), 200);
+    Ok(())
+}
+
+#[tokio::test]
+async fn signatures_can_be_bound_to_the_client_network() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({
+            "trusted_proxies": ["127.0.0.1"],
+            "paths": { "/dl": { "signature": TOKEN } }
+        }))
+        .file("/dl/a.bin", "bound content")
+        .start()
+        .await?;
+    let client = client();
+    let get = |query: String, client_ip: &'static str| {
+        client
+            .get(node.url(&format!("/dl/a.bin?{}", query)))
+            .header("X-Forwarded-For", client_ip)
+            .send()
+    };
+    let binding = IpBinding::new("203.0.113.7".parse()?, 24).unwrap();
+    let signature = create_signature_with_params(
+        "/dl/a.bin",
+        get_expire_time(600),
+        TOKEN,
+        None,
+        "",
+        Some(&binding),
+    );
+    let bound = format!("{}&$={}", binding.query_pair(), signature);
+
+    let same_network = get(bound.clone(), "203.0.113.99").await?;
+    assert_eq!(same_network.text().await?, "bound content");
+    assert_eq!(get(bound.clone(), "198.51.100.1").await?.status(), 402);
+    // Dropping or widening the binding breaks the signature
+    let unbound = format!("$={}", signature);
+    assert_eq!(get(unbound, "203.0.113.7").await?.status(), 402);
+    let widened = bound.replacen("dfs_ip=24", "dfs_ip=8", 1);
+    assert_eq!(get(widened, "203.1.2.3").await?.status(), 402);
+    Ok(())
+}
+
+#[tokio::test]
+async fn rotated_signing_keys_accept_old_and_new_signatures() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({
+            "management_token": "management-token",
+            "paths": { "/dl": { "signature": ["new-token", "old-token"] } }
+        }))
+        .file("/dl/a.bin", "rotated content")
+        .start()
+        .await?;
+    let client = client();
+
+    for token in ["new-token", "old-token"] {
+        let signed = client
+            .get(node.signed_url("/dl/a.bin", token, None))
+            .send()
+            .await?;
+        assert_eq!(signed.text().await?, "rotated content");
+    }
+    let retired = client
+        .get(node.signed_url("/dl/a.bin", "retired-token", None))
+        .send()
+        .await?;
+    assert_eq!(retired.status(), 402);
+
+    // The node signs with the first key
+    let signed: Value = client
+        .post(node.url("/-/sign"))
+        .bearer_auth("management-token")
+        .json(&json!(["/dl/a.bin"]))
+        .send()
+        .await?
+        .json()
+        .await?;
+    let url = signed["urls"][0]["url"].as_str().unwrap();
+    let signature = url.split_once("$=").unwrap().1;
+    let expire_time = u32::from_str_radix(&signature[..8], 16)?;
+    assert_eq!(
+        signature,
+        create_signature("/dl/a.bin", expire_time, "new-token", None)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn sync_of_a_removed_directory_is_gone() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({
+            "version": 1,
+            "tombstone_secs": 3600,
+            "paths": { "/game": {}, "/old": {} }
+        }))
+        .file("/old/a.bin", "kept for rollback")
+        .start()
+        .await?;
+    node.reconfigure(json!({
+        "version": 2,
+        "tombstone_secs": 3600,
+        "paths": { "/game": {} }
+    }))
+    .await?;
+
+    // Not an empty directory, which would tell patchers to delete everything
+    let manifest = json!({ "files": [{ "path": "a.bin", "size": 17 }] });
+    let response = client()
+        .post(node.url("/-/sync/old/"))
+        .json(&manifest)
+        .send()
+        .await?;
+    assert_eq!(response.status(), 410);
+    Ok(())
+}
+
+#[tokio::test]
+async fn sync_directories_are_percent_decoded() -> Result<()> {
+    let node = TestNode::builder()
+        .config(json!({ "paths": { "/游戏": {} } }))
+        .file("/游戏/a b.bin", "content")
+        .start()
+        .await?;
+
+    let response = client()
+        .get(node.url("/-/sync/%E6%B8%B8%E6%88%8F/"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), 200);
+    let manifest: Value = response.json().await?;
+    assert_eq!(manifest["files"][0]["path"], "a b.bin");
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use base64::Engine;
+use dfsnode::config::node_status;
+use dfsnode::testing::{TestNode, client};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+
+const PIECE_LENGTH: usize = 64 * 1024;
+const SYNC_TIMEOUT_SECS: u64 = 60;
+
+// Bencoded single-file .torrent for `content`, trackerless
+fn single_file_torrent(name: &str, content: &[u8]) -> Vec<u8> {
+    let pieces: Vec<u8> = content
+        .chunks(PIECE_LENGTH)
+        .flat_map(|piece| Sha1::digest(piece).to_vec())
+        .collect();
+    let mut torrent = Vec::new();
+    torrent.extend_from_slice(b"d4:infod");
+    torrent.extend_from_slice(format!("6:lengthi{}e", content.len()).as_bytes());
+    torrent.extend_from_slice(format!("4:name{}:{}", name.len(), name).as_bytes());
+    torrent.extend_from_slice(format!("12:piece lengthi{}e", PIECE_LENGTH).as_bytes());
+    torrent.extend_from_slice(format!("6:pieces{}:", pieces.len()).as_bytes());
+    torrent.extend_from_slice(&pieces);
+    torrent.extend_from_slice(b"ee");
+    torrent
+}
+
+#[tokio::test]
+async fn torrent_content_is_synced_from_a_seeder() -> Result<()> {
+    let content: Vec<u8> = (0..5 * PIECE_LENGTH + 123)
+        .map(|_| rand::random::<u8>())
+        .collect();
+    let torrent =
+        base64::engine::general_purpose::STANDARD.encode(single_file_torrent("pack.bin", &content));
+
+    let seeder = TestNode::builder()
+        .config(json!({
+            "paths": { "/game": {} },
+            "torrents": [{ "path": "/game", "torrent": torrent }]
+        }))
+        .file("/game/pack.bin", content.clone())
+        .start()
+        .await?;
+    let node = TestNode::builder()
+        .config(json!({
+            "paths": { "/game": {} },
+            "torrents": [{
+                "path": "/game",
+                "torrent": torrent,
+                "initial_peers": [seeder.bt_addr.to_string()]
+            }]
+        }))
+        .start()
+        .await?;
+
+    // Poll the torrent status rather than the file, which exists (preallocated)
+    // before the pieces arrive
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(SYNC_TIMEOUT_SECS);
+    loop {
+        let status = serde_json::to_value(node_status(&node.state))?;
+        if status["torrents"][0]["finished"] == true {
+            break;
+        }
+        if tokio::time::Instant::now() >= deadline {
+            bail!(
+                "torrent not synced within {}s: {}",
+                SYNC_TIMEOUT_SECS,
+                status
+            );
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+
+    let response = client().get(node.url("/game/pack.bin")).send().await?;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.bytes().await?.as_ref(), content.as_slice());
+    Ok(())
+}